@@ -0,0 +1,108 @@
+//! Micro-benchmark for the Opus bundle decode hot path
+//!
+//! Run with `cargo bench --bench decode`. Besides the criterion timings, the
+//! number of heap allocations per decoded bundle is printed up front.
+
+use audiopus::coder::Encoder;
+use audiopus::{Application, Channels, SampleRate};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[allow(dead_code)]
+#[path = "../src/audio/decoder.rs"]
+mod decoder;
+
+use decoder::OpusDecoder;
+
+/// Counts allocations so the bench can report allocator churn per bundle
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const FRAME_SAMPLES: usize = 320; // 20ms at 16kHz
+const FRAMES_PER_BUNDLE: usize = 3;
+const BUNDLES: usize = 250; // ~15s of audio
+
+/// Build a stream of bundles shaped like the Memo device sends them:
+/// [bundle_index:1][num_frames:1]([frame_size:1][frame_data:N])*
+fn realistic_bundles() -> Vec<Vec<u8>> {
+    let encoder = Encoder::new(SampleRate::Hz16000, Channels::Mono, Application::Voip).unwrap();
+    let mut bundles = Vec::with_capacity(BUNDLES);
+    let mut t = 0usize;
+
+    for index in 0..BUNDLES {
+        let mut bundle = vec![(index % 256) as u8, FRAMES_PER_BUNDLE as u8];
+
+        for _ in 0..FRAMES_PER_BUNDLE {
+            // 440Hz tone with a little amplitude modulation so frames differ
+            let pcm: Vec<i16> = (0..FRAME_SAMPLES)
+                .map(|i| {
+                    let n = (t + i) as f32;
+                    let envelope = 0.5 + 0.5 * (n / 8000.0).sin();
+                    ((n * 440.0 * std::f32::consts::TAU / 16000.0).sin() * envelope * 8000.0) as i16
+                })
+                .collect();
+            t += FRAME_SAMPLES;
+
+            let mut packet = [0u8; 255];
+            let len = encoder.encode(&pcm, &mut packet).unwrap();
+            bundle.push(len as u8);
+            bundle.extend_from_slice(&packet[..len]);
+        }
+
+        bundles.push(bundle);
+    }
+
+    bundles
+}
+
+fn report_allocations(bundles: &[Vec<u8>]) {
+    let mut decoder = OpusDecoder::new(16000, Channels::Mono).unwrap();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for bundle in bundles {
+        black_box(decoder.decode(bundle).unwrap());
+    }
+    let after = ALLOCATIONS.load(Ordering::Relaxed);
+
+    println!(
+        "decode: {:.2} allocations per bundle ({} frames per bundle)",
+        (after - before) as f64 / bundles.len() as f64,
+        FRAMES_PER_BUNDLE
+    );
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let bundles = realistic_bundles();
+    report_allocations(&bundles);
+
+    let mut group = c.benchmark_group("opus_decode");
+    group.throughput(Throughput::Elements(bundles.len() as u64));
+    group.bench_function("bundle_stream", |b| {
+        let mut decoder = OpusDecoder::new(16000, Channels::Mono).unwrap();
+        b.iter(|| {
+            for bundle in &bundles {
+                black_box(decoder.decode(black_box(bundle)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
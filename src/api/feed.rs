@@ -0,0 +1,201 @@
+use crate::config::FeedFormat;
+use crate::storage::{Storage, Transcription};
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+/// Characters of text used as an item's title
+const TITLE_CHARS: usize = 60;
+
+/// Recent transcriptions rendered as RSS or Atom (`api.feed`)
+pub struct Feed {
+    format: FeedFormat,
+    items: usize,
+    /// Shown as the feed's title and author, e.g. the node's display name
+    title: String,
+}
+
+impl Feed {
+    pub fn new(format: FeedFormat, items: usize, title: &str) -> Self {
+        Self {
+            format,
+            items: items.max(1),
+            title: title.to_string(),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self.format {
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            _ => "application/rss+xml; charset=utf-8",
+        }
+    }
+
+    /// The feed document for the latest transcriptions; `url` is where it
+    /// is served, for the feed's own link
+    pub fn render(&self, storage: &Storage, url: &str) -> Result<String> {
+        let transcriptions = storage.get_recent_transcriptions(self.items)?;
+        Ok(match self.format {
+            FeedFormat::Atom => self.atom(&transcriptions, url),
+            _ => self.rss(&transcriptions, url),
+        })
+    }
+
+    fn rss(&self, transcriptions: &[Transcription], url: &str) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<rss version=\"2.0\">\n<channel>\n");
+        out.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        out.push_str(&format!("<link>{}</link>\n", escape(url)));
+        out.push_str(&format!(
+            "<description>Transcriptions from {}</description>\n",
+            escape(&self.title)
+        ));
+        if let Some(latest) = transcriptions.first() {
+            let built = time(latest).to_rfc2822();
+            out.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", built));
+        }
+        for transcription in transcriptions {
+            out.push_str("<item>\n");
+            out.push_str(&format!("<title>{}</title>\n", escape(&title(&transcription.text))));
+            out.push_str(&format!(
+                "<description>{}</description>\n",
+                escape(&description(transcription))
+            ));
+            out.push_str(&format!(
+                "<guid isPermaLink=\"false\">{}</guid>\n",
+                escape(&transcription.id)
+            ));
+            out.push_str(&format!("<pubDate>{}</pubDate>\n", time(transcription).to_rfc2822()));
+            out.push_str("</item>\n");
+        }
+        out.push_str("</channel>\n</rss>\n");
+        out
+    }
+
+    fn atom(&self, transcriptions: &[Transcription], url: &str) -> String {
+        let updated = transcriptions.first().map(time).unwrap_or_else(Utc::now);
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        out.push_str(&format!("<id>{}</id>\n", escape(url)));
+        out.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        out.push_str(&format!("<link rel=\"self\" href=\"{}\"/>\n", escape(url)));
+        out.push_str(&format!("<updated>{}</updated>\n", rfc3339(updated)));
+        out.push_str(&format!("<author><name>{}</name></author>\n", escape(&self.title)));
+        for transcription in transcriptions {
+            out.push_str("<entry>\n");
+            out.push_str(&format!(
+                "<id>urn:memo-node:transcription:{}</id>\n",
+                escape(&transcription.id)
+            ));
+            out.push_str(&format!("<title>{}</title>\n", escape(&title(&transcription.text))));
+            out.push_str(&format!("<updated>{}</updated>\n", rfc3339(time(transcription))));
+            out.push_str(&format!(
+                "<content type=\"text\">{}</content>\n",
+                escape(&description(transcription))
+            ));
+            out.push_str("</entry>\n");
+        }
+        out.push_str("</feed>\n");
+        out
+    }
+}
+
+fn time(transcription: &Transcription) -> DateTime<Utc> {
+    DateTime::from_timestamp(transcription.timestamp, 0).unwrap_or_default()
+}
+
+fn rfc3339(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// The start of `text`, cut at a word boundary where possible
+fn title(text: &str) -> String {
+    let text = text.trim();
+    let Some((cut, _)) = text.char_indices().nth(TITLE_CHARS) else {
+        return text.to_string();
+    };
+    let head = &text[..cut];
+    let head = head.rsplit_once(char::is_whitespace).map_or(head, |(words, _)| words);
+    format!("{}…", head.trim_end())
+}
+
+/// Full text followed by when and where it was recorded
+fn description(transcription: &Transcription) -> String {
+    let mut source = transcription.source_node.clone();
+    if let Some(device) = &transcription.memo_device_id {
+        source.push_str(&format!(", device {}", device));
+    }
+    format!(
+        "{}\n\nRecorded {} UTC on {} ({})",
+        transcription.text,
+        time(transcription).format("%Y-%m-%d %H:%M:%S"),
+        source,
+        transcription.source_type
+    )
+}
+
+/// `text` escaped for XML character data and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters aren't allowed in XML 1.0 at all
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_lists_latest_first() {
+        let path = std::env::temp_dir().join(format!("memo-node-feed-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        for (id, timestamp) in [("old", 1_717_243_200), ("new", 1_717_243_260)] {
+            storage
+                .insert_transcription(&Transcription {
+                    text: format!("{} memo & more", id),
+                    source_node: "node-a".to_string(),
+                    ..Transcription::sample(id, timestamp)
+                })
+                .unwrap();
+        }
+
+        let url = "http://pi/feed";
+        let rss = Feed::new(FeedFormat::Rss, 1, "Kitchen").render(&storage, url).unwrap();
+        assert!(rss.contains("<title>new memo &amp; more</title>"));
+        assert!(rss.contains("Jun 2024 12:01:00 +0000</pubDate>"));
+        assert!(!rss.contains("old memo"));
+
+        let atom = Feed::new(FeedFormat::Atom, 20, "Kitchen").render(&storage, url).unwrap();
+        assert!(atom.find("new memo").unwrap() < atom.find("old memo").unwrap());
+        assert!(atom.contains("<updated>2024-06-01T12:01:00Z</updated>"));
+        assert!(atom.contains("Recorded 2024-06-01 12:00:00 UTC on node-a"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_title_cuts_at_word_boundary() {
+        assert_eq!(title("  short memo "), "short memo");
+        let long = "remember to call the plumber about the leaking kitchen tap before friday";
+        assert_eq!(title(long), "remember to call the plumber about the leaking kitchen tap…");
+        assert_eq!(title(&"é".repeat(70)), format!("{}…", "é".repeat(60)));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("fish & <chips> \"now\"\u{1}"),
+            "fish &amp; &lt;chips&gt; &quot;now&quot;"
+        );
+    }
+}
@@ -1,14 +1,47 @@
+use crate::config::ApiConfig;
+use crate::transcribe::TRUNCATION_MARKER;
 use anyhow::{Context, Result};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::time::Duration;
 use tokio::time::sleep;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
+
+/// Version of the posted JSON payload; bump when fields change meaning or
+/// are removed (added fields don't need a bump)
+pub const PAYLOAD_SCHEMA_VERSION: u32 = 1;
 
 /// HTTP client for posting transcriptions to HTTPS endpoint
 pub struct HttpClient {
     client: Client,
     endpoint: String,
+    /// Salt for hashing `source_node`; `None` sends the real node id
+    source_salt: Option<String>,
+    /// Retries for connection/DNS failures, separate from error responses
+    network_backoff: Backoff,
+    /// Log posts instead of sending them (`--dry-run`)
+    dry_run: bool,
+    /// Add `schema_version` and `node_version` to payloads
+    version_fields: bool,
+    /// Longer text is truncated before posting; 0 disables
+    max_text_bytes: usize,
+}
+
+/// Stable, salted stand-in for a node id, so the endpoint can group by node
+/// without learning the real id
+pub fn anonymize_source(source_node: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(b":");
+    hasher.update(source_node.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
 
 impl HttpClient {
@@ -19,99 +52,306 @@ impl HttpClient {
             .build()
             .context("Failed to create HTTP client")?;
 
-        Ok(Self { client, endpoint })
+        Ok(Self {
+            client,
+            endpoint,
+            source_salt: None,
+            network_backoff: Backoff {
+                base_delay: Duration::from_secs(10),
+                max_retries: 5,
+            },
+            dry_run: false,
+            version_fields: false,
+            max_text_bytes: 0,
+        })
     }
 
-    /// Post a transcription to the configured HTTPS endpoint
-    /// 
-    /// Uses exponential backoff retry logic:
-    /// - First retry: 1 second
-    /// - Second retry: 2 seconds
-    /// - Third retry: 4 seconds
-    /// - Max 3 retries
-    pub async fn post_transcription(
+    /// Client for the configured `api.https_endpoint`, if any
+    ///
+    /// A client that fails to initialize is logged and treated as disabled.
+    pub fn from_config(api: &ApiConfig) -> Option<Self> {
+        let endpoint = api.https_endpoint.as_ref().filter(|e| !e.is_empty())?;
+
+        match HttpClient::new(endpoint.clone()) {
+            Ok(client) => {
+                info!("HTTP client initialized for endpoint: {}", endpoint);
+                let client = client
+                    .with_network_backoff(
+                        Duration::from_secs(api.http_network_backoff_secs),
+                        api.http_network_retries,
+                    )
+                    .with_version_fields(api.http_include_version)
+                    .with_max_text_bytes(api.http_max_text_bytes);
+                if !api.http_anonymize_source {
+                    return Some(client);
+                }
+                if api.http_anonymize_salt.is_empty() {
+                    warn!("http_anonymize_source is on with an empty salt; node ids can be brute-forced");
+                }
+                Some(client.with_anonymized_source(api.http_anonymize_salt.clone()))
+            }
+            Err(e) => {
+                warn!("Failed to initialize HTTP client: {}. HTTPS posting will be disabled.", e);
+                None
+            }
+        }
+    }
+
+    /// Replace `source_node` in posted payloads with a salted hash
+    pub fn with_anonymized_source(mut self, salt: String) -> Self {
+        self.source_salt = Some(salt);
+        self
+    }
+
+    /// Use a separate, longer backoff for connection and DNS failures, which
+    /// usually mean the network is down rather than the server overloaded
+    pub fn with_network_backoff(mut self, base_delay: Duration, max_retries: u32) -> Self {
+        self.network_backoff = Backoff {
+            base_delay,
+            max_retries,
+        };
+        self
+    }
+
+    /// Tag payloads with `schema_version` (`PAYLOAD_SCHEMA_VERSION`) and
+    /// `node_version` (this build's package version)
+    pub fn with_version_fields(mut self, version_fields: bool) -> Self {
+        self.version_fields = version_fields;
+        self
+    }
+
+    /// Truncate text longer than `max_text_bytes` (0 disables) before
+    /// posting, for endpoints that reject large payloads
+    pub fn with_max_text_bytes(mut self, max_text_bytes: usize) -> Self {
+        self.max_text_bytes = max_text_bytes;
+        self
+    }
+
+    /// Log posts instead of sending them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// JSON body posted for one transcription
+    fn payload(
         &self,
         id: &str,
         timestamp: i64,
         text: &str,
         source_node: &str,
         memo_device_id: Option<&str>,
-    ) -> Result<()> {
-        let payload = json!({
+        duration_ms: Option<i64>,
+    ) -> serde_json::Value {
+        let source_node = match &self.source_salt {
+            Some(salt) => anonymize_source(source_node, salt),
+            None => source_node.to_string(),
+        };
+
+        let mut payload = json!({
             "id": id,
             "timestamp": timestamp,
             "text": text,
             "source_node": source_node,
             "memo_device_id": memo_device_id,
+            "duration_ms": duration_ms,
         });
+        if self.version_fields {
+            payload["schema_version"] = json!(PAYLOAD_SCHEMA_VERSION);
+            payload["node_version"] = json!(env!("CARGO_PKG_VERSION"));
+        }
+        payload
+    }
 
-        let mut retry_count = 0;
-        const MAX_RETRIES: u32 = 3;
+    /// Post a transcription to the configured HTTPS endpoint
+    ///
+    /// Failures are retried with exponential backoff, counted separately per
+    /// category:
+    /// - Error responses and other request errors: 1s, 2s, 4s (max 3 retries)
+    /// - Connection/DNS errors and timeouts: the network backoff (by default
+    ///   10s, 20s, 40s, 80s, 160s)
+    ///
+    /// 4xx responses other than 429 fail immediately: the same payload
+    /// would be rejected again.
+    pub async fn post_transcription(
+        &self,
+        id: &str,
+        timestamp: i64,
+        text: &str,
+        source_node: &str,
+        memo_device_id: Option<&str>,
+        duration_ms: Option<i64>,
+    ) -> Result<()> {
+        let posted_text = truncate_text(text, self.max_text_bytes);
+        if posted_text.len() != text.len() {
+            warn!(
+                "Truncated transcription {} from {} to {} bytes for the HTTPS endpoint (api.http_max_text_bytes)",
+                id,
+                text.len(),
+                posted_text.len()
+            );
+        }
+        let payload = self.payload(
+            id,
+            timestamp,
+            &posted_text,
+            source_node,
+            memo_device_id,
+            duration_ms,
+        );
+
+        if self.dry_run {
+            info!("Dry run: would POST transcription {} to {}", id, self.endpoint);
+            return Ok(());
+        }
+
+        let mut server_retries = 0;
+        let mut network_retries = 0;
 
         loop {
-            match self
+            let (category, error) = match self
                 .client
                 .post(&self.endpoint)
                 .json(&payload)
                 .send()
                 .await
             {
+                Ok(response) if response.status().is_success() => {
+                    debug!(
+                        "Successfully posted transcription {} to {}",
+                        id, self.endpoint
+                    );
+                    return Ok(());
+                }
                 Ok(response) => {
-                    if response.status().is_success() {
-                        debug!(
-                            "Successfully posted transcription {} to {}",
-                            id, self.endpoint
-                        );
-                        return Ok(());
-                    } else {
-                        let status = response.status();
-                        let error_text = response
-                            .text()
-                            .await
-                            .unwrap_or_else(|_| "Unknown error".to_string());
-                        
-                        if retry_count < MAX_RETRIES {
-                            retry_count += 1;
-                            let delay = Duration::from_secs(2_u64.pow(retry_count - 1));
-                            warn!(
-                                "HTTP POST failed with status {}: {}. Retrying in {:?} (attempt {}/{})",
-                                status, error_text, delay, retry_count, MAX_RETRIES
-                            );
-                            sleep(delay).await;
-                            continue;
-                        } else {
-                            return Err(anyhow::anyhow!(
-                                "HTTP POST failed after {} retries: status {} - {}",
-                                MAX_RETRIES,
-                                status,
-                                error_text
-                            ));
-                        }
-                    }
+                    let status = response.status();
+                    let error_text = response
+                        .text()
+                        .await
+                        .unwrap_or_else(|_| "Unknown error".to_string());
+                    (
+                        FailureCategory::of_status(status),
+                        format!("status {} - {}", status, error_text),
+                    )
                 }
-                Err(e) => {
-                    if retry_count < MAX_RETRIES {
-                        retry_count += 1;
-                        let delay = Duration::from_secs(2_u64.pow(retry_count - 1));
-                        warn!(
-                            "HTTP POST error: {}. Retrying in {:?} (attempt {}/{})",
-                            e, delay, retry_count, MAX_RETRIES
-                        );
-                        sleep(delay).await;
-                        continue;
-                    } else {
-                        return Err(anyhow::anyhow!(
-                            "HTTP POST failed after {} retries: {}",
-                            MAX_RETRIES,
-                            e
-                        ));
-                    }
+                Err(e) => (FailureCategory::of(&e), e.to_string()),
+            };
+
+            let (backoff, retries) = match category {
+                FailureCategory::Rejected => {
+                    return Err(anyhow::anyhow!("HTTP POST rejected, not retrying: {}", error));
                 }
+                FailureCategory::Network => (&self.network_backoff, &mut network_retries),
+                _ => (&SERVER_BACKOFF, &mut server_retries),
+            };
+
+            if *retries >= backoff.max_retries {
+                return Err(anyhow::anyhow!(
+                    "HTTP POST failed ({}) after {} retries: {}",
+                    category.as_str(),
+                    *retries,
+                    error
+                ));
             }
+
+            *retries += 1;
+            let delay = backoff.delay(*retries);
+            warn!(
+                "HTTP POST failed ({}): {}. Retrying in {:?} (attempt {}/{})",
+                category.as_str(),
+                error,
+                delay,
+                *retries,
+                backoff.max_retries
+            );
+            sleep(delay).await;
         }
     }
 }
 
+/// Why a POST attempt failed; each category has its own retry budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureCategory {
+    /// Couldn't reach the server: DNS, refused connection, timeout
+    Network,
+    /// The server answered with a 5xx or 429 (or another non-success)
+    /// status that may succeed later
+    Response,
+    /// The server refused this payload (4xx other than 429); never retried
+    Rejected,
+    /// Anything else, e.g. a malformed request or TLS failure
+    Request,
+}
+
+impl FailureCategory {
+    fn of(error: &reqwest::Error) -> Self {
+        if error.is_connect() || error.is_timeout() {
+            FailureCategory::Network
+        } else {
+            FailureCategory::Request
+        }
+    }
+
+    fn of_status(status: StatusCode) -> Self {
+        if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+            FailureCategory::Rejected
+        } else {
+            FailureCategory::Response
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FailureCategory::Network => "network",
+            FailureCategory::Response => "response",
+            FailureCategory::Rejected => "rejected",
+            FailureCategory::Request => "request",
+        }
+    }
+}
+
+/// `text` cut to at most `max_bytes` bytes on a character boundary, ending
+/// with `TRUNCATION_MARKER`; unchanged if it fits or `max_bytes` is 0
+fn truncate_text(text: &str, max_bytes: usize) -> Cow<'_, str> {
+    if max_bytes == 0 || text.len() <= max_bytes {
+        return Cow::Borrowed(text);
+    }
+
+    let mut end = max_bytes.saturating_sub(TRUNCATION_MARKER.len_utf8());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut truncated = text[..end].to_string();
+    truncated.push(TRUNCATION_MARKER);
+    Cow::Owned(truncated)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    base_delay: Duration,
+    max_retries: u32,
+}
+
+/// Longest wait between retries, however many have failed
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+impl Backoff {
+    /// Delay before the `attempt`th retry (1-based): doubling from the base,
+    /// up to `MAX_RETRY_DELAY`
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay
+            .checked_mul(factor)
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+const SERVER_BACKOFF: Backoff = Backoff {
+    base_delay: Duration::from_secs(1),
+    max_retries: 3,
+};
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,4 +362,65 @@ mod tests {
         let client = HttpClient::new("https://example.com/api".to_string());
         assert!(client.is_ok());
     }
+
+    #[test]
+    fn test_payload_version_fields() {
+        let client = HttpClient::new("https://example.com/api".to_string()).unwrap();
+        let payload = client.payload("a", 1, "hi", "pi", None, None);
+        assert!(payload.get("schema_version").is_none());
+
+        let payload = client.with_version_fields(true).payload("a", 1, "hi", "pi", None, None);
+        assert_eq!(payload["schema_version"], PAYLOAD_SCHEMA_VERSION);
+        assert_eq!(payload["node_version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_anonymize_source_is_stable_and_salted() {
+        let hashed = anonymize_source("pi-workshop", "salt");
+        assert_eq!(hashed, anonymize_source("pi-workshop", "salt"));
+        assert_eq!(hashed.len(), 16);
+        assert!(!hashed.contains("pi-workshop"));
+        assert_ne!(hashed, anonymize_source("pi-workshop", "other"));
+        assert_ne!(hashed, anonymize_source("macbook", "salt"));
+    }
+
+    #[test]
+    fn test_only_client_errors_other_than_429_are_rejected() {
+        let category = FailureCategory::of_status;
+        assert_eq!(category(StatusCode::PAYLOAD_TOO_LARGE), FailureCategory::Rejected);
+        assert_eq!(category(StatusCode::BAD_REQUEST), FailureCategory::Rejected);
+        assert_eq!(category(StatusCode::TOO_MANY_REQUESTS), FailureCategory::Response);
+        assert_eq!(category(StatusCode::SERVICE_UNAVAILABLE), FailureCategory::Response);
+    }
+
+    #[test]
+    fn test_truncate_text_to_bytes() {
+        assert_eq!(truncate_text("hello", 0), "hello");
+        assert_eq!(truncate_text("hello", 5), "hello");
+        // The 3-byte marker fits within the limit
+        assert_eq!(truncate_text("hello world", 8), "hello…");
+        // Never cuts inside a multi-byte character
+        let truncated = truncate_text("ééééé", 8);
+        assert_eq!(truncated, "éé…");
+        assert!(truncated.len() <= 8);
+    }
+
+    #[test]
+    fn test_backoff_doubles_from_base() {
+        let backoff = Backoff {
+            base_delay: Duration::from_secs(10),
+            max_retries: 5,
+        };
+        assert_eq!(backoff.delay(1), Duration::from_secs(10));
+        assert_eq!(backoff.delay(3), Duration::from_secs(40));
+        assert_eq!(backoff.delay(12), MAX_RETRY_DELAY);
+        assert_eq!(backoff.delay(u32::MAX), MAX_RETRY_DELAY);
+
+        // Would overflow Duration
+        let backoff = Backoff {
+            base_delay: Duration::from_secs(u64::MAX / 2),
+            max_retries: 5,
+        };
+        assert_eq!(backoff.delay(3), MAX_RETRY_DELAY);
+    }
 }
@@ -1,5 +1,11 @@
+pub mod feed;
 pub mod http;
+pub mod mqtt;
+pub mod rest;
 pub mod websocket;
 
+pub use feed::Feed;
 pub use http::HttpClient;
+pub use mqtt::MqttClient;
+pub use rest::RestServer;
 pub use websocket::WebSocketServer;
@@ -0,0 +1,193 @@
+use crate::config::ApiConfig;
+use crate::storage::Transcription;
+use anyhow::{Context, Result};
+use serde_json::json;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{info, warn};
+
+/// Publishes stored transcriptions as JSON to `api.mqtt_topic`
+///
+/// Messages wait in a queue of `api.mqtt_queue_size` while the broker is
+/// unreachable and go out once it reconnects; when the queue is full new
+/// transcriptions are dropped rather than published.
+pub struct MqttClient {
+    tx: mpsc::Sender<Vec<u8>>,
+    topic: String,
+    queue_size: usize,
+}
+
+impl MqttClient {
+    /// Client for the configured `api.mqtt_host`, if any
+    ///
+    /// A client that fails to initialize is logged and treated as disabled.
+    /// Must be called from within the Tokio runtime.
+    pub fn from_config(api: &ApiConfig, node_id: &str) -> Option<Self> {
+        if api.mqtt_host.is_empty() {
+            return None;
+        }
+
+        let queue_size = api.mqtt_queue_size.max(1);
+        let (tx, rx) = mpsc::channel(queue_size);
+        match connect(api, node_id, rx) {
+            Ok(()) => {
+                info!(
+                    "MQTT client initialized for {}:{}, topic {}",
+                    api.mqtt_host, api.mqtt_port, api.mqtt_topic
+                );
+                Some(Self {
+                    tx,
+                    topic: api.mqtt_topic.clone(),
+                    queue_size,
+                })
+            }
+            Err(e) => {
+                warn!("Failed to initialize MQTT client: {:#}. MQTT publishing will be disabled.", e);
+                None
+            }
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Queue one stored transcription for publishing
+    ///
+    /// Fails if the queue is full, i.e. the broker has been unreachable for
+    /// a while; the transcription is not published.
+    pub fn publish(&self, transcription: &Transcription) -> Result<()> {
+        let payload =
+            serde_json::to_vec(&payload(transcription)).context("Failed to encode MQTT payload")?;
+        self.tx.try_send(payload).map_err(|e| match e {
+            TrySendError::Full(_) => anyhow::anyhow!(
+                "MQTT queue is full ({} messages); dropping transcription {}",
+                self.queue_size,
+                transcription.id
+            ),
+            TrySendError::Closed(_) => anyhow::anyhow!("MQTT connection task has stopped"),
+        })
+    }
+}
+
+/// JSON message published for one transcription
+fn payload(transcription: &Transcription) -> serde_json::Value {
+    json!({
+        "id": transcription.id,
+        "timestamp": transcription.timestamp,
+        "text": transcription.text,
+        "source_node": transcription.source_node,
+        "source_type": transcription.source_type.to_string(),
+        "memo_device_id": transcription.memo_device_id,
+        "session_id": transcription.session_id,
+        "duration_ms": transcription.duration_ms,
+        "metadata": transcription.metadata.0,
+    })
+}
+
+/// Spawn the tasks that keep a broker connection and publish messages
+/// from `rx`
+///
+/// rumqttc reconnects on the next poll after a connection error; the poll
+/// loop backs off between attempts so an unreachable broker isn't hammered.
+#[cfg(feature = "mqtt")]
+fn connect(api: &ApiConfig, node_id: &str, mut rx: mpsc::Receiver<Vec<u8>>) -> Result<()> {
+    use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, Transport};
+    use std::time::Duration;
+    use tracing::debug;
+
+    const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+    let client_id = if api.mqtt_client_id.is_empty() {
+        format!("memo-node-{}", node_id)
+    } else {
+        api.mqtt_client_id.clone()
+    };
+    let mut options = MqttOptions::new(client_id, &api.mqtt_host, api.mqtt_port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if !api.mqtt_username.is_empty() {
+        options.set_credentials(&api.mqtt_username, &api.mqtt_password);
+    }
+    if api.mqtt_tls {
+        options.set_transport(Transport::tls_with_default_config());
+    }
+
+    // `rx` is the bounded queue; the client's own request channel only needs
+    // room for the message being handed over
+    let (client, mut eventloop) = AsyncClient::new(options, 1);
+    let topic = api.mqtt_topic.clone();
+    tokio::spawn(async move {
+        while let Some(payload) = rx.recv().await {
+            if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, false, payload).await {
+                warn!("Failed to publish to MQTT topic {}: {}", topic, e);
+            }
+        }
+    });
+
+    let broker = format!("{}:{}", api.mqtt_host, api.mqtt_port);
+    tokio::spawn(async move {
+        let mut failures = 0u32;
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}", broker);
+                    failures = 0;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    failures += 1;
+                    let delay = Duration::from_secs(1 << failures.min(5)).min(MAX_RECONNECT_DELAY);
+                    if failures == 1 {
+                        warn!("MQTT broker {} unreachable: {}. Reconnecting in {:?}", broker, e, delay);
+                    } else {
+                        debug!("MQTT broker {} still unreachable: {}. Retrying in {:?}", broker, e, delay);
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn connect(_api: &ApiConfig, _node_id: &str, _rx: mpsc::Receiver<Vec<u8>>) -> Result<()> {
+    anyhow::bail!("api.mqtt_host is set but memo-node was built without the `mqtt` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SourceType;
+
+    #[test]
+    fn test_payload_fields() {
+        let payload = payload(&Transcription {
+            text: "buy milk".to_string(),
+            memo_device_id: Some("memo-1".to_string()),
+            duration_ms: Some(1_500),
+            ..Transcription::sample("t1", 1_700_000_000)
+        });
+        assert_eq!(payload["id"], "t1");
+        assert_eq!(payload["text"], "buy milk");
+        assert_eq!(payload["source_type"], SourceType::Device.to_string());
+        assert_eq!(payload["memo_device_id"], "memo-1");
+        assert_eq!(payload["duration_ms"], 1_500);
+        assert!(payload["session_id"].is_null());
+    }
+
+    #[test]
+    fn test_full_queue_drops_new_messages() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let client = MqttClient {
+            tx,
+            topic: "memo/transcriptions".to_string(),
+            queue_size: 1,
+        };
+
+        client.publish(&Transcription::sample("t1", 1_700_000_000)).unwrap();
+        assert!(client.publish(&Transcription::sample("t2", 1_700_000_001)).is_err());
+        let queued: serde_json::Value = serde_json::from_slice(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(queued["id"], "t1");
+        assert!(rx.try_recv().is_err());
+    }
+}
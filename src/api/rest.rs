@@ -0,0 +1,234 @@
+use crate::api::Feed;
+use crate::api::websocket::TranscriptionData;
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// Request heads larger than this are rejected
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Connections that haven't sent a full request head by then are dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const JSON: &str = "application/json";
+
+/// Minimal read-only HTTP API for clients that can't hold a WebSocket open
+///
+/// Serves `GET /transcriptions/{id}`, and `GET /feed` when a feed is set;
+/// everything else is a 404 or 405. Bodies other than the feed are JSON, with
+/// `{"error": ...}` on failure.
+pub struct RestServer {
+    storage: Storage,
+    feed: Option<Arc<Feed>>,
+}
+
+impl RestServer {
+    pub fn new(storage: Storage) -> Self {
+        Self { storage, feed: None }
+    }
+
+    /// Serve recent transcriptions as RSS or Atom at `/feed`
+    pub fn with_feed(mut self, feed: Feed) -> Self {
+        self.feed = Some(Arc::new(feed));
+        self
+    }
+
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        let addr = listener
+            .local_addr()
+            .context("Failed to get REST listener address")?;
+        info!("REST API listening on {}", addr);
+
+        loop {
+            let (stream, peer_addr) = listener.accept().await.context("Failed to accept REST connection")?;
+            let storage = self.storage.clone();
+            let feed = self.feed.clone();
+            tokio::spawn(async move {
+                let feed = feed.as_deref();
+                if let Err(e) = handle_connection(&storage, feed, stream, peer_addr).await {
+                    debug!("REST request from {} failed: {:#}", peer_addr, e);
+                }
+            });
+        }
+    }
+}
+
+/// Serve one request, then close the connection
+async fn handle_connection(
+    storage: &Storage,
+    feed: Option<&Feed>,
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let deadline = tokio::time::Instant::now() + REQUEST_TIMEOUT;
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() > MAX_REQUEST_BYTES {
+            let body = json!({ "error": "request too large" });
+            return write_response(&mut stream, 431, JSON, &body.to_string()).await;
+        }
+        let n = tokio::time::timeout_at(deadline, stream.read(&mut chunk))
+            .await
+            .context("Timed out reading request")?
+            .context("Failed to read request")?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    let request_line = head.lines().next().unwrap_or_default();
+    debug!("REST request from {}: {}", peer_addr, request_line);
+
+    if let Some(feed) = feed.filter(|_| is_feed_request(request_line)) {
+        let host = head.lines().skip(1).find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("host").then(|| value.trim())
+        });
+        let url = format!("http://{}/feed", host.unwrap_or("localhost"));
+        return match feed.render(storage, &url) {
+            Ok(body) => write_response(&mut stream, 200, feed.content_type(), &body).await,
+            Err(e) => {
+                warn!("Failed to render feed: {:#}", e);
+                let body = json!({ "error": "storage error" });
+                write_response(&mut stream, 500, JSON, &body.to_string()).await
+            }
+        };
+    }
+
+    let (status, body) = route(storage, request_line);
+    write_response(&mut stream, status, JSON, &body.to_string()).await
+}
+
+/// Whether `request_line` is `GET /feed`, with or without a query string
+fn is_feed_request(request_line: &str) -> bool {
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    method == "GET" && path.split('?').next() == Some("/feed")
+}
+
+/// Status and JSON body for a request line like `GET /transcriptions/abc HTTP/1.1`
+fn route(storage: &Storage, request_line: &str) -> (u16, serde_json::Value) {
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    // Ignore any query string
+    let path = path.split('?').next().unwrap_or_default();
+
+    let Some(id) = path.strip_prefix("/transcriptions/").filter(|id| !id.is_empty() && !id.contains('/'))
+    else {
+        return (404, json!({ "error": "not found" }));
+    };
+    if method != "GET" {
+        return (405, json!({ "error": "method not allowed" }));
+    }
+    let Some(id) = percent_decode(id) else {
+        return (400, json!({ "error": "malformed id" }));
+    };
+    let id = id.as_str();
+
+    match storage.find_transcription(id) {
+        Ok(Some(transcription)) => (200, json!(TranscriptionData::from(transcription))),
+        Ok(None) => (404, json!({ "error": format!("transcription {} not found", id) })),
+        Err(e) => {
+            warn!("Failed to look up transcription {}: {:#}", id, e);
+            (500, json!({ "error": "storage error" }))
+        }
+    }
+}
+
+/// Decode `%XX` escapes in a path segment; `None` if one is malformed or
+/// the result isn't UTF-8
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    );
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .context("Failed to write response")?;
+    stream.shutdown().await.ok();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{SourceType, Transcription};
+
+    #[test]
+    fn test_route_by_id() {
+        let path = std::env::temp_dir().join(format!("memo-node-rest-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        storage
+            .insert_transcription(&Transcription {
+                text: "hello".to_string(),
+                source_node: "node-a".to_string(),
+                source_type: SourceType::Manual,
+                ..Transcription::sample("abc", 1_717_243_200)
+            })
+            .unwrap();
+
+        let (status, body) = route(&storage, "GET /transcriptions/abc HTTP/1.1");
+        assert_eq!(status, 200);
+        assert_eq!(body["text"], "hello");
+
+        let (status, body) = route(&storage, "GET /transcriptions/nope HTTP/1.1");
+        assert_eq!(status, 404);
+        assert_eq!(body["error"], "transcription nope not found");
+
+        let (status, body) = route(&storage, "GET /transcriptions/%61bc HTTP/1.1");
+        assert_eq!(status, 200);
+        assert_eq!(body["id"], "abc");
+        assert_eq!(route(&storage, "GET /transcriptions/ab%zz HTTP/1.1").0, 400);
+        assert_eq!(route(&storage, "GET /transcriptions/ab%2 HTTP/1.1").0, 400);
+
+        assert_eq!(route(&storage, "DELETE /transcriptions/abc HTTP/1.1").0, 405);
+        assert_eq!(route(&storage, "GET / HTTP/1.1").0, 404);
+        assert!(is_feed_request("GET /feed?n=5 HTTP/1.1"));
+        assert!(!is_feed_request("POST /feed HTTP/1.1"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
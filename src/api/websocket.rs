@@ -1,14 +1,28 @@
-use crate::storage::{Storage, Transcription};
+use crate::audio::RecordingEvent;
+use crate::logging::{LogBuffer, LogLine};
+use crate::metrics::{Metrics, MetricsRates, MetricsSnapshot};
+use crate::storage::{self, Metadata, SourceType, Storage, Transcription};
+use crate::sync::PeerEvent;
 use anyhow::{Context, Result};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info, warn};
 
+/// How long a connection being closed by the server waits for its last
+/// messages to be sent
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum ServerMessage {
@@ -19,13 +33,108 @@ pub enum ServerMessage {
         text: String,
         source_node: String,
         memo_device_id: Option<String>,
+        source_type: SourceType,
+        /// See `TranscriptionData::session_id`
+        session_id: Option<String>,
+        device_battery: Option<u8>,
+        /// See `TranscriptionData::metadata`
+        #[serde(default)]
+        metadata: BTreeMap<String, String>,
+        /// See `TranscriptionData::duration_ms`
+        #[serde(default)]
+        duration_ms: Option<i64>,
+        /// See `TranscriptionData::short_code`
+        #[serde(default)]
+        short_code: Option<String>,
+        /// See `TranscriptionData::cursor`
+        cursor: i64,
     },
     #[serde(rename = "peer_connected")]
-    PeerConnected { node_id: String },
+    PeerConnected { node_id: String, display_name: String },
     #[serde(rename = "peer_disconnected")]
-    PeerDisconnected { node_id: String },
+    PeerDisconnected { node_id: String, display_name: String },
+    /// A device's button started recording (with `api.recording_events`)
+    #[serde(rename = "recording_started")]
+    RecordingStarted { device: String },
+    /// A device's button stopped recording; its transcription follows once
+    /// the audio is transcribed
+    #[serde(rename = "recording_stopped")]
+    RecordingStopped { device: String },
     #[serde(rename = "history")]
     History { transcriptions: Vec<TranscriptionData> },
+    /// Part of a history reply larger than the configured chunk size; the
+    /// chunks concatenated, up to the one with `done`, are the full reply
+    #[serde(rename = "history_chunk")]
+    HistoryChunk {
+        transcriptions: Vec<TranscriptionData>,
+        done: bool,
+    },
+    /// Live messages were dropped; the client should re-request history
+    #[serde(rename = "resync")]
+    Resync { missed: u64 },
+    /// A stored transcription matched a `keyword_rules` entry and was tagged
+    #[serde(rename = "keyword_matched")]
+    KeywordMatched {
+        id: String,
+        tag: String,
+        pattern: String,
+    },
+    /// Reply to `get_stats`, and pushed every `api.stats_interval_secs`:
+    /// audio pipeline counters, BLE devices and connected clients and peers
+    #[serde(rename = "stats")]
+    Stats {
+        recording: bool,
+        /// Connected WebSocket clients
+        #[serde(default)]
+        clients: usize,
+        /// Peers currently connected for sync
+        #[serde(default)]
+        peers: usize,
+        /// Growth since the previous pushed `stats`; absent from `get_stats`
+        /// replies and the first push
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rates: Option<MetricsRates>,
+        #[serde(flatten)]
+        metrics: MetricsSnapshot,
+    },
+    /// Reply to `get_logs`: recent daemon log lines, oldest first
+    #[serde(rename = "logs")]
+    Logs { lines: Vec<LogLine> },
+    /// Reply to `set_context`: the context now applied to new transcriptions
+    #[serde(rename = "context")]
+    Context { context: BTreeMap<String, String> },
+    /// A request couldn't be answered, e.g. `get_transcription` for an
+    /// unknown id
+    #[serde(rename = "error")]
+    Error { message: String },
+}
+
+impl From<Transcription> for ServerMessage {
+    fn from(t: Transcription) -> Self {
+        ServerMessage::Transcription {
+            id: t.id,
+            timestamp: t.timestamp,
+            text: t.text,
+            source_node: t.source_node,
+            memo_device_id: t.memo_device_id,
+            source_type: t.source_type,
+            session_id: t.session_id,
+            device_battery: t.device_battery,
+            metadata: t.metadata.0,
+            duration_ms: t.duration_ms,
+            short_code: t.short_code,
+            cursor: t.cursor,
+        }
+    }
+}
+
+impl From<RecordingEvent> for ServerMessage {
+    fn from(event: RecordingEvent) -> Self {
+        match event {
+            RecordingEvent::Started { device } => ServerMessage::RecordingStarted { device },
+            RecordingEvent::Stopped { device } => ServerMessage::RecordingStopped { device },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,80 +144,368 @@ pub struct TranscriptionData {
     pub text: String,
     pub source_node: String,
     pub memo_device_id: Option<String>,
+    pub source_type: SourceType,
+    /// Shared by consecutive rows of one recording session (the id of its
+    /// first row) when `transcription.merge_gap_ms` is set; `None` otherwise
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Battery percentage of the recording device when the row was stored
+    #[serde(default)]
+    pub device_battery: Option<u8>,
+    /// Context set with `set_context` when the row was created
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Length of the recording in milliseconds; `None` for typed, imported
+    /// or older rows
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+    /// Code like `MEMO-7Q2` accepted in place of `id` on this node
+    /// (`storage.short_codes`); `None` when off or for older rows
+    #[serde(default)]
+    pub short_code: Option<String>,
+    /// Position of the row in this node's store order: strictly increasing
+    /// with each insert (including rows arriving by sync, whose timestamps
+    /// can be old), unique per node and shared by history and live
+    /// `transcription` messages. Rows stored before cursors existed have
+    /// small values, and a row replaced by a re-sync gets a new cursor, so
+    /// clients should still dedupe by `id`.
+    pub cursor: i64,
+}
+
+impl From<Transcription> for TranscriptionData {
+    fn from(t: Transcription) -> Self {
+        TranscriptionData {
+            id: t.id,
+            timestamp: t.timestamp,
+            text: t.text,
+            source_node: t.source_node,
+            memo_device_id: t.memo_device_id,
+            source_type: t.source_type,
+            session_id: t.session_id,
+            device_battery: t.device_battery,
+            metadata: t.metadata.0,
+            duration_ms: t.duration_ms,
+            short_code: t.short_code,
+            cursor: t.cursor,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum ClientMessage {
+    /// Without `after_cursor`: the newest `limit` rows by timestamp, newest
+    /// first. With it: up to `limit` rows stored after that cursor, in cursor
+    /// order, for catching up without gaps.
     #[serde(rename = "get_history")]
-    GetHistory { limit: Option<usize> },
+    GetHistory {
+        limit: Option<usize>,
+        #[serde(default)]
+        after_cursor: Option<i64>,
+    },
+    #[serde(rename = "get_stats")]
+    GetStats,
+    /// One row by id or short code, answered with `transcription` or `error`
+    #[serde(rename = "get_transcription")]
+    GetTranscription { id: String },
+    /// Attach these key-value pairs (location, project, ...) to
+    /// transcriptions created from now on, replacing the previous context;
+    /// an empty map clears it
+    #[serde(rename = "set_context")]
+    SetContext { context: HashMap<String, String> },
+    /// The newest `limit` (default 100) log lines kept in memory, only
+    /// those at `level` (e.g. "warn") or more severe if given
+    #[serde(rename = "get_logs")]
+    GetLogs {
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        level: Option<String>,
+    },
 }
 
+/// Live transcription events are best-effort: a slow client (or a burst
+/// larger than the channel capacity) can miss messages. When that happens the
+/// client is sent `resync` and should re-request history, which is read from
+/// storage and is therefore complete (every transcription is stored before it
+/// is broadcast).
 pub struct WebSocketServer {
     storage: Storage,
     broadcast_tx: broadcast::Sender<Transcription>,
-    clients: Arc<RwLock<Vec<broadcast::Sender<ServerMessage>>>>,
+    /// Other live events (peer changes, keyword matches), sent as-is
+    events_tx: broadcast::Sender<ServerMessage>,
+    /// Connected clients by connection id; removed when they disconnect
+    clients: Arc<RwLock<Vec<(u64, broadcast::Sender<ServerMessage>)>>>,
+    next_client_id: AtomicU64,
+    /// Connections beyond this are closed right after the handshake; 0 disables
+    max_clients: usize,
+    /// Peer presence changes from `PeerManager`, taken when serving starts
+    peer_events_rx: Option<mpsc::UnboundedReceiver<PeerEvent>>,
+    /// Button presses from `BleAudioReceiver`, taken when serving starts
+    recording_events_rx: Option<mpsc::UnboundedReceiver<RecordingEvent>>,
+    metrics: Arc<Metrics>,
+    /// Recording state reported in `stats`
+    is_recording: Option<Arc<AtomicBool>>,
+    /// History replies with more rows than this are sent as `history_chunk`
+    /// messages; 0 always sends a single `history`
+    history_chunk_size: usize,
+    /// Context changed by `set_context`, shared with the pipeline
+    context: Option<Arc<Mutex<Metadata>>>,
+    /// Recent log lines served by `get_logs`
+    log_buffer: Option<LogBuffer>,
+    /// Peers connected according to the forwarded peer events
+    connected_peers: Mutex<BTreeSet<String>>,
+    /// Interval for pushing `stats` to clients; zero disables it
+    stats_interval: Duration,
+    /// Close a client's connection when its request hits a fatal storage
+    /// error, see `with_close_on_fatal_storage_error`
+    close_on_fatal_storage_error: bool,
 }
 
 impl WebSocketServer {
     pub fn new(
         storage: Storage,
         broadcast_tx: broadcast::Sender<Transcription>,
+        metrics: Arc<Metrics>,
     ) -> Self {
+        let (events_tx, _) = broadcast::channel(100);
+
         Self {
             storage,
             broadcast_tx,
+            events_tx,
             clients: Arc::new(RwLock::new(Vec::new())),
+            next_client_id: AtomicU64::new(0),
+            max_clients: 0,
+            peer_events_rx: None,
+            recording_events_rx: None,
+            metrics,
+            is_recording: None,
+            history_chunk_size: 0,
+            context: None,
+            log_buffer: None,
+            connected_peers: Mutex::new(BTreeSet::new()),
+            stats_interval: Duration::ZERO,
+            close_on_fatal_storage_error: true,
         }
     }
 
-    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
-        let listener = TcpListener::bind(addr)
-            .await
-            .context("Failed to bind WebSocket server")?;
+    /// Forward peer presence changes to clients as `peer_connected` and
+    /// `peer_disconnected`
+    pub fn with_peer_events(mut self, peer_events_rx: mpsc::UnboundedReceiver<PeerEvent>) -> Self {
+        self.peer_events_rx = Some(peer_events_rx);
+        self
+    }
 
-        info!("WebSocket server listening on {}", addr);
+    /// Forward recording state changes to clients as `recording_started` and
+    /// `recording_stopped`
+    pub fn with_recording_events(
+        mut self,
+        recording_events_rx: mpsc::UnboundedReceiver<RecordingEvent>,
+    ) -> Self {
+        self.recording_events_rx = Some(recording_events_rx);
+        self
+    }
+
+    /// Report the device recording state in `stats`
+    pub fn with_recording_state(mut self, is_recording: Arc<AtomicBool>) -> Self {
+        self.is_recording = Some(is_recording);
+        self
+    }
+
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    /// Split history replies larger than `history_chunk_size` rows into
+    /// `history_chunk` messages
+    pub fn with_history_chunk_size(mut self, history_chunk_size: usize) -> Self {
+        self.history_chunk_size = history_chunk_size;
+        self
+    }
+
+    /// Let clients change the context attached to new transcriptions with
+    /// `set_context`
+    pub fn with_context(mut self, context: Arc<Mutex<Metadata>>) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Serve `get_logs` from `log_buffer`
+    pub fn with_log_buffer(mut self, log_buffer: LogBuffer) -> Self {
+        self.log_buffer = Some(log_buffer);
+        self
+    }
+
+    /// Push `stats`, with rates since the previous push, to every client
+    /// each `stats_interval`
+    pub fn with_stats_interval(mut self, stats_interval: Duration) -> Self {
+        self.stats_interval = stats_interval;
+        self
+    }
 
+    /// Whether to close a client's connection after replying to a request
+    /// that failed because the database is unusable (on by default); other
+    /// failures are answered with an `error` message and the connection
+    /// stays open
+    pub fn with_close_on_fatal_storage_error(mut self, close: bool) -> Self {
+        self.close_on_fatal_storage_error = close;
+        self
+    }
+
+    /// Sender for pushing other `ServerMessage`s to every connected client
+    pub fn event_sender(&self) -> broadcast::Sender<ServerMessage> {
+        self.events_tx.clone()
+    }
+
+    /// Start forwarding peer events and transcriptions to clients, returning
+    /// the server for `accept`
+    ///
+    /// Connected clients and the event sender outlive any one listener, so
+    /// the accept loop can be restarted without disturbing them.
+    pub fn start(mut self) -> Arc<Self> {
+        let peer_events_rx = self.peer_events_rx.take();
+        let recording_events_rx = self.recording_events_rx.take();
         let server = Arc::new(self);
 
+        if let Some(peer_events_rx) = peer_events_rx {
+            let server_clone = server.clone();
+            tokio::spawn(async move {
+                server_clone.forward_peer_events(peer_events_rx).await;
+            });
+        }
+
+        if let Some(mut recording_events_rx) = recording_events_rx {
+            let server_clone = server.clone();
+            tokio::spawn(async move {
+                while let Some(event) = recording_events_rx.recv().await {
+                    server_clone.broadcast_to_clients(ServerMessage::from(event)).await;
+                }
+            });
+        }
+
         // Spawn task to broadcast transcriptions to all clients
         let server_clone = server.clone();
         tokio::spawn(async move {
             server_clone.broadcast_loop().await;
         });
 
-        while let Ok((stream, peer_addr)) = listener.accept().await {
-            let server = server.clone();
+        if !server.stats_interval.is_zero() {
+            let server_clone = server.clone();
+            tokio::spawn(async move {
+                server_clone.stats_loop().await;
+            });
+        }
+
+        server
+    }
+
+    /// Accept connections on `listener` until accepting fails
+    pub async fn accept(self: &Arc<Self>, listener: TcpListener) -> Result<()> {
+        let addr = listener
+            .local_addr()
+            .context("Failed to get WebSocket listener address")?;
+        info!("WebSocket server listening on {}", addr);
+
+        loop {
+            let (stream, peer_addr) = listener
+                .accept()
+                .await
+                .context("Failed to accept WebSocket connection")?;
+            let server = self.clone();
             tokio::spawn(async move {
                 if let Err(e) = server.handle_connection(stream, peer_addr).await {
                     error!("WebSocket error for {}: {}", peer_addr, e);
                 }
             });
         }
-
-        Ok(())
     }
 
     async fn broadcast_loop(&self) {
         let mut rx = self.broadcast_tx.subscribe();
-
-        while let Ok(transcription) = rx.recv().await {
-            let msg = ServerMessage::Transcription {
-                id: transcription.id,
-                timestamp: transcription.timestamp,
-                text: transcription.text,
-                source_node: transcription.source_node,
-                memo_device_id: transcription.memo_device_id,
+        let mut events_rx = self.events_tx.subscribe();
+
+        loop {
+            let msg = tokio::select! {
+                result = rx.recv() => match result {
+                    Ok(transcription) => ServerMessage::from(transcription),
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("WebSocket broadcast lagged, dropped {} transcriptions", missed);
+                        Metrics::add(&self.metrics.broadcast_dropped, missed);
+                        ServerMessage::Resync { missed }
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                result = events_rx.recv() => match result {
+                    Ok(msg) => msg,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("WebSocket event broadcast lagged, dropped {} events", missed);
+                        Metrics::add(&self.metrics.broadcast_dropped, missed);
+                        continue;
+                    }
+                    // `self` holds a sender, so this never closes
+                    Err(RecvError::Closed) => continue,
+                },
             };
 
             self.broadcast_to_clients(msg).await;
         }
     }
 
+    async fn stats_loop(&self) {
+        let mut interval = tokio::time::interval(self.stats_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut previous: Option<(MetricsSnapshot, Instant)> = None;
+
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let snapshot = self.metrics.snapshot();
+            let rates = previous
+                .as_ref()
+                .map(|(previous, at)| MetricsRates::between(previous, &snapshot, now - *at));
+            previous = Some((snapshot.clone(), now));
+
+            if !self.clients.read().await.is_empty() {
+                let msg = self.stats(snapshot, rates).await;
+                self.broadcast_to_clients(msg).await;
+            }
+        }
+    }
+
+    async fn stats(&self, metrics: MetricsSnapshot, rates: Option<MetricsRates>) -> ServerMessage {
+        ServerMessage::Stats {
+            recording: self
+                .is_recording
+                .as_ref()
+                .is_some_and(|r| r.load(Ordering::Acquire)),
+            clients: self.clients.read().await.len(),
+            peers: self.connected_peers.lock().unwrap().len(),
+            rates,
+            metrics,
+        }
+    }
+
+    async fn forward_peer_events(&self, mut peer_events_rx: mpsc::UnboundedReceiver<PeerEvent>) {
+        while let Some(event) = peer_events_rx.recv().await {
+            match event {
+                PeerEvent::Connected { node_id, display_name } => {
+                    self.connected_peers.lock().unwrap().insert(node_id.clone());
+                    self.notify_peer_connected(node_id, display_name).await;
+                }
+                PeerEvent::Disconnected { node_id, display_name } => {
+                    self.connected_peers.lock().unwrap().remove(&node_id);
+                    self.notify_peer_disconnected(node_id, display_name).await;
+                }
+            }
+        }
+    }
+
     async fn broadcast_to_clients(&self, msg: ServerMessage) {
         let clients = self.clients.read().await;
 
-        for client_tx in clients.iter() {
+        for (_, client_tx) in clients.iter() {
             if let Err(e) = client_tx.send(msg.clone()) {
                 warn!("Failed to broadcast to client: {}", e);
             }
@@ -118,42 +515,69 @@ impl WebSocketServer {
     async fn handle_connection(&self, stream: TcpStream, addr: SocketAddr) -> Result<()> {
         info!("New WebSocket connection from {}", addr);
 
-        let ws_stream = tokio_tungstenite::accept_async(stream)
+        let mut ws_stream = tokio_tungstenite::accept_async(stream)
             .await
             .context("Failed to accept WebSocket connection")?;
 
-        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-
         // Create a channel for this client
         let (client_tx, mut client_rx) = broadcast::channel::<ServerMessage>(100);
-        let (response_tx, mut response_rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
 
-        // Add client to the list
-        {
+        // Add client to the list, unless the server is full
+        let client_id = {
             let mut clients = self.clients.write().await;
-            clients.push(client_tx);
-        }
+            if self.max_clients > 0 && clients.len() >= self.max_clients {
+                None
+            } else {
+                let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+                clients.push((id, client_tx));
+                Some(id)
+            }
+        };
+
+        let Some(client_id) = client_id else {
+            warn!(
+                "Rejecting WebSocket connection from {}: {} clients already connected",
+                addr, self.max_clients
+            );
+            let _ = ws_stream
+                .close(Some(CloseFrame {
+                    code: CloseCode::Again,
+                    reason: "Too many clients".into(),
+                }))
+                .await;
+            return Ok(());
+        };
+
+        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        let (response_tx, mut response_rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
 
         // Spawn task to send messages to this client
-        let send_task = tokio::spawn(async move {
+        let metrics = self.metrics.clone();
+        let mut send_task = tokio::spawn(async move {
             loop {
                 tokio::select! {
                     result = client_rx.recv() => {
-                        match result {
-                            Ok(msg) => {
-                                if let Ok(json) = serde_json::to_string(&msg) {
-                                    if ws_sender.send(Message::Text(json)).await.is_err() {
-                                        break;
-                                    }
-                                }
+                        let msg = match result {
+                            Ok(msg) => msg,
+                            Err(RecvError::Lagged(missed)) => {
+                                warn!("WebSocket client {} lagged, dropped {} messages", addr, missed);
+                                Metrics::add(&metrics.broadcast_dropped, missed);
+                                ServerMessage::Resync { missed }
+                            }
+                            Err(RecvError::Closed) => break,
+                        };
+
+                        if let Ok(json) = serde_json::to_string(&msg) {
+                            if ws_sender.send(Message::Text(json)).await.is_err() {
+                                break;
                             }
-                            Err(_) => break,
                         }
                     }
                     result = response_rx.recv() => {
                         match result {
                             Some(msg) => {
-                                if ws_sender.send(msg).await.is_err() {
+                                let close = msg.is_close();
+                                if ws_sender.send(msg).await.is_err() || close {
                                     break;
                                 }
                             }
@@ -165,13 +589,16 @@ impl WebSocketServer {
         });
 
         // Handle incoming messages from client
+        let mut closing = false;
         while let Some(msg_result) = ws_receiver.next().await {
             match msg_result {
                 Ok(Message::Text(text)) => {
                     debug!("Received message from {}: {}", addr, text);
 
-                    if let Err(e) = self.handle_client_message(&text, &response_tx).await {
-                        error!("Error handling client message: {}", e);
+                    if !self.respond(&text, &response_tx).await {
+                        info!("Closing connection to {} after a fatal storage error", addr);
+                        closing = true;
+                        break;
                     }
                 }
                 Ok(Message::Close(_)) => {
@@ -189,12 +616,54 @@ impl WebSocketServer {
             }
         }
 
+        if closing {
+            // Let the error reply and close frame go out first
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, &mut send_task).await;
+        }
         send_task.abort();
+        self.clients
+            .write()
+            .await
+            .retain(|(id, _)| *id != client_id);
         info!("Connection closed for {}", addr);
 
         Ok(())
     }
 
+    /// Answer one client message, replying with an `error` message if that
+    /// fails; returns false if the connection should be closed
+    async fn respond(
+        &self,
+        text: &str,
+        response_tx: &tokio::sync::mpsc::UnboundedSender<Message>,
+    ) -> bool {
+        let Err(e) = self.handle_client_message(text, response_tx).await else {
+            return true;
+        };
+
+        let fatal = storage::is_fatal_error(&e);
+        if fatal {
+            error!("Storage unusable while handling client message: {:#}", e);
+        } else {
+            warn!("Error handling client message: {:#}", e);
+        }
+        let reply = ServerMessage::Error {
+            message: format!("{:#}", e),
+        };
+        if let Ok(json) = serde_json::to_string(&reply) {
+            let _ = response_tx.send(Message::Text(json));
+        }
+
+        if !(fatal && self.close_on_fatal_storage_error) {
+            return true;
+        }
+        let _ = response_tx.send(Message::Close(Some(CloseFrame {
+            code: CloseCode::Error,
+            reason: "storage unavailable".into(),
+        })));
+        false
+    }
+
     async fn handle_client_message(
         &self,
         text: &str,
@@ -204,24 +673,72 @@ impl WebSocketServer {
             .context("Failed to parse client message")?;
 
         match client_msg {
-            ClientMessage::GetHistory { limit } => {
-                let transcriptions = self
-                    .storage
-                    .get_recent_transcriptions(limit.unwrap_or(100))?;
-
-                let data: Vec<TranscriptionData> = transcriptions
-                    .into_iter()
-                    .map(|t| TranscriptionData {
-                        id: t.id,
-                        timestamp: t.timestamp,
-                        text: t.text,
-                        source_node: t.source_node,
-                        memo_device_id: t.memo_device_id,
-                    })
-                    .collect();
-
-                let response = ServerMessage::History {
-                    transcriptions: data,
+            ClientMessage::GetHistory { limit, after_cursor } => {
+                let limit = limit.unwrap_or(100);
+                let transcriptions = match after_cursor {
+                    Some(cursor) => self.storage.get_transcriptions_after_cursor(cursor, limit)?,
+                    None => self.storage.get_recent_transcriptions(limit)?,
+                };
+
+                let data: Vec<TranscriptionData> =
+                    transcriptions.into_iter().map(TranscriptionData::from).collect();
+
+                for response in history_messages(data, self.history_chunk_size) {
+                    let json = serde_json::to_string(&response)?;
+                    response_tx.send(Message::Text(json))?;
+                }
+            }
+            ClientMessage::GetStats => {
+                let response = self.stats(self.metrics.snapshot(), None).await;
+
+                let json = serde_json::to_string(&response)?;
+                response_tx.send(Message::Text(json))?;
+            }
+            ClientMessage::GetTranscription { id } => {
+                let response = match self.storage.find_transcription(&id)? {
+                    Some(transcription) => ServerMessage::from(transcription),
+                    None => ServerMessage::Error {
+                        message: format!("transcription {} not found", id),
+                    },
+                };
+
+                let json = serde_json::to_string(&response)?;
+                response_tx.send(Message::Text(json))?;
+            }
+            ClientMessage::SetContext { context } => {
+                let response = match &self.context {
+                    Some(shared) => {
+                        let context: BTreeMap<String, String> = context.into_iter().collect();
+                        info!("Transcription context set to {:?}", context);
+                        *shared.lock().unwrap() = Metadata(context.clone());
+                        ServerMessage::Context { context }
+                    }
+                    None => ServerMessage::Error {
+                        message: "set_context is not supported by this server".to_string(),
+                    },
+                };
+
+                let json = serde_json::to_string(&response)?;
+                response_tx.send(Message::Text(json))?;
+            }
+            ClientMessage::GetLogs { limit, level } => {
+                let level = level
+                    .as_deref()
+                    .map(str::parse::<tracing::Level>)
+                    .transpose();
+                let response = match (&self.log_buffer, level) {
+                    (Some(log_buffer), Ok(level)) if log_buffer.is_enabled() => {
+                        let level = level.unwrap_or(tracing::Level::TRACE);
+                        ServerMessage::Logs {
+                            lines: log_buffer.recent(limit.unwrap_or(100), level),
+                        }
+                    }
+                    (_, Err(e)) => ServerMessage::Error {
+                        message: format!("invalid log level: {}", e),
+                    },
+                    _ => ServerMessage::Error {
+                        message: "log buffer is disabled (logging.buffer_lines = 0)".to_string(),
+                    },
                 };
 
                 let json = serde_json::to_string(&response)?;
@@ -232,13 +749,184 @@ impl WebSocketServer {
         Ok(())
     }
 
-    pub async fn notify_peer_connected(&self, node_id: String) {
-        let msg = ServerMessage::PeerConnected { node_id };
+    pub async fn notify_peer_connected(&self, node_id: String, display_name: String) {
+        let msg = ServerMessage::PeerConnected { node_id, display_name };
         self.broadcast_to_clients(msg).await;
     }
 
-    pub async fn notify_peer_disconnected(&self, node_id: String) {
-        let msg = ServerMessage::PeerDisconnected { node_id };
+    pub async fn notify_peer_disconnected(&self, node_id: String, display_name: String) {
+        let msg = ServerMessage::PeerDisconnected { node_id, display_name };
         self.broadcast_to_clients(msg).await;
     }
 }
+
+/// A history reply: one `history` message, or `history_chunk` messages of
+/// at most `chunk_size` rows when there are more rows than that
+fn history_messages(data: Vec<TranscriptionData>, chunk_size: usize) -> Vec<ServerMessage> {
+    if chunk_size == 0 || data.len() <= chunk_size {
+        return vec![ServerMessage::History { transcriptions: data }];
+    }
+
+    let chunks = data.len().div_ceil(chunk_size);
+    let mut data = data.into_iter();
+    (1..=chunks)
+        .map(|n| ServerMessage::HistoryChunk {
+            transcriptions: data.by_ref().take(chunk_size).collect(),
+            done: n == chunks,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::PeerManager;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[tokio::test]
+    async fn test_added_peer_is_broadcast_as_peer_connected() {
+        let path = std::env::temp_dir().join(format!("memo-node-ws-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+
+        let (peer_events_tx, peer_events_rx) = mpsc::unbounded_channel();
+        let peer_manager = PeerManager::new("self".to_string(), storage.clone(), 30, 0)
+            .with_events(peer_events_tx);
+
+        let (broadcast_tx, _) = broadcast::channel(16);
+        let server = Arc::new(WebSocketServer::new(
+            storage,
+            broadcast_tx,
+            Arc::new(Metrics::default()),
+        ));
+
+        // Stand in for a connected client
+        let (client_tx, mut client_rx) = broadcast::channel(16);
+        server.clients.write().await.push((0, client_tx));
+
+        let forwarder = server.clone();
+        tokio::spawn(async move { forwarder.forward_peer_events(peer_events_rx).await });
+
+        peer_manager
+            .add_peer(
+                "pi-workshop".to_string(),
+                Some("Workshop Pi".to_string()),
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                9876,
+            )
+            .await;
+
+        let msg = tokio::time::timeout(std::time::Duration::from_secs(1), client_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match msg {
+            ServerMessage::PeerConnected { node_id, display_name } => {
+                assert_eq!(node_id, "pi-workshop");
+                assert_eq!(display_name, "Workshop Pi");
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// The `error` message in `response`, if it is one
+    fn error_message(response: Option<Message>) -> Option<String> {
+        let Some(Message::Text(json)) = response else {
+            return None;
+        };
+        match serde_json::from_str(&json).ok()? {
+            ServerMessage::Error { message } => Some(message),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transient_storage_error_is_answered_and_connection_kept() {
+        let path = std::env::temp_dir().join(format!("memo-node-ws-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        let (broadcast_tx, _) = broadcast::channel(16);
+        let server = WebSocketServer::new(storage, broadcast_tx, Arc::new(Metrics::default()));
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel();
+        let request = r#"{"type": "get_history", "data": {}}"#;
+
+        // Another process briefly moves the table out of the way
+        let other = rusqlite::Connection::open(&path).unwrap();
+        other
+            .execute_batch("ALTER TABLE transcriptions RENAME TO transcriptions_moved")
+            .unwrap();
+        assert!(server.respond(request, &response_tx).await);
+        let message = error_message(response_rx.try_recv().ok()).unwrap();
+        assert!(message.contains("transcriptions"), "{}", message);
+        assert!(response_rx.try_recv().is_err(), "connection was closed");
+
+        // Once storage recovers, the same connection is answered normally
+        other
+            .execute_batch("ALTER TABLE transcriptions_moved RENAME TO transcriptions")
+            .unwrap();
+        assert!(server.respond(request, &response_tx).await);
+        assert_eq!(error_message(response_rx.try_recv().ok()), None);
+
+        // Malformed requests get an error reply too
+        assert!(server.respond("not json", &response_tx).await);
+        assert!(error_message(response_rx.try_recv().ok()).is_some());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fatal_storage_errors() {
+        let sqlite_error = |code| -> anyhow::Error {
+            let e = rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None);
+            anyhow::Error::new(e).context("Failed to query history")
+        };
+        assert!(storage::is_fatal_error(&sqlite_error(rusqlite::ffi::SQLITE_CORRUPT)));
+        assert!(storage::is_fatal_error(&sqlite_error(rusqlite::ffi::SQLITE_CANTOPEN)));
+        assert!(!storage::is_fatal_error(&sqlite_error(rusqlite::ffi::SQLITE_BUSY)));
+        assert!(!storage::is_fatal_error(&anyhow::anyhow!("Failed to parse client message")));
+    }
+
+    #[test]
+    fn test_large_history_is_chunked() {
+        let rows = |n: usize| -> Vec<TranscriptionData> {
+            (0..n)
+                .map(|i| TranscriptionData {
+                    id: i.to_string(),
+                    timestamp: i as i64,
+                    text: "memo".to_string(),
+                    source_node: "node-a".to_string(),
+                    memo_device_id: None,
+                    source_type: SourceType::Device,
+                    session_id: None,
+                    device_battery: None,
+                    metadata: BTreeMap::new(),
+                    duration_ms: None,
+                    short_code: None,
+                    cursor: i as i64,
+                })
+                .collect()
+        };
+
+        let single = history_messages(rows(3), 3);
+        assert!(matches!(&single[..], [ServerMessage::History { transcriptions }] if transcriptions.len() == 3));
+        assert_eq!(history_messages(rows(10), 0).len(), 1);
+
+        let chunks: Vec<(Vec<String>, bool)> = history_messages(rows(5), 2)
+            .into_iter()
+            .map(|msg| match msg {
+                ServerMessage::HistoryChunk { transcriptions, done } => {
+                    (transcriptions.into_iter().map(|t| t.id).collect(), done)
+                }
+                other => panic!("unexpected message: {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                (vec!["0".to_string(), "1".to_string()], false),
+                (vec!["2".to_string(), "3".to_string()], false),
+                (vec!["4".to_string()], true),
+            ]
+        );
+    }
+}
@@ -1,11 +1,15 @@
+use crate::metrics::{Metrics, Subsystem};
 use anyhow::{Context, Result};
 use btleplug::api::{
-    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType,
+    Central, CharPropFlags, Characteristic, Manager as _, Peripheral as _, ScanFilter,
+    ValueNotification, WriteType,
 };
-use btleplug::platform::{Manager, Peripheral};
-use futures_util::StreamExt;
-use std::collections::HashSet;
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures_util::{Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
 use std::sync::{Arc, atomic::{AtomicBool, Ordering}, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
@@ -22,35 +26,315 @@ const RESP_SPEECH_END: u8 = 0x02;    // Button pressed again - stop recording
 const CMD_START_RECORDING: u8 = 10;
 const CMD_END_RECORDING: u8 = 12;
 
+/// Battery percentage below which a device is reported as needing a charge
+const LOW_BATTERY_PERCENT: u8 = 20;
+
+/// Wait between attempts to find a working adapter once every one failed
+const ADAPTER_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Indices of the adapters described by `infos` (their `adapter_info`) to
+/// try, in order
+///
+/// With `preferred` empty every adapter is tried in the order the OS lists
+/// them; otherwise only adapters whose info contains an entry of
+/// `preferred`, in the order of the entries.
+fn adapter_order(infos: &[String], preferred: &[String]) -> Vec<usize> {
+    if preferred.is_empty() {
+        return (0..infos.len()).collect();
+    }
+    let mut order = Vec::new();
+    for wanted in preferred {
+        for (index, info) in infos.iter().enumerate() {
+            if info.contains(wanted.as_str()) && !order.contains(&index) {
+                order.push(index);
+            }
+        }
+    }
+    order
+}
+
+/// Per-device cap on audio notifications; 0 disables a limit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimit {
+    pub max_packets_per_sec: u32,
+    pub max_bytes_per_sec: u64,
+}
+
+/// Fixed one-second window counter enforcing a `RateLimit`
+struct RateLimiter {
+    limit: RateLimit,
+    window_start: Instant,
+    packets: u32,
+    bytes: u64,
+    dropped: u32,
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            window_start: now,
+            packets: 0,
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Count a packet of `len` bytes, returning whether to keep it and, when
+    /// a window in which packets were dropped has just ended, how many were
+    fn admit(&mut self, len: usize, now: Instant) -> (bool, Option<u32>) {
+        let mut dropped_last_window = None;
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            if self.dropped > 0 {
+                dropped_last_window = Some(self.dropped);
+            }
+            self.window_start = now;
+            self.packets = 0;
+            self.bytes = 0;
+            self.dropped = 0;
+        }
+
+        let packets = self.packets + 1;
+        let bytes = self.bytes + len as u64;
+        let over_packets = self.limit.max_packets_per_sec > 0 && packets > self.limit.max_packets_per_sec;
+        let over_bytes = self.limit.max_bytes_per_sec > 0 && bytes > self.limit.max_bytes_per_sec;
+
+        if over_packets || over_bytes {
+            self.dropped += 1;
+            return (false, dropped_last_window);
+        }
+
+        self.packets = packets;
+        self.bytes = bytes;
+        (true, dropped_last_window)
+    }
+}
+
+/// Per-device memory of control commands sent, so the same command isn't
+/// re-sent to a device within `cooldown` (e.g. START while it is still
+/// being set up). Different commands are never held back, and a device's
+/// memory is cleared when its link drops, so the START a reconnect needs
+/// always goes out.
+struct CommandThrottle {
+    cooldown: Duration,
+    last_sent: HashMap<String, (u8, Instant)>,
+}
+
+impl CommandThrottle {
+    fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Whether `command` may be sent to `device` now
+    fn allow(&self, device: &str, command: u8, now: Instant) -> bool {
+        match self.last_sent.get(device) {
+            Some(&(last, at)) => last != command || now.duration_since(at) >= self.cooldown,
+            None => true,
+        }
+    }
+
+    fn record(&mut self, device: &str, command: u8, now: Instant) {
+        self.last_sent.insert(device.to_string(), (command, now));
+    }
+
+    /// Forget the commands sent to `device`, after it disconnected
+    fn forget(&mut self, device: &str) {
+        self.last_sent.remove(device);
+    }
+}
+
+/// Mark every device connected through a failed adapter as lost at `now`,
+/// so the scan loop reconnects it through the next adapter; returns their
+/// names
+fn lose_links(connected: &mut HashMap<String, LinkState>, now: Instant) -> Vec<String> {
+    let mut lost = Vec::new();
+    for (device, state) in connected.iter_mut() {
+        if matches!(state, LinkState::Up(_)) {
+            *state = LinkState::Lost(now);
+            lost.push(device.clone());
+        }
+    }
+    lost.sort();
+    lost
+}
+
+/// Report `device` disconnected once `grace` has passed since it was lost
+/// at `lost_at`, unless it has reconnected (or been lost again) since
+async fn expire_lost_link(
+    connected_devices: Arc<Mutex<HashMap<String, LinkState>>>,
+    metrics: Arc<Metrics>,
+    device: String,
+    lost_at: Instant,
+    grace: Duration,
+) {
+    tokio::time::sleep(grace).await;
+
+    let mut connected = connected_devices.lock().unwrap();
+    if connected.get(&device) == Some(&LinkState::Lost(lost_at)) {
+        warn!("{} disconnected", device);
+        connected.remove(&device);
+        drop_device_metrics(&metrics, &device);
+    }
+}
+
+/// Stop listing `device` in the status metrics
+fn drop_device_metrics(metrics: &Metrics, device: &str) {
+    metrics.ble_devices.lock().unwrap().remove(device);
+    metrics.ble_battery.lock().unwrap().remove(device);
+    metrics.ble_bytes_received.lock().unwrap().remove(device);
+}
+
+/// Connection state of a set-up device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    /// Set up at this time, which tells the connection apart from earlier
+    /// ones to the same device
+    Up(Instant),
+    /// The notification stream ended at this time; the device is reported
+    /// disconnected if it hasn't come back once the grace period has passed
+    Lost(Instant),
+}
+
+/// One audio notification, tagged with where it came from
+#[derive(Debug, Clone)]
+pub struct AudioPacket {
+    pub device: String,
+    /// Index of the characteristic in the receiver's audio characteristic list
+    pub stream: usize,
+    pub data: Vec<u8>,
+}
+
+/// Recording started or stopped by a device's button
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordingEvent {
+    Started { device: String },
+    Stopped { device: String },
+}
+
 pub struct BleAudioReceiver {
     service_uuid: Uuid,
-    characteristic_uuid: Uuid,
-    audio_tx: mpsc::UnboundedSender<Vec<u8>>,
+    characteristic_uuids: Vec<Uuid>,
+    audio_tx: mpsc::UnboundedSender<AudioPacket>,
     is_recording: Arc<AtomicBool>,
-    connected_devices: Arc<Mutex<HashSet<String>>>, // Track connected device names
+    connected_devices: Arc<Mutex<HashMap<String, LinkState>>>, // Track connected device names
+    rate_limit: RateLimit,
+    metrics: Arc<Metrics>,
+    disconnect_grace: Duration,
+    /// Battery level characteristic and how often to read it
+    battery: Option<(Uuid, Duration)>,
+    command_throttle: Arc<Mutex<CommandThrottle>>,
+    /// Devices connected (or reconnecting) at once; 0 means no limit
+    max_devices: usize,
+    /// Reconnect a device that sends no audio for this long while recording
+    watchdog_timeout: Option<Duration>,
+    /// Devices already reported as skipped for lack of a free slot
+    skipped_devices: Mutex<HashSet<String>>,
+    recording_events_tx: Option<mpsc::UnboundedSender<RecordingEvent>>,
+    /// Adapters to use, by part of their name, most preferred first; empty
+    /// uses any
+    adapters: Vec<String>,
 }
 
 impl BleAudioReceiver {
+    /// Receive audio from every characteristic in `characteristic_uuids`;
+    /// packets are tagged with the index of the one they came from
     pub fn new(
         service_uuid: Uuid,
-        characteristic_uuid: Uuid,
-    ) -> (Self, mpsc::UnboundedReceiver<Vec<u8>>, Arc<AtomicBool>) {
+        characteristic_uuids: Vec<Uuid>,
+    ) -> (Self, mpsc::UnboundedReceiver<AudioPacket>, Arc<AtomicBool>) {
         let (audio_tx, audio_rx) = mpsc::unbounded_channel();
         let is_recording = Arc::new(AtomicBool::new(true)); // Start recording by default
 
         (
             Self {
                 service_uuid,
-                characteristic_uuid,
+                characteristic_uuids,
                 audio_tx,
                 is_recording: is_recording.clone(),
-                connected_devices: Arc::new(Mutex::new(HashSet::new())),
+                connected_devices: Arc::new(Mutex::new(HashMap::new())),
+                rate_limit: RateLimit::default(),
+                metrics: Arc::new(Metrics::default()),
+                disconnect_grace: Duration::ZERO,
+                battery: None,
+                command_throttle: Arc::new(Mutex::new(CommandThrottle::new(Duration::ZERO))),
+                max_devices: 0,
+                watchdog_timeout: None,
+                skipped_devices: Mutex::new(HashSet::new()),
+                recording_events_tx: None,
+                adapters: Vec::new(),
             },
             audio_rx,
             is_recording,
         )
     }
 
+    /// Record connection failures in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Drop audio from any device that exceeds `rate_limit`
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Keep a device that drops its link listed as connected for
+    /// `disconnect_grace`, so a quick reconnect isn't reported as a
+    /// disconnect
+    pub fn with_disconnect_grace(mut self, disconnect_grace: Duration) -> Self {
+        self.disconnect_grace = disconnect_grace;
+        self
+    }
+
+    /// Read the battery level from `characteristic_uuid` every
+    /// `poll_interval` on devices that have it
+    pub fn with_battery(mut self, characteristic_uuid: Uuid, poll_interval: Duration) -> Self {
+        self.battery = Some((characteristic_uuid, poll_interval));
+        self
+    }
+
+    /// Don't re-send the same control command to a device within `cooldown`
+    pub fn with_command_cooldown(mut self, cooldown: Duration) -> Self {
+        self.command_throttle = Arc::new(Mutex::new(CommandThrottle::new(cooldown)));
+        self
+    }
+
+    /// Connect to at most `max_devices` devices at once; others are skipped
+    /// until one disconnects
+    pub fn with_max_devices(mut self, max_devices: usize) -> Self {
+        self.max_devices = max_devices;
+        self
+    }
+
+    /// Drop and re-establish the connection to a device that has sent no
+    /// audio for `timeout` while recording; zero disables the watchdog
+    pub fn with_watchdog(mut self, timeout: Duration) -> Self {
+        self.watchdog_timeout = (!timeout.is_zero()).then_some(timeout);
+        self
+    }
+
+    /// Report button presses that start or stop recording on
+    /// `recording_events_tx`
+    pub fn with_recording_events(
+        mut self,
+        recording_events_tx: mpsc::UnboundedSender<RecordingEvent>,
+    ) -> Self {
+        self.recording_events_tx = Some(recording_events_tx);
+        self
+    }
+
+    /// Only use adapters whose info (e.g. `hci1 (usb:...)`) contains one of
+    /// `adapters`, preferring earlier entries and failing over to later ones
+    pub fn with_adapters(mut self, adapters: Vec<String>) -> Self {
+        self.adapters = adapters;
+        self
+    }
+
     pub async fn start(self: Arc<Self>) -> Result<()> {
         info!("Starting BLE audio receiver");
 
@@ -58,20 +342,87 @@ impl BleAudioReceiver {
             .await
             .context("Failed to create BLE manager")?;
 
+        // No usable adapter at startup is an error, as on a host without BLE
+        let (mut adapter, mut adapter_info) = self.select_adapter(&manager).await?;
+
+        loop {
+            if let Err(e) = self.scan(&adapter).await {
+                warn!("BLE adapter {} failed: {:#}; looking for another", adapter_info, e);
+                let error = format!("Adapter {} failed: {:#}", adapter_info, e);
+                self.metrics.record_error(Subsystem::Ble, error);
+            }
+            let _ = adapter.stop_scan().await;
+
+            // Links through the failed adapter may never report ending, and
+            // an Up device is never reconnected, so treat them all as lost
+            let lost_at = Instant::now();
+            let lost = lose_links(&mut self.connected_devices.lock().unwrap(), lost_at);
+            for device in lost {
+                info!("{} was connected through {}; reconnecting it", device, adapter_info);
+                self.command_throttle.lock().unwrap().forget(&device);
+                tokio::spawn(expire_lost_link(
+                    self.connected_devices.clone(),
+                    self.metrics.clone(),
+                    device,
+                    lost_at,
+                    self.disconnect_grace,
+                ));
+            }
+
+            // The failed adapter is tried again in its turn, in case it
+            // recovered; otherwise wait for any adapter to come back
+            (adapter, adapter_info) = loop {
+                match self.select_adapter(&manager).await {
+                    Ok(selected) => break selected,
+                    Err(e) => {
+                        warn!(
+                            "No usable BLE adapter: {:#}. Retrying in {:?}",
+                            e, ADAPTER_RETRY_DELAY
+                        );
+                        tokio::time::sleep(ADAPTER_RETRY_DELAY).await;
+                    }
+                }
+            };
+        }
+    }
+
+    /// Start scanning on the first adapter, in `adapter_order`, that allows
+    /// it, returning it with its info
+    async fn select_adapter(&self, manager: &Manager) -> Result<(Adapter, String)> {
         let adapters = manager.adapters().await.context("Failed to get BLE adapters")?;
-        let adapter = adapters
-            .into_iter()
-            .next()
-            .context("No BLE adapters found")?;
+        if adapters.is_empty() {
+            anyhow::bail!("No BLE adapters found");
+        }
 
-        info!("Using BLE adapter: {}", adapter.adapter_info().await?);
+        let mut infos = Vec::new();
+        for adapter in &adapters {
+            let info = adapter.adapter_info().await;
+            infos.push(info.unwrap_or_else(|e| format!("unknown adapter ({})", e)));
+        }
 
-        // Start scanning
-        adapter
-            .start_scan(ScanFilter::default())
-            .await
-            .context("Failed to start BLE scan")?;
+        let order = adapter_order(&infos, &self.adapters);
+        if order.is_empty() {
+            anyhow::bail!(
+                "None of the BLE adapters ({}) match audio.ble_adapters",
+                infos.join(", ")
+            );
+        }
+
+        for index in order {
+            match adapters[index].start_scan(ScanFilter::default()).await {
+                Ok(()) => {
+                    info!("Using BLE adapter: {}", infos[index]);
+                    return Ok((adapters[index].clone(), infos[index].clone()));
+                }
+                Err(e) => warn!("Failed to start BLE scan on {}: {}", infos[index], e),
+            }
+        }
+        anyhow::bail!("No BLE adapter could start scanning")
+    }
 
+    /// Connect to Memo devices seen by `adapter`, returning only once the
+    /// adapter fails or disappears
+    async fn scan(&self, adapter: &Adapter) -> Result<()> {
         info!(
             "Scanning for Memo devices with service UUID {}",
             self.service_uuid
@@ -81,6 +432,11 @@ impl BleAudioReceiver {
         loop {
             tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
+            // Fails once a removed adapter is gone from the OS
+            adapter
+                .adapter_info()
+                .await
+                .context("Adapter is no longer available")?;
             let peripherals = adapter
                 .peripherals()
                 .await
@@ -89,6 +445,7 @@ impl BleAudioReceiver {
             for peripheral in peripherals {
                 if let Err(e) = self.try_connect_device(&peripheral).await {
                     debug!("Failed to connect to device: {}", e);
+                    self.metrics.record_error(Subsystem::Ble, format!("{:#}", e));
                 }
             }
         }
@@ -105,16 +462,37 @@ impl BleAudioReceiver {
         }
 
         // Check if we're already connected and set up for this device
-        {
-            let mut connected = self.connected_devices.lock().unwrap();
-            if connected.contains(&local_name) {
-                // Already connected and set up, skip
+        let state = self.connected_devices.lock().unwrap().get(&local_name).copied();
+        match state {
+            Some(LinkState::Up(_)) => {
+                // Already connected and set up; just refresh its signal strength
+                if let Some(rssi) = properties.rssi {
+                    if let Some(entry) = self.metrics.ble_devices.lock().unwrap().get_mut(&local_name) {
+                        *entry = Some(rssi);
+                    }
+                }
                 return Ok(());
             }
+            Some(LinkState::Lost(_)) => info!("Reconnecting to {} within the disconnect grace period", local_name),
+            None => {
+                // Devices within their disconnect grace period keep their slot
+                let in_use = self.connected_devices.lock().unwrap().len();
+                if self.max_devices > 0 && in_use >= self.max_devices {
+                    if self.skipped_devices.lock().unwrap().insert(local_name.clone()) {
+                        info!(
+                            "Skipping Memo device {}: already connected to {} devices (audio.max_devices)",
+                            local_name, in_use
+                        );
+                    } else {
+                        debug!("Still skipping {}: no free device slot", local_name);
+                    }
+                    return Ok(());
+                }
+                self.skipped_devices.lock().unwrap().remove(&local_name);
+                info!("Found Memo device: {}", local_name);
+            }
         }
 
-        info!("Found Memo device: {}", local_name);
-
         // Connect to the device
         let was_connected = peripheral.is_connected().await?;
         if !was_connected {
@@ -133,10 +511,16 @@ impl BleAudioReceiver {
 
         // Find characteristics
         let characteristics = peripheral.characteristics();
-        let audio_char = characteristics
-            .iter()
-            .find(|c| c.uuid == self.characteristic_uuid)
-            .context("Audio characteristic not found")?;
+        let mut audio_chars = Vec::new();
+        for (stream, uuid) in self.characteristic_uuids.iter().enumerate() {
+            match characteristics.iter().find(|c| c.uuid == *uuid) {
+                Some(characteristic) => audio_chars.push((stream, characteristic)),
+                None => warn!("Audio characteristic {} not found on {}", uuid, local_name),
+            }
+        }
+        if audio_chars.is_empty() {
+            anyhow::bail!("Audio characteristic not found");
+        }
 
         let control_tx_uuid = Uuid::parse_str(CONTROL_TX_UUID)
             .context("Failed to parse control TX UUID")?;
@@ -150,7 +534,7 @@ impl BleAudioReceiver {
             .iter()
             .find(|c| c.uuid == control_rx_uuid);
 
-        info!("Found audio characteristic on {}", local_name);
+        info!("Found {} audio characteristic(s) on {}", audio_chars.len(), local_name);
         if control_tx_char.is_some() {
             info!("Found control TX characteristic on {}", local_name);
         }
@@ -158,140 +542,514 @@ impl BleAudioReceiver {
             info!("Found control RX characteristic on {}", local_name);
         }
 
-        // Subscribe to audio notifications
-        self.subscribe_to_audio(&peripheral, audio_char, &local_name)
-            .await?;
+        // btleplug merges every characteristic's notifications into one
+        // stream per peripheral, so open it once (before subscribing, so no
+        // early events are missed) and dispatch by characteristic uuid
+        let notification_stream = peripheral
+            .notifications()
+            .await
+            .context("Failed to get notification stream")?;
+
+        for (stream, audio_char) in &audio_chars {
+            peripheral
+                .subscribe(audio_char)
+                .await
+                .context("Failed to subscribe to characteristic")?;
+            info!("Subscribed to audio stream {} from {}", stream, local_name);
+        }
 
         // Subscribe to control TX notifications (button events)
         if let Some(control_tx) = control_tx_char {
-            self.subscribe_to_control(peripheral.clone(), control_tx, &local_name)
-                .await?;
+            peripheral
+                .subscribe(control_tx)
+                .await
+                .context("Failed to subscribe to control characteristic")?;
+            info!("Subscribed to control events from {}", local_name);
+        }
+
+        // Mark this device as connected and set up; the dispatch task clears
+        // this when the stream ends
+        let connected_at = Instant::now();
+        {
+            let mut connected = self.connected_devices.lock().unwrap();
+            connected.insert(local_name.clone(), LinkState::Up(connected_at));
+        }
+
+        self.metrics
+            .ble_devices
+            .lock()
+            .unwrap()
+            .insert(local_name.clone(), properties.rssi);
+
+        let dispatch = NotificationDispatch {
+            audio_streams: audio_chars.iter().map(|(stream, c)| (c.uuid, *stream)).collect(),
+            control_uuid: control_tx_uuid,
+            audio_tx: self.audio_tx.clone(),
+            is_recording: self.is_recording.clone(),
+            // START goes out on every connect
+            recording: AtomicBool::new(true),
+            connected_devices: self.connected_devices.clone(),
+            connected_at,
+            command_throttle: self.command_throttle.clone(),
+            device_name: local_name.clone(),
+            rate_limit: self.rate_limit,
+            metrics: self.metrics.clone(),
+            disconnect_grace: self.disconnect_grace,
+            peripheral: peripheral.clone(),
+            watchdog_timeout: self.watchdog_timeout,
+            recording_events_tx: self.recording_events_tx.clone(),
+        };
+        tokio::spawn(dispatch.run(notification_stream));
+
+        if let Some((battery_uuid, poll_interval)) = self.battery {
+            let battery_char = characteristics
+                .iter()
+                .find(|c| c.uuid == battery_uuid && c.properties.contains(CharPropFlags::READ));
+            match battery_char {
+                Some(battery_char) => {
+                    tokio::spawn(poll_battery(
+                        peripheral.clone(),
+                        battery_char.clone(),
+                        local_name.clone(),
+                        LinkState::Up(connected_at),
+                        self.connected_devices.clone(),
+                        self.metrics.clone(),
+                        poll_interval,
+                    ));
+                }
+                None => debug!("{} has no readable battery characteristic", local_name),
+            }
         }
 
         // Send START command to begin recording (if control RX is available)
         if let Some(control_rx) = control_rx_char {
-            info!("Sending START_RECORDING command to {}", local_name);
-            let start_cmd = vec![CMD_START_RECORDING];
-            if let Err(e) = peripheral.write(control_rx, &start_cmd, WriteType::WithoutResponse).await {
-                warn!("Failed to send START command: {}", e);
-            } else {
-                info!("START_RECORDING command sent to {}", local_name);
+            if self.send_command(peripheral, control_rx, &local_name, CMD_START_RECORDING).await {
                 self.is_recording.store(true, Ordering::Release);
             }
         }
 
-        // Mark this device as connected and set up
-        {
-            let mut connected = self.connected_devices.lock().unwrap();
-            connected.insert(local_name.clone());
-        }
-
         Ok(())
     }
 
-    async fn subscribe_to_audio(
+    /// Write a control command unless the same one went to this device
+    /// within the cooldown; returns whether it was sent
+    async fn send_command(
         &self,
         peripheral: &Peripheral,
-        characteristic: &Characteristic,
+        control_rx: &Characteristic,
         device_name: &str,
-    ) -> Result<()> {
-        peripheral
-            .subscribe(characteristic)
-            .await
-            .context("Failed to subscribe to characteristic")?;
+        command: u8,
+    ) -> bool {
+        let name = command_name(command);
+        if !self.command_throttle.lock().unwrap().allow(device_name, command, Instant::now()) {
+            info!("Not re-sending {} to {}: sent within the command cooldown", name, device_name);
+            return false;
+        }
 
-        info!("Subscribed to audio from {}", device_name);
+        info!("Sending {} command to {}", name, device_name);
+        if let Err(e) = peripheral.write(control_rx, &[command], WriteType::WithoutResponse).await {
+            warn!("Failed to send {} command: {}", name, e);
+            return false;
+        }
+        self.command_throttle
+            .lock()
+            .unwrap()
+            .record(device_name, command, Instant::now());
+        info!("{} command sent to {}", name, device_name);
+        true
+    }
+}
 
-        let audio_tx = self.audio_tx.clone();
-        let peripheral = peripheral.clone();
-        let characteristic = characteristic.clone();
-        let device_name = device_name.to_string();
+fn command_name(command: u8) -> &'static str {
+    match command {
+        CMD_START_RECORDING => "START_RECORDING",
+        CMD_END_RECORDING => "END_RECORDING",
+        _ => "unknown",
+    }
+}
 
-        tokio::spawn(async move {
-            let mut notification_stream = peripheral.notifications().await.unwrap();
+/// Routes one peripheral's notifications to the audio pipeline or the
+/// recording state
+struct NotificationDispatch {
+    /// Audio characteristic and the stream index it's tagged with
+    audio_streams: Vec<(Uuid, usize)>,
+    control_uuid: Uuid,
+    audio_tx: mpsc::UnboundedSender<AudioPacket>,
+    /// Shared by all devices; follows the latest button press on any
+    is_recording: Arc<AtomicBool>,
+    /// This device's own recording state, which the watchdog goes by so a
+    /// device isn't expected to send audio while another one records
+    recording: AtomicBool,
+    connected_devices: Arc<Mutex<HashMap<String, LinkState>>>,
+    /// When this connection was set up; once the device's state is anything
+    /// but `Up` with this time, a failover or newer connection has taken over
+    connected_at: Instant,
+    command_throttle: Arc<Mutex<CommandThrottle>>,
+    device_name: String,
+    rate_limit: RateLimit,
+    metrics: Arc<Metrics>,
+    disconnect_grace: Duration,
+    peripheral: Peripheral,
+    watchdog_timeout: Option<Duration>,
+    recording_events_tx: Option<mpsc::UnboundedSender<RecordingEvent>>,
+}
 
-            while let Some(data) = notification_stream.next().await {
-                if data.uuid == characteristic.uuid {
-                    debug!("Received {} bytes of audio data", data.value.len());
+impl NotificationDispatch {
+    /// Whether this is still the device's live connection
+    fn is_current(&self) -> bool {
+        self.connected_devices.lock().unwrap().get(&self.device_name)
+            == Some(&LinkState::Up(self.connected_at))
+    }
+
+    /// Dispatch until the stream ends (usually a disconnect)
+    async fn run(self, mut notification_stream: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>) {
+        // Track last control value to avoid duplicate processing
+        let mut last_control_value: Option<u8> = None;
+        let mut rate_limiter = RateLimiter::new(self.rate_limit, Instant::now());
+        let mut last_audio = Instant::now();
 
-                    if let Err(e) = audio_tx.send(data.value) {
-                        error!("Failed to send audio data: {}", e);
-                        break;
+        loop {
+            let next = match self.watchdog_timeout {
+                None => notification_stream.next().await,
+                Some(timeout) => {
+                    let remaining = timeout.saturating_sub(last_audio.elapsed());
+                    match tokio::time::timeout(remaining, notification_stream.next()).await {
+                        Ok(next) => next,
+                        // No audio is expected while this device is stopped
+                        Err(_) if !self.recording.load(Ordering::Acquire) => {
+                            last_audio = Instant::now();
+                            continue;
+                        }
+                        Err(_) => {
+                            warn!(
+                                "Watchdog: no audio from {} for {:?} while recording, reconnecting",
+                                self.device_name, timeout
+                            );
+                            self.metrics.record_error(
+                                Subsystem::Ble,
+                                format!("{}: no audio for {:?}, reconnecting", self.device_name, timeout),
+                            );
+                            if let Err(e) = self.peripheral.disconnect().await {
+                                debug!("Failed to disconnect {}: {}", self.device_name, e);
+                            }
+                            break;
+                        }
                     }
                 }
+            };
+            let Some(data) = next else {
+                break;
+            };
+            if !self.is_current() {
+                debug!("Connection to {} was replaced, closing it", self.device_name);
+                if let Err(e) = self.peripheral.disconnect().await {
+                    debug!("Failed to disconnect {}: {}", self.device_name, e);
+                }
+                break;
             }
 
-            warn!("Audio notification stream ended for {}", device_name);
-        });
+            let audio_stream = self
+                .audio_streams
+                .iter()
+                .find(|(uuid, _)| *uuid == data.uuid)
+                .map(|(_, stream)| *stream);
 
-        Ok(())
-    }
+            if let Some(stream) = audio_stream {
+                last_audio = Instant::now();
+                debug!("Received {} bytes of audio data on stream {}", data.value.len(), stream);
+                Metrics::add(&self.metrics.audio_packets_received, 1);
+                Metrics::add(&self.metrics.audio_bytes_received, data.value.len() as u64);
+                *self
+                    .metrics
+                    .ble_bytes_received
+                    .lock()
+                    .unwrap()
+                    .entry(self.device_name.clone())
+                    .or_default() += data.value.len() as u64;
 
-    async fn subscribe_to_control(
-        &self,
-        peripheral: Peripheral,
-        characteristic: &Characteristic,
-        device_name: &str,
-    ) -> Result<()> {
-        peripheral
-            .subscribe(characteristic)
-            .await
-            .context("Failed to subscribe to control characteristic")?;
-
-        info!("Subscribed to control events from {}", device_name);
+                // A runaway peripheral shouldn't be able to flood the decoder
+                let (admitted, dropped_last_window) = rate_limiter.admit(data.value.len(), Instant::now());
+                if let Some(dropped) = dropped_last_window {
+                    warn!("Rate limit: dropped {} audio packets from {} in the last second", dropped, self.device_name);
+                }
+                if !admitted {
+                    Metrics::add(&self.metrics.audio_packets_rate_limited, 1);
+                    continue;
+                }
 
-        let is_recording = self.is_recording.clone();
-        let peripheral_clone = peripheral.clone();
-        let characteristic_uuid = characteristic.uuid;
-        let device_name = device_name.to_string();
+                let packet = AudioPacket {
+                    device: self.device_name.clone(),
+                    stream,
+                    data: data.value,
+                };
+                if let Err(e) = self.audio_tx.send(packet) {
+                    error!("Failed to send audio data: {}", e);
+                    break;
+                }
+            } else if data.uuid == self.control_uuid && !data.value.is_empty() {
+                // Give audio the full watchdog timeout to start after a
+                // button press
+                last_audio = Instant::now();
+                let control_value = data.value[0];
 
-        tokio::spawn(async move {
-            let mut notification_stream = match peripheral_clone.notifications().await {
-                Ok(stream) => stream,
-                Err(e) => {
-                    error!("Failed to get notification stream for control: {}", e);
-                    return;
+                // Skip if we just processed this value (debounce duplicates)
+                if last_control_value == Some(control_value) {
+                    continue;
                 }
-            };
+                last_control_value = Some(control_value);
 
-            // Track last control value to avoid duplicate processing
-            let mut last_control_value: Option<u8> = None;
-            
-            while let Some(data) = notification_stream.next().await {
-                if data.uuid == characteristic_uuid && !data.value.is_empty() {
-                    let control_value = data.value[0];
-                    
-                    // Skip if we just processed this value (debounce duplicates)
-                    if last_control_value == Some(control_value) {
-                        continue;
+                let event = handle_control_event(control_value, &self.recording, &self.device_name);
+                if let Some(event) = event {
+                    let started = matches!(event, RecordingEvent::Started { .. });
+                    self.is_recording.store(started, Ordering::Release);
+                    if let Some(events_tx) = &self.recording_events_tx {
+                        let _ = events_tx.send(event);
                     }
-                    last_control_value = Some(control_value);
-                    
-                    match control_value {
-                        RESP_SPEECH_START => {
-                            let current = is_recording.load(Ordering::Acquire);
-                            if !current {
-                                info!("Button pressed - starting recording on {}", device_name);
-                                is_recording.store(true, Ordering::Release);
-                            }
-                        }
-                        RESP_SPEECH_END => {
-                            let current = is_recording.load(Ordering::Acquire);
-                            if current {
-                                info!("Button pressed again - stopping recording on {}", device_name);
-                                is_recording.store(false, Ordering::Release);
-                            }
-                        }
-                        _ => {
-                            debug!("Received control event: 0x{:02X} from {}", control_value, device_name);
-                        }
+                }
+            }
+        }
+
+        // After a failover the device's state belongs to the failover or to
+        // the connection that replaced this one
+        if !self.is_current() {
+            debug!("Notification stream ended for replaced connection to {}", self.device_name);
+            return;
+        }
+
+        // The device will need START again once it reconnects
+        self.command_throttle.lock().unwrap().forget(&self.device_name);
+
+        if self.disconnect_grace.is_zero() {
+            warn!("Notification stream ended for {}", self.device_name);
+            // Let the scan loop set the device up again when it reappears
+            self.connected_devices.lock().unwrap().remove(&self.device_name);
+            drop_device_metrics(&self.metrics, &self.device_name);
+            return;
+        }
+
+        // The scan loop reconnects Lost devices; only report the disconnect
+        // if this particular loss is still current after the grace period
+        debug!("Notification stream ended for {}, waiting {:?} before reporting", self.device_name, self.disconnect_grace);
+        let lost_at = Instant::now();
+        self.connected_devices
+            .lock()
+            .unwrap()
+            .insert(self.device_name.clone(), LinkState::Lost(lost_at));
+
+        expire_lost_link(
+            self.connected_devices.clone(),
+            self.metrics.clone(),
+            self.device_name.clone(),
+            lost_at,
+            self.disconnect_grace,
+        )
+        .await;
+    }
+}
+
+/// Read a device's battery level every `interval` for as long as its
+/// connection stays `link`
+async fn poll_battery(
+    peripheral: Peripheral,
+    characteristic: Characteristic,
+    device_name: String,
+    link: LinkState,
+    connected_devices: Arc<Mutex<HashMap<String, LinkState>>>,
+    metrics: Arc<Metrics>,
+    interval: Duration,
+) {
+    info!("Reading battery level of {} every {:?}", device_name, interval);
+    let mut warned_low = false;
+
+    loop {
+        if connected_devices.lock().unwrap().get(&device_name) != Some(&link) {
+            debug!("Stopped reading battery level of {}", device_name);
+            return;
+        }
+
+        match peripheral.read(&characteristic).await {
+            Ok(value) => match value.first() {
+                Some(&level) => {
+                    let level = level.min(100);
+                    debug!("{} battery at {}%", device_name, level);
+                    if level < LOW_BATTERY_PERCENT && !warned_low {
+                        warn!("{} battery low ({}%), charge it soon", device_name, level);
                     }
+                    warned_low = level < LOW_BATTERY_PERCENT;
+                    metrics.ble_battery.lock().unwrap().insert(device_name.clone(), level);
                 }
+                None => debug!("Empty battery level from {}", device_name),
+            },
+            Err(e) => debug!("Failed to read battery level of {}: {}", device_name, e),
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Apply a button event to the recording state, returning the transition
+/// it caused, if any
+fn handle_control_event(
+    control_value: u8,
+    is_recording: &AtomicBool,
+    device_name: &str,
+) -> Option<RecordingEvent> {
+    let device = device_name.to_string();
+    match control_value {
+        RESP_SPEECH_START => {
+            let current = is_recording.load(Ordering::Acquire);
+            if !current {
+                info!("Button pressed - starting recording on {}", device_name);
+                is_recording.store(true, Ordering::Release);
+                return Some(RecordingEvent::Started { device });
             }
+        }
+        RESP_SPEECH_END => {
+            let current = is_recording.load(Ordering::Acquire);
+            if current {
+                info!("Button pressed again - stopping recording on {}", device_name);
+                is_recording.store(false, Ordering::Release);
+                return Some(RecordingEvent::Stopped { device });
+            }
+        }
+        _ => {
+            debug!("Received control event: 0x{:02X} from {}", control_value, device_name);
+        }
+    }
+    None
+}
 
-            warn!("Control notification stream ended for {}", device_name);
-        });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(())
+    #[test]
+    fn test_rate_limiter_drops_over_limit_and_resets() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(
+            RateLimit {
+                max_packets_per_sec: 2,
+                max_bytes_per_sec: 250,
+            },
+            start,
+        );
+
+        assert_eq!(limiter.admit(100, start), (true, None));
+        assert_eq!(limiter.admit(200, start), (false, None)); // over bytes
+        assert_eq!(limiter.admit(100, start), (true, None));
+        assert_eq!(limiter.admit(10, start), (false, None)); // over packets
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.admit(100, later), (true, Some(2)));
+    }
+
+    #[test]
+    fn test_command_throttle_dedupes_within_cooldown() {
+        let start = Instant::now();
+        let mut throttle = CommandThrottle::new(Duration::from_secs(5));
+
+        assert!(throttle.allow("memo-1", CMD_START_RECORDING, start));
+        throttle.record("memo-1", CMD_START_RECORDING, start);
+
+        let soon = start + Duration::from_secs(1);
+        assert!(!throttle.allow("memo-1", CMD_START_RECORDING, soon));
+        assert!(throttle.allow("memo-1", CMD_END_RECORDING, soon));
+        assert!(throttle.allow("memo-2", CMD_START_RECORDING, soon));
+
+        assert!(throttle.allow("memo-1", CMD_START_RECORDING, start + Duration::from_secs(5)));
+
+        // A quick reconnect gets its START
+        throttle.forget("memo-1");
+        assert!(throttle.allow("memo-1", CMD_START_RECORDING, soon));
+    }
+
+    #[test]
+    fn test_control_events_report_transitions_only() {
+        let is_recording = AtomicBool::new(false);
+        let started = RecordingEvent::Started {
+            device: "memo".to_string(),
+        };
+        let stopped = RecordingEvent::Stopped {
+            device: "memo".to_string(),
+        };
+
+        assert_eq!(handle_control_event(RESP_SPEECH_START, &is_recording, "memo"), Some(started));
+        assert!(is_recording.load(Ordering::Acquire));
+        assert_eq!(handle_control_event(RESP_SPEECH_START, &is_recording, "memo"), None);
+        assert_eq!(handle_control_event(RESP_SPEECH_END, &is_recording, "memo"), Some(stopped));
+        assert!(!is_recording.load(Ordering::Acquire));
+        assert_eq!(handle_control_event(0x7f, &is_recording, "memo"), None);
+    }
+
+    #[test]
+    fn test_adapter_order_follows_preference() {
+        let infos = vec![
+            "hci0 (pci:built-in)".to_string(),
+            "hci1 (usb:v0A12p0001)".to_string(),
+            "hci2 (usb:v0B05p17CB)".to_string(),
+        ];
+
+        assert_eq!(adapter_order(&infos, &[]), vec![0, 1, 2]);
+        let preferred = ["usb:v0A12".to_string(), "hci0".to_string()];
+        // hci2 isn't listed, so it is never used
+        assert_eq!(adapter_order(&infos, &preferred), vec![1, 0]);
+        assert_eq!(adapter_order(&infos, &["usb".to_string()]), vec![1, 2]);
+        assert!(adapter_order(&infos, &["hci9".to_string()]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failover_loses_up_links_until_they_reconnect() {
+        let start = Instant::now();
+        let connected_devices = Arc::new(Mutex::new(HashMap::from([
+            ("memo-1".to_string(), LinkState::Up(start)),
+            ("memo-2".to_string(), LinkState::Up(start)),
+            ("memo-3".to_string(), LinkState::Lost(start)),
+        ])));
+        let metrics = Arc::new(Metrics::default());
+        metrics.ble_devices.lock().unwrap().insert("memo-1".to_string(), Some(-60));
+        metrics.ble_devices.lock().unwrap().insert("memo-2".to_string(), Some(-70));
+
+        let lost_at = start + Duration::from_secs(1);
+        let lost = lose_links(&mut connected_devices.lock().unwrap(), lost_at);
+        assert_eq!(lost, ["memo-1", "memo-2"]);
+        assert_eq!(connected_devices.lock().unwrap()["memo-3"], LinkState::Lost(start));
+
+        // memo-2 reconnects through the new adapter within the grace period
+        let grace = Duration::from_millis(20);
+        let expiries: Vec<_> = lost
+            .into_iter()
+            .map(|device| {
+                tokio::spawn(expire_lost_link(
+                    connected_devices.clone(),
+                    metrics.clone(),
+                    device,
+                    lost_at,
+                    grace,
+                ))
+            })
+            .collect();
+        let reconnected_at = Instant::now();
+        connected_devices
+            .lock()
+            .unwrap()
+            .insert("memo-2".to_string(), LinkState::Up(reconnected_at));
+        for expiry in expiries {
+            expiry.await.unwrap();
+        }
+
+        let connected = connected_devices.lock().unwrap();
+        assert!(!connected.contains_key("memo-1"));
+        assert!(!metrics.ble_devices.lock().unwrap().contains_key("memo-1"));
+        assert_eq!(connected["memo-2"], LinkState::Up(reconnected_at));
+        assert_eq!(metrics.ble_devices.lock().unwrap()["memo-2"], Some(-70));
+    }
+
+    #[test]
+    fn test_rate_limiter_disabled_by_default() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(RateLimit::default(), start);
+        for _ in 0..1000 {
+            assert!(limiter.admit(10_000, start).0);
+        }
     }
 }
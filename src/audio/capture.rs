@@ -0,0 +1,146 @@
+use crate::config::RecordingFormat;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::Path;
+
+/// Split a bundle capture into raw BLE bundles
+///
+/// A capture is a sequence of records, each a 2-byte little-endian length
+/// followed by that many bytes of bundle exactly as received over BLE.
+pub fn parse_capture(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut bundles = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let header = data
+            .get(offset..offset + 2)
+            .with_context(|| format!("Truncated length prefix at byte {}", offset))?;
+        let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+        offset += 2;
+
+        let bundle = data.get(offset..offset + len).with_context(|| {
+            format!(
+                "Bundle {} at byte {} claims {} bytes but only {} remain",
+                bundles.len(),
+                offset,
+                len,
+                data.len() - offset
+            )
+        })?;
+        bundles.push(bundle.to_vec());
+        offset += len;
+    }
+
+    Ok(bundles)
+}
+
+/// Write mono 16-bit PCM as a WAV file
+pub fn write_wav(path: &Path, samples: &[i16], sample_rate: u32) -> Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // byte rate
+    out.extend_from_slice(&2u16.to_le_bytes()); // block align
+    out.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    std::fs::File::create(path)
+        .and_then(|mut file| file.write_all(&out))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Save a recording in `format`
+pub fn write_recording(path: &Path, samples: &[i16], sample_rate: u32, format: RecordingFormat) -> Result<()> {
+    match format {
+        RecordingFormat::Wav => write_wav(path, samples, sample_rate),
+        RecordingFormat::Opus => super::ogg::write_ogg_opus(path, samples, sample_rate),
+    }
+}
+
+/// Read a recording saved by `write_recording`, by its extension (`.wav`
+/// or `.opus`), returning the samples and sample rate
+pub fn read_recording(path: &Path) -> Result<(Vec<i16>, u32)> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("opus") => super::ogg::read_ogg_opus(path),
+        _ => read_wav(path),
+    }
+}
+
+/// Read a mono 16-bit PCM WAV file (as written by `write_wav`), returning
+/// the samples and sample rate
+pub fn read_wav(path: &Path) -> Result<(Vec<i16>, u32)> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        anyhow::bail!("{} is not a WAV file", path.display());
+    }
+
+    let mut format = None;
+    let mut offset = 12;
+    while let Some(header) = data.get(offset..offset + 8) {
+        let id = &header[..4];
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let body = data
+            .get(offset + 8..offset + 8 + len)
+            .with_context(|| format!("Truncated WAV chunk in {}", path.display()))?;
+
+        if id == b"fmt " && body.len() >= 16 {
+            let audio_format = u16::from_le_bytes([body[0], body[1]]);
+            let channels = u16::from_le_bytes([body[2], body[3]]);
+            let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+            let bits = u16::from_le_bytes([body[14], body[15]]);
+            if audio_format != 1 || channels != 1 || bits != 16 {
+                anyhow::bail!("{} is not mono 16-bit PCM", path.display());
+            }
+            format = Some(sample_rate);
+        } else if id == b"data" {
+            let sample_rate = format.with_context(|| format!("No fmt chunk before data in {}", path.display()))?;
+            return Ok((crate::transcribe::pcm_to_samples(body), sample_rate));
+        }
+
+        // Chunks are padded to an even length
+        offset += 8 + len + len % 2;
+    }
+
+    anyhow::bail!("No data chunk in {}", path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_roundtrip() {
+        let path = std::env::temp_dir().join(format!("memo-node-wav-{}.wav", uuid::Uuid::new_v4()));
+        let samples = vec![0, 1, -1, i16::MAX, i16::MIN];
+        write_wav(&path, &samples, 16000).unwrap();
+
+        assert_eq!(read_wav(&path).unwrap(), (samples, 16000));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_capture() {
+        let data = [2, 0, 7, 1, 0, 0, 3, 0, 8, 1, 5];
+        let bundles = parse_capture(&data).unwrap();
+        assert_eq!(bundles, vec![vec![7, 1], vec![], vec![8, 1, 5]]);
+    }
+
+    #[test]
+    fn test_parse_capture_rejects_truncated_bundle() {
+        assert!(parse_capture(&[4, 0, 7, 1]).is_err());
+        assert!(parse_capture(&[2, 0, 7, 1, 9]).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use super::OpusDecoder;
+use crate::config::{AudioConfig, BundleLayout, InputCodec};
+use anyhow::Result;
+use audiopus::Channels;
+
+/// Sample rate Whisper expects
+const OUTPUT_SAMPLE_RATE: u32 = 16000;
+
+/// Turns audio notifications into 16kHz mono i16 samples, whichever
+/// encoding the device sends
+pub enum AudioDecoder {
+    Opus(OpusDecoder),
+    Pcm(PcmDecoder),
+}
+
+impl AudioDecoder {
+    pub fn new(config: &AudioConfig) -> Result<Self> {
+        let format = match config.codec {
+            InputCodec::Opus => {
+                let decoder = OpusDecoder::new(OUTPUT_SAMPLE_RATE, Channels::Mono)?
+                    .with_layout(config.bundle_layout);
+                return Ok(Self::Opus(decoder));
+            }
+            InputCodec::PcmS16le => PcmFormat::S16le,
+            InputCodec::PcmF32le => PcmFormat::F32le,
+        };
+
+        if config.pcm_sample_rate == 0 || config.pcm_channels == 0 {
+            anyhow::bail!("audio.pcm_sample_rate and audio.pcm_channels must be non-zero");
+        }
+
+        Ok(Self::Pcm(PcmDecoder::new(
+            format,
+            config.pcm_sample_rate,
+            config.pcm_channels,
+        )))
+    }
+
+    /// Whether packets start with a `bundle_index` byte that can be reordered
+    /// on (assumed while an `auto` bundle layout is undetected)
+    pub fn has_bundle_index(&self) -> bool {
+        match self {
+            Self::Opus(decoder) => decoder.layout() != BundleLayout::NoIndex,
+            Self::Pcm(_) => false,
+        }
+    }
+
+    /// See `OpusDecoder::detect_layout`; a no-op for other codecs
+    pub fn detect_layout(&mut self, packet: &[u8]) {
+        if let Self::Opus(decoder) = self {
+            decoder.detect_layout(packet);
+        }
+    }
+
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<i16>> {
+        match self {
+            Self::Opus(decoder) => decoder.decode(packet),
+            Self::Pcm(decoder) => Ok(decoder.decode(packet)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    S16le,
+    F32le,
+}
+
+impl PcmFormat {
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            PcmFormat::S16le => 2,
+            PcmFormat::F32le => 4,
+        }
+    }
+}
+
+/// Raw interleaved PCM to 16kHz mono
+///
+/// Packets don't have to end on a frame boundary; leftover bytes are kept
+/// for the next packet. Resampling is linear interpolation, which is plenty
+/// for speech.
+pub struct PcmDecoder {
+    format: PcmFormat,
+    channels: usize,
+    /// Input samples per output sample
+    step: f64,
+    /// Bytes of an incomplete frame from the previous packet
+    carry: Vec<u8>,
+    /// Read position, relative to `last`, for the next output sample
+    position: f64,
+    /// Last mono input sample of the previous packet
+    last: Option<f32>,
+}
+
+impl PcmDecoder {
+    pub fn new(format: PcmFormat, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            format,
+            channels: channels as usize,
+            step: sample_rate as f64 / OUTPUT_SAMPLE_RATE as f64,
+            carry: Vec::new(),
+            position: 0.0,
+            last: None,
+        }
+    }
+
+    pub fn decode(&mut self, packet: &[u8]) -> Vec<i16> {
+        self.carry.extend_from_slice(packet);
+
+        let frame_bytes = self.format.bytes_per_sample() * self.channels;
+        let usable = self.carry.len() - self.carry.len() % frame_bytes;
+
+        let mono: Vec<f32> = self.carry[..usable]
+            .chunks_exact(frame_bytes)
+            .map(|frame| {
+                let sum: f32 = frame
+                    .chunks_exact(self.format.bytes_per_sample())
+                    .map(|sample| self.sample_to_f32(sample))
+                    .sum();
+                sum / self.channels as f32
+            })
+            .collect();
+        self.carry.drain(..usable);
+
+        let resampled = if self.step == 1.0 {
+            mono
+        } else {
+            self.resample(&mono)
+        };
+
+        resampled
+            .into_iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect()
+    }
+
+    fn sample_to_f32(&self, bytes: &[u8]) -> f32 {
+        match self.format {
+            PcmFormat::S16le => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32,
+            PcmFormat::F32le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    fn resample(&mut self, input: &[f32]) -> Vec<f32> {
+        if input.is_empty() {
+            return Vec::new();
+        }
+
+        // Interpolate over the previous packet's last sample followed by this one
+        let mut samples = Vec::with_capacity(input.len() + 1);
+        samples.extend(self.last);
+        samples.extend_from_slice(input);
+
+        let mut output = Vec::with_capacity((input.len() as f64 / self.step) as usize + 1);
+        while self.position + 1.0 < samples.len() as f64 {
+            let index = self.position as usize;
+            let frac = (self.position - index as f64) as f32;
+            output.push(samples[index] * (1.0 - frac) + samples[index + 1] * frac);
+            self.position += self.step;
+        }
+
+        // Keep the position relative to the sample carried into the next packet
+        self.position -= (samples.len() - 1) as f64;
+        self.last = samples.last().copied();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_s16le_carries_partial_samples() {
+        let mut decoder = PcmDecoder::new(PcmFormat::S16le, 16000, 1);
+        let bytes: Vec<u8> = [1000i16, -2000, 3000]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        let first = decoder.decode(&bytes[..3]);
+        let second = decoder.decode(&bytes[3..]);
+
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 2);
+        let all: Vec<i16> = first.into_iter().chain(second).collect();
+        for (decoded, original) in all.iter().zip([1000i16, -2000, 3000]) {
+            assert!((decoded - original).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_f32le_stereo_is_mixed_down_and_clamped() {
+        let mut decoder = PcmDecoder::new(PcmFormat::F32le, 16000, 2);
+        let bytes: Vec<u8> = [0.5f32, -0.5, 2.0, 2.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        assert_eq!(decoder.decode(&bytes), vec![0, i16::MAX]);
+    }
+
+    #[test]
+    fn test_resamples_to_16khz_across_packets() {
+        let mut decoder = PcmDecoder::new(PcmFormat::S16le, 48000, 1);
+        let packet: Vec<u8> = vec![0i16; 480].iter().flat_map(|s| s.to_le_bytes()).collect();
+
+        let total: usize = (0..10).map(|_| decoder.decode(&packet).len()).sum();
+
+        // 100ms at 48kHz in, ~100ms at 16kHz out
+        assert!((1599..=1600).contains(&total), "got {} samples", total);
+    }
+}
@@ -1,10 +1,66 @@
 use anyhow::{Context, Result};
 use audiopus::{coder::Decoder, Channels, SampleRate};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Most frames a bundle declares; more means the header was misread
+const MAX_FRAMES: usize = 10;
+
+/// Consecutive bundles with an invalid frame count before suggesting
+/// another `audio.bundle_layout`
+const INVALID_BUNDLES_WARNING: u32 = 50;
+
+/// The frames of `bundle_data` (a bundle without any index byte), if its
+/// header describes exactly the bytes present
+fn parse_frames(bundle_data: &[u8]) -> Option<Vec<&[u8]>> {
+    let num_frames = *bundle_data.first()? as usize;
+    if num_frames == 0 || num_frames > MAX_FRAMES {
+        return None;
+    }
+
+    let mut frames = Vec::with_capacity(num_frames);
+    let mut offset = 1;
+    for _ in 0..num_frames {
+        let frame_size = *bundle_data.get(offset)? as usize;
+        let frame = bundle_data.get(offset + 1..offset + 1 + frame_size)?;
+        if frame.is_empty() {
+            return None;
+        }
+        frames.push(frame);
+        offset += 1 + frame_size;
+    }
+
+    (offset == bundle_data.len()).then_some(frames)
+}
+
+/// Header of the Opus bundles a device sends (`audio.bundle_layout`)
+///
+/// Defined here rather than in `config` because `benches/decode.rs` builds
+/// this file on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum BundleLayout {
+    /// `[bundle_index][num_frames][frame_size][frame]...`
+    #[default]
+    WithIndex,
+    /// `[num_frames][frame_size][frame]...`, as sent by some firmware
+    NoIndex,
+    /// Try both on the first bundles and keep the one that decodes
+    Auto,
+}
 
 pub struct OpusDecoder {
     decoder: Decoder,
     sample_rate: u32,
     frame_size_samples: usize,
+    /// PCM scratch buffer reused for every frame to avoid per-frame allocations
+    scratch: Vec<i16>,
+    /// `Auto` until `detect_layout` has settled on one
+    layout: BundleLayout,
+    /// Bundles in a row with an invalid frame count, see
+    /// `INVALID_BUNDLES_WARNING`
+    invalid_bundles: u32,
 }
 
 impl OpusDecoder {
@@ -26,38 +82,94 @@ impl OpusDecoder {
             decoder,
             sample_rate,
             frame_size_samples,
+            scratch: vec![0i16; frame_size_samples],
+            layout: BundleLayout::WithIndex,
+            invalid_bundles: 0,
         })
     }
 
+    /// Expect bundles in `layout`; `Auto` settles on one with `detect_layout`
+    pub fn with_layout(mut self, layout: BundleLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// The bundle layout in use; `Auto` while still undetected
+    pub fn layout(&self) -> BundleLayout {
+        self.layout
+    }
+
+    /// With an `Auto` layout, try parsing and decoding `bundle` both with and
+    /// without a leading `bundle_index` and keep the layout that works
+    /// (preferring `WithIndex` if both do). Bundles that fit neither, such as
+    /// fragments, leave the layout undecided.
+    pub fn detect_layout(&mut self, bundle: &[u8]) {
+        if self.layout != BundleLayout::Auto {
+            return;
+        }
+
+        for (layout, bundle_data) in [
+            (BundleLayout::WithIndex, bundle.get(1..).unwrap_or_default()),
+            (BundleLayout::NoIndex, bundle),
+        ] {
+            let Some(frames) = parse_frames(bundle_data) else {
+                continue;
+            };
+            // A throwaway decoder, so trial decodes don't disturb the real one
+            let Ok(mut trial) = Decoder::new(SampleRate::Hz16000, Channels::Mono) else {
+                return;
+            };
+            let decodes = frames
+                .iter()
+                .all(|frame| trial.decode(Some(*frame), &mut self.scratch, false).is_ok_and(|n| n > 0));
+            if decodes {
+                tracing::info!("Detected Opus bundle layout: {:?}", layout);
+                self.layout = layout;
+                return;
+            }
+        }
+    }
+
     pub fn decode(&mut self, encoded: &[u8]) -> Result<Vec<i16>> {
         if encoded.is_empty() {
             return Ok(Vec::new());
         }
 
         // Memo device sends bundles: [bundle_index:1][num_frames:1][frame1_size:1][frame1_data:N]...
-        // Skip bundle_index (first byte) and parse bundle
-        if encoded.len() < 2 {
-            tracing::debug!("Packet too short: {} bytes", encoded.len());
-            return Ok(Vec::new()); // Not enough data for a bundle
-        }
+        // Skip bundle_index (first byte) and parse bundle; some firmware
+        // leaves the index out. An undetected layout is read as the default.
+        let (bundle_index, bundle_data) = match self.layout {
+            BundleLayout::NoIndex => (None, encoded),
+            BundleLayout::WithIndex | BundleLayout::Auto => {
+                if encoded.len() < 2 {
+                    tracing::debug!("Packet too short: {} bytes", encoded.len());
+                    return Ok(Vec::new()); // Not enough data for a bundle
+                }
+                (Some(encoded[0]), &encoded[1..])
+            }
+        };
 
-        let bundle_index = encoded[0];
-        let bundle_data = &encoded[1..]; // Skip bundle_index
-        
-        if bundle_data.is_empty() {
-            return Ok(Vec::new());
-        }
-        
         let num_frames = bundle_data[0] as usize;
-        
+
         // Sanity check - reasonable number of frames
-        if num_frames == 0 || num_frames > 10 {
-            tracing::debug!("Invalid frame count: {} (bundle_index: {}, total_len: {})", 
+        if num_frames == 0 || num_frames > MAX_FRAMES {
+            tracing::debug!("Invalid frame count: {} (bundle_index: {:?}, total_len: {})",
                 num_frames, bundle_index, encoded.len());
+            self.invalid_bundles += 1;
+            if self.invalid_bundles == INVALID_BUNDLES_WARNING && self.layout != BundleLayout::Auto {
+                tracing::warn!(
+                    "{} Opus bundles in a row had an invalid frame count with audio.bundle_layout = {:?}; \
+                     the device may use another layout, try audio.bundle_layout = \"auto\"",
+                    INVALID_BUNDLES_WARNING,
+                    self.layout
+                );
+            }
             return Ok(Vec::new());
         }
-        
-        let mut all_samples = Vec::new();
+        self.invalid_bundles = 0;
+
+        // Size the output once for the whole bundle
+        let mut all_samples = Vec::with_capacity(num_frames * self.frame_size_samples);
         let mut offset = 1; // Skip frame count byte
 
         // Decode each frame in the bundle
@@ -87,13 +199,10 @@ impl OpusDecoder {
             let frame_data = &bundle_data[offset..offset + frame_size];
             
             // Decode this frame using audiopus (same as memo-stt)
-            let mut pcm = vec![0i16; self.frame_size_samples];
-            
-            match self.decoder.decode(Some(frame_data), &mut pcm, false) {
+            match self.decoder.decode(Some(frame_data), &mut self.scratch, false) {
                 Ok(samples_decoded) => {
                     if samples_decoded > 0 {
-                        pcm.truncate(samples_decoded);
-                        all_samples.extend_from_slice(&pcm);
+                        all_samples.extend_from_slice(&self.scratch[..samples_decoded]);
                     }
                 }
                 Err(e) => {
@@ -132,4 +241,42 @@ mod tests {
         // 20ms at 16kHz = 320 samples
         assert_eq!(decoder.frame_size_samples, 320);
     }
+
+    #[test]
+    fn test_detects_bundles_without_index() {
+        let encoder = audiopus::coder::Encoder::new(
+            SampleRate::Hz16000,
+            Channels::Mono,
+            audiopus::Application::Voip,
+        )
+        .unwrap();
+        let tone: Vec<i16> = (0..320).map(|n| ((n as f32 * 0.3).sin() * 8000.0) as i16).collect();
+        let mut frame = [0u8; 400];
+        let len = encoder.encode(&tone[..], &mut frame[..]).unwrap();
+
+        // [num_frames][frame_size][frame] twice, no bundle_index
+        let mut bundle = vec![2u8];
+        for _ in 0..2 {
+            bundle.push(len as u8);
+            bundle.extend_from_slice(&frame[..len]);
+        }
+        let mut with_index = vec![7u8];
+        with_index.extend_from_slice(&bundle);
+
+        let mut decoder = OpusDecoder::new(16000, Channels::Mono)
+            .unwrap()
+            .with_layout(BundleLayout::Auto);
+        decoder.detect_layout(&bundle[..3]); // a fragment decides nothing
+        assert_eq!(decoder.layout(), BundleLayout::Auto);
+        decoder.detect_layout(&bundle);
+        assert_eq!(decoder.layout(), BundleLayout::NoIndex);
+        assert_eq!(decoder.decode(&bundle).unwrap().len(), 640);
+
+        let mut decoder = OpusDecoder::new(16000, Channels::Mono)
+            .unwrap()
+            .with_layout(BundleLayout::Auto);
+        decoder.detect_layout(&with_index);
+        assert_eq!(decoder.layout(), BundleLayout::WithIndex);
+        assert_eq!(decoder.decode(&with_index).unwrap().len(), 640);
+    }
 }
@@ -0,0 +1,176 @@
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Most frames a Memo bundle declares; anything above is not a bundle header
+const MAX_FRAMES: usize = 10;
+
+/// What a buffer holds, judged by the bundle header
+/// `[bundle_index][num_frames][frame_size][frame]...`
+#[derive(Debug, PartialEq, Eq)]
+enum BundleLength {
+    /// Every declared frame is present
+    Complete,
+    /// More bytes are needed to know or reach the declared length
+    Incomplete,
+    /// Not a bundle header; passed on for the decoder to reject
+    Invalid,
+}
+
+fn bundle_length(buf: &[u8]) -> BundleLength {
+    let Some(&num_frames) = buf.get(1) else {
+        return BundleLength::Incomplete;
+    };
+    let num_frames = num_frames as usize;
+    if num_frames == 0 || num_frames > MAX_FRAMES {
+        return BundleLength::Invalid;
+    }
+
+    let mut offset = 2;
+    for _ in 0..num_frames {
+        let Some(&frame_size) = buf.get(offset) else {
+            return BundleLength::Incomplete;
+        };
+        offset += 1 + frame_size as usize;
+    }
+    if offset > buf.len() {
+        BundleLength::Incomplete
+    } else {
+        BundleLength::Complete
+    }
+}
+
+/// Rebuilds Opus bundles that the device split across several BLE
+/// notifications because they didn't fit the MTU
+///
+/// A notification that holds less than its header declares is kept, and
+/// following notifications are appended until the bundle is complete. A
+/// partial bundle is discarded if it isn't completed within `timeout`, or
+/// if a complete bundle with the next index arrives first (its missing
+/// fragment was lost). A `timeout` of zero passes notifications through.
+pub struct BundleAssembler {
+    timeout: Duration,
+    partial: Option<(Vec<u8>, Instant)>,
+    discarded: u64,
+}
+
+impl BundleAssembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            partial: None,
+            discarded: 0,
+        }
+    }
+
+    /// Incomplete bundles dropped so far
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Add one notification, returning the bundle it completes, if any
+    pub fn push(&mut self, data: Vec<u8>, now: Instant) -> Option<Vec<u8>> {
+        if self.timeout.is_zero() || data.is_empty() {
+            return Some(data);
+        }
+
+        if let Some((mut partial, started)) = self.partial.take() {
+            let next_bundle = bundle_length(&data) == BundleLength::Complete
+                && data[0] == partial[0].wrapping_add(1);
+            if now.duration_since(started) > self.timeout || next_bundle {
+                debug!(
+                    "Discarding incomplete audio bundle {} ({} bytes)",
+                    partial[0],
+                    partial.len()
+                );
+                self.discarded += 1;
+            } else {
+                partial.extend_from_slice(&data);
+                return self.complete_or_hold(partial, started);
+            }
+        }
+
+        self.complete_or_hold(data, now)
+    }
+
+    /// Discard the partial bundle, if any, as when the device stops sending
+    pub fn clear(&mut self) {
+        if let Some((partial, _)) = self.partial.take() {
+            debug!(
+                "Discarding incomplete audio bundle {} ({} bytes)",
+                partial[0],
+                partial.len()
+            );
+            self.discarded += 1;
+        }
+    }
+
+    fn complete_or_hold(&mut self, buf: Vec<u8>, started: Instant) -> Option<Vec<u8>> {
+        match bundle_length(&buf) {
+            BundleLength::Incomplete => {
+                self.partial = Some((buf, started));
+                None
+            }
+            BundleLength::Complete | BundleLength::Invalid => Some(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bundle `index` with two frames of 4 and 3 bytes
+    fn bundle(index: u8) -> Vec<u8> {
+        vec![index, 2, 4, 1, 2, 3, 4, 3, 5, 6, 7]
+    }
+
+    #[test]
+    fn test_whole_bundle_passes_through() {
+        let mut assembler = BundleAssembler::new(Duration::from_millis(200));
+        assert_eq!(assembler.push(bundle(0), Instant::now()), Some(bundle(0)));
+    }
+
+    #[test]
+    fn test_split_bundle_is_reassembled() {
+        let mut assembler = BundleAssembler::new(Duration::from_millis(200));
+        let now = Instant::now();
+        let whole = bundle(7);
+
+        // Split inside a frame, then inside the header of the second frame
+        assert_eq!(assembler.push(whole[..5].to_vec(), now), None);
+        assert_eq!(assembler.push(whole[5..7].to_vec(), now), None);
+        assert_eq!(assembler.push(whole[7..].to_vec(), now), Some(whole));
+        assert_eq!(assembler.push(bundle(8), now), Some(bundle(8)));
+        assert_eq!(assembler.discarded(), 0);
+    }
+
+    #[test]
+    fn test_stale_partial_is_discarded() {
+        let mut assembler = BundleAssembler::new(Duration::from_millis(200));
+        let now = Instant::now();
+        let whole = bundle(3);
+
+        assert_eq!(assembler.push(whole[..4].to_vec(), now), None);
+        let later = now + Duration::from_millis(500);
+        // Not appended to the stale partial
+        assert_eq!(assembler.push(bundle(9), later), Some(bundle(9)));
+        assert_eq!(assembler.discarded(), 1);
+    }
+
+    #[test]
+    fn test_partial_dropped_when_next_bundle_arrives() {
+        let mut assembler = BundleAssembler::new(Duration::from_millis(200));
+        let now = Instant::now();
+
+        assert_eq!(assembler.push(bundle(3)[..6].to_vec(), now), None);
+        assert_eq!(assembler.push(bundle(4), now), Some(bundle(4)));
+        assert_eq!(assembler.discarded(), 1);
+    }
+
+    #[test]
+    fn test_disabled_passes_fragments_through() {
+        let mut assembler = BundleAssembler::new(Duration::ZERO);
+        let fragment = bundle(1)[..4].to_vec();
+        assert_eq!(assembler.push(fragment.clone(), Instant::now()), Some(fragment));
+    }
+}
@@ -1,5 +1,17 @@
 pub mod ble;
+pub mod capture;
+pub mod codec;
 pub mod decoder;
+pub mod fragment;
+pub mod ogg;
+pub mod reorder;
+pub mod startup;
+pub mod streams;
 
-pub use ble::BleAudioReceiver;
+pub use ble::{BleAudioReceiver, RateLimit, RecordingEvent};
+pub use codec::AudioDecoder;
 pub use decoder::OpusDecoder;
+pub use fragment::BundleAssembler;
+pub use reorder::ReorderBuffer;
+pub use startup::StartupBuffer;
+pub use streams::AudioStreams;
@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use audiopus::coder::{Decoder, Encoder};
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use std::io::Write;
+use std::path::Path;
+
+/// Samples per 20ms Opus frame at 16kHz
+const FRAME_SAMPLES: usize = 320;
+
+/// Ogg Opus granule positions always count 48kHz samples
+const GRANULE_RATE: u32 = 48000;
+
+/// Encoder lookahead at 48kHz that players skip at the start (libopus
+/// reports 312 for 16kHz VoIP encoding)
+const PRE_SKIP: u16 = 312;
+
+/// Opus packets per Ogg page: one second of audio, well under the 255
+/// lacing values a page can hold for packets of this size
+const PACKETS_PER_PAGE: usize = 50;
+
+/// Bitrate for re-encoded speech; ~3KB per second of audio
+const BITRATE: i32 = 24_000;
+
+const STREAM_SERIAL: u32 = 0x6d656d6f; // "memo"
+
+/// Ogg page CRC: polynomial 0x04c11db7, no reflection, zero init and xorout
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Append one Ogg page holding `packets` to `out`
+fn write_page(out: &mut Vec<u8>, header_type: u8, granule: u64, sequence: u32, packets: &[Vec<u8>]) {
+    let mut lacing = Vec::new();
+    for packet in packets {
+        lacing.resize(lacing.len() + packet.len() / 255, 255);
+        lacing.push((packet.len() % 255) as u8);
+    }
+    debug_assert!(lacing.len() <= 255, "too many segments for one page");
+
+    let start = out.len();
+    out.extend_from_slice(b"OggS");
+    out.push(0); // version
+    out.push(header_type);
+    out.extend_from_slice(&granule.to_le_bytes());
+    out.extend_from_slice(&STREAM_SERIAL.to_le_bytes());
+    out.extend_from_slice(&sequence.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // CRC, filled in below
+    out.push(lacing.len() as u8);
+    out.extend_from_slice(&lacing);
+    for packet in packets {
+        out.extend_from_slice(packet);
+    }
+
+    let crc = crc32(&out[start..]);
+    out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Encode mono 16kHz PCM as Opus and write it as an Ogg Opus file
+///
+/// The last frame is padded with silence; the final granule position marks
+/// where the real audio ends, so `read_ogg_opus` returns the original length.
+pub fn write_ogg_opus(path: &Path, samples: &[i16], sample_rate: u32) -> Result<()> {
+    if sample_rate != 16000 {
+        anyhow::bail!("Opus recordings must be 16kHz, got {}Hz", sample_rate);
+    }
+
+    let mut encoder = Encoder::new(SampleRate::Hz16000, Channels::Mono, Application::Voip)
+        .context("Failed to create Opus encoder")?;
+    encoder
+        .set_bitrate(Bitrate::BitsPerSecond(BITRATE))
+        .context("Failed to set Opus bitrate")?;
+
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(1); // channels
+    head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+
+    let vendor = b"memo-node";
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes()); // user comments
+
+    let mut out = Vec::new();
+    write_page(&mut out, 0x02, 0, 0, &[head]);
+    write_page(&mut out, 0x00, 0, 1, &[tags]);
+
+    let scale = (GRANULE_RATE / sample_rate) as u64;
+    let frames: Vec<&[i16]> = samples.chunks(FRAME_SAMPLES).collect();
+    let pages = frames.len().div_ceil(PACKETS_PER_PAGE).max(1);
+    let mut encoded = [0u8; 1500];
+    let mut frame = [0i16; FRAME_SAMPLES];
+    let mut samples_written = 0u64;
+
+    for page in 0..pages {
+        let mut packets = Vec::new();
+        for chunk in frames.iter().skip(page * PACKETS_PER_PAGE).take(PACKETS_PER_PAGE) {
+            frame[..chunk.len()].copy_from_slice(chunk);
+            frame[chunk.len()..].fill(0);
+            let len = encoder
+                .encode(&frame[..], &mut encoded[..])
+                .context("Failed to encode Opus frame")?;
+            packets.push(encoded[..len].to_vec());
+            samples_written += chunk.len() as u64;
+        }
+
+        let last = page + 1 == pages;
+        let granule = PRE_SKIP as u64 + samples_written * scale;
+        write_page(&mut out, if last { 0x04 } else { 0x00 }, granule, page as u32 + 2, &packets);
+    }
+
+    std::fs::File::create(path)
+        .and_then(|mut file| file.write_all(&out))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Read an Ogg Opus file written by `write_ogg_opus`, returning 16kHz mono
+/// samples and the sample rate
+pub fn read_ogg_opus(path: &Path) -> Result<(Vec<i16>, u32)> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    // Reassemble packets from the pages' lacing values
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut partial = Vec::new();
+    let mut last_granule = 0u64;
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = data
+            .get(offset..offset + 27)
+            .filter(|header| &header[..4] == b"OggS")
+            .with_context(|| format!("{} has no Ogg page at byte {}", path.display(), offset))?;
+        let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[22..26].try_into().unwrap());
+        let segments = header[26] as usize;
+        let lacing = data
+            .get(offset + 27..offset + 27 + segments)
+            .with_context(|| format!("Truncated Ogg page in {}", path.display()))?;
+        let body_len: usize = lacing.iter().map(|&len| len as usize).sum();
+        let page_end = offset + 27 + segments + body_len;
+        let page = data
+            .get(offset..page_end)
+            .with_context(|| format!("Truncated Ogg page in {}", path.display()))?;
+
+        let mut unsigned = page.to_vec();
+        unsigned[22..26].fill(0);
+        if crc32(&unsigned) != crc {
+            anyhow::bail!("Corrupt Ogg page at byte {} of {}", offset, path.display());
+        }
+
+        let mut body = offset + 27 + segments;
+        for &len in lacing {
+            partial.extend_from_slice(&data[body..body + len as usize]);
+            body += len as usize;
+            if len < 255 {
+                packets.push(std::mem::take(&mut partial));
+            }
+        }
+        // -1 marks a page on which no packet ends
+        if granule != u64::MAX {
+            last_granule = granule;
+        }
+        offset = page_end;
+    }
+
+    let mut packets = packets.into_iter();
+    let head = packets
+        .next()
+        .filter(|head| head.len() >= 19 && head.starts_with(b"OpusHead"))
+        .with_context(|| format!("{} is not an Ogg Opus file", path.display()))?;
+    if head[9] != 1 {
+        anyhow::bail!("{} has {} channels; only mono is supported", path.display(), head[9]);
+    }
+    let pre_skip = u16::from_le_bytes([head[10], head[11]]) as u64;
+    packets.next(); // OpusTags
+
+    let mut decoder =
+        Decoder::new(SampleRate::Hz16000, Channels::Mono).context("Failed to create Opus decoder")?;
+    let scale = (GRANULE_RATE / 16000) as u64;
+    // Longest Opus packet is 120ms
+    let mut scratch = vec![0i16; 16000 * 120 / 1000];
+    let mut samples = Vec::new();
+    for packet in packets {
+        let decoded = decoder
+            .decode(Some(packet.as_slice()), &mut scratch, false)
+            .with_context(|| format!("Failed to decode Opus packet in {}", path.display()))?;
+        samples.extend_from_slice(&scratch[..decoded]);
+    }
+
+    let skip = (pre_skip / scale) as usize;
+    let len = (last_granule.saturating_sub(pre_skip) / scale) as usize;
+    let end = (skip + len).min(samples.len());
+    Ok((samples.get(skip..end).unwrap_or_default().to_vec(), 16000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_matches_ogg_polynomial() {
+        // CRC-32/CKSUM check value without its final inversion
+        assert_eq!(crc32(b"123456789"), !0x765e_7680);
+    }
+
+    #[test]
+    fn test_ogg_opus_roundtrip_keeps_length() {
+        let path = std::env::temp_dir().join(format!("memo-node-{}.opus", uuid::Uuid::new_v4()));
+        // 1.23s of a 440Hz tone, not a whole number of frames or pages
+        let samples: Vec<i16> = (0..19_680)
+            .map(|n| ((n as f32 * 440.0 * std::f32::consts::TAU / 16000.0).sin() * 8000.0) as i16)
+            .collect();
+
+        write_ogg_opus(&path, &samples, 16000).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len() as usize;
+        assert!(size < samples.len() * 2 / 4, "{} bytes is not much smaller than PCM", size);
+
+        let (decoded, sample_rate) = read_ogg_opus(&path).unwrap();
+        assert_eq!(sample_rate, 16000);
+        assert_eq!(decoded.len(), samples.len());
+        assert!(decoded.iter().any(|&sample| sample.unsigned_abs() > 1000));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Releases BLE audio bundles in `bundle_index` order
+///
+/// BLE notifications can arrive slightly out of order. Bundles that arrive
+/// ahead of the next expected index are held until the gap fills; if more
+/// than `window` bundles are waiting, the missing ones are given up on and
+/// playback skips ahead. Bundles that arrive after their slot was released
+/// or skipped are dropped. A `window` of 0 disables reordering.
+///
+/// Indices wrap from 255 to 0, and the device restarts them at 0 when it
+/// reconnects. A bundle behind the next expected index that is neither within
+/// `window` of it nor one of the last skipped bundles can't be a straggler, so
+/// it is taken as such a reset: whatever is held is released and ordering
+/// restarts from the new index.
+pub struct ReorderBuffer {
+    window: usize,
+    next_index: Option<u8>,
+    pending: HashMap<u8, Vec<u8>>,
+    /// First index and length of the most recent run of skipped bundles
+    skipped: Option<(u8, u8)>,
+}
+
+impl ReorderBuffer {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            next_index: None,
+            pending: HashMap::new(),
+            skipped: None,
+        }
+    }
+
+    /// Add a bundle, returning any bundles that are now ready, in order
+    pub fn push(&mut self, bundle: Vec<u8>) -> Vec<Vec<u8>> {
+        if self.window == 0 || bundle.is_empty() {
+            return vec![bundle];
+        }
+
+        let index = bundle[0];
+        let next = *self.next_index.get_or_insert(index);
+
+        // Indices wrap at 255, so compare by wrapping distance: anything in
+        // the "back half" of the ring is behind the next expected index
+        let mut released = Vec::new();
+        let behind = next.wrapping_sub(index);
+        if behind > 0 && behind <= 128 {
+            if usize::from(behind) <= self.window || self.was_skipped(index) {
+                debug!("Dropping late audio bundle {} (expected {})", index, next);
+                return Vec::new();
+            }
+            debug!("Audio bundle index reset to {} (expected {}), restarting", index, next);
+            released = self.flush();
+            self.next_index = Some(index);
+        }
+
+        self.pending.insert(index, bundle);
+        released.extend(self.release_ready());
+
+        if self.pending.len() > self.window {
+            let oldest = self.oldest_pending().expect("pending is non-empty");
+            let next = self.next_index.unwrap_or(oldest);
+            debug!("Audio bundle(s) {}..{} missing, skipping ahead", next, oldest);
+            self.skipped = Some((next, oldest.wrapping_sub(next)));
+            self.next_index = Some(oldest);
+            released.extend(self.release_ready());
+        }
+
+        released
+    }
+
+    /// Release everything still held, in order, and start fresh
+    pub fn flush(&mut self) -> Vec<Vec<u8>> {
+        let mut released = Vec::with_capacity(self.pending.len());
+        while let Some(oldest) = self.oldest_pending() {
+            self.next_index = Some(oldest);
+            released.extend(self.release_ready());
+        }
+        self.next_index = None;
+        self.skipped = None;
+        released
+    }
+
+    fn was_skipped(&self, index: u8) -> bool {
+        self.skipped
+            .is_some_and(|(start, len)| index.wrapping_sub(start) < len)
+    }
+
+    fn oldest_pending(&self) -> Option<u8> {
+        let next = self.next_index?;
+        self.pending
+            .keys()
+            .copied()
+            .min_by_key(|index| index.wrapping_sub(next))
+    }
+
+    fn release_ready(&mut self) -> Vec<Vec<u8>> {
+        let mut released = Vec::new();
+        while let Some(next) = self.next_index {
+            match self.pending.remove(&next) {
+                Some(bundle) => {
+                    released.push(bundle);
+                    self.next_index = Some(next.wrapping_add(1));
+                }
+                None => break,
+            }
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_all(buffer: &mut ReorderBuffer, indices: &[u8]) -> Vec<u8> {
+        indices
+            .iter()
+            .flat_map(|&i| buffer.push(vec![i, 1, 0]))
+            .map(|bundle| bundle[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_shuffled_bundles_released_in_order() {
+        let mut buffer = ReorderBuffer::new(4);
+        assert_eq!(push_all(&mut buffer, &[0, 2, 1, 3, 5, 4, 6]), vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_missing_bundle_skipped_and_late_bundle_dropped() {
+        let mut buffer = ReorderBuffer::new(3);
+        assert_eq!(push_all(&mut buffer, &[0, 1, 3, 4, 5, 6, 7, 2]), vec![0, 1, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_flush_releases_pending_in_order() {
+        let mut buffer = ReorderBuffer::new(4);
+        assert_eq!(push_all(&mut buffer, &[0, 3, 2]), vec![0]);
+        let flushed: Vec<u8> = buffer.flush().into_iter().map(|b| b[0]).collect();
+        assert_eq!(flushed, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_wrap_from_255_to_0() {
+        let mut buffer = ReorderBuffer::new(4);
+        assert_eq!(
+            push_all(&mut buffer, &[253, 254, 0, 255, 1, 2]),
+            vec![253, 254, 255, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_reset_to_0_mid_stream() {
+        let mut buffer = ReorderBuffer::new(4);
+        // Held bundle 103 is released before the restarted stream
+        assert_eq!(
+            push_all(&mut buffer, &[100, 101, 103, 0, 1, 3, 2]),
+            vec![100, 101, 103, 0, 1, 2, 3]
+        );
+        // A straggler within the window is still just late
+        assert_eq!(push_all(&mut buffer, &[4, 1, 5]), vec![4, 5]);
+    }
+
+    #[test]
+    fn test_disabled_passes_through() {
+        let mut buffer = ReorderBuffer::new(0);
+        assert_eq!(push_all(&mut buffer, &[3, 1, 2]), vec![3, 1, 2]);
+    }
+}
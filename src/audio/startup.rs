@@ -0,0 +1,154 @@
+use crate::transcribe::AudioSource;
+use std::collections::VecDeque;
+
+/// Decoded audio held while the transcription model loads
+///
+/// Keeps at most `max_samples` of the most recent audio; older chunks are
+/// dropped and counted so the loss can be logged once the model is ready.
+/// Recordings that end while buffered, and the audio of each source within
+/// a recording, are kept apart, so each can be transcribed on its own.
+pub struct StartupBuffer {
+    /// Chunks, oldest first, with the number of the recording they belong
+    /// to and where they came from
+    chunks: VecDeque<(usize, AudioSource, Vec<i16>)>,
+    /// Number of the recording in progress
+    recording: usize,
+    samples: usize,
+    max_samples: usize,
+    dropped_samples: usize,
+}
+
+impl StartupBuffer {
+    pub fn new(max_samples: usize) -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            recording: 0,
+            samples: 0,
+            max_samples,
+            dropped_samples: 0,
+        }
+    }
+
+    pub fn push(&mut self, source: AudioSource, chunk: Vec<i16>) {
+        self.samples += chunk.len();
+        self.chunks.push_back((self.recording, source, chunk));
+
+        while self.samples > self.max_samples {
+            let Some((_, _, oldest)) = self.chunks.pop_front() else {
+                break;
+            };
+            self.samples -= oldest.len();
+            self.dropped_samples += oldest.len();
+        }
+    }
+
+    pub fn buffered_samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn dropped_samples(&self) -> usize {
+        self.dropped_samples
+    }
+
+    /// End the recording in progress; audio pushed after this starts a
+    /// new one
+    pub fn end_recording(&mut self) {
+        if self.chunks.back().is_some_and(|(recording, _, _)| *recording == self.recording) {
+            self.recording += 1;
+        }
+    }
+
+    /// The buffered recordings that ended, oldest first, one per source,
+    /// and the audio of each source in the recording still in progress
+    pub fn into_recordings(self) -> (Vec<(AudioSource, Vec<i16>)>, Vec<(AudioSource, Vec<i16>)>) {
+        let in_progress = self.recording;
+        let mut recordings: Vec<(usize, AudioSource, Vec<i16>)> = Vec::new();
+        for (recording, source, chunk) in self.chunks {
+            let existing = recordings
+                .iter_mut()
+                .find(|(r, s, _)| *r == recording && *s == source);
+            match existing {
+                Some((_, _, audio)) => audio.extend_from_slice(&chunk),
+                None => recordings.push((recording, source, chunk)),
+            }
+        }
+
+        let (current, ended): (Vec<_>, Vec<_>) = recordings
+            .into_iter()
+            .partition(|(recording, _, _)| *recording == in_progress);
+        let by_source = |recordings: Vec<(usize, AudioSource, Vec<i16>)>| -> Vec<_> {
+            recordings.into_iter().map(|(_, source, audio)| (source, audio)).collect()
+        };
+        (by_source(ended), by_source(current))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(device: &str) -> AudioSource {
+        AudioSource {
+            device: device.to_string(),
+            stream: 0,
+        }
+    }
+
+    #[test]
+    fn test_keeps_most_recent_audio() {
+        let mut buffer = StartupBuffer::new(5);
+        buffer.push(source("memo-1"), vec![1, 1]);
+        buffer.push(source("memo-1"), vec![2, 2]);
+        buffer.push(source("memo-1"), vec![3, 3]);
+
+        assert_eq!(buffer.buffered_samples(), 4);
+        assert_eq!(buffer.dropped_samples(), 2);
+        assert_eq!(buffer.into_recordings(), (vec![], vec![(source("memo-1"), vec![2, 2, 3, 3])]));
+    }
+
+    #[test]
+    fn test_zero_capacity_drops_everything() {
+        let mut buffer = StartupBuffer::new(0);
+        buffer.push(source("memo-1"), vec![1, 2, 3]);
+
+        assert_eq!(buffer.buffered_samples(), 0);
+        assert_eq!(buffer.dropped_samples(), 3);
+    }
+
+    #[test]
+    fn test_recordings_that_end_are_kept_apart() {
+        let mut buffer = StartupBuffer::new(100);
+        buffer.end_recording(); // nothing recorded yet
+        buffer.push(source("memo-1"), vec![1, 1]);
+        buffer.push(source("memo-1"), vec![1]);
+        buffer.end_recording();
+        buffer.end_recording();
+        buffer.push(source("memo-1"), vec![2, 2]);
+        buffer.end_recording();
+
+        let ended = vec![(source("memo-1"), vec![1, 1, 1]), (source("memo-1"), vec![2, 2])];
+        assert_eq!(buffer.into_recordings(), (ended, vec![]));
+
+        let mut buffer = StartupBuffer::new(3);
+        buffer.push(source("memo-1"), vec![1, 1]);
+        buffer.end_recording();
+        buffer.push(source("memo-1"), vec![2, 2]);
+        buffer.push(source("memo-1"), vec![3]);
+
+        // The first recording was dropped whole; the second is still going
+        assert_eq!(buffer.into_recordings(), (vec![], vec![(source("memo-1"), vec![2, 2, 3])]));
+    }
+
+    #[test]
+    fn test_sources_are_kept_apart() {
+        let mut buffer = StartupBuffer::new(100);
+        buffer.push(source("memo-1"), vec![1]);
+        buffer.push(source("memo-2"), vec![2]);
+        buffer.push(source("memo-1"), vec![1]);
+        buffer.end_recording();
+        buffer.push(source("memo-2"), vec![3]);
+
+        let ended = vec![(source("memo-1"), vec![1, 1]), (source("memo-2"), vec![2])];
+        assert_eq!(buffer.into_recordings(), (ended, vec![(source("memo-2"), vec![3])]));
+    }
+}
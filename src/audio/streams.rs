@@ -0,0 +1,427 @@
+use super::ble::AudioPacket;
+use super::{AudioDecoder, BundleAssembler, ReorderBuffer, StartupBuffer};
+use crate::config::AudioConfig;
+use crate::metrics::Metrics;
+use crate::transcribe::{AudioSource, DecodedAudio, SAMPLE_RATE};
+use anyhow::Result;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info, warn};
+
+/// How often the recording state is checked while no audio arrives
+const RECORDING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Decoder state of one device stream
+struct Stream {
+    decoder: AudioDecoder,
+    assembler: BundleAssembler,
+    reorder: ReorderBuffer,
+}
+
+/// Decodes the BLE audio packets of every device stream for the transcriber
+///
+/// Audio only arrives while recording, so it starts flowing while the model
+/// is still downloading or loading; up to `with_startup_buffer` samples of
+/// it are held until the transcriber is ready. Recordings that end in the
+/// meantime are then handed over whole, one `DecodedAudio::Recording` each.
+///
+/// When recording stops, every stream's out-of-order and partial bundles
+/// are settled before `DecodedAudio::Stopped` marks the end of the
+/// recording, so its tail isn't lost or carried into the next one.
+pub struct AudioStreams {
+    config: AudioConfig,
+    is_recording: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    streams: HashMap<AudioSource, Stream>,
+    startup_buffer_samples: usize,
+}
+
+impl AudioStreams {
+    /// Fails if the codec settings in `config` are invalid
+    pub fn new(
+        config: AudioConfig,
+        is_recording: Arc<AtomicBool>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self> {
+        // Each device stream gets its own decoder state; build one up front
+        // to validate the codec settings
+        AudioDecoder::new(&config)?;
+        Ok(Self {
+            config,
+            is_recording,
+            metrics,
+            streams: HashMap::new(),
+            startup_buffer_samples: 0,
+        })
+    }
+
+    /// Hold up to `samples` of audio decoded before the transcriber is
+    /// ready, dropping the oldest beyond that
+    pub fn with_startup_buffer(mut self, samples: usize) -> Self {
+        self.startup_buffer_samples = samples;
+        self
+    }
+
+    /// Decode packets from `audio_rx` onto `decoded_tx` until `audio_rx`
+    /// closes, holding the audio until `transcriber_ready` fires
+    pub async fn run(
+        mut self,
+        mut audio_rx: mpsc::UnboundedReceiver<AudioPacket>,
+        decoded_tx: mpsc::UnboundedSender<DecodedAudio>,
+        mut transcriber_ready: oneshot::Receiver<()>,
+    ) {
+        let mut startup_buffer = Some(StartupBuffer::new(self.startup_buffer_samples));
+        let mut was_recording = self.is_recording.load(Ordering::Acquire);
+
+        loop {
+            let recording = self.is_recording.load(Ordering::Acquire);
+            if was_recording && !recording {
+                self.recording_stopped(startup_buffer.as_mut(), &decoded_tx);
+            }
+            was_recording = recording;
+
+            tokio::select! {
+                _ = &mut transcriber_ready, if startup_buffer.is_some() => {
+                    let mut buffer = startup_buffer.take().expect("checked above");
+                    if was_recording && !self.is_recording.load(Ordering::Acquire) {
+                        self.recording_stopped(Some(&mut buffer), &decoded_tx);
+                        was_recording = false;
+                    }
+                    self.release_startup_buffer(buffer, &decoded_tx);
+                }
+                packet = audio_rx.recv() => {
+                    let Some(packet) = packet else {
+                        break;
+                    };
+
+                    // Only decode if we're recording
+                    if !self.is_recording.load(Ordering::Acquire) {
+                        continue;
+                    }
+
+                    let source = AudioSource {
+                        device: packet.device,
+                        stream: packet.stream,
+                    };
+                    for samples in self.decode(&source, packet.data) {
+                        match startup_buffer.as_mut() {
+                            Some(buffer) => buffer.push(source.clone(), samples),
+                            None => send(
+                                &decoded_tx,
+                                DecodedAudio::Samples {
+                                    source: source.clone(),
+                                    samples,
+                                },
+                            ),
+                        }
+                    }
+                }
+                // Wakes the loop to notice a recording that stopped
+                _ = tokio::time::sleep(RECORDING_POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Decode one packet from `source`, returning the audio it completes,
+    /// if any
+    fn decode(&mut self, source: &AudioSource, data: Vec<u8>) -> Vec<Vec<i16>> {
+        let stream = match self.streams.entry(source.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match AudioDecoder::new(&self.config) {
+                Ok(decoder) => entry.insert(Stream {
+                    decoder,
+                    assembler: BundleAssembler::new(Duration::from_millis(
+                        self.config.fragment_timeout_ms,
+                    )),
+                    reorder: ReorderBuffer::new(self.config.reorder_window),
+                }),
+                Err(e) => {
+                    error!("Failed to create audio decoder for {:?}: {:#}", entry.key(), e);
+                    return Vec::new();
+                }
+            },
+        };
+
+        // Raw PCM packets, and bundles without a bundle_index, have
+        // nothing to reorder on nor bundles to reassemble
+        stream.decoder.detect_layout(&data);
+        let bundles = if stream.decoder.has_bundle_index() {
+            // Bundles split across notifications are rebuilt first;
+            // incomplete ones that are given up on count as failed
+            let discarded = stream.assembler.discarded();
+            let bundle = stream.assembler.push(data, Instant::now());
+            Metrics::add(
+                &self.metrics.audio_decode_failed,
+                stream.assembler.discarded() - discarded,
+            );
+            let Some(bundle) = bundle else {
+                return Vec::new();
+            };
+
+            // Release bundles in bundle_index order
+            stream.reorder.push(bundle)
+        } else {
+            vec![data]
+        };
+
+        decode_bundles(&mut stream.decoder, bundles, &self.metrics)
+    }
+
+    /// End the recording: decode the bundles still held for reordering,
+    /// give up on partial ones, and mark the end
+    fn recording_stopped(
+        &mut self,
+        startup_buffer: Option<&mut StartupBuffer>,
+        decoded_tx: &mpsc::UnboundedSender<DecodedAudio>,
+    ) {
+        let mut tail = Vec::new();
+        for (source, stream) in self.streams.iter_mut() {
+            let discarded = stream.assembler.discarded();
+            stream.assembler.clear();
+            Metrics::add(
+                &self.metrics.audio_decode_failed,
+                stream.assembler.discarded() - discarded,
+            );
+            let bundles = stream.reorder.flush();
+            for samples in decode_bundles(&mut stream.decoder, bundles, &self.metrics) {
+                tail.push((source.clone(), samples));
+            }
+        }
+
+        match startup_buffer {
+            Some(buffer) => {
+                for (source, samples) in tail {
+                    buffer.push(source, samples);
+                }
+                buffer.end_recording();
+            }
+            None => {
+                for (source, samples) in tail {
+                    send(decoded_tx, DecodedAudio::Samples { source, samples });
+                }
+                send(decoded_tx, DecodedAudio::Stopped);
+            }
+        }
+    }
+
+    /// Log what happened to the audio held during startup and hand it over
+    fn release_startup_buffer(
+        &self,
+        buffer: StartupBuffer,
+        decoded_tx: &mpsc::UnboundedSender<DecodedAudio>,
+    ) {
+        if buffer.dropped_samples() > 0 {
+            warn!(
+                "Dropped {:.1}s of audio captured while the model was loading (transcription.startup_buffer_secs = {})",
+                buffer.dropped_samples() as f64 / SAMPLE_RATE as f64,
+                self.startup_buffer_samples / SAMPLE_RATE as usize
+            );
+        }
+        if buffer.buffered_samples() > 0 {
+            info!(
+                "Transcriber ready, releasing {:.1}s of audio captured during startup",
+                buffer.buffered_samples() as f64 / SAMPLE_RATE as f64
+            );
+        }
+
+        let (ended, current) = buffer.into_recordings();
+        for (source, samples) in ended {
+            send(decoded_tx, DecodedAudio::Recording { source, samples });
+        }
+        for (source, samples) in current {
+            send(decoded_tx, DecodedAudio::Samples { source, samples });
+        }
+    }
+}
+
+fn decode_bundles(
+    decoder: &mut AudioDecoder,
+    bundles: Vec<Vec<u8>>,
+    metrics: &Metrics,
+) -> Vec<Vec<i16>> {
+    let mut decoded_bundles = Vec::with_capacity(bundles.len());
+    for bundle in bundles {
+        match decoder.decode(&bundle) {
+            Ok(decoded) if decoded.is_empty() => {
+                Metrics::add(&metrics.audio_decode_failed, 1);
+            }
+            Ok(decoded) => {
+                Metrics::add(&metrics.audio_decoded, 1);
+                decoded_bundles.push(decoded);
+            }
+            Err(e) => {
+                // Only log decode errors at debug level to reduce noise
+                debug!("Failed to decode audio: {}", e);
+                Metrics::add(&metrics.audio_decode_failed, 1);
+            }
+        }
+    }
+    decoded_bundles
+}
+
+fn send(decoded_tx: &mpsc::UnboundedSender<DecodedAudio>, audio: DecodedAudio) {
+    if let Err(e) = decoded_tx.send(audio) {
+        error!("Failed to send decoded audio: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm_config() -> AudioConfig {
+        toml::from_str(
+            r#"
+            memo_service_uuid = ""
+            memo_characteristic_uuid = ""
+            codec = "pcm_s16le"
+            "#,
+        )
+        .unwrap()
+    }
+
+    /// `samples` samples of raw 16kHz PCM from `device`
+    fn pcm_packet(device: &str, samples: usize) -> AudioPacket {
+        AudioPacket {
+            device: device.to_string(),
+            stream: 0,
+            data: vec![0; samples * 2],
+        }
+    }
+
+    /// Memo bundle `index` holding one 20ms Opus frame
+    fn opus_bundle(index: u8) -> Vec<u8> {
+        let encoder = audiopus::coder::Encoder::new(
+            audiopus::SampleRate::Hz16000,
+            audiopus::Channels::Mono,
+            audiopus::Application::Voip,
+        )
+        .unwrap();
+        let tone: Vec<i16> = (0..320).map(|n| ((n as f32 * 0.3).sin() * 8000.0) as i16).collect();
+        let mut frame = [0u8; 400];
+        let len = encoder.encode(&tone[..], &mut frame[..]).unwrap();
+        let mut bundle = vec![index, 1, len as u8];
+        bundle.extend_from_slice(&frame[..len]);
+        bundle
+    }
+
+    fn summary(audio: &DecodedAudio) -> String {
+        match audio {
+            DecodedAudio::Samples { source, samples } => {
+                format!("{} samples from {}", samples.len(), source.device)
+            }
+            DecodedAudio::Recording { source, samples } => {
+                format!("recording of {} from {}", samples.len(), source.device)
+            }
+            DecodedAudio::Stopped => "stopped".to_string(),
+        }
+    }
+
+    /// Long enough for the decoder to notice a change of recording state
+    async fn settle() {
+        tokio::time::sleep(RECORDING_POLL_INTERVAL * 3).await;
+    }
+
+    #[tokio::test]
+    async fn test_recording_that_stops_before_the_transcriber_is_ready_is_kept_whole() {
+        let is_recording = Arc::new(AtomicBool::new(true));
+        let streams = AudioStreams::new(pcm_config(), is_recording.clone(), Arc::default())
+            .unwrap()
+            .with_startup_buffer(SAMPLE_RATE as usize);
+        let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (decoded_tx, mut decoded_rx) = mpsc::unbounded_channel();
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let task = tokio::spawn(streams.run(audio_rx, decoded_tx, ready_rx));
+
+        // Two recordings end while the model loads, and a third is going
+        // when it's ready
+        for samples in [300, 200] {
+            is_recording.store(true, Ordering::Release);
+            audio_tx.send(pcm_packet("memo-1", samples)).unwrap();
+            audio_tx.send(pcm_packet("memo-1", 100)).unwrap();
+            settle().await;
+            is_recording.store(false, Ordering::Release);
+            settle().await;
+        }
+        is_recording.store(true, Ordering::Release);
+        audio_tx.send(pcm_packet("memo-1", 50)).unwrap();
+        settle().await;
+        ready_tx.send(()).unwrap();
+        settle().await;
+        audio_tx.send(pcm_packet("memo-1", 25)).unwrap();
+        drop(audio_tx);
+        task.await.unwrap();
+
+        let mut decoded = Vec::new();
+        while let Some(audio) = decoded_rx.recv().await {
+            decoded.push(summary(&audio));
+        }
+        assert_eq!(
+            decoded,
+            [
+                "recording of 400 from memo-1",
+                "recording of 300 from memo-1",
+                "50 samples from memo-1",
+                "25 samples from memo-1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_held_bundles_are_flushed_when_recording_stops() {
+        let is_recording = Arc::new(AtomicBool::new(true));
+        let metrics = Arc::new(Metrics::default());
+        let config = toml::from_str(
+            r#"
+            memo_service_uuid = ""
+            memo_characteristic_uuid = ""
+            "#,
+        )
+        .unwrap();
+        let streams = AudioStreams::new(config, is_recording.clone(), metrics.clone()).unwrap();
+        let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (decoded_tx, mut decoded_rx) = mpsc::unbounded_channel();
+        let (ready_tx, ready_rx) = oneshot::channel();
+        ready_tx.send(()).unwrap();
+        let task = tokio::spawn(streams.run(audio_rx, decoded_tx, ready_rx));
+        let packet = |data: Vec<u8>| AudioPacket {
+            device: "memo-1".to_string(),
+            stream: 0,
+            data,
+        };
+
+        // Bundle 2 waits for the lost bundle 1, and bundle 3 for the rest
+        // of its fragments, when recording stops
+        audio_tx.send(packet(opus_bundle(0))).unwrap();
+        audio_tx.send(packet(opus_bundle(2))).unwrap();
+        audio_tx.send(packet(opus_bundle(3)[..3].to_vec())).unwrap();
+        settle().await;
+        is_recording.store(false, Ordering::Release);
+        settle().await;
+
+        // The next recording starts afresh at the device's new index
+        is_recording.store(true, Ordering::Release);
+        audio_tx.send(packet(opus_bundle(9))).unwrap();
+        drop(audio_tx);
+        task.await.unwrap();
+
+        let mut decoded = Vec::new();
+        while let Some(audio) = decoded_rx.recv().await {
+            decoded.push(summary(&audio));
+        }
+        assert_eq!(
+            decoded,
+            [
+                "320 samples from memo-1",
+                "320 samples from memo-1",
+                "stopped",
+                "320 samples from memo-1",
+            ]
+        );
+        assert_eq!(metrics.audio_decode_failed.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Name of the main database inside a backup, whatever it is called on disk
+const MAIN_DATABASE: &str = "transcriptions.db";
+const DATABASE_DIR: &str = "database";
+const CONFIG_FILE: &str = "config.toml";
+
+/// What a backup archive contained
+#[derive(Debug, Default)]
+pub struct BackupContents {
+    pub databases: Vec<String>,
+    pub config: bool,
+}
+
+/// Write a tar archive holding a consistent copy of every database file
+/// (under `database/`) and, if given, the user config file (`config.toml`)
+pub fn create_backup(
+    storage: &Storage,
+    config_file: Option<&Path>,
+    output: &Path,
+) -> Result<BackupContents> {
+    let staging = std::env::temp_dir().join(format!("memo-node-backup-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&staging).context("Failed to create staging directory")?;
+
+    let result = write_archive(storage, config_file, output, &staging);
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+fn write_archive(
+    storage: &Storage,
+    config_file: Option<&Path>,
+    output: &Path,
+    staging: &Path,
+) -> Result<BackupContents> {
+    let databases = storage.backup_to(staging, MAIN_DATABASE)?;
+
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut archive = tar::Builder::new(file);
+
+    for name in &databases {
+        archive
+            .append_path_with_name(staging.join(name), format!("{}/{}", DATABASE_DIR, name))
+            .with_context(|| format!("Failed to add {} to backup", name))?;
+    }
+
+    let config = match config_file {
+        Some(path) => {
+            archive
+                .append_path_with_name(path, CONFIG_FILE)
+                .with_context(|| format!("Failed to add {} to backup", path.display()))?;
+            true
+        }
+        None => false,
+    };
+
+    archive
+        .into_inner()
+        .context("Failed to finish backup archive")?
+        .sync_all()
+        .context("Failed to flush backup archive")?;
+
+    Ok(BackupContents { databases, config })
+}
+
+/// List what a backup archive would restore
+pub fn read_backup(input: &Path) -> Result<BackupContents> {
+    let mut contents = BackupContents::default();
+    for_each_entry(input, |kind| {
+        match kind {
+            Entry::Database(name) => contents.databases.push(name),
+            Entry::Config => contents.config = true,
+        }
+        Ok(None)
+    })?;
+    Ok(contents)
+}
+
+/// Unpack a backup, putting the main database at `storage_path`, day files
+/// next to it and the config at `config_path`. Existing files are replaced.
+pub fn restore_backup(input: &Path, storage_path: &Path, config_path: &Path) -> Result<BackupContents> {
+    let storage_dir = storage_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    let mut contents = BackupContents::default();
+    for_each_entry(input, |kind| {
+        let target = match &kind {
+            Entry::Database(name) if name == MAIN_DATABASE => storage_path.to_path_buf(),
+            Entry::Database(name) => storage_dir.join(name),
+            Entry::Config => config_path.to_path_buf(),
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        match kind {
+            Entry::Database(name) => {
+                // A stale WAL would be replayed over the restored file
+                for extension in ["-wal", "-shm"] {
+                    let mut sidecar = target.as_os_str().to_owned();
+                    sidecar.push(extension);
+                    let _ = std::fs::remove_file(PathBuf::from(sidecar));
+                }
+                contents.databases.push(name);
+            }
+            Entry::Config => contents.config = true,
+        }
+
+        Ok(Some(target))
+    })?;
+
+    Ok(contents)
+}
+
+enum Entry {
+    Database(String),
+    Config,
+}
+
+/// Walk the archive, calling `f` for each recognised entry. If `f` returns a
+/// path, the entry is unpacked there.
+fn for_each_entry(
+    input: &Path,
+    mut f: impl FnMut(Entry) -> Result<Option<PathBuf>>,
+) -> Result<()> {
+    let file = File::open(input).with_context(|| format!("Failed to open {}", input.display()))?;
+    let mut archive = tar::Archive::new(file);
+
+    for entry in archive.entries().context("Failed to read backup archive")? {
+        let mut entry = entry.context("Failed to read backup entry")?;
+        let path = entry.path().context("Invalid path in backup")?.into_owned();
+
+        let kind = match classify(&path) {
+            Some(kind) => kind,
+            None => {
+                warn!("Skipping unexpected backup entry {}", path.display());
+                continue;
+            }
+        };
+
+        if let Some(target) = f(kind)? {
+            entry
+                .unpack(&target)
+                .with_context(|| format!("Failed to restore {}", target.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Only plain `database/<name>.db` files and `config.toml` are restored, so a
+/// crafted archive can't write anywhere else
+fn classify(path: &Path) -> Option<Entry> {
+    if path == Path::new(CONFIG_FILE) {
+        return Some(Entry::Config);
+    }
+
+    let name = path.strip_prefix(DATABASE_DIR).ok()?.to_str()?;
+    let is_plain_name = !name.is_empty() && !name.contains(['/', '\\']) && !name.starts_with('.');
+    (is_plain_name && name.ends_with(".db")).then(|| Entry::Database(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Transcription;
+
+    #[test]
+    fn test_backup_restore_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("memo-node-backup-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("old")).unwrap();
+
+        let storage = Storage::new(&dir.join("old/memo.db")).unwrap();
+        storage
+            .insert_transcription(&Transcription {
+                text: "hello".to_string(),
+                source_node: "pi".to_string(),
+                ..Transcription::sample("a", 1_717_243_200)
+            })
+            .unwrap();
+        std::fs::write(dir.join("old/config.toml"), "[node]\nid = \"pi\"\n").unwrap();
+
+        let archive = dir.join("backup.tar");
+        let created = create_backup(&storage, Some(&dir.join("old/config.toml")), &archive).unwrap();
+        assert_eq!(created.databases, vec![MAIN_DATABASE]);
+        assert!(created.config);
+
+        let restored = restore_backup(
+            &archive,
+            &dir.join("new/data/memo.db"),
+            &dir.join("new/config/config.toml"),
+        )
+        .unwrap();
+        assert_eq!(restored.databases, vec![MAIN_DATABASE]);
+
+        let storage = Storage::new(&dir.join("new/data/memo.db")).unwrap();
+        assert_eq!(storage.get_all_transcriptions().unwrap().len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("new/config/config.toml")).unwrap(),
+            "[node]\nid = \"pi\"\n"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_classify_rejects_paths_outside_backup_layout() {
+        assert!(matches!(classify(Path::new("config.toml")), Some(Entry::Config)));
+        assert!(matches!(classify(Path::new("database/memo-2024-06-01.db")), Some(Entry::Database(_))));
+        assert!(classify(Path::new("database/../../etc/passwd.db")).is_none());
+        assert!(classify(Path::new("database/notes.txt")).is_none());
+        assert!(classify(Path::new("other/transcriptions.db")).is_none());
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Set once the pre-epoch warning has been logged
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Current Unix time in seconds
+///
+/// A clock set before 1970 (an unsynced RTC, or a bad NTP step) reads as 0
+/// instead of panicking; rows stamped meanwhile sort first, and the sync
+/// high-water mark check treats later real times as newer.
+pub fn unix_now() -> i64 {
+    unix_secs(SystemTime::now())
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(e) => {
+            if !WARNED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "System clock is {}s before the Unix epoch; using 0 until it is set",
+                    e.duration().as_secs()
+                );
+            }
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pre_epoch_clock_clamps_to_zero() {
+        assert_eq!(unix_secs(UNIX_EPOCH - Duration::from_secs(86_400)), 0);
+        assert_eq!(unix_secs(UNIX_EPOCH + Duration::from_millis(1_500)), 1);
+    }
+}
@@ -1,6 +1,11 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub use crate::audio::decoder::BundleLayout;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
@@ -10,47 +15,850 @@ pub struct Config {
     pub storage: StorageConfig,
     pub sync: SyncConfig,
     pub api: ApiConfig,
+    pub logging: LoggingConfig,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NodeConfig {
     pub id: String,
+    /// Human-readable name shown in place of the id
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Apply `MEMO_NODE_*` environment overrides (see `env_overrides`).
+    /// Only read from the config files; the environment can't change it.
+    #[serde(default = "default_env_overrides")]
+    pub env_overrides: bool,
+    /// Config keys (`section.field`) environment variables may override;
+    /// empty allows every key
+    #[serde(default)]
+    pub env_allowlist: Vec<String>,
+}
+
+fn default_env_overrides() -> bool {
+    true
+}
+
+impl NodeConfig {
+    /// The configured display name, falling back to the node id
+    pub fn display_name(&self) -> &str {
+        match self.display_name.as_deref() {
+            Some(name) if !name.is_empty() => name,
+            _ => &self.id,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AudioConfig {
     pub memo_service_uuid: String,
     pub memo_characteristic_uuid: String,
+    /// Audio characteristics to subscribe to, one stream each; when set, it
+    /// replaces `memo_characteristic_uuid`
+    #[serde(default)]
+    pub memo_characteristic_uuids: Vec<String>,
+    /// Bundles held while waiting for an out-of-order bundle; 0 disables reordering
+    #[serde(default = "default_reorder_window")]
+    pub reorder_window: usize,
+    /// Milliseconds to wait for the rest of a bundle split across
+    /// notifications; 0 disables reassembly
+    #[serde(default = "default_fragment_timeout_ms")]
+    pub fragment_timeout_ms: u64,
+    /// Encoding of the audio characteristic's notifications
+    #[serde(default)]
+    pub codec: InputCodec,
+    /// Whether Opus bundles start with a `bundle_index` byte
+    #[serde(default)]
+    pub bundle_layout: BundleLayout,
+    /// Sample rate of raw PCM input; resampled to 16kHz
+    #[serde(default = "default_pcm_sample_rate")]
+    pub pcm_sample_rate: u32,
+    /// Interleaved channels of raw PCM input; mixed down to mono
+    #[serde(default = "default_pcm_channels")]
+    pub pcm_channels: u16,
+    /// File format of saved recordings (`transcription.save_failed_audio`
+    /// and `transcription.save_recordings`)
+    #[serde(default)]
+    pub recording_format: RecordingFormat,
+    /// BCM GPIO pin driven high while recording (needs the `gpio` feature)
+    #[serde(default)]
+    pub led_gpio_pin: Option<u8>,
+    /// Audio notifications accepted per device per second; 0 disables
+    #[serde(default = "default_max_packets_per_sec")]
+    pub max_packets_per_sec: u32,
+    /// Audio bytes accepted per device per second; 0 disables
+    #[serde(default = "default_max_bytes_per_sec")]
+    pub max_bytes_per_sec: u64,
+    /// Milliseconds a device may be gone before it's reported disconnected
+    #[serde(default = "default_disconnect_grace_ms")]
+    pub disconnect_grace_ms: u64,
+    /// Milliseconds during which the same control command isn't re-sent to
+    /// a device; 0 disables the cooldown
+    #[serde(default = "default_command_cooldown_ms")]
+    pub command_cooldown_ms: u64,
+    /// Devices connected at once; 0 means no limit
+    #[serde(default)]
+    pub max_devices: usize,
+    /// Seconds without audio from a device, while recording, before it is
+    /// reconnected; 0 disables the watchdog
+    #[serde(default = "default_watchdog_timeout_secs")]
+    pub watchdog_timeout_secs: u64,
+    /// BLE adapters to use, matched against part of the adapter's name,
+    /// most preferred first; empty uses any adapter
+    #[serde(default)]
+    pub ble_adapters: Vec<String>,
+    /// Characteristic read for the device's battery percentage; empty
+    /// disables battery reporting
+    #[serde(default = "default_battery_characteristic_uuid")]
+    pub battery_characteristic_uuid: String,
+    /// Seconds between battery reads
+    #[serde(default = "default_battery_poll_secs")]
+    pub battery_poll_secs: u64,
+}
+
+impl AudioConfig {
+    /// Audio characteristic UUIDs, in stream order
+    pub fn audio_characteristic_uuids(&self) -> Vec<&str> {
+        if self.memo_characteristic_uuids.is_empty() {
+            vec![self.memo_characteristic_uuid.as_str()]
+        } else {
+            self.memo_characteristic_uuids.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+fn default_max_packets_per_sec() -> u32 {
+    200
+}
+
+fn default_max_bytes_per_sec() -> u64 {
+    64_000
+}
+
+fn default_disconnect_grace_ms() -> u64 {
+    2000
+}
+
+fn default_command_cooldown_ms() -> u64 {
+    5000
+}
+
+fn default_watchdog_timeout_secs() -> u64 {
+    30
+}
+
+/// Standard GATT Battery Level characteristic
+fn default_battery_characteristic_uuid() -> String {
+    "00002A19-0000-1000-8000-00805F9B34FB".to_string()
+}
+
+fn default_battery_poll_secs() -> u64 {
+    300
+}
+
+fn default_reorder_window() -> usize {
+    4
+}
+
+fn default_fragment_timeout_ms() -> u64 {
+    200
+}
+
+fn default_pcm_sample_rate() -> u32 {
+    16000
+}
+
+fn default_pcm_channels() -> u16 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputCodec {
+    /// Memo bundles of Opus frames
+    #[default]
+    Opus,
+    /// Raw signed 16-bit little-endian PCM
+    PcmS16le,
+    /// Raw 32-bit float little-endian PCM
+    PcmF32le,
+}
+
+/// How saved recordings are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// Uncompressed 16-bit PCM
+    #[default]
+    Wav,
+    /// Re-encoded as Opus in an Ogg container, roughly a tenth of the size
+    Opus,
+}
+
+impl RecordingFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Wav => "wav",
+            RecordingFormat::Opus => "opus",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TranscriptionConfig {
     pub model: String,
+    /// Models tried in order if the primary model fails to download or load
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub fallback_models: Vec<String>,
+    /// Older spelling of `fallback_models`, usually a single name; moved to
+    /// the front of `fallback_models` on load
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing)]
+    pub fallback_model: Vec<String>,
     #[serde(default = "default_threads")]
     pub threads: u8,
+    /// Where Whisper runs; a GPU lifts the Pi model size warnings
+    #[serde(default)]
+    pub compute: Compute,
+    /// Engines transcribing in parallel; each holds its own copy of the model
+    #[serde(default = "default_engines")]
+    pub engines: usize,
+    /// Finished recordings allowed to wait for a free engine
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    /// Transcribe fixed windows while recording stays on instead of waiting
+    /// for a stop event
+    #[serde(default)]
+    pub continuous: bool,
+    /// Seconds of audio per window in continuous mode
+    #[serde(default = "default_continuous_window_secs")]
+    pub continuous_window_secs: u64,
+    /// Minimum time between transcription starts; recordings finished in
+    /// between are merged into one. 0 disables the cooldown
+    #[serde(default)]
+    pub cooldown_ms: u64,
+    /// Prompt Whisper with the text of the last few recordings; unsupported
+    /// with memo-stt, which takes no prompt
+    #[serde(default)]
+    pub context_priming: bool,
+    /// Recordings whose text goes into the prompt with `context_priming`
+    #[serde(default = "default_context_priming_transcriptions")]
+    pub context_priming_transcriptions: usize,
+    /// Longest prompt, in characters, with `context_priming`
+    #[serde(default = "default_context_priming_max_chars")]
+    pub context_priming_max_chars: usize,
+    /// Seconds of audio held while the model downloads and loads; older
+    /// audio is dropped (and the amount logged)
+    #[serde(default = "default_startup_buffer_secs")]
+    pub startup_buffer_secs: u64,
+    /// Extra attempts at a recording whose transcription failed
+    #[serde(default)]
+    pub retries: u32,
+    /// Times each engine is reloaded after panicking before its worker
+    /// stops; the daemon exits once no workers are left
+    #[serde(default = "default_transcription_max_restarts")]
+    pub max_restarts: u32,
+    /// Save the audio of recordings that still failed after retrying to
+    /// `failed-audio/` in the data directory
+    #[serde(default)]
+    pub save_failed_audio: bool,
+    /// Save the audio of each stored recording to `recordings/` in the data
+    /// directory, named after its row, so `reprocess` can re-transcribe it
+    #[serde(default)]
+    pub save_recordings: bool,
+    /// `host:port` of a peer's gRPC server to transcribe on instead of a
+    /// local model
+    #[serde(default)]
+    pub remote_transcriber: Option<String>,
+    /// Transcribe audio sent by relay peers
+    #[serde(default)]
+    pub serve_remote: bool,
+    /// Longest recording, in seconds, accepted from a relay peer
+    #[serde(default = "default_serve_remote_max_secs")]
+    pub serve_remote_max_secs: u64,
+    /// Shell command run after each stored transcription
+    #[serde(default)]
+    pub on_transcription_command: Option<String>,
+    /// Seconds before `on_transcription_command` is killed
+    #[serde(default = "default_hook_timeout")]
+    pub on_transcription_timeout: u64,
+    /// Also send each stored transcription to journald as a structured
+    /// entry (needs the `journald` feature)
+    #[serde(default)]
+    pub journald: bool,
+    /// Tag transcriptions containing these keywords
+    #[serde(default)]
+    pub keyword_rules: Vec<KeywordRule>,
+    /// Maximum characters per stored transcription; 0 disables the limit
+    #[serde(default)]
+    pub max_text_length: usize,
+    /// What to do with text longer than `max_text_length`
+    #[serde(default)]
+    pub overflow: TextOverflow,
+    /// Phrases that are dropped when they make up the whole transcription
+    #[serde(default)]
+    pub blocklist: Vec<String>,
+    /// Drop transcriptions with fewer words than this; 0 disables the check
+    #[serde(default)]
+    pub min_words: usize,
+    /// Link consecutive transcriptions from one device into one session
+    /// (`session_id`) when a recording starts no more than this long after
+    /// the previous one ended; 0 disables linking
+    #[serde(default)]
+    pub merge_gap_ms: u64,
+    /// Key-value context stored with new transcriptions until a WebSocket
+    /// client replaces it with `set_context`
+    #[serde(default)]
+    pub context: BTreeMap<String, String>,
+    /// Trim and collapse whitespace before storing
+    #[serde(default)]
+    pub normalize_text: bool,
+    /// Also NFC-normalize unicode when `normalize_text` is set
+    #[serde(default)]
+    pub normalize_unicode: bool,
+    /// Replace emails, phone and card numbers (and `redact_patterns`) with
+    /// `redaction_placeholder` before storing
+    #[serde(default)]
+    pub redact: bool,
+    /// Extra regexes redacted when `redact` is set
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+    #[serde(default = "default_redaction_placeholder")]
+    pub redaction_placeholder: String,
+}
+
+/// A list of strings, or a single string as one item, so configs written
+/// with `fallback_model = "base.en"` keep loading
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(item) => vec![item],
+        OneOrMany::Many(items) => items,
+    })
+}
+
+fn default_context_priming_transcriptions() -> usize {
+    3
+}
+
+fn default_context_priming_max_chars() -> usize {
+    400
+}
+
+fn default_redaction_placeholder() -> String {
+    "[REDACTED]".to_string()
+}
+
+/// Hardware Whisper runs on (`transcription.compute`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compute {
+    Cpu,
+    /// A GPU if one is found, else the CPU
+    #[default]
+    Auto,
+    /// A GPU, falling back to the CPU with a warning if none is found
+    Gpu,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextOverflow {
+    /// Cut the text and append an ellipsis marker
+    #[default]
+    Truncate,
+    /// Store the text as several consecutive rows
+    Split,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KeywordRule {
+    /// Case-insensitive substring, or a regex if `regex` is set
+    pub pattern: String,
+    /// Tag added to matching transcriptions
+    pub tag: String,
+    #[serde(default)]
+    pub regex: bool,
 }
 
 fn default_threads() -> u8 {
     4
 }
 
+fn default_engines() -> usize {
+    1
+}
+
+fn default_queue_size() -> usize {
+    8
+}
+
+fn default_continuous_window_secs() -> u64 {
+    15
+}
+
+fn default_serve_remote_max_secs() -> u64 {
+    600
+}
+
+fn default_startup_buffer_secs() -> u64 {
+    60
+}
+
+fn default_hook_timeout() -> u64 {
+    10
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StorageConfig {
-    pub path: String,
+    /// Database path; defaults to `transcriptions.db` in the `--data-dir`
+    /// override if one is given, else `~/.memo/transcriptions.db`
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Store transcriptions in one database file per day next to `path`
+    #[serde(default)]
+    pub rolling: bool,
+    /// Maximum number of day files kept open at once in rolling mode
+    #[serde(default = "default_max_open_files")]
+    pub max_open_files: usize,
+    /// Integrity-check database files on open and move corrupt ones aside
+    #[serde(default)]
+    pub auto_recover: bool,
+    /// Commit daemon inserts in batches of this many rows; 0 commits each
+    /// insert immediately
+    #[serde(default)]
+    pub write_buffer_rows: usize,
+    /// Longest a buffered insert waits before being committed
+    #[serde(default = "default_write_buffer_ms")]
+    pub write_buffer_ms: u64,
+    /// What to do when a row arrives with the id of a stored row from
+    /// another node or with different text
+    #[serde(default)]
+    pub on_id_collision: IdCollisionMode,
+    /// Serve queries from a second, read-only connection (switches the
+    /// database to WAL journaling)
+    #[serde(default)]
+    pub read_connection: bool,
+    /// Stop storing new transcriptions while the storage filesystem has
+    /// less than this many MB free; 0 disables the check
+    #[serde(default = "default_min_free_mb")]
+    pub min_free_mb: u64,
+    /// Give each stored transcription a short code like `MEMO-7Q2` that can
+    /// be used in place of its id
+    #[serde(default)]
+    pub short_codes: bool,
+    /// Prefix of short codes
+    #[serde(default = "default_short_code_prefix")]
+    pub short_code_prefix: String,
+}
+
+/// Handling of a transcription whose id is already taken by a different row
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdCollisionMode {
+    /// Overwrite the stored row
+    #[default]
+    Replace,
+    /// Keep the stored row and refuse the new one
+    Reject,
+    /// Keep both, storing the new row under an id derived from its id and
+    /// source node
+    Rename,
+}
+
+fn default_max_open_files() -> usize {
+    4
+}
+
+fn default_write_buffer_ms() -> u64 {
+    1000
+}
+
+fn default_min_free_mb() -> u64 {
+    100
+}
+
+fn default_short_code_prefix() -> String {
+    "MEMO".to_string()
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SyncConfig {
     pub grpc_port: u16,
+    /// Interface address the gRPC server listens on
+    #[serde(default = "default_sync_bind_address")]
+    pub bind_address: IpAddr,
+    /// Advertise and browse for peers over mDNS; false syncs with static
+    /// peers only
+    #[serde(default = "default_discovery_enabled")]
+    pub discovery_enabled: bool,
     pub sync_interval: u64,
+    /// Seconds between full (`since=0`) reconciles with each peer; 0 disables
+    #[serde(default = "default_full_reconcile_interval")]
+    pub full_reconcile_interval: u64,
+    /// Times to retry binding the gRPC port before giving up
+    #[serde(default = "default_bind_retries")]
+    pub bind_retries: u32,
+    /// Consecutive restarts of the gRPC server before the daemon exits
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    /// Direction used for discovered peers and static peers without one
+    #[serde(default)]
+    pub default_direction: SyncDirection,
+    /// Only pull rows created by these nodes; empty pulls every row
+    #[serde(default)]
+    pub default_source_nodes: Vec<String>,
+    /// Peers to sync with regardless of mDNS discovery
+    #[serde(default)]
+    pub peers: Vec<StaticPeerConfig>,
+    /// Known peers not seen for this many seconds aren't restored at
+    /// startup; 0 restores them all
+    #[serde(default = "default_peer_expiry_secs")]
+    pub peer_expiry_secs: u64,
+    /// Secret every node in the cluster must present on gRPC calls
+    #[serde(default)]
+    pub cluster_secret: Option<String>,
+    /// What to do about peers whose clocks disagree with ours
+    #[serde(default)]
+    pub clock_skew: ClockSkewMode,
+    /// Skew tolerated before `clock_skew = "reject"` drops a row
+    #[serde(default = "default_max_clock_skew_secs")]
+    pub max_clock_skew_secs: i64,
+    /// Store a pull's rows in one batch once the whole stream has arrived,
+    /// and only then advance the peer's high-water mark
+    #[serde(default = "default_batch_pulls")]
+    pub batch_pulls: bool,
+    /// Sign rows this node creates with its ed25519 key (`node.key` in the
+    /// data directory, generated on first use)
+    #[serde(default)]
+    pub sign_transcriptions: bool,
+    /// Drop received rows whose signature doesn't verify instead of storing
+    /// them with a warning
+    #[serde(default)]
+    pub reject_invalid_signatures: bool,
+}
+
+fn default_transcription_max_restarts() -> u32 {
+    3
+}
+
+fn default_max_clock_skew_secs() -> i64 {
+    300
+}
+
+fn default_batch_pulls() -> bool {
+    true
+}
+
+/// Handling of peers whose clocks are off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockSkewMode {
+    /// Store peer timestamps as received
+    #[default]
+    Off,
+    /// Measure each peer's clock offset and shift the timestamps of rows it
+    /// created onto our clock
+    Adjust,
+    /// Drop rows timestamped more than `max_clock_skew_secs` in our future
+    Reject,
+}
+
+fn default_discovery_enabled() -> bool {
+    true
+}
+
+fn default_sync_bind_address() -> IpAddr {
+    IpAddr::from([0, 0, 0, 0])
+}
+
+fn default_full_reconcile_interval() -> u64 {
+    3600
+}
+
+fn default_peer_expiry_secs() -> u64 {
+    7 * 24 * 3600
+}
+
+/// Which halves of a sync run against a peer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncDirection {
+    /// Only fetch the peer's transcriptions
+    Pull,
+    /// Only send our own transcriptions to the peer
+    Push,
+    #[default]
+    Both,
+}
+
+impl SyncDirection {
+    pub fn pulls(self) -> bool {
+        matches!(self, SyncDirection::Pull | SyncDirection::Both)
+    }
+
+    pub fn pushes(self) -> bool {
+        matches!(self, SyncDirection::Push | SyncDirection::Both)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StaticPeerConfig {
+    pub node_id: String,
+    pub address: IpAddr,
+    #[serde(default = "default_grpc_port")]
+    pub grpc_port: u16,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Overrides `sync.default_direction` for this peer
+    #[serde(default)]
+    pub direction: Option<SyncDirection>,
+    /// Overrides `sync.default_source_nodes` for this peer
+    #[serde(default)]
+    pub source_nodes: Option<Vec<String>>,
+}
+
+fn default_grpc_port() -> u16 {
+    9876
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ApiConfig {
     pub websocket_port: u16,
     pub listen_address: String,
+    /// Port for the read-only REST API on `listen_address`; 0 (the
+    /// default) disables it
+    #[serde(default)]
+    pub rest_port: u16,
+    /// Serve recent transcriptions as a feed at `GET /feed` on the REST API
+    #[serde(default)]
+    pub feed: FeedFormat,
+    /// Most recent transcriptions listed in the feed
+    #[serde(default = "default_feed_items")]
+    pub feed_items: usize,
     #[serde(default)]
     pub https_endpoint: Option<String>,
+    /// Send a salted hash instead of the real `source_node` to the endpoint
+    #[serde(default)]
+    pub http_anonymize_source: bool,
+    /// Salt for `http_anonymize_source`; keep it secret and stable
+    #[serde(default)]
+    pub http_anonymize_salt: String,
+    /// Add `schema_version` and `node_version` to posted payloads
+    #[serde(default = "default_http_include_version")]
+    pub http_include_version: bool,
+    /// First delay before retrying a POST that failed to connect (DNS,
+    /// refused, timeout); doubles on each retry
+    #[serde(default = "default_http_network_backoff_secs")]
+    pub http_network_backoff_secs: u64,
+    /// Retries for POSTs that failed to connect
+    #[serde(default = "default_http_network_retries")]
+    pub http_network_retries: u32,
+    /// Truncate text longer than this many UTF-8 bytes before posting, for
+    /// endpoints with a payload size limit; 0 posts the full text
+    #[serde(default)]
+    pub http_max_text_bytes: usize,
+    /// MQTT broker to publish transcriptions to; empty disables publishing
+    /// (needs the `mqtt` feature)
+    #[serde(default)]
+    pub mqtt_host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub mqtt_port: u16,
+    #[serde(default = "default_mqtt_topic")]
+    pub mqtt_topic: String,
+    /// Client id sent to the broker; empty uses `memo-node-<node id>`
+    #[serde(default)]
+    pub mqtt_client_id: String,
+    /// Username and password for the broker; an empty username sends none
+    #[serde(default)]
+    pub mqtt_username: String,
+    #[serde(default)]
+    pub mqtt_password: String,
+    /// Connect to the broker over TLS, verifying it against the system roots
+    #[serde(default)]
+    pub mqtt_tls: bool,
+    /// Messages held while the broker is unreachable; newer ones are dropped
+    #[serde(default = "default_mqtt_queue_size")]
+    pub mqtt_queue_size: usize,
+    /// Times to retry binding the WebSocket port before giving up
+    #[serde(default = "default_bind_retries")]
+    pub bind_retries: u32,
+    /// Consecutive restarts of the WebSocket or REST server before the
+    /// daemon exits
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    /// Maximum concurrent WebSocket clients; 0 disables the limit
+    #[serde(default = "default_max_clients")]
+    pub max_clients: usize,
+    /// History replies with more rows than this are split into
+    /// `history_chunk` messages; 0 disables chunking
+    #[serde(default)]
+    pub history_chunk_size: usize,
+    /// Push a `stats` message with counters and rates to every WebSocket
+    /// client this often; 0 only answers `get_stats`
+    #[serde(default)]
+    pub stats_interval_secs: u64,
+    /// Send `recording_started` and `recording_stopped` to WebSocket clients
+    /// when a device's button changes the recording state
+    #[serde(default)]
+    pub recording_events: bool,
+    /// Close a WebSocket client's connection after a request fails because
+    /// the database is unusable; other failures only get an `error` reply
+    #[serde(default = "default_close_on_fatal_storage_error")]
+    pub close_on_fatal_storage_error: bool,
+}
+
+fn default_close_on_fatal_storage_error() -> bool {
+    true
+}
+
+/// Format of the REST API's `/feed`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    /// No feed endpoint
+    #[default]
+    Off,
+    /// RSS 2.0
+    Rss,
+    Atom,
+}
+
+fn default_feed_items() -> usize {
+    20
+}
+
+fn default_bind_retries() -> u32 {
+    5
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_max_clients() -> usize {
+    16
+}
+
+fn default_http_network_backoff_secs() -> u64 {
+    10
+}
+
+fn default_http_include_version() -> bool {
+    true
+}
+
+fn default_http_network_retries() -> u32 {
+    5
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic() -> String {
+    "memo/transcriptions".to_string()
+}
+
+fn default_mqtt_queue_size() -> usize {
+    100
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoggingConfig {
+    /// Daemon log file, relative to the data directory unless absolute;
+    /// empty logs to stdout only
+    #[serde(default)]
+    pub log_file: String,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// Size at which `rotation = "size"` starts a new file
+    #[serde(default = "default_log_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Log files kept, including the current one
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+    /// Recent log lines kept in memory for the WebSocket `get_logs`
+    /// message; 0 disables it
+    #[serde(default = "default_log_buffer_lines")]
+    pub buffer_lines: usize,
+}
+
+/// When the log file is rotated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+    /// Once the file reaches `max_size_mb`
+    Size,
+}
+
+fn default_log_max_size_mb() -> u64 {
+    10
+}
+
+fn default_log_max_files() -> usize {
+    7
+}
+
+fn default_log_buffer_lines() -> usize {
+    500
+}
+
+/// Set from `--data-dir`
+static DATA_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+const DEFAULT_STORAGE_PATH: &str = "~/.memo/transcriptions.db";
+
+/// The embedded default config, documenting every setting
+const DEFAULT_CONFIG: &str = include_str!("../config/default.toml");
+
+/// The node id line in `DEFAULT_CONFIG`
+const DEFAULT_NODE_ID_LINE: &str = "id = \"memo-node\"";
+
+/// Prefix of environment variables that override config keys
+const ENV_PREFIX: &str = "MEMO_NODE_";
+
+/// Overrides taken from `env`: `MEMO_NODE_<SECTION>_<FIELD>=value` sets
+/// `<section>.<field>`
+///
+/// The name after the prefix is lowercased and split at its first `_`, so
+/// `MEMO_NODE_SYNC_GRPC_PORT` sets `sync.grpc_port` and
+/// `MEMO_NODE_NODE_ID` sets `node.id`. Nothing deeper than a section's
+/// fields can be set, and values are parsed as the field's type (numbers,
+/// `true`/`false`, strings); lists can't be given. `MEMO_NODE_DATA_DIR` and
+/// the `node.env_*` settings themselves are never overrides. With a
+/// non-empty `allowlist`, only keys in it are overridden.
+fn env_overrides(
+    env: impl IntoIterator<Item = (String, String)>,
+    allowlist: &[String],
+) -> Vec<(String, String)> {
+    env.into_iter()
+        .filter_map(|(name, value)| {
+            let (section, field) = name.strip_prefix(ENV_PREFIX)?.split_once('_')?;
+            if section.is_empty() || field.is_empty() || name == "MEMO_NODE_DATA_DIR" {
+                return None;
+            }
+            let key = format!("{}.{}", section.to_ascii_lowercase(), field.to_ascii_lowercase());
+            if key.starts_with("node.env_") {
+                return None;
+            }
+            if !allowlist.is_empty() && !allowlist.contains(&key) {
+                return None;
+            }
+            Some((key, value))
+        })
+        .collect()
 }
 
 impl Config {
@@ -58,30 +866,45 @@ impl Config {
         let config_dir = Self::config_dir()?;
         std::fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
 
+        let user_config_path = Self::user_config_path()?;
+        let user_config = user_config_path.exists().then_some(user_config_path.as_path());
+        Self::load_from(user_config, std::env::vars())
+    }
+
+    /// Layer the embedded defaults, `user_config` and the overrides from
+    /// `env` allowed by `node.env_overrides` and `node.env_allowlist`
+    pub(crate) fn load_from(
+        user_config: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self> {
         let mut builder = config::Config::builder()
             // Start with default config from the embedded file
-            .add_source(config::File::from_str(
-                include_str!("../config/default.toml"),
-                config::FileFormat::Toml,
-            ));
+            .add_source(config::File::from_str(DEFAULT_CONFIG, config::FileFormat::Toml));
 
         // Override with user config if it exists
-        let user_config_path = config_dir.join("config.toml");
-        if user_config_path.exists() {
-            builder = builder.add_source(config::File::from(user_config_path));
+        if let Some(path) = user_config {
+            builder = builder.add_source(config::File::from(path));
         }
 
-        // Override with environment variables (MEMO_NODE_*)
-        builder = builder.add_source(
-            config::Environment::with_prefix("MEMO_NODE")
-                .separator("_")
-                .try_parsing(true),
-        );
+        // Whether the environment may override anything is up to the files
+        let files = builder.build_cloned().context("Failed to build configuration")?;
+        if files.get_bool("node.env_overrides").unwrap_or(true) {
+            let allowlist: Vec<String> = files.get("node.env_allowlist").unwrap_or_default();
+            for (key, value) in env_overrides(env, &allowlist) {
+                builder = builder
+                    .set_override(&key, value)
+                    .with_context(|| format!("Invalid environment override for {}", key))?;
+            }
+        }
 
         let config = builder.build().context("Failed to build configuration")?;
-        config
+        let mut config: Self = config
             .try_deserialize()
-            .context("Failed to deserialize configuration")
+            .context("Failed to deserialize configuration")?;
+
+        let legacy = std::mem::take(&mut config.transcription.fallback_model);
+        config.transcription.fallback_models.splice(0..0, legacy);
+        Ok(config)
     }
 
     pub fn config_dir() -> Result<PathBuf> {
@@ -91,24 +914,128 @@ impl Config {
             .to_path_buf())
     }
 
+    pub fn user_config_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("config.toml"))
+    }
+
+    /// The default config with `node_id` in place of the placeholder id
+    pub fn default_config_with_id(node_id: &str) -> String {
+        DEFAULT_CONFIG.replacen(
+            DEFAULT_NODE_ID_LINE,
+            &format!("id = {}", toml::Value::String(node_id.to_string())),
+            1,
+        )
+    }
+
+    /// Write the default config with `node_id` to the user config path,
+    /// refusing to replace an existing file unless `force` is set
+    pub fn write_default_user_config(node_id: &str, force: bool) -> Result<PathBuf> {
+        let path = Self::user_config_path()?;
+        if path.exists() && !force {
+            anyhow::bail!("{} already exists; pass --force to overwrite it", path.display());
+        }
+
+        std::fs::create_dir_all(Self::config_dir()?).context("Failed to create config directory")?;
+        std::fs::write(&path, Self::default_config_with_id(node_id))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Set `[section] key = value` in the user config file, creating it if needed
+    ///
+    /// Other settings in the file are preserved.
+    pub fn set_user_value(section: &str, key: &str, value: toml::Value) -> Result<PathBuf> {
+        let path = Self::user_config_path()?;
+        std::fs::create_dir_all(Self::config_dir()?).context("Failed to create config directory")?;
+
+        let mut table: toml::Table = if path.exists() {
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .parse()
+                .with_context(|| format!("Failed to parse {}", path.display()))?
+        } else {
+            toml::Table::new()
+        };
+
+        table
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .with_context(|| format!("[{}] in {} is not a table", section, path.display()))?
+            .insert(key.to_string(), value);
+
+        let contents = toml::to_string_pretty(&table).context("Failed to serialize config")?;
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(path)
+    }
+
+    /// Use `dir` as the data directory for this process (`--data-dir`),
+    /// taking precedence over `MEMO_NODE_DATA_DIR`
+    pub fn set_data_dir_override(dir: PathBuf) {
+        let _ = DATA_DIR_OVERRIDE.set(dir);
+    }
+
+    fn data_dir_override() -> Option<PathBuf> {
+        DATA_DIR_OVERRIDE.get().cloned().or_else(|| {
+            std::env::var_os("MEMO_NODE_DATA_DIR")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        })
+    }
+
+    /// Directory for the status file and, unless `storage.path` is set, the
+    /// database: `--data-dir`, then `MEMO_NODE_DATA_DIR`, then the platform
+    /// data directory
     pub fn data_dir() -> Result<PathBuf> {
-        let dir = directories::ProjectDirs::from("", "", "memo-node")
-            .context("Failed to determine data directory")?
-            .data_dir()
-            .to_path_buf();
+        let dir = match Self::data_dir_override() {
+            Some(dir) => dir,
+            None => directories::ProjectDirs::from("", "", "memo-node")
+                .context("Failed to determine data directory")?
+                .data_dir()
+                .to_path_buf(),
+        };
         std::fs::create_dir_all(&dir).context("Failed to create data directory")?;
         Ok(dir)
     }
 
+    pub fn status_path() -> Result<PathBuf> {
+        Ok(Self::data_dir()?.join("status.json"))
+    }
+
+    /// This node's signing key for `sync.sign_transcriptions`
+    pub fn node_key_path() -> Result<PathBuf> {
+        Ok(Self::data_dir()?.join("node.key"))
+    }
+
+    /// Where `transcription.save_failed_audio` writes WAV files
+    pub fn failed_audio_dir() -> Result<PathBuf> {
+        Ok(Self::data_dir()?.join("failed-audio"))
+    }
+
+    /// Where `transcription.save_recordings` writes each row's audio
+    pub fn recordings_dir() -> Result<PathBuf> {
+        Ok(Self::data_dir()?.join("recordings"))
+    }
+
+    /// Database path: `storage.path` if set, else `transcriptions.db` in the
+    /// overridden data directory, else `~/.memo/transcriptions.db`
     pub fn storage_path(&self) -> Result<PathBuf> {
-        let path = if self.storage.path.starts_with('~') {
+        let configured = match (&self.storage.path, Self::data_dir_override()) {
+            (Some(path), _) if !path.is_empty() => path.clone(),
+            (_, Some(_)) => return Ok(Self::data_dir()?.join("transcriptions.db")),
+            _ => DEFAULT_STORAGE_PATH.to_string(),
+        };
+
+        let path = if configured.starts_with('~') {
             let home = directories::UserDirs::new()
                 .context("Failed to determine home directory")?
                 .home_dir()
                 .to_path_buf();
-            home.join(self.storage.path.trim_start_matches("~/"))
+            home.join(configured.trim_start_matches("~/"))
         } else {
-            PathBuf::from(&self.storage.path)
+            PathBuf::from(&configured)
         };
 
         if let Some(parent) = path.parent() {
@@ -118,3 +1045,101 @@ impl Config {
         Ok(path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_with_id() {
+        let contents = Config::default_config_with_id("pi-kitchen");
+        let table: toml::Table = contents.parse().unwrap();
+        assert_eq!(table["node"]["id"].as_str(), Some("pi-kitchen"));
+        // Comments documenting the settings are kept
+        assert!(contents.contains("# Unique identifier for this node"));
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// A user config file holding `contents`
+    fn user_config(contents: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("memo-node-config-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_env_overrides_split_at_first_underscore() {
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("MEMO_NODE_NODE_ID", "pi-workshop"),
+                ("MEMO_NODE_SYNC_GRPC_PORT", "9976"),
+                ("MEMO_NODE_SYNC_BATCH_PULLS", "false"),
+                ("MEMO_NODE_API_HTTPS_ENDPOINT", "https://example.com/memos"),
+                ("MEMO_NODE_DATA_DIR", "/tmp/elsewhere"),
+                ("OTHER_SYNC_GRPC_PORT", "1"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.node.id, "pi-workshop");
+        assert_eq!(config.sync.grpc_port, 9976);
+        assert!(!config.sync.batch_pulls);
+        assert_eq!(config.api.https_endpoint.as_deref(), Some("https://example.com/memos"));
+    }
+
+    #[test]
+    fn test_env_overrides_can_be_disabled() {
+        let path = user_config("[node]\nid = \"from-file\"\nenv_overrides = false\n");
+        let config = Config::load_from(
+            Some(&path),
+            env(&[
+                ("MEMO_NODE_NODE_ID", "from-env"),
+                // The environment can't turn overrides back on
+                ("MEMO_NODE_NODE_ENV_OVERRIDES", "true"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.node.id, "from-file");
+        assert!(!config.node.env_overrides);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fallback_model_accepts_a_single_name() {
+        let path =
+            user_config("[transcription]\nmodel = \"small.en\"\nfallback_model = \"base.en\"\n");
+        let config = Config::load_from(Some(&path), Vec::new()).unwrap();
+        assert_eq!(config.transcription.fallback_models, ["base.en"]);
+        std::fs::remove_file(&path).unwrap();
+
+        let path = user_config(
+            "[transcription]\nmodel = \"small.en\"\nfallback_models = [\"base.en\", \"tiny.en\"]\n",
+        );
+        let config = Config::load_from(Some(&path), Vec::new()).unwrap();
+        assert_eq!(config.transcription.fallback_models, ["base.en", "tiny.en"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_env_allowlist_limits_overrides() {
+        let path =
+            user_config("[node]\nid = \"from-file\"\nenv_allowlist = [\"sync.grpc_port\"]\n");
+        let config = Config::load_from(
+            Some(&path),
+            env(&[("MEMO_NODE_NODE_ID", "from-env"), ("MEMO_NODE_SYNC_GRPC_PORT", "9976")]),
+        )
+        .unwrap();
+
+        assert_eq!(config.node.id, "from-file");
+        assert_eq!(config.sync.grpc_port, 9976);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
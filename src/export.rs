@@ -0,0 +1,181 @@
+use crate::storage::{SourceType, Transcription};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    /// Guess the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// A transcription as one CSV record, with `metadata` as a JSON object
+/// string (empty if there is none) so it stays one column
+#[derive(Serialize)]
+struct CsvRecord<'a> {
+    id: &'a str,
+    timestamp: i64,
+    text: &'a str,
+    source_node: &'a str,
+    memo_device_id: Option<&'a str>,
+    synced: bool,
+    source_type: SourceType,
+    session_id: Option<&'a str>,
+    device_battery: Option<u8>,
+    metadata: String,
+    duration_ms: Option<i64>,
+    signature: Option<&'a str>,
+    signer_key: Option<&'a str>,
+    short_code: Option<&'a str>,
+    original_text: Option<&'a str>,
+}
+
+impl<'a> From<&'a Transcription> for CsvRecord<'a> {
+    fn from(t: &'a Transcription) -> Self {
+        Self {
+            id: &t.id,
+            timestamp: t.timestamp,
+            text: &t.text,
+            source_node: &t.source_node,
+            memo_device_id: t.memo_device_id.as_deref(),
+            synced: t.synced,
+            source_type: t.source_type,
+            session_id: t.session_id.as_deref(),
+            device_battery: t.device_battery,
+            metadata: if t.metadata.is_empty() {
+                String::new()
+            } else {
+                t.metadata.to_json()
+            },
+            duration_ms: t.duration_ms,
+            signature: t.signature.as_deref(),
+            signer_key: t.signer_key.as_deref(),
+            short_code: t.short_code.as_deref(),
+            original_text: t.original_text.as_deref(),
+        }
+    }
+}
+
+/// Write transcriptions as a JSON array or RFC 4180 CSV with a header row
+///
+/// Both formats are UTF-8. CSV fields containing quotes, commas or newlines
+/// are quoted, with embedded quotes doubled. `metadata` is an object in
+/// JSON and a JSON object string in CSV.
+pub fn write_transcriptions<W: Write>(
+    writer: W,
+    format: ExportFormat,
+    transcriptions: &[Transcription],
+) -> Result<()> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(writer, transcriptions)
+                .context("Failed to write JSON export")?;
+        }
+        ExportFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for transcription in transcriptions {
+                csv_writer
+                    .serialize(CsvRecord::from(transcription))
+                    .context("Failed to write CSV row")?;
+            }
+            csv_writer.flush().context("Failed to flush CSV export")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read transcriptions written by `write_transcriptions`
+pub fn read_transcriptions<R: Read>(reader: R, format: ExportFormat) -> Result<Vec<Transcription>> {
+    match format {
+        ExportFormat::Json => serde_json::from_reader(reader).context("Failed to parse JSON export"),
+        ExportFormat::Csv => csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<Vec<Transcription>, _>>()
+            .context("Failed to parse CSV export"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Metadata;
+
+    fn tricky_transcriptions() -> Vec<Transcription> {
+        vec![
+            Transcription {
+                id: "a".to_string(),
+                timestamp: 1_717_243_200,
+                text: "She said \"call me\", then left\nsecond line 🎉 café".to_string(),
+                source_node: "pi-workshop".to_string(),
+                memo_device_id: Some("memo,1".to_string()),
+                metadata: Metadata(
+                    [("location".to_string(), "Workshop, bench \"2\"".to_string())].into(),
+                ),
+                duration_ms: Some(4200),
+                ..Transcription::default()
+            },
+            Transcription {
+                id: "b".to_string(),
+                timestamp: 1_717_243_201,
+                text: "plain".to_string(),
+                source_node: "macbook".to_string(),
+                synced: true,
+                source_type: SourceType::Peer,
+                ..Transcription::default()
+            },
+        ]
+    }
+
+    fn roundtrip(format: ExportFormat) -> (String, Vec<Transcription>) {
+        let rows = tricky_transcriptions();
+        let mut buffer = Vec::new();
+        write_transcriptions(&mut buffer, format, &rows).unwrap();
+        let imported = read_transcriptions(buffer.as_slice(), format).unwrap();
+        (String::from_utf8(buffer).unwrap(), imported)
+    }
+
+    #[test]
+    fn test_csv_quotes_special_characters() {
+        let (csv, _) = roundtrip(ExportFormat::Csv);
+        assert!(csv.contains("\"She said \"\"call me\"\", then left\nsecond line 🎉 café\""));
+        assert!(csv.contains("\"memo,1\""));
+    }
+
+    #[test]
+    fn test_csv_roundtrip() {
+        let (_, imported) = roundtrip(ExportFormat::Csv);
+        assert_eq!(imported, tricky_transcriptions());
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let (json, imported) = roundtrip(ExportFormat::Json);
+        let rows: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(rows[0]["metadata"], serde_json::json!({ "location": "Workshop, bench \"2\"" }));
+        assert_eq!(rows[1]["metadata"], serde_json::json!({}));
+        assert_eq!(imported, tricky_transcriptions());
+    }
+
+    #[test]
+    fn test_json_import_accepts_metadata_as_string() {
+        let json = r#"[{"id": "a", "timestamp": 1717243200, "text": "memo a", "source_node": "test-node",
+            "memo_device_id": null, "synced": false, "source_type": "device",
+            "metadata": "{\"project\":\"field-notes\"}"}]"#;
+        let imported = read_transcriptions(json.as_bytes(), ExportFormat::Json).unwrap();
+        assert_eq!(imported[0].metadata.0["project"], "field-notes");
+    }
+}
@@ -0,0 +1,116 @@
+use crate::storage::Transcription;
+use anyhow::{Context, Result};
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// Shell command run after each stored transcription
+///
+/// The command runs via `sh -c` with the transcription in `MEMO_ID`,
+/// `MEMO_TIMESTAMP`, `MEMO_TEXT`, `MEMO_SOURCE_NODE`, `MEMO_DEVICE_ID`,
+/// `MEMO_SOURCE_TYPE` and `MEMO_METADATA` (a JSON object, `{}` if empty). It
+/// is killed if it outlives `timeout`.
+#[derive(Debug, Clone)]
+pub struct CommandHook {
+    command: String,
+    timeout: Duration,
+}
+
+impl CommandHook {
+    pub fn new(command: String, timeout: Duration) -> Self {
+        Self { command, timeout }
+    }
+
+    /// Run the hook in the background, logging failures
+    pub fn spawn(&self, transcription: &Transcription) -> JoinHandle<()> {
+        let hook = self.clone();
+        let transcription = transcription.clone();
+        tokio::spawn(async move {
+            match hook.run(&transcription).await {
+                Ok(status) if status.success() => {
+                    debug!("Transcription hook finished for {}", transcription.id);
+                }
+                Ok(status) => {
+                    warn!("Transcription hook exited with {} for {}", status, transcription.id);
+                }
+                Err(e) => {
+                    warn!("Transcription hook failed for {}: {:#}", transcription.id, e);
+                }
+            }
+        })
+    }
+
+    pub async fn run(&self, transcription: &Transcription) -> Result<ExitStatus> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("MEMO_ID", &transcription.id)
+            .env("MEMO_TIMESTAMP", transcription.timestamp.to_string())
+            .env("MEMO_TEXT", &transcription.text)
+            .env("MEMO_SOURCE_NODE", &transcription.source_node)
+            .env(
+                "MEMO_DEVICE_ID",
+                transcription.memo_device_id.as_deref().unwrap_or_default(),
+            )
+            .env("MEMO_SOURCE_TYPE", transcription.source_type.as_str())
+            .env("MEMO_METADATA", transcription.metadata.to_json())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to spawn `{}`", self.command))?;
+
+        match tokio::time::timeout(self.timeout, child.wait()).await {
+            Ok(status) => status.context("Failed to wait for hook"),
+            Err(_) => {
+                let _ = child.kill().await;
+                Err(anyhow::anyhow!("Timed out after {:?}", self.timeout))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Metadata;
+
+    #[tokio::test]
+    async fn test_hook_receives_fields_as_env() {
+        let hook = CommandHook::new(
+            r#"test "$MEMO_ID" = abc && test "$MEMO_TEXT" = 'it'"'"'s "quoted"; $(not run)'"#
+                .to_string(),
+            Duration::from_secs(5),
+        );
+        let transcription = Transcription {
+            text: "it's \"quoted\"; $(not run)".to_string(),
+            ..Transcription::sample("abc", 1_717_243_200)
+        };
+        assert!(hook.run(&transcription).await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn test_hook_receives_metadata_as_json_object() {
+        let hook = CommandHook::new(
+            r#"test "$MEMO_METADATA" = '{"project":"field-notes"}'"#.to_string(),
+            Duration::from_secs(5),
+        );
+        let transcription = Transcription {
+            metadata: Metadata([("project".to_string(), "field-notes".to_string())].into()),
+            ..Transcription::sample("abc", 1_717_243_200)
+        };
+        assert!(hook.run(&transcription).await.unwrap().success());
+
+        let hook =
+            CommandHook::new(r#"test "$MEMO_METADATA" = '{}'"#.to_string(), Duration::from_secs(5));
+        assert!(hook.run(&Transcription::sample("abc", 1_717_243_200)).await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn test_hook_times_out() {
+        let hook = CommandHook::new("sleep 5".to_string(), Duration::from_millis(100));
+        let transcription = Transcription::sample("abc", 1_717_243_200);
+        assert!(hook.run(&transcription).await.is_err());
+    }
+}
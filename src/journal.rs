@@ -0,0 +1,75 @@
+use crate::storage::Transcription;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Tracing target of transcription entries. Only events with this target
+/// reach journald, and they are kept out of the normal log output.
+pub const TARGET: &str = "memo_node::journal";
+
+/// Whether `layer` managed to connect to journald
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Layer sending transcription entries to journald as structured fields
+/// (`MEMO_ID`, `MEMO_TEXT`, `MEMO_SOURCE_NODE`, ...)
+///
+/// `None` if journald isn't reachable. Runs before logging is set up, so
+/// failures are reported later by `enabled`.
+#[cfg(feature = "journald")]
+pub fn layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use tracing_subscriber::filter::Targets;
+
+    let layer = tracing_journald::layer().ok()?;
+    CONNECTED.store(true, Ordering::Relaxed);
+    Some(
+        layer
+            .with_field_prefix(Some("MEMO".to_string()))
+            .with_syslog_identifier("memo-node".to_string())
+            .with_filter(Targets::new().with_target(TARGET, tracing::Level::INFO)),
+    )
+}
+
+#[cfg(not(feature = "journald"))]
+pub fn layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    None::<tracing_subscriber::layer::Identity>
+}
+
+/// Whether `transcription.journald` can take effect, warning if not
+pub fn enabled(requested: bool) -> bool {
+    if !requested {
+        return false;
+    }
+    if !cfg!(feature = "journald") {
+        tracing::warn!(
+            "transcription.journald is set but memo-node was built without the `journald` feature; ignoring"
+        );
+        return false;
+    }
+    if !CONNECTED.load(Ordering::Relaxed) {
+        tracing::warn!("transcription.journald is set but journald isn't reachable; ignoring");
+        return false;
+    }
+    true
+}
+
+/// Send one stored transcription to journald
+pub fn emit_transcription(transcription: &Transcription) {
+    tracing::info!(
+        target: TARGET,
+        id = %transcription.id,
+        timestamp = transcription.timestamp,
+        text = %transcription.text,
+        source_node = %transcription.source_node,
+        source_type = %transcription.source_type,
+        memo_device_id = transcription.memo_device_id.as_deref().unwrap_or(""),
+        "{}",
+        transcription.text
+    );
+}
@@ -0,0 +1,118 @@
+use crate::config::KeywordRule;
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+
+enum Matcher {
+    /// Lowercased substring
+    Substring(String),
+    Regex(Regex),
+}
+
+struct CompiledRule {
+    pattern: String,
+    tag: String,
+    matcher: Matcher,
+}
+
+/// A rule that matched a transcription
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeywordMatch<'a> {
+    pub pattern: &'a str,
+    pub tag: &'a str,
+}
+
+/// `transcription.keyword_rules`, compiled once at startup
+///
+/// All matching is case-insensitive, whether by substring or regex.
+pub struct KeywordMatcher {
+    rules: Vec<CompiledRule>,
+}
+
+impl KeywordMatcher {
+    pub fn new(rules: &[KeywordRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let matcher = if rule.regex {
+                    Matcher::Regex(
+                        RegexBuilder::new(&rule.pattern)
+                            .case_insensitive(true)
+                            .build()
+                            .with_context(|| format!("Invalid keyword regex '{}'", rule.pattern))?,
+                    )
+                } else {
+                    Matcher::Substring(rule.pattern.to_lowercase())
+                };
+
+                Ok(CompiledRule {
+                    pattern: rule.pattern.clone(),
+                    tag: rule.tag.clone(),
+                    matcher,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Rules matching `text`, in configuration order
+    pub fn matches(&self, text: &str) -> Vec<KeywordMatch<'_>> {
+        let lowered = text.to_lowercase();
+
+        self.rules
+            .iter()
+            .filter(|rule| match &rule.matcher {
+                Matcher::Substring(needle) => lowered.contains(needle.as_str()),
+                Matcher::Regex(regex) => regex.is_match(text),
+            })
+            .map(|rule| KeywordMatch {
+                pattern: &rule.pattern,
+                tag: &rule.tag,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, tag: &str, regex: bool) -> KeywordRule {
+        KeywordRule {
+            pattern: pattern.to_string(),
+            tag: tag.to_string(),
+            regex,
+        }
+    }
+
+    #[test]
+    fn test_substring_is_case_insensitive() {
+        let matcher = KeywordMatcher::new(&[rule("remind me", "reminder", false)]).unwrap();
+        let matches = matcher.matches("Remind Me to call the bank");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].tag, "reminder");
+        assert!(matcher.matches("nothing to see").is_empty());
+    }
+
+    #[test]
+    fn test_regex_rules() {
+        let matcher = KeywordMatcher::new(&[
+            rule(r"\btodo\b", "todo", true),
+            rule("buy", "shopping", false),
+        ])
+        .unwrap();
+
+        let tags: Vec<&str> = matcher
+            .matches("TODO: buy milk")
+            .iter()
+            .map(|m| m.tag)
+            .collect();
+        assert_eq!(tags, vec!["todo", "shopping"]);
+        assert!(matcher.matches("todos are fine").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_regex_is_rejected() {
+        assert!(KeywordMatcher::new(&[rule("(", "broken", true)]).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Mirror `is_recording` onto an LED wired to GPIO `pin` (BCM numbering):
+/// high while recording, low otherwise
+///
+/// Needs the `gpio` feature and a Raspberry Pi; elsewhere a configured pin
+/// is logged and ignored.
+#[cfg(feature = "gpio")]
+pub fn spawn_recording_led(pin: u8, is_recording: Arc<AtomicBool>) {
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    let mut output = match rppal::gpio::Gpio::new().and_then(|gpio| gpio.get(pin)) {
+        Ok(pin) => pin.into_output_low(),
+        Err(e) => {
+            warn!("Recording LED disabled: can't open GPIO {}: {}", pin, e);
+            return;
+        }
+    };
+    tracing::info!("Recording LED on GPIO {}", pin);
+
+    // is_recording is a plain flag, so poll it
+    tokio::spawn(async move {
+        let mut lit = false;
+        loop {
+            let recording = is_recording.load(Ordering::Acquire);
+            if recording != lit {
+                if recording {
+                    output.set_high();
+                } else {
+                    output.set_low();
+                }
+                lit = recording;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    });
+}
+
+#[cfg(not(feature = "gpio"))]
+pub fn spawn_recording_led(pin: u8, _is_recording: Arc<AtomicBool>) {
+    warn!(
+        "audio.led_gpio_pin = {} is set but memo-node was built without the `gpio` feature; ignoring",
+        pin
+    );
+}
@@ -0,0 +1,305 @@
+use crate::config::{Config, LogRotation, LoggingConfig};
+use crate::journal;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+/// Set up logging to stdout and `log_buffer`, plus `logging.log_file` if
+/// `config` is given
+///
+/// The returned guard flushes the file writer when dropped, so it must be
+/// held until the process exits.
+pub fn init(config: Option<&LoggingConfig>, log_buffer: &LogBuffer) -> Option<WorkerGuard> {
+    let file = config
+        .filter(|config| !config.log_file.is_empty())
+        .map(|config| file_writer(config).map(|writer| (writer, config.log_file.clone())));
+    let (file_layer, guard, file_error) = match file {
+        Some(Ok((writer, log_file))) => {
+            let (writer, guard) = tracing_appender::non_blocking(writer);
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_ansi(false)
+                .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+                    metadata.target() != journal::TARGET
+                }));
+            (Some((layer, log_file)), Some(guard), None)
+        }
+        Some(Err(e)) => (None, None, Some(e)),
+        None => (None, None, None),
+    };
+    let log_file = file_layer.as_ref().map(|(_, log_file)| log_file.clone());
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "memo_node=debug,info,mdns_sd=warn".into()),
+        )
+        .with(
+            tracing_subscriber::fmt::layer().with_filter(tracing_subscriber::filter::filter_fn(
+                |metadata| metadata.target() != journal::TARGET,
+            )),
+        )
+        .with(file_layer.map(|(layer, _)| layer))
+        .with(log_buffer.clone())
+        .with(journal::layer())
+        .init();
+
+    if let Some(log_file) = log_file {
+        tracing::debug!("Also logging to {}", log_file);
+    }
+    if let Some(e) = file_error {
+        tracing::warn!("Not logging to a file: {:#}", e);
+    }
+    guard
+}
+
+/// One log event kept by `LogBuffer`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogLine {
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+    /// `ERROR`, `WARN`, `INFO`, `DEBUG` or `TRACE`
+    pub level: String,
+    pub target: String,
+    /// The message followed by any other fields as `name=value`
+    pub message: String,
+}
+
+/// Tracing layer keeping the last `capacity` log lines in memory, so they
+/// can be fetched over the WebSocket API; a capacity of 0 keeps nothing
+#[derive(Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<(Level, LogLine)>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Up to `limit` of the newest lines at `min_level` or more severe,
+    /// oldest first
+    pub fn recent(&self, limit: usize, min_level: Level) -> Vec<LogLine> {
+        let lines = self.lines.lock().unwrap();
+        let mut recent: Vec<LogLine> = lines
+            .iter()
+            .rev()
+            // More verbose levels compare greater
+            .filter(|(level, _)| *level <= min_level)
+            .take(limit)
+            .map(|(_, line)| line.clone())
+            .collect();
+        recent.reverse();
+        recent
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let metadata = event.metadata();
+        if self.capacity == 0 || metadata.target() == journal::TARGET {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let line = LogLine {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        };
+
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back((*metadata.level(), line));
+    }
+}
+
+/// Formats an event's message and fields like the stdout log
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Writer for `config.log_file`, rotated as configured. Relative paths are
+/// resolved against the data directory.
+fn file_writer(config: &LoggingConfig) -> Result<Box<dyn Write + Send>> {
+    let path = Config::data_dir()?.join(&config.log_file);
+    let dir = path.parent().context("Log file has no parent directory")?;
+    std::fs::create_dir_all(dir).context("Failed to create log directory")?;
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("Invalid log file name")?;
+    let max_files = config.max_files.max(1);
+
+    let rotation = match config.rotation {
+        LogRotation::Size => {
+            let max_bytes = config.max_size_mb.max(1) * 1024 * 1024;
+            return Ok(Box::new(SizeRotatingWriter::new(path.clone(), max_bytes, max_files)?));
+        }
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+    };
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name)
+        .max_log_files(max_files)
+        .build(dir)
+        .context("Failed to open log file")?;
+    Ok(Box::new(appender))
+}
+
+/// Appends to `path`, moving it to `path.1` (and `path.1` to `path.2`, ...)
+/// once it reaches `max_bytes`, keeping `max_files` files in total
+struct SizeRotatingWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRotatingWriter {
+    fn new(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<Self> {
+        let file = open_append(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files > 1 {
+            for n in (1..self.max_files - 1).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        } else {
+            std::fs::remove_file(&self.path)?;
+        }
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_rotation_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("memo-node-logs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("memo-node.log");
+        let mut writer = SizeRotatingWriter::new(path.clone(), 10, 3).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "fourth\n");
+        assert_eq!(read(writer.rotated_path(1)), "third\n");
+        assert_eq!(read(writer.rotated_path(2)), "second\n");
+        assert!(!writer.rotated_path(3).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_buffer_keeps_newest_lines_by_level() {
+        let buffer = LogBuffer::new(3);
+        let subscriber = tracing_subscriber::registry().with(buffer.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::warn!(peer = "hub", "second");
+            tracing::debug!("third");
+            tracing::error!("fourth");
+        });
+
+        let messages = |lines: Vec<LogLine>| -> Vec<String> {
+            lines.into_iter().map(|line| line.message).collect()
+        };
+        assert_eq!(messages(buffer.recent(10, Level::TRACE)), ["second peer=hub", "third", "fourth"]);
+        assert_eq!(messages(buffer.recent(10, Level::WARN)), ["second peer=hub", "fourth"]);
+        assert_eq!(messages(buffer.recent(1, Level::TRACE)), ["fourth"]);
+        assert_eq!(buffer.recent(1, Level::ERROR)[0].level, "ERROR");
+    }
+}
@@ -1,32 +1,57 @@
 mod api;
 mod audio;
+mod backup;
+mod clock;
 mod config;
+mod export;
+mod hooks;
+mod journal;
+mod keywords;
+mod led;
+mod logging;
+mod metrics;
+mod monitor;
+mod net;
+mod pipeline;
+mod redact;
+mod status;
 mod storage;
 mod sync;
 mod transcribe;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 use std::sync::atomic::Ordering;
-use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error, info};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use uuid::Uuid;
-
-use api::{HttpClient, WebSocketServer};
-use audio::{BleAudioReceiver, OpusDecoder};
-use config::Config;
-use storage::{Storage, Transcription};
-use sync::{Discovery, PeerManager, PeerSyncServer};
-use transcribe::WhisperTranscriber;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{error, info};
+
+use api::{Feed, RestServer, WebSocketServer};
+use audio::{AudioStreams, BleAudioReceiver, OpusDecoder, RateLimit};
+use config::{BundleLayout, Compute, Config, FeedFormat};
+use export::ExportFormat;
+use metrics::{Metrics, Subsystem};
+use pipeline::TranscriptionPipeline;
+use status::StatusFile;
+use storage::{Metadata, SourceType, Storage, Transcription};
+use sync::{ClusterSecret, Discovery, DiscoveryEvent, PeerManager, PeerSyncServer};
+use transcribe::{TranscriptionQueue, WhisperTranscriber};
 use tracing::warn;
 
 #[derive(Parser)]
 #[command(name = "memo-node")]
 #[command(about = "Memo Network Node - Transcription and sync daemon", long_about = None)]
 struct Cli {
+    /// Data directory for the status file and default database location
+    /// (overrides MEMO_NODE_DATA_DIR)
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+    /// Log storage inserts, HTTPS posts, hooks and peer pushes instead of
+    /// performing them
+    #[arg(long, global = true)]
+    dry_run: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -35,38 +60,230 @@ struct Cli {
 enum Commands {
     /// Start the memo-node daemon
     Start,
+    /// Write a documented default config with a fresh node id
+    Init {
+        /// Overwrite an existing config
+        #[arg(long)]
+        force: bool,
+    },
     /// Show node status
     Status,
+    /// List known peers
+    Peers,
+    /// List the Memo devices connected to the running daemon
+    Devices,
+    /// Compare the transcriptions held here and by another node
+    VerifySync {
+        /// The other node's gRPC address: host or host:port (defaults to
+        /// sync.grpc_port)
+        peer: String,
+        /// Only compare transcriptions newer than this Unix timestamp
+        #[arg(long, default_value = "0")]
+        since: i64,
+    },
+    /// List or switch Whisper models
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
     /// Show recent transcription logs
     Logs {
         /// Number of logs to show
         #[arg(short, long, default_value = "10")]
         limit: usize,
     },
+    /// Export all transcriptions as JSON or CSV
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "json")]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Import transcriptions from a JSON or CSV export
+    Import {
+        /// Export file to read
+        path: PathBuf,
+        /// Input format (guessed from the file extension if omitted)
+        #[arg(short, long, value_enum)]
+        format: Option<ExportFormat>,
+    },
+    /// Store each line of stdin as a transcription from this node
+    Ingest,
+    /// Write the database and user config to a tar archive
+    Backup {
+        /// Archive to create
+        output: PathBuf,
+    },
+    /// Replace the database and user config with those from a backup
+    Restore {
+        /// Archive written by `backup`
+        path: PathBuf,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Live view of a running daemon's audio pipeline, devices and recent
+    /// transcriptions
+    Monitor,
+    /// Re-transcribe saved recordings with another model. Rows whose audio
+    /// `transcription.save_recordings` kept are updated in place, keeping
+    /// their first text as `original_text`; recordings kept by
+    /// `transcription.save_failed_audio` are stored as new rows.
+    Reprocess {
+        /// Model to transcribe with (e.g. small.en)
+        #[arg(long)]
+        model: String,
+        /// Only recordings from this day (YYYY-MM-DD, UTC) onwards
+        #[arg(long)]
+        since: Option<chrono::NaiveDate>,
+    },
+    /// Measure transcription latency on this machine
+    Bench {
+        /// Model to benchmark (defaults to transcription.model)
+        #[arg(long)]
+        model: Option<String>,
+        /// Seconds of synthetic audio to transcribe
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+        /// Transcribe this 16kHz WAV file instead of synthetic audio
+        #[arg(long, conflicts_with = "duration")]
+        wav: Option<PathBuf>,
+        /// Number of timed runs
+        #[arg(long, default_value_t = 5)]
+        runs: u32,
+    },
+    /// Decode a capture of raw BLE audio bundles and report per-bundle stats
+    DecodeFile {
+        /// Capture file: bundles each prefixed with a 2-byte little-endian length
+        path: PathBuf,
+        /// Write the decoded audio to this WAV file
+        #[arg(long)]
+        wav: Option<PathBuf>,
+        /// Bundle header layout, as `audio.bundle_layout`
+        #[arg(long, value_enum, default_value = "auto")]
+        layout: BundleLayout,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModelsCommand {
+    /// Show known models and whether they are downloaded
+    List,
+    /// Validate a model and save it as the configured model
+    Set {
+        /// Model name (e.g. base.en) or full model filename
+        name: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "memo_node=debug,info,mdns_sd=warn".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     let cli = Cli::parse();
+    if let Some(data_dir) = cli.data_dir {
+        Config::set_data_dir_override(data_dir);
+    }
+
+    // Initialize tracing; only the daemon also logs to a file and keeps
+    // recent lines in memory. A config that fails to load is reported by
+    // start_daemon.
+    let log_config = matches!(cli.command, Commands::Start)
+        .then(|| Config::load().ok())
+        .flatten()
+        .map(|config| config.logging);
+    let log_buffer = logging::LogBuffer::new(log_config.as_ref().map_or(0, |config| config.buffer_lines));
+    let _log_guard = logging::init(log_config.as_ref(), &log_buffer);
+    if cli.dry_run {
+        warn!("Dry run: nothing will be stored, posted or pushed to peers");
+    }
 
     match cli.command {
-        Commands::Start => start_daemon().await,
-        Commands::Status => show_status().await,
-        Commands::Logs { limit } => show_logs(limit).await,
+        Commands::Start => start_daemon(cli.dry_run, log_buffer).await,
+        Commands::Init { force } => init_config(force, cli.dry_run),
+        Commands::Status => show_status(cli.dry_run).await,
+        Commands::Peers => show_peers(cli.dry_run).await,
+        Commands::Devices => show_devices(),
+        Commands::VerifySync { peer, since } => verify_sync(&peer, since, cli.dry_run).await,
+        Commands::Models { command } => match command {
+            ModelsCommand::List => list_models(),
+            ModelsCommand::Set { name } => set_model(&name),
+        },
+        Commands::Logs { limit } => show_logs(limit, cli.dry_run).await,
+        Commands::Export { format, output } => {
+            export_transcriptions(format, output.as_deref(), cli.dry_run)
+        }
+        Commands::Import { path, format } => import_transcriptions(&path, format, cli.dry_run),
+        Commands::Ingest => ingest_stdin(cli.dry_run).await,
+        Commands::Backup { output } => backup_node(&output, cli.dry_run),
+        Commands::Restore { path, yes } => restore_node(&path, yes),
+        Commands::Monitor => {
+            let config = Config::load()?;
+            let url = format!("ws://{}:{}", config.api.listen_address, config.api.websocket_port);
+            monitor::run(&url).await
+        }
+        Commands::Reprocess { model, since } => reprocess(&model, since, cli.dry_run).await,
+        Commands::Bench {
+            model,
+            duration,
+            wav,
+            runs,
+        } => bench_model(model, duration, wav.as_deref(), runs),
+        Commands::DecodeFile { path, wav, layout } => {
+            decode_file(&path, wav.as_deref(), layout)
+        }
+    }
+}
+
+fn init_config(force: bool, dry_run: bool) -> Result<()> {
+    let node_id = format!("memo-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+
+    if dry_run {
+        println!(
+            "Dry run: would write a default config with node id {} to {}",
+            node_id,
+            Config::user_config_path()?.display()
+        );
+        return Ok(());
     }
+
+    let path = Config::write_default_user_config(&node_id, force)?;
+    println!("Wrote default config to {}", path.display());
+    println!("Node id: {} (change [node] id to something memorable)", node_id);
+
+    Ok(())
+}
+
+/// Open the configured storage; with `dry_run` the database is opened
+/// read-only and never migrated
+fn open_storage(config: &Config, dry_run: bool) -> Result<Storage> {
+    let storage_path = config.storage_path()?;
+    let storage = if dry_run && config.storage.rolling {
+        Storage::new_rolling_read_only(&storage_path, config.storage.max_open_files)?
+    } else if dry_run {
+        Storage::open_read_only(&storage_path)?
+    } else if config.storage.rolling {
+        Storage::new_rolling(
+            &storage_path,
+            config.storage.max_open_files,
+            config.storage.auto_recover,
+        )?
+    } else {
+        Storage::open(&storage_path, config.storage.auto_recover)?
+    };
+    Ok(if config.storage.short_codes {
+        storage.with_short_codes(&config.storage.short_code_prefix)
+    } else {
+        storage
+    })
 }
 
-async fn start_daemon() -> Result<()> {
+/// How long shutdown waits for in-progress and final transcriptions
+const SHUTDOWN_TRANSCRIPTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn start_daemon(dry_run: bool, log_buffer: logging::LogBuffer) -> Result<()> {
     info!("Starting memo-node daemon");
+    let started = std::time::Instant::now();
 
     // Load configuration
     let config = Config::load()?;
@@ -74,58 +291,190 @@ async fn start_daemon() -> Result<()> {
 
     // Initialize storage
     let storage_path = config.storage_path()?;
-    let storage = Storage::new(&storage_path)?;
+    let mut storage = open_storage(&config, dry_run)?
+        .with_dry_run(dry_run)
+        .with_id_collision(config.storage.on_id_collision);
     info!("Storage initialized at {}", storage_path.display());
 
-    // Initialize HTTP client if endpoint is configured
-    let http_client: Option<Arc<HttpClient>> = if let Some(ref endpoint) = config.api.https_endpoint {
-        if endpoint.is_empty() {
-            None
-        } else {
-            match HttpClient::new(endpoint.clone()) {
-                Ok(client) => {
-                    info!("HTTP client initialized for endpoint: {}", endpoint);
-                    Some(Arc::new(client))
-                }
-                Err(e) => {
-                    warn!("Failed to initialize HTTP client: {}. HTTPS posting will be disabled.", e);
-                    None
+    if config.storage.min_free_mb > 0 {
+        storage = storage.with_min_free_space(&storage_path, config.storage.min_free_mb);
+    }
+
+    if config.storage.read_connection {
+        storage = storage.with_read_connection(&storage_path)?;
+        info!("Serving queries from a separate read connection");
+    }
+    if config.storage.write_buffer_rows > 0 {
+        let max_delay = Duration::from_millis(config.storage.write_buffer_ms);
+        storage = storage.with_write_buffer(config.storage.write_buffer_rows, max_delay);
+        info!(
+            "Buffering writes: up to {} rows or {}ms per commit",
+            config.storage.write_buffer_rows, config.storage.write_buffer_ms
+        );
+
+        let storage = storage.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(max_delay.max(Duration::from_millis(10)));
+            loop {
+                ticker.tick().await;
+                if let Err(e) = storage.flush_if_due() {
+                    error!("Failed to flush buffered writes: {:#}", e);
                 }
             }
-        }
-    } else {
-        None
-    };
+        });
+    }
+
+    let metrics = Arc::new(Metrics::default());
+
+    // Record daemon runtime state for the `status` command
+    let status_file = StatusFile::create(Config::status_path()?)?;
 
     // Create channels for new transcriptions
     let (transcription_tx, transcription_rx) = mpsc::unbounded_channel::<Transcription>();
     let (ws_broadcast_tx, _) = broadcast::channel::<Transcription>(100);
 
+    // Initialize audio pipeline (created early so the WebSocket server can
+    // report the recording state)
+    let service_uuid = config
+        .audio
+        .memo_service_uuid
+        .parse()
+        .context("Invalid service UUID")?;
+    let char_uuids = config
+        .audio
+        .audio_characteristic_uuids()
+        .into_iter()
+        .map(|uuid| {
+            uuid.parse()
+                .with_context(|| format!("Invalid characteristic UUID {}", uuid))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (ble_receiver, audio_rx, is_recording) = BleAudioReceiver::new(service_uuid, char_uuids);
+
+    // Keep the status file's metrics and recording state fresh
+    let status_file_heartbeat = status_file.clone();
+    let metrics_heartbeat = metrics.clone();
+    let is_recording_heartbeat = is_recording.clone();
+    let storage_heartbeat = storage.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(tokio::time::Duration::from_secs(10));
+        loop {
+            ticker.tick().await;
+            let snapshot = metrics_heartbeat.snapshot();
+            let recording = is_recording_heartbeat.load(Ordering::Acquire);
+            let low_disk_space = storage_heartbeat.is_low_on_disk_space();
+            if let Err(e) = status_file_heartbeat.update(|status| {
+                status.metrics = snapshot;
+                status.recording = recording;
+                status.low_disk_space = low_disk_space;
+            }) {
+                warn!("Failed to update status file: {}", e);
+            }
+        }
+    });
+
+    let (recording_events_tx, recording_events_rx) = mpsc::unbounded_channel();
+
     // Initialize WebSocket server for memo-desktop
     let ws_addr = format!("{}:{}", config.api.listen_address, config.api.websocket_port)
         .parse()
         .context("Invalid WebSocket address")?;
-    let ws_server = WebSocketServer::new(storage.clone(), ws_broadcast_tx.clone());
+    let ws_listener = net::bind_with_retry(ws_addr, config.api.bind_retries, "WebSocket").await?;
+    let (peer_events_tx, peer_events_rx) = mpsc::unbounded_channel();
+    // Context attached to new transcriptions, changeable over the WebSocket API
+    let transcription_context = Arc::new(std::sync::Mutex::new(Metadata(
+        config.transcription.context.clone(),
+    )));
+    let ws_server = WebSocketServer::new(storage.clone(), ws_broadcast_tx.clone(), metrics.clone())
+        .with_context(transcription_context.clone())
+        .with_log_buffer(log_buffer)
+        .with_max_clients(config.api.max_clients)
+        .with_history_chunk_size(config.api.history_chunk_size)
+        .with_stats_interval(Duration::from_secs(config.api.stats_interval_secs))
+        .with_close_on_fatal_storage_error(config.api.close_on_fatal_storage_error)
+        .with_peer_events(peer_events_rx)
+        .with_recording_state(is_recording.clone())
+        .with_recording_events(recording_events_rx);
+    let ws_events_tx = ws_server.event_sender();
 
-    tokio::spawn(async move {
-        if let Err(e) = ws_server.serve(ws_addr).await {
-            error!("WebSocket server error: {}", e);
+    // Servers are restarted if they exit; one that keeps failing stops the daemon
+    let (fatal_tx, mut fatal_rx) = mpsc::unbounded_channel();
+    let ws_server = ws_server.start();
+    spawn_supervised(
+        "WebSocket",
+        ws_listener,
+        config.api.bind_retries,
+        config.api.max_restarts,
+        fatal_tx.clone(),
+        move |listener| {
+            let ws_server = ws_server.clone();
+            async move { ws_server.accept(listener).await }
+        },
+    );
+
+    if config.api.rest_port != 0 {
+        let rest_addr = format!("{}:{}", config.api.listen_address, config.api.rest_port)
+            .parse()
+            .context("Invalid REST address")?;
+        let rest_listener = net::bind_with_retry(rest_addr, config.api.bind_retries, "REST").await?;
+        let mut rest_server = RestServer::new(storage.clone());
+        if config.api.feed != FeedFormat::Off {
+            rest_server = rest_server.with_feed(Feed::new(
+                config.api.feed,
+                config.api.feed_items,
+                config.node.display_name(),
+            ));
+            info!("Serving a {:?} feed at /feed on the REST port", config.api.feed);
         }
-    });
+        let rest_server = Arc::new(rest_server);
+        spawn_supervised(
+            "REST",
+            rest_listener,
+            config.api.bind_retries,
+            config.api.max_restarts,
+            fatal_tx.clone(),
+            move |listener| {
+                let rest_server = rest_server.clone();
+                async move { rest_server.serve(listener).await }
+            },
+        );
+    } else if config.api.feed != FeedFormat::Off {
+        warn!("api.feed is set but the REST API is off; set api.rest_port to serve it");
+    }
+
+    // Queue of finished recordings, shared with the gRPC server so relay
+    // peers can use this node's engines
+    let (transcription_queue, transcription_jobs) =
+        TranscriptionQueue::bounded(config.transcription.queue_size);
 
     // Initialize gRPC server for peer sync
-    let grpc_server = PeerSyncServer::new(
+    let cluster_secret = ClusterSecret::new(config.sync.cluster_secret.as_deref())?;
+    let mut grpc_server = PeerSyncServer::new(
         config.node.id.clone(),
         storage.clone(),
         transcription_tx.clone(),
-    );
-    let grpc_port = config.sync.grpc_port;
+    )
+    .with_cluster_secret(cluster_secret.clone())
+    .with_reject_invalid_signatures(config.sync.reject_invalid_signatures);
+    if config.transcription.serve_remote {
+        info!("Accepting audio from relay peers for transcription");
+        grpc_server = grpc_server.with_transcriber(
+            transcription_queue.clone(),
+            Duration::from_secs(config.transcription.serve_remote_max_secs),
+        );
+    }
+    let grpc_addr = std::net::SocketAddr::new(config.sync.bind_address, config.sync.grpc_port);
+    let grpc_listener = net::bind_with_retry(grpc_addr, config.sync.bind_retries, "gRPC").await?;
 
-    tokio::spawn(async move {
-        if let Err(e) = grpc_server.serve(grpc_port).await {
-            error!("gRPC server error: {}", e);
-        }
-    });
+    spawn_supervised(
+        "gRPC",
+        grpc_listener,
+        config.sync.bind_retries,
+        config.sync.max_restarts,
+        fatal_tx.clone(),
+        move |listener| grpc_server.clone().serve(listener),
+    );
 
     // Bridge: forward transcriptions from gRPC to WebSocket broadcast
     let ws_broadcast_tx_clone = ws_broadcast_tx.clone();
@@ -137,11 +486,58 @@ async fn start_daemon() -> Result<()> {
     });
 
     // Initialize peer manager
-    let peer_manager = Arc::new(PeerManager::new(
-        config.node.id.clone(),
-        storage.clone(),
-        config.sync.sync_interval,
-    ));
+    let peer_directions = config
+        .sync
+        .peers
+        .iter()
+        .filter_map(|peer| Some((peer.node_id.clone(), peer.direction?)))
+        .collect();
+    let peer_source_nodes = config
+        .sync
+        .peers
+        .iter()
+        .filter_map(|peer| Some((peer.node_id.clone(), peer.source_nodes.clone()?)))
+        .collect();
+    let peer_manager = Arc::new(
+        PeerManager::new(
+            config.node.id.clone(),
+            storage.clone(),
+            config.sync.sync_interval,
+            config.sync.full_reconcile_interval,
+        )
+        .with_directions(config.sync.default_direction, peer_directions)
+        .with_source_filters(config.sync.default_source_nodes.clone(), peer_source_nodes)
+        .with_events(peer_events_tx)
+        .with_metrics(metrics.clone())
+        .with_clock_skew(config.sync.clock_skew, config.sync.max_clock_skew_secs)
+        .with_batch_pulls(config.sync.batch_pulls)
+        .with_reject_invalid_signatures(config.sync.reject_invalid_signatures)
+        .with_cluster_secret(cluster_secret.clone())
+        .with_peer_expiry(config.sync.peer_expiry_secs)
+        .with_dry_run(dry_run),
+    );
+
+    // Peers from previous runs; mDNS updates their addresses as it finds them
+    match peer_manager.restore_peers().await {
+        Ok(0) => {}
+        Ok(count) => info!("Restored {} known peers", count),
+        Err(e) => warn!("Failed to restore known peers: {:#}", e),
+    }
+
+    for peer in &config.sync.peers {
+        info!(
+            "Adding static peer: {} at {}:{}",
+            peer.node_id, peer.address, peer.grpc_port
+        );
+        peer_manager
+            .add_peer(
+                peer.node_id.clone(),
+                peer.display_name.clone(),
+                peer.address,
+                peer.grpc_port,
+            )
+            .await;
+    }
 
     // Start sync loop
     let peer_manager_clone = peer_manager.clone();
@@ -149,183 +545,503 @@ async fn start_daemon() -> Result<()> {
         peer_manager_clone.start_sync_loop().await;
     });
 
-    // Initialize mDNS discovery
-    let (discovery, mut peer_rx) = Discovery::new(config.node.id.clone(), config.sync.grpc_port)?;
-    discovery.start()?;
+    // Initialize mDNS discovery; kept alive until shutdown
+    let _discovery = if config.sync.discovery_enabled {
+        let (discovery, mut peer_rx) = Discovery::new(
+            config.node.id.clone(),
+            config.node.display_name().to_string(),
+            config.sync.grpc_port,
+        )?;
+        let discovery = discovery.with_cluster(cluster_secret.fingerprint());
+        discovery.start()?;
 
-    // Handle discovered peers
-    let peer_manager_clone = peer_manager.clone();
-    tokio::spawn(async move {
-        while let Some(peer) = peer_rx.recv().await {
-            info!("Adding peer: {} at {}:{}", peer.node_id, peer.address, peer.grpc_port);
-            peer_manager_clone
-                .add_peer(peer.node_id, peer.address, peer.grpc_port)
-                .await;
-        }
-    });
+        // Handle discovered peers; static peers stay even if their mDNS record goes
+        let static_peers: std::collections::HashSet<String> =
+            config.sync.peers.iter().map(|peer| peer.node_id.clone()).collect();
+        let peer_manager_clone = peer_manager.clone();
+        let metrics_discovery = metrics.clone();
+        let own_node_id = config.node.id.clone();
+        tokio::spawn(async move {
+            while let Some(event) = peer_rx.recv().await {
+                match event {
+                    DiscoveryEvent::Found(peer) => {
+                        info!(
+                            "Adding peer: {} ({}) at {}:{}",
+                            peer.display_name.as_deref().unwrap_or(&peer.node_id),
+                            peer.node_id,
+                            peer.address,
+                            peer.grpc_port
+                        );
+                        peer_manager_clone
+                            .add_peer(peer.node_id, peer.display_name, peer.address, peer.grpc_port)
+                            .await;
+                    }
+                    DiscoveryEvent::Lost { node_id } => {
+                        if !static_peers.contains(&node_id) {
+                            info!("Removing peer: {}", node_id);
+                            peer_manager_clone.remove_peer(&node_id).await;
+                        }
+                    }
+                    DiscoveryEvent::DuplicateNodeId { address } => {
+                        metrics_discovery.record_error(
+                            Subsystem::Sync,
+                            format!("Node at {} uses our node_id {}", address, own_node_id),
+                        );
+                    }
+                }
+            }
+        });
 
-    // Initialize audio pipeline
-    let service_uuid = config
-        .audio
-        .memo_service_uuid
-        .parse()
-        .context("Invalid service UUID")?;
-    let char_uuid = config
-        .audio
-        .memo_characteristic_uuid
-        .parse()
-        .context("Invalid characteristic UUID")?;
+        Some(discovery)
+    } else {
+        info!("mDNS discovery disabled; syncing with static peers only");
+        None
+    };
 
-    let (ble_receiver, mut audio_rx, is_recording) = BleAudioReceiver::new(service_uuid, char_uuid);
+    // Start the BLE audio receiver
+    let mut ble_receiver = ble_receiver
+        .with_rate_limit(RateLimit {
+            max_packets_per_sec: config.audio.max_packets_per_sec,
+            max_bytes_per_sec: config.audio.max_bytes_per_sec,
+        })
+        .with_metrics(metrics.clone())
+        .with_disconnect_grace(Duration::from_millis(config.audio.disconnect_grace_ms))
+        .with_command_cooldown(Duration::from_millis(config.audio.command_cooldown_ms))
+        .with_max_devices(config.audio.max_devices)
+        .with_watchdog(Duration::from_secs(config.audio.watchdog_timeout_secs))
+        .with_adapters(config.audio.ble_adapters.clone());
+    if !config.audio.battery_characteristic_uuid.is_empty() {
+        let battery_uuid = config
+            .audio
+            .battery_characteristic_uuid
+            .parse()
+            .context("Invalid battery characteristic UUID")?;
+        ble_receiver = ble_receiver.with_battery(
+            battery_uuid,
+            Duration::from_secs(config.audio.battery_poll_secs.max(1)),
+        );
+    }
+    if config.api.recording_events {
+        ble_receiver = ble_receiver.with_recording_events(recording_events_tx);
+    }
     let ble_receiver = Arc::new(ble_receiver);
 
+    if let Some(pin) = config.audio.led_gpio_pin {
+        led::spawn_recording_led(pin, is_recording.clone());
+    }
+
+    let metrics_ble = metrics.clone();
     tokio::spawn(async move {
         if let Err(e) = ble_receiver.start().await {
             error!("BLE receiver error: {}", e);
+            metrics_ble.record_error(Subsystem::Ble, format!("{:#}", e));
         }
     });
 
-    // Initialize audio decoder
+    // Initialize audio decoder. BLE audio starts arriving while the model is
+    // still downloading or loading; hold a bounded amount of it until the
+    // transcriber is ready
     let (decoded_tx, decoded_rx) = mpsc::unbounded_channel();
-    let is_recording_decoder = is_recording.clone();
-    tokio::spawn(async move {
-        let mut decoder = OpusDecoder::new(16000, audiopus::Channels::Mono).unwrap();
-
-        while let Some(encoded_audio) = audio_rx.recv().await {
-            // Only decode if we're recording
-            if !is_recording_decoder.load(Ordering::Acquire) {
-                continue;
-            }
-
-            match decoder.decode(&encoded_audio) {
-                Ok(decoded) => {
-                    if !decoded.is_empty() {
-                        if let Err(e) = decoded_tx.send(decoded) {
-                            error!("Failed to send decoded audio: {}", e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    // Only log decode errors at debug level to reduce noise
-                    debug!("Failed to decode audio: {}", e);
-                }
-            }
-        }
-    });
+    let (transcriber_ready_tx, transcriber_ready_rx) = oneshot::channel::<()>();
+    let startup_buffer_samples =
+        config.transcription.startup_buffer_secs as usize * transcribe::SAMPLE_RATE as usize;
+    let audio_streams =
+        AudioStreams::new(config.audio.clone(), is_recording.clone(), metrics.clone())?
+            .with_startup_buffer(startup_buffer_samples);
+    tokio::spawn(audio_streams.run(audio_rx, decoded_tx, transcriber_ready_rx));
 
     // Initialize transcriber
     let is_recording_transcriber = is_recording.clone();
-    let (transcriber, mut transcription_rx) = WhisperTranscriber::new(
-        &config.transcription.model,
-        config.transcription.threads,
-        decoded_rx,
-        is_recording_transcriber,
-    )?;
-
-    tokio::spawn(async move {
-        if let Err(e) = transcriber.start().await {
-            error!("Transcriber error: {}", e);
+    let (transcriber, mut transcription_rx) = match &config.transcription.remote_transcriber {
+        Some(address) if !address.is_empty() => WhisperTranscriber::remote(
+            address.clone(),
+            cluster_secret.clone(),
+            decoded_rx,
+            is_recording_transcriber,
+            metrics.clone(),
+        ),
+        _ => {
+            let compute = transcribe::select_compute(config.transcription.compute);
+            let (transcriber, transcription_rx) = WhisperTranscriber::new(
+                &config.transcription.model,
+                &config.transcription.fallback_models,
+                config.transcription.threads,
+                compute,
+                decoded_rx,
+                is_recording_transcriber,
+                metrics.clone(),
+            )?;
+            (
+                transcriber.with_pool(config.transcription.engines)?,
+                transcription_rx,
+            )
         }
+    };
+    let transcriber = if config.transcription.continuous {
+        transcriber.with_continuous(config.transcription.continuous_window_secs)
+    } else {
+        transcriber
+    };
+    let failed_audio_dir = if config.transcription.save_failed_audio {
+        Some(Config::failed_audio_dir()?)
+    } else {
+        None
+    };
+    let (transcriber_shutdown_tx, transcriber_shutdown_rx) = oneshot::channel();
+    let transcriber = transcriber
+        .with_retries(config.transcription.retries, failed_audio_dir)
+        .with_recording_format(config.audio.recording_format)
+        .with_max_restarts(config.transcription.max_restarts)
+        .with_cooldown(Duration::from_millis(config.transcription.cooldown_ms))
+        .with_shutdown(transcriber_shutdown_rx);
+    let transcriber = if config.transcription.context_priming {
+        transcriber.with_context_priming(
+            config.transcription.context_priming_transcriptions,
+            config.transcription.context_priming_max_chars,
+        )
+    } else {
+        transcriber
+    };
+    let _ = transcriber_ready_tx.send(());
+
+    // Runs in its own task so a panic is caught here; either way, audio
+    // would otherwise pile up untranscribed, so the daemon exits
+    let metrics_transcriber = metrics.clone();
+    let transcriber_task = tokio::spawn(async move {
+        let result = tokio::spawn(transcriber.start(transcription_queue, transcription_jobs)).await;
+        let e = match result {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e,
+            Err(e) => anyhow::anyhow!("Transcriber task died: {}", e),
+        };
+        error!("Transcriber stopped: {:#}", e);
+        metrics_transcriber.record_error(Subsystem::Transcription, format!("{:#}", e));
+        let _ = fatal_tx.send(e);
     });
 
     // Handle transcriptions
-    let node_id = config.node.id.clone();
-    let storage_clone = storage.clone();
-    let ws_broadcast_tx_clone2 = ws_broadcast_tx.clone();
-    let http_client_clone = http_client.clone();
+    let pipeline = TranscriptionPipeline::new(&config, storage.clone(), metrics.clone())?
+        .with_dry_run(dry_run)
+        .with_context(transcription_context)
+        .with_broadcast(ws_broadcast_tx.clone(), ws_events_tx)
+        .with_status_file(status_file.clone());
 
-    tokio::spawn(async move {
-        while let Some(text) = transcription_rx.recv().await {
-            let timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
-
-            let transcription = Transcription {
-                id: Uuid::new_v4().to_string(),
-                timestamp,
-                text: text.clone(),
-                source_node: node_id.clone(),
-                memo_device_id: None,
-                synced: false,
-            };
-
-            // Store in database
-            if let Err(e) = storage_clone.insert_transcription(&transcription) {
-                error!("Failed to store transcription: {}", e);
-            } else {
-                info!("Stored transcription: {}", transcription.text);
-                let _ = ws_broadcast_tx_clone2.send(transcription.clone());
-
-                // Post to HTTPS endpoint if configured
-                if let Some(client) = &http_client_clone {
-                    let transcription_clone = transcription.clone();
-                    let client_clone = client.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = client_clone
-                            .post_transcription(
-                                &transcription_clone.id,
-                                transcription_clone.timestamp,
-                                &transcription_clone.text,
-                                &transcription_clone.source_node,
-                                transcription_clone.memo_device_id.as_deref(),
-                            )
-                            .await
-                        {
-                            // Log error but don't crash - HTTP failures shouldn't block transcription
-                            warn!("Failed to post transcription to HTTPS endpoint: {}", e);
-                        }
-                    });
-                }
-            }
+    // Ends once the transcriber has stopped and its last result is stored
+    let pipeline_task = tokio::spawn(async move {
+        while let Some(recording) = transcription_rx.recv().await {
+            pipeline.process_recording(&recording);
         }
+        pipeline.flush().await;
     });
 
     info!("memo-node daemon started successfully");
     info!("WebSocket API: {}:{}", config.api.listen_address, config.api.websocket_port);
-    info!("gRPC peer sync: 0.0.0.0:{}", config.sync.grpc_port);
+    info!("gRPC peer sync: {}", grpc_addr);
 
     // Keep running
-    tokio::signal::ctrl_c().await?;
-    info!("Shutting down...");
+    let fatal = tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            None
+        }
+        Some(e) = fatal_rx.recv() => Some(e),
+    };
+    match &fatal {
+        None => info!("Shutting down..."),
+        Some(e) => error!("Shutting down: {:#}", e),
+    }
+    // Let an in-progress transcription finish and the audio recorded since
+    // be transcribed and stored before buffered writes are flushed
+    let _ = transcriber_shutdown_tx.send(());
+    let drained = async {
+        let _ = transcriber_task.await;
+        let _ = pipeline_task.await;
+    };
+    if tokio::time::timeout(SHUTDOWN_TRANSCRIPTION_TIMEOUT, drained).await.is_err() {
+        warn!(
+            "Gave up waiting for transcriptions after {}s; the last recording may be lost",
+            SHUTDOWN_TRANSCRIPTION_TIMEOUT.as_secs()
+        );
+    }
+    if let Err(e) = storage.flush() {
+        error!("Failed to flush buffered writes on shutdown: {:#}", e);
+    }
+    status_file.remove();
+    info!("{}", metrics.session_summary(started.elapsed().as_secs() as i64));
 
-    Ok(())
+    fatal.map_or(Ok(()), Err)
+}
+
+/// Run a server under `net::supervise` in the background, reporting it on
+/// `fatal_tx` if it can't be kept running
+fn spawn_supervised<F, Fut>(
+    name: &'static str,
+    listener: tokio::net::TcpListener,
+    bind_retries: u32,
+    max_restarts: u32,
+    fatal_tx: mpsc::UnboundedSender<anyhow::Error>,
+    serve: F,
+) where
+    F: FnMut(tokio::net::TcpListener) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Result<()>> + Send,
+{
+    tokio::spawn(async move {
+        if let Err(e) = net::supervise(name, listener, bind_retries, max_restarts, serve).await {
+            let _ = fatal_tx.send(e);
+        }
+    });
 }
 
-async fn show_status() -> Result<()> {
+async fn show_status(dry_run: bool) -> Result<()> {
     let config = Config::load()?;
-    let storage_path = config.storage_path()?;
-    let storage = Storage::new(&storage_path)?;
+    let storage = open_storage(&config, dry_run)?;
 
     let (total, synced) = storage.count_transcriptions()?;
     let local = total - synced;
     let peers = storage.get_peers()?;
+    let daemon = StatusFile::read(&Config::status_path()?)?;
+
+    let now = clock::unix_now();
+
+    if config.node.display_name() == config.node.id {
+        println!("Node: {}", config.node.id);
+    } else {
+        println!("Node: {} ({})", config.node.display_name(), config.node.id);
+    }
+
+    match daemon {
+        Some(daemon) if !daemon.is_stale(now) => {
+            println!(
+                "Daemon: running (pid {}, up {})",
+                daemon.pid,
+                status::format_duration(daemon.uptime_secs(now))
+            );
+            if daemon.low_disk_space {
+                println!(
+                    "  WARNING: disk almost full (below storage.min_free_mb); \
+                     new transcriptions are not being stored"
+                );
+            }
+            println!("  Transcriptions since start: {}", daemon.transcriptions_since_start);
+            match daemon.last_transcription_at {
+                Some(ts) => {
+                    let formatted = chrono::DateTime::from_timestamp(ts, 0)
+                        .unwrap()
+                        .format("%Y-%m-%d %H:%M:%S");
+                    println!("  Last transcription: {} ({}s ago)", formatted, now - ts);
+                }
+                None => println!("  Last transcription: (none)"),
+            }
+            println!(
+                "  Dropped transcriptions: {} empty, {} blocklisted, {} too short",
+                daemon.metrics.transcriptions_empty,
+                daemon.metrics.transcriptions_blocklisted,
+                daemon.metrics.transcriptions_too_short
+            );
+            if daemon.metrics.broadcast_dropped > 0 {
+                println!("  Dropped live broadcasts: {}", daemon.metrics.broadcast_dropped);
+            }
+            if !daemon.metrics.last_errors.is_empty() {
+                println!("  Last errors:");
+                for (subsystem, last_error) in &daemon.metrics.last_errors {
+                    let formatted = chrono::DateTime::from_timestamp(last_error.at, 0)
+                        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    println!(
+                        "    {}: {} ({}, {}s ago)",
+                        subsystem.as_str(),
+                        last_error.message,
+                        formatted,
+                        now - last_error.at
+                    );
+                }
+            }
+        }
+        Some(daemon) => {
+            println!(
+                "Daemon: not running (stale status from pid {}, last update {}s ago)",
+                daemon.pid,
+                now - daemon.updated_at
+            );
+        }
+        None => println!("Daemon: not running"),
+    }
 
-    println!("Node: {}", config.node.id);
     println!("Transcriptions: {} local, {} synced", local, synced);
     println!("Peers:");
 
     if peers.is_empty() {
         println!("  (none)");
     } else {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-
         for peer in peers {
             let seconds_ago = now - peer.last_seen;
-            println!("  {} (last seen {}s ago)", peer.node_id, seconds_ago);
+            println!("  {} (last seen {}s ago)", peer.display_name(), seconds_ago);
         }
     }
 
     Ok(())
 }
 
-async fn show_logs(limit: usize) -> Result<()> {
+async fn show_peers(dry_run: bool) -> Result<()> {
     let config = Config::load()?;
-    let storage_path = config.storage_path()?;
-    let storage = Storage::new(&storage_path)?;
+    let storage = open_storage(&config, dry_run)?;
+
+    let peers = storage.get_peers()?;
+
+    if peers.is_empty() {
+        println!("No known peers");
+        return Ok(());
+    }
+
+    let now = clock::unix_now();
+
+    println!("Peers:");
+    for peer in peers {
+        let last_sync = chrono::DateTime::from_timestamp(peer.last_sync_timestamp, 0)
+            .unwrap()
+            .format("%Y-%m-%d %H:%M:%S");
+        println!(
+            "  {} [{}] (last seen {}s ago, synced up to {})",
+            peer.display_name(),
+            peer.node_id,
+            now - peer.last_seen,
+            last_sync
+        );
+        if let (Some(address), Some(grpc_port)) = (&peer.address, peer.grpc_port) {
+            println!("    at {}:{}", address, grpc_port);
+        }
+        if let Some(offset) = peer.clock_offset.filter(|offset| *offset != 0) {
+            println!("    clock {:+}s from ours", offset);
+        }
+    }
+
+    Ok(())
+}
+
+fn show_devices() -> Result<()> {
+    let now = clock::unix_now();
+    let daemon = match StatusFile::read(&Config::status_path()?)? {
+        Some(daemon) if !daemon.is_stale(now) => daemon,
+        _ => {
+            println!("Daemon: not running");
+            return Ok(());
+        }
+    };
+
+    let devices = &daemon.metrics.ble_devices;
+    if devices.is_empty() {
+        println!("No devices connected");
+        return Ok(());
+    }
+
+    println!(
+        "Devices ({}, as of {}s ago):",
+        if daemon.recording { "recording" } else { "not recording" },
+        now - daemon.updated_at
+    );
+    for (name, rssi) in devices {
+        let rssi = rssi.map(|r| format!("{} dBm", r)).unwrap_or_else(|| "? dBm".to_string());
+        let received = daemon.metrics.ble_bytes_received.get(name).copied().unwrap_or(0);
+        let battery = daemon
+            .metrics
+            .ble_battery
+            .get(name)
+            .map(|battery| format!(", {}% battery", battery))
+            .unwrap_or_default();
+        println!(
+            "  {:<24} {:<8} {:.1} KiB received{}",
+            name,
+            rssi,
+            received as f64 / 1024.0,
+            battery
+        );
+    }
+
+    Ok(())
+}
+
+async fn verify_sync(peer: &str, since: i64, dry_run: bool) -> Result<()> {
+    let config = Config::load()?;
+    let storage = open_storage(&config, dry_run)?;
+    let cluster_secret = ClusterSecret::new(config.sync.cluster_secret.as_deref())?;
+
+    let addr = sync::verify::peer_url(peer, config.sync.grpc_port);
+
+    let diff = sync::verify::verify_sync(&storage, &cluster_secret, addr.clone(), since).await?;
+    println!(
+        "{} transcriptions here, {} on {}",
+        diff.local_count, diff.remote_count, addr
+    );
+    if diff.is_consistent() {
+        println!("Both nodes hold the same transcriptions");
+        return Ok(());
+    }
+
+    if !diff.missing_locally.is_empty() {
+        println!("Missing here ({}):", diff.missing_locally.len());
+        for id in &diff.missing_locally {
+            println!("  {}", id);
+        }
+    }
+    if !diff.missing_on_peer.is_empty() {
+        println!("Missing on the peer ({}):", diff.missing_on_peer.len());
+        for id in &diff.missing_on_peer {
+            println!("  {}", id);
+        }
+    }
+    anyhow::bail!("Nodes hold different transcriptions")
+}
+
+fn list_models() -> Result<()> {
+    let config = Config::load()?;
+    let current = &config.transcription.model;
+
+    match transcribe::model_cache_dir() {
+        Some(dir) => println!("Models (cache: {}):", dir.display()),
+        None => println!("Models:"),
+    }
+
+    let mut names: Vec<&str> = transcribe::KNOWN_MODELS.to_vec();
+    if !names.contains(&current.as_str()) {
+        names.push(current);
+    }
+
+    for name in names {
+        let marker = if name == current { "*" } else { " " };
+        let file = transcribe::map_model_name_to_path(name).unwrap_or_else(|_| "?".to_string());
+        let state = match transcribe::is_model_cached(name) {
+            Ok(true) => "downloaded",
+            Ok(false) => "not downloaded",
+            Err(_) => "invalid",
+        };
+        println!("{} {:<10} {:<26} {}", marker, name, file, state);
+    }
+
+    Ok(())
+}
+
+fn set_model(name: &str) -> Result<()> {
+    // Rejects unknown names; warns if the model is too large for a Pi
+    transcribe::map_model_name_to_path(name)?;
+    let compute = Config::load()?.transcription.compute;
+    if transcribe::detect_compute(compute) == Compute::Cpu {
+        transcribe::validate_model_for_pi(name)?;
+    }
+
+    let path = Config::set_user_value("transcription", "model", toml::Value::String(name.to_string()))?;
+    println!("Model set to {} in {}", name, path.display());
+
+    if !transcribe::is_model_cached(name)? {
+        println!("The model will be downloaded the next time the daemon starts");
+    }
+    println!("Restart the daemon to use the new model");
+
+    Ok(())
+}
+
+async fn show_logs(limit: usize, dry_run: bool) -> Result<()> {
+    let config = Config::load()?;
+    let storage = open_storage(&config, dry_run)?;
 
     let transcriptions = storage.get_recent_transcriptions(limit)?;
 
@@ -339,11 +1055,482 @@ async fn show_logs(limit: usize) -> Result<()> {
         let timestamp = chrono::DateTime::from_timestamp(t.timestamp, 0)
             .unwrap()
             .format("%Y-%m-%d %H:%M:%S");
+        let tags: String = storage
+            .get_tags(t)?
+            .iter()
+            .map(|tag| format!(" #{}", tag))
+            .collect();
+        let duration = t
+            .duration_ms
+            .map(|ms| format!(" ({:.1}s)", ms as f64 / 1000.0))
+            .unwrap_or_default();
         println!(
-            "[{}] [{}] {}",
-            timestamp, t.source_node, t.text
+            "[{}] [{}]{} {}{}",
+            timestamp, t.source_node, duration, t.text, tags
         );
     }
 
     Ok(())
 }
+
+fn export_transcriptions(
+    format: ExportFormat,
+    output: Option<&std::path::Path>,
+    dry_run: bool,
+) -> Result<()> {
+    let config = Config::load()?;
+    let storage = open_storage(&config, dry_run)?;
+    let transcriptions = storage.get_all_transcriptions()?;
+
+    match output {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            export::write_transcriptions(std::io::BufWriter::new(file), format, &transcriptions)?;
+            println!("Exported {} transcriptions to {}", transcriptions.len(), path.display());
+        }
+        None => {
+            export::write_transcriptions(std::io::stdout().lock(), format, &transcriptions)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn import_transcriptions(path: &std::path::Path, format: Option<ExportFormat>, dry_run: bool) -> Result<()> {
+    let format = format
+        .or_else(|| ExportFormat::from_path(path))
+        .context("Cannot tell the format from the file extension; pass --format")?;
+
+    let config = Config::load()?;
+    let storage = open_storage(&config, dry_run)?.with_dry_run(dry_run);
+
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let transcriptions = export::read_transcriptions(std::io::BufReader::new(file), format)?;
+
+    let mut imported = 0;
+    for transcription in transcriptions.iter().cloned() {
+        // Codes in an export belong to the node it came from
+        let transcription = Transcription {
+            short_code: None,
+            ..transcription
+        };
+        if storage.insert_transcription_if_absent(&transcription)?.is_some() {
+            imported += 1;
+        }
+    }
+
+    println!(
+        "Imported {} transcriptions ({} already present)",
+        imported,
+        transcriptions.len() - imported
+    );
+
+    Ok(())
+}
+
+/// Back up with SQLite's online backup API, so this is safe while the
+/// daemon is running
+fn backup_node(output: &std::path::Path, dry_run: bool) -> Result<()> {
+    let config = Config::load()?;
+    let storage = open_storage(&config, dry_run)?;
+
+    let user_config_path = Config::user_config_path()?;
+    let config_file = user_config_path.exists().then_some(user_config_path.as_path());
+
+    let contents = backup::create_backup(&storage, config_file, output)?;
+    println!(
+        "Backed up {} database file(s){} to {}",
+        contents.databases.len(),
+        if contents.config { " and config.toml" } else { "" },
+        output.display()
+    );
+
+    Ok(())
+}
+
+fn restore_node(path: &std::path::Path, yes: bool) -> Result<()> {
+    let contents = backup::read_backup(path)?;
+    if contents.databases.is_empty() && !contents.config {
+        anyhow::bail!("{} doesn't contain a memo-node backup", path.display());
+    }
+
+    // The database location may itself come from the config being replaced,
+    // so resolve it from the config currently in effect
+    let config = Config::load()?;
+    let storage_path = config.storage_path()?;
+    let user_config_path = Config::user_config_path()?;
+
+    println!("Restoring from {}:", path.display());
+    for name in &contents.databases {
+        println!("  database/{}", name);
+    }
+    if contents.config {
+        println!("  config.toml -> {}", user_config_path.display());
+    }
+    println!("Existing files next to {} will be overwritten.", storage_path.display());
+    println!("Stop the daemon before restoring.");
+
+    if !yes {
+        use std::io::Write;
+        print!("Continue? [y/N] ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).context("Failed to read answer")?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+
+    let restored = backup::restore_backup(path, &storage_path, &user_config_path)?;
+    println!(
+        "Restored {} database file(s){}",
+        restored.databases.len(),
+        if restored.config { " and config.toml" } else { "" }
+    );
+
+    Ok(())
+}
+
+/// Read lines from stdin and run each through the same pipeline as a
+/// transcribed recording (blocklist, length limit, storage, hook, keyword
+/// tags, HTTPS post). Peers pick the rows up on their next sync; a running
+/// daemon's WebSocket clients see them in history rather than live.
+async fn ingest_stdin(dry_run: bool) -> Result<()> {
+    use tokio::io::AsyncBufReadExt;
+
+    let config = Config::load()?;
+    let storage = open_storage(&config, dry_run)?.with_dry_run(dry_run);
+    let pipeline = TranscriptionPipeline::new(&config, storage, Arc::new(Metrics::default()))?
+        .with_dry_run(dry_run);
+
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut stored = 0;
+    while let Some(line) = lines.next_line().await.context("Failed to read stdin")? {
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        stored += pipeline.process(text, SourceType::Manual).len();
+    }
+
+    pipeline.flush().await;
+    println!("Ingested {} transcriptions", stored);
+
+    Ok(())
+}
+
+/// Rows with audio saved by `transcription.save_recordings` in `dir`,
+/// recorded on `since` or later, oldest first
+fn saved_recordings(
+    dir: &std::path::Path,
+    storage: &Storage,
+    since: Option<chrono::NaiveDate>,
+) -> Result<Vec<(Transcription, PathBuf)>> {
+    let mut recordings = Vec::new();
+    if !dir.exists() {
+        return Ok(recordings);
+    }
+
+    // <id>.wav (or .opus)
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to list {}", dir.display()))? {
+        let path = entry.context("Failed to read directory entry")?.path();
+        let Some(id) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".wav").or_else(|| name.strip_suffix(".opus")))
+        else {
+            continue;
+        };
+        let Some(row) = storage.get_transcription(id)? else {
+            println!("  {}: no stored transcription with this id, skipping", id);
+            continue;
+        };
+        let recorded = chrono::DateTime::from_timestamp(row.timestamp, 0).map(|dt| dt.date_naive());
+        if since.is_some_and(|since| recorded.is_some_and(|day| day < since)) {
+            continue;
+        }
+        recordings.push((row, path));
+    }
+
+    recordings.sort_by_key(|(row, _)| row.timestamp);
+    Ok(recordings)
+}
+
+/// Recordings saved by `transcription.save_failed_audio` in `dir`, recorded
+/// on `since` or later, oldest first
+fn failed_recordings(
+    dir: &std::path::Path,
+    since: Option<chrono::NaiveDate>,
+) -> Result<Vec<(chrono::NaiveDateTime, PathBuf)>> {
+    // failed-YYYYMMDD-HHMMSS-mmm.wav (or .opus)
+    let mut recordings = Vec::new();
+    if !dir.exists() {
+        return Ok(recordings);
+    }
+
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to list {}", dir.display()))? {
+        let path = entry.context("Failed to read directory entry")?.path();
+        let recorded = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| {
+                let name = name.strip_prefix("failed-")?;
+                name.strip_suffix(".wav").or_else(|| name.strip_suffix(".opus"))
+            })
+            .and_then(|stamp| chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S-%3f").ok());
+        match recorded {
+            Some(recorded) if !since.is_some_and(|since| recorded.date() < since) => {
+                recordings.push((recorded, path));
+            }
+            _ => {}
+        }
+    }
+
+    recordings.sort();
+    Ok(recordings)
+}
+
+/// Transcribe a recording saved by `write_recording`, returning its text and
+/// length
+fn transcribe_saved(
+    engine: &mut memo_stt::SttEngine,
+    path: &std::path::Path,
+) -> Result<(String, i64)> {
+    let (samples, sample_rate) = audio::capture::read_recording(path)?;
+    if sample_rate != transcribe::SAMPLE_RATE {
+        anyhow::bail!("expected {}Hz audio, got {}Hz", transcribe::SAMPLE_RATE, sample_rate);
+    }
+    engine
+        .transcribe(&samples)
+        .map(|text| (text, transcribe::duration_ms(samples.len())))
+        .map_err(|e| anyhow::anyhow!("Transcription error: {}", e))
+}
+
+async fn reprocess(model: &str, since: Option<chrono::NaiveDate>, dry_run: bool) -> Result<()> {
+    let config = Config::load()?;
+    let storage = open_storage(&config, dry_run)?.with_dry_run(dry_run);
+    let recordings_dir = Config::recordings_dir()?;
+    let failed_dir = Config::failed_audio_dir()?;
+    let done_dir = failed_dir.join("reprocessed");
+
+    let saved = saved_recordings(&recordings_dir, &storage, since)?;
+    let failed = failed_recordings(&failed_dir, since)?;
+    if saved.is_empty() && failed.is_empty() {
+        println!(
+            "No saved recordings to reprocess in {} or {}",
+            recordings_dir.display(),
+            failed_dir.display()
+        );
+        return Ok(());
+    }
+
+    println!("Reprocessing {} recordings with {}", saved.len() + failed.len(), model);
+    let compute = transcribe::select_compute(config.transcription.compute);
+    let mut engine = transcribe::load_engine(model, compute)?;
+    let pipeline = TranscriptionPipeline::new(&config, storage, Arc::new(Metrics::default()))?
+        .with_dry_run(dry_run);
+
+    let (mut changed, mut unchanged, mut stored, mut empty, mut errors) = (0, 0, 0, 0, 0);
+
+    // Rows transcribed before: update them in place
+    for (row, path) in saved {
+        match transcribe_saved(&mut engine, &path) {
+            Ok((text, _)) if text.trim().is_empty() => {
+                println!("  {}: no speech, keeping \"{}\"", row.id, row.text);
+                empty += 1;
+            }
+            Ok((text, _)) => match pipeline.retranscribe(&row, &text)? {
+                Some(updated) => {
+                    println!("  {}: \"{}\" -> \"{}\"", row.id, row.text, updated.text);
+                    changed += 1;
+                }
+                None => unchanged += 1,
+            },
+            Err(e) => {
+                println!("  {}: failed: {:#}", row.id, e);
+                errors += 1;
+            }
+        }
+    }
+
+    // Recordings that never made it into a row: store them as new rows
+    for (recorded, path) in failed {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        match transcribe_saved(&mut engine, &path) {
+            Ok((text, _)) if text.trim().is_empty() => {
+                println!("  {}: no speech", name);
+                empty += 1;
+                continue;
+            }
+            Ok((text, duration_ms)) => {
+                let rows = pipeline.process_at(
+                    &text,
+                    SourceType::Device,
+                    recorded.and_utc().timestamp(),
+                    Some(duration_ms),
+                );
+                println!("  {}: {}", name, text.trim());
+                stored += rows.len();
+            }
+            Err(e) => {
+                println!("  {}: failed: {:#}", name, e);
+                errors += 1;
+                continue;
+            }
+        }
+
+        // Keep the audio, but don't pick it up again on the next run
+        if dry_run {
+            continue;
+        }
+        std::fs::create_dir_all(&done_dir)
+            .with_context(|| format!("Failed to create {}", done_dir.display()))?;
+        std::fs::rename(&path, done_dir.join(&name))
+            .with_context(|| format!("Failed to move {} aside", path.display()))?;
+    }
+
+    pipeline.flush().await;
+    println!(
+        "{} transcriptions changed, {} unchanged; {} stored from failed recordings; \
+         {} recordings had no speech, {} failed",
+        changed, unchanged, stored, empty, errors
+    );
+
+    Ok(())
+}
+
+fn bench_model(
+    model: Option<String>,
+    duration: u64,
+    wav: Option<&std::path::Path>,
+    runs: u32,
+) -> Result<()> {
+    let config = Config::load()?;
+    let model = model.unwrap_or_else(|| config.transcription.model.clone());
+    let samples = match wav {
+        Some(path) => {
+            let (samples, sample_rate) = audio::capture::read_wav(path)?;
+            if sample_rate != transcribe::SAMPLE_RATE {
+                anyhow::bail!("Expected {}Hz audio, got {}Hz", transcribe::SAMPLE_RATE, sample_rate);
+            }
+            samples
+        }
+        None => transcribe::synthetic_speech(duration.max(1)),
+    };
+    let audio_len = Duration::from_secs_f64(samples.len() as f64 / transcribe::SAMPLE_RATE as f64);
+
+    println!("Loading {}", model);
+    let load_started = std::time::Instant::now();
+    let compute = transcribe::select_compute(config.transcription.compute);
+    let mut engine = transcribe::load_engine(&model, compute)?;
+    println!("Loaded and warmed up in {:.2}s", load_started.elapsed().as_secs_f64());
+
+    println!("Transcribing {:.1}s of audio {} times", audio_len.as_secs_f64(), runs.max(1));
+    let mut latencies = Vec::new();
+    for run in 1..=runs.max(1) {
+        let started = std::time::Instant::now();
+        engine
+            .transcribe(&samples)
+            .map_err(|e| anyhow::anyhow!("Transcription error: {}", e))?;
+        let latency = started.elapsed();
+        println!("  run {}: {:.2}s", run, latency.as_secs_f64());
+        latencies.push(latency);
+    }
+
+    let stats = transcribe::LatencyStats::from_runs(&latencies, audio_len)
+        .context("No runs completed")?;
+    println!();
+    println!(
+        "Latency: min {:.2}s, median {:.2}s, max {:.2}s",
+        stats.min.as_secs_f64(),
+        stats.median.as_secs_f64(),
+        stats.max.as_secs_f64()
+    );
+    println!(
+        "Real-time factor: {:.2} ({})",
+        stats.real_time_factor,
+        if stats.real_time_factor <= 1.0 {
+            "keeps up with live audio"
+        } else {
+            "slower than live audio"
+        }
+    );
+
+    Ok(())
+}
+
+fn decode_file(
+    path: &std::path::Path,
+    wav: Option<&std::path::Path>,
+    layout: BundleLayout,
+) -> Result<()> {
+    const FRAME_SAMPLES: usize = 320; // 20ms at 16kHz
+
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let bundles = audio::capture::parse_capture(&data)?;
+    let mut decoder = OpusDecoder::new(16000, audiopus::Channels::Mono)?.with_layout(layout);
+
+    let mut pcm = Vec::new();
+    let mut short_bundles = 0;
+    let mut index_gaps = 0;
+    let mut previous_index: Option<u8> = None;
+
+    println!("bundle  index  bytes  frames  samples");
+    for (n, bundle) in bundles.iter().enumerate() {
+        decoder.detect_layout(bundle);
+        // An undetected layout is decoded as `WithIndex`, so read it that way
+        let (index, frames) = match decoder.layout() {
+            BundleLayout::NoIndex => (None, bundle.first()),
+            BundleLayout::WithIndex | BundleLayout::Auto => {
+                (bundle.first().copied(), bundle.get(1))
+            }
+        };
+        let frames = frames.copied().unwrap_or(0) as usize;
+        let samples = decoder.decode(bundle)?;
+
+        let mut notes = Vec::new();
+        if samples.len() < frames * FRAME_SAMPLES {
+            short_bundles += 1;
+            notes.push(format!("expected {} samples", frames * FRAME_SAMPLES));
+        }
+        if let (Some(previous), Some(index)) = (previous_index, index) {
+            if index != previous.wrapping_add(1) {
+                index_gaps += 1;
+                notes.push(format!("index jumped from {}", previous));
+            }
+        }
+        previous_index = index.or(previous_index);
+
+        println!(
+            "{:>6}  {:>5}  {:>5}  {:>6}  {:>7}  {}",
+            n,
+            index.map(|i| i.to_string()).unwrap_or_else(|| "-".to_string()),
+            bundle.len(),
+            frames,
+            samples.len(),
+            notes.join(", ")
+        );
+        pcm.extend_from_slice(&samples);
+    }
+
+    println!();
+    println!(
+        "{} bundles, {} samples ({:.2}s), {} short, {} index gaps, layout {:?}",
+        bundles.len(),
+        pcm.len(),
+        pcm.len() as f64 / decoder.sample_rate() as f64,
+        short_bundles,
+        index_gaps,
+        decoder.layout()
+    );
+
+    if let Some(wav) = wav {
+        audio::capture::write_wav(wav, &pcm, decoder.sample_rate())?;
+        println!("Wrote {}", wav.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Parts of the daemon whose most recent error is reported by `status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subsystem {
+    Ble,
+    Sync,
+    Http,
+    Transcription,
+}
+
+impl Subsystem {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Subsystem::Ble => "BLE",
+            Subsystem::Sync => "sync",
+            Subsystem::Http => "HTTP",
+            Subsystem::Transcription => "transcription",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastError {
+    pub message: String,
+    /// Unix timestamp of the error
+    pub at: i64,
+}
+
+/// Process-wide counters shared across the daemon's tasks
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Live WebSocket messages dropped because a receiver lagged behind
+    pub broadcast_dropped: AtomicU64,
+    /// Recordings for which the model returned no text
+    pub transcriptions_empty: AtomicU64,
+    /// Transcriptions dropped for matching `transcription.blocklist`
+    pub transcriptions_blocklisted: AtomicU64,
+    /// Transcriptions dropped for having fewer than `transcription.min_words`
+    pub transcriptions_too_short: AtomicU64,
+    /// Audio notifications received over BLE, before rate limiting
+    pub audio_packets_received: AtomicU64,
+    pub audio_bytes_received: AtomicU64,
+    /// Audio notifications dropped by the per-device rate limit
+    pub audio_packets_rate_limited: AtomicU64,
+    /// Audio packets that decoded to samples
+    pub audio_decoded: AtomicU64,
+    /// Audio packets that failed to decode or decoded to nothing
+    pub audio_decode_failed: AtomicU64,
+    /// Connected BLE devices by name, with their last known RSSI
+    pub ble_devices: Mutex<BTreeMap<String, Option<i16>>>,
+    /// Last battery percentage read from connected devices that report one
+    pub ble_battery: Mutex<BTreeMap<String, u8>>,
+    /// Audio bytes received from each connected device since it connected
+    pub ble_bytes_received: Mutex<BTreeMap<String, u64>>,
+    /// Most recent error per subsystem
+    pub last_errors: Mutex<BTreeMap<Subsystem, LastError>>,
+    /// Transcriptions stored by the pipeline (not received from peers)
+    pub transcriptions_stored: AtomicU64,
+    /// Transcriptions posted to the HTTPS endpoint, and posts that failed
+    /// after retries
+    pub http_posted: AtomicU64,
+    pub http_failed: AtomicU64,
+    /// Peers synced with successfully at least once
+    pub peers_synced: Mutex<BTreeSet<String>>,
+}
+
+impl Metrics {
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Remember `error` as the latest failure of `subsystem`
+    pub fn record_error(&self, subsystem: Subsystem, error: impl fmt::Display) {
+        self.last_errors.lock().unwrap().insert(
+            subsystem,
+            LastError {
+                message: error.to_string(),
+                at: chrono::Utc::now().timestamp(),
+            },
+        );
+    }
+
+    /// One-line recap of the run, logged on shutdown
+    pub fn session_summary(&self, uptime_secs: i64) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let peers = self.peers_synced.lock().unwrap();
+        let peers = if peers.is_empty() {
+            "no peers".to_string()
+        } else {
+            format!(
+                "{} peer(s) ({})",
+                peers.len(),
+                peers.iter().cloned().collect::<Vec<_>>().join(", ")
+            )
+        };
+
+        format!(
+            "Session summary: up {}, {} transcriptions stored, {:.1} KiB of audio received ({} packets, {} decode failures), synced with {}, HTTPS posts {} succeeded / {} failed",
+            crate::status::format_duration(uptime_secs),
+            load(&self.transcriptions_stored),
+            load(&self.audio_bytes_received) as f64 / 1024.0,
+            load(&self.audio_packets_received),
+            load(&self.audio_decode_failed),
+            peers,
+            load(&self.http_posted),
+            load(&self.http_failed),
+        )
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            broadcast_dropped: self.broadcast_dropped.load(Ordering::Relaxed),
+            transcriptions_empty: self.transcriptions_empty.load(Ordering::Relaxed),
+            transcriptions_blocklisted: self.transcriptions_blocklisted.load(Ordering::Relaxed),
+            transcriptions_too_short: self.transcriptions_too_short.load(Ordering::Relaxed),
+            audio_packets_received: self.audio_packets_received.load(Ordering::Relaxed),
+            audio_bytes_received: self.audio_bytes_received.load(Ordering::Relaxed),
+            audio_packets_rate_limited: self.audio_packets_rate_limited.load(Ordering::Relaxed),
+            audio_decoded: self.audio_decoded.load(Ordering::Relaxed),
+            audio_decode_failed: self.audio_decode_failed.load(Ordering::Relaxed),
+            transcriptions_stored: self.transcriptions_stored.load(Ordering::Relaxed),
+            ble_devices: self.ble_devices.lock().unwrap().clone(),
+            ble_battery: self.ble_battery.lock().unwrap().clone(),
+            ble_bytes_received: self.ble_bytes_received.lock().unwrap().clone(),
+            last_errors: self.last_errors.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Point-in-time copy of `Metrics`, written to the status file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    #[serde(default)]
+    pub broadcast_dropped: u64,
+    #[serde(default)]
+    pub transcriptions_empty: u64,
+    #[serde(default)]
+    pub transcriptions_blocklisted: u64,
+    #[serde(default)]
+    pub transcriptions_too_short: u64,
+    #[serde(default)]
+    pub audio_packets_received: u64,
+    #[serde(default)]
+    pub audio_bytes_received: u64,
+    #[serde(default)]
+    pub audio_packets_rate_limited: u64,
+    #[serde(default)]
+    pub audio_decoded: u64,
+    #[serde(default)]
+    pub audio_decode_failed: u64,
+    #[serde(default)]
+    pub transcriptions_stored: u64,
+    #[serde(default)]
+    pub ble_devices: BTreeMap<String, Option<i16>>,
+    #[serde(default)]
+    pub ble_battery: BTreeMap<String, u8>,
+    #[serde(default)]
+    pub ble_bytes_received: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub last_errors: BTreeMap<Subsystem, LastError>,
+}
+
+impl MetricsSnapshot {
+    /// Share of decoded audio packets that produced samples, if any were seen
+    pub fn decode_success_rate(&self) -> Option<f64> {
+        let total = self.audio_decoded + self.audio_decode_failed;
+        (total > 0).then(|| self.audio_decoded as f64 / total as f64)
+    }
+}
+
+/// How fast counters grew between two snapshots, for live dashboards
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsRates {
+    pub audio_bytes_per_sec: f64,
+    /// Audio packets decoded to samples per second
+    pub audio_decoded_per_sec: f64,
+    pub audio_decode_failed_per_sec: f64,
+    /// Transcriptions stored by the pipeline per minute
+    pub transcriptions_per_min: f64,
+}
+
+impl MetricsRates {
+    /// Rates over the `elapsed` time from `previous` to `current`; counters
+    /// that went backwards count as zero
+    pub fn between(
+        previous: &MetricsSnapshot,
+        current: &MetricsSnapshot,
+        elapsed: Duration,
+    ) -> Self {
+        let secs = elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return Self::default();
+        }
+        let per_sec = |previous: u64, current: u64| current.saturating_sub(previous) as f64 / secs;
+
+        Self {
+            audio_bytes_per_sec: per_sec(previous.audio_bytes_received, current.audio_bytes_received),
+            audio_decoded_per_sec: per_sec(previous.audio_decoded, current.audio_decoded),
+            audio_decode_failed_per_sec: per_sec(
+                previous.audio_decode_failed,
+                current.audio_decode_failed,
+            ),
+            transcriptions_per_min: per_sec(
+                previous.transcriptions_stored,
+                current.transcriptions_stored,
+            ) * 60.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_summary() {
+        let metrics = Metrics::default();
+        Metrics::add(&metrics.transcriptions_stored, 3);
+        Metrics::add(&metrics.audio_bytes_received, 2048);
+        Metrics::add(&metrics.http_posted, 2);
+        Metrics::add(&metrics.http_failed, 1);
+        metrics.peers_synced.lock().unwrap().insert("pi-b".to_string());
+        metrics.peers_synced.lock().unwrap().insert("pi-a".to_string());
+
+        let summary = metrics.session_summary(65);
+        assert!(summary.contains("up 1m 5s"), "{}", summary);
+        assert!(summary.contains("3 transcriptions stored"), "{}", summary);
+        assert!(summary.contains("2.0 KiB of audio"), "{}", summary);
+        assert!(summary.contains("synced with 2 peer(s) (pi-a, pi-b)"), "{}", summary);
+        assert!(summary.contains("2 succeeded / 1 failed"), "{}", summary);
+    }
+
+    #[test]
+    fn test_rates_between_snapshots() {
+        let metrics = Metrics::default();
+        let previous = metrics.snapshot();
+        Metrics::add(&metrics.audio_bytes_received, 8000);
+        Metrics::add(&metrics.audio_decoded, 100);
+        Metrics::add(&metrics.audio_decode_failed, 2);
+        Metrics::add(&metrics.transcriptions_stored, 1);
+        let current = metrics.snapshot();
+
+        let rates = MetricsRates::between(&previous, &current, Duration::from_secs(2));
+        assert_eq!(rates.audio_bytes_per_sec, 4000.0);
+        assert_eq!(rates.audio_decoded_per_sec, 50.0);
+        assert_eq!(rates.audio_decode_failed_per_sec, 1.0);
+        assert_eq!(rates.transcriptions_per_min, 30.0);
+
+        // A restarted daemon's counters start over rather than going negative
+        let rates = MetricsRates::between(&current, &previous, Duration::from_secs(2));
+        assert_eq!(rates, MetricsRates::default());
+        assert_eq!(
+            MetricsRates::between(&previous, &current, Duration::ZERO),
+            MetricsRates::default()
+        );
+    }
+}
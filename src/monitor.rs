@@ -0,0 +1,182 @@
+use crate::api::websocket::{ClientMessage, ServerMessage};
+use crate::metrics::MetricsSnapshot;
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Recent transcriptions kept on screen
+const RECENT_TRANSCRIPTIONS: usize = 8;
+
+/// Live operator view of a running daemon, refreshed once a second from
+/// its WebSocket API until interrupted
+pub async fn run(url: &str) -> Result<()> {
+    let (ws_stream, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .with_context(|| format!("Failed to connect to the daemon at {} (is it running?)", url))?;
+    let (mut sender, mut receiver) = ws_stream.split();
+
+    let request = |msg: &ClientMessage| -> Result<Message> {
+        Ok(Message::Text(serde_json::to_string(msg)?))
+    };
+    sender
+        .send(request(&ClientMessage::GetHistory {
+            limit: Some(RECENT_TRANSCRIPTIONS),
+            after_cursor: None,
+        })?)
+        .await?;
+
+    let mut view = MonitorView::default();
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                sender.send(request(&ClientMessage::GetStats)?).await?;
+                view.render(url);
+            }
+            msg = receiver.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => {
+                        anyhow::bail!("Daemon closed the connection");
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e).context("WebSocket error"),
+                };
+                if let Ok(msg) = serde_json::from_str::<ServerMessage>(&text) {
+                    view.apply(msg);
+                }
+            }
+            _ = &mut ctrl_c => return Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MonitorView {
+    recording: bool,
+    stats: Option<MetricsSnapshot>,
+    /// Previous stats, for per-second rates
+    previous: Option<MetricsSnapshot>,
+    /// (timestamp, source, text), newest first
+    recent: VecDeque<(i64, String, String)>,
+    /// History chunks received so far
+    history_chunks: Vec<(i64, String, String)>,
+}
+
+impl MonitorView {
+    fn apply(&mut self, msg: ServerMessage) {
+        match msg {
+            ServerMessage::Stats { recording, metrics, .. } => {
+                self.recording = recording;
+                self.previous = self.stats.replace(metrics);
+            }
+            ServerMessage::History { transcriptions } => {
+                self.recent = transcriptions
+                    .into_iter()
+                    .map(|t| (t.timestamp, t.source_node, t.text))
+                    .collect();
+            }
+            ServerMessage::HistoryChunk { transcriptions, done } => {
+                self.history_chunks
+                    .extend(transcriptions.into_iter().map(|t| (t.timestamp, t.source_node, t.text)));
+                if done {
+                    self.recent = std::mem::take(&mut self.history_chunks).into();
+                }
+            }
+            ServerMessage::Transcription {
+                timestamp,
+                source_node,
+                text,
+                ..
+            } => {
+                self.recent.push_front((timestamp, source_node, text));
+                self.recent.truncate(RECENT_TRANSCRIPTIONS);
+            }
+            _ => {}
+        }
+    }
+
+    fn render(&self, url: &str) {
+        let mut out = String::new();
+        // Clear the screen and move the cursor home
+        out.push_str("\x1b[2J\x1b[H");
+        let _ = writeln!(out, "memo-node monitor ({})  Ctrl-C to quit\n", url);
+
+        match &self.stats {
+            Some(stats) => {
+                let rate = |current: u64, previous: Option<u64>| {
+                    previous.map(|p| current.saturating_sub(p)).unwrap_or(0)
+                };
+                let previous = self.previous.as_ref();
+
+                let _ = writeln!(out, "Recording:       {}", if self.recording { "yes" } else { "no" });
+                let _ = writeln!(
+                    out,
+                    "Packets:         {} total, {}/s ({} bytes/s), {} rate-limited",
+                    stats.audio_packets_received,
+                    rate(stats.audio_packets_received, previous.map(|p| p.audio_packets_received)),
+                    rate(stats.audio_bytes_received, previous.map(|p| p.audio_bytes_received)),
+                    stats.audio_packets_rate_limited
+                );
+                let success = stats
+                    .decode_success_rate()
+                    .map(|rate| format!("{:.1}%", rate * 100.0))
+                    .unwrap_or_else(|| "-".to_string());
+                let _ = writeln!(
+                    out,
+                    "Decode:          {} ok, {} failed ({} success)",
+                    stats.audio_decoded, stats.audio_decode_failed, success
+                );
+                let _ = writeln!(
+                    out,
+                    "Dropped:         {} empty, {} blocklisted, {} too short, {} live broadcasts",
+                    stats.transcriptions_empty,
+                    stats.transcriptions_blocklisted,
+                    stats.transcriptions_too_short,
+                    stats.broadcast_dropped
+                );
+
+                let _ = writeln!(out, "\nDevices:");
+                if stats.ble_devices.is_empty() {
+                    let _ = writeln!(out, "  (none connected)");
+                }
+                for (name, rssi) in &stats.ble_devices {
+                    let rssi = rssi.map(|r| format!("{} dBm", r)).unwrap_or_else(|| "? dBm".to_string());
+                    match stats.ble_battery.get(name) {
+                        Some(battery) => {
+                            let _ = writeln!(out, "  {:<24} {:<10} {}% battery", name, rssi, battery);
+                        }
+                        None => {
+                            let _ = writeln!(out, "  {:<24} {}", name, rssi);
+                        }
+                    }
+                }
+            }
+            None => {
+                let _ = writeln!(out, "Waiting for stats...");
+            }
+        }
+
+        let _ = writeln!(out, "\nRecent transcriptions:");
+        if self.recent.is_empty() {
+            let _ = writeln!(out, "  (none)");
+        }
+        for (timestamp, source, text) in &self.recent {
+            let time = chrono::DateTime::from_timestamp(*timestamp, 0)
+                .map(|dt| dt.format("%H:%M:%S").to_string())
+                .unwrap_or_default();
+            let _ = writeln!(out, "  [{}] {}: {}", time, source, text);
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+    }
+}
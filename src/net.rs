@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// A server that ran this long before exiting is considered to have been
+/// healthy, so its restart count starts over
+const STABLE_AFTER: Duration = Duration::from_secs(300);
+
+/// Longest wait between server restarts
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Bind a TCP listener, retrying with exponential backoff if the address is busy
+///
+/// Waits 1s, 2s, 4s, ... between attempts and gives up after `retries`
+/// retries, returning the last bind error.
+pub async fn bind_with_retry(addr: SocketAddr, retries: u32, name: &str) -> Result<TcpListener> {
+    let mut attempt = 0;
+
+    loop {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => return Ok(listener),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                let delay = Duration::from_secs(2_u64.pow(attempt - 1));
+                warn!(
+                    "Failed to bind {} server on {}: {}. Retrying in {:?} (attempt {}/{})",
+                    name, addr, e, delay, attempt, retries
+                );
+                sleep(delay).await;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to bind {} server on {} after {} retries", name, addr, retries)
+                });
+            }
+        }
+    }
+}
+
+/// Run `serve` on `listener`, and whenever it exits, rebind the same address
+/// and run it again
+///
+/// Restarts back off 1s, 2s, 4s, ... up to a minute. After `max_restarts`
+/// consecutive restarts (or a failed rebind) this returns an error, which
+/// callers should treat as fatal. Servers are never expected to return, so
+/// an `Ok` exit counts as a failure too.
+pub async fn supervise<F, Fut>(
+    name: &str,
+    mut listener: TcpListener,
+    bind_retries: u32,
+    max_restarts: u32,
+    mut serve: F,
+) -> Result<()>
+where
+    F: FnMut(TcpListener) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let addr = listener
+        .local_addr()
+        .with_context(|| format!("Failed to get {} listener address", name))?;
+    let mut restarts = 0;
+
+    loop {
+        let started = Instant::now();
+        match serve(listener).await {
+            Ok(()) => warn!("{} server stopped unexpectedly", name),
+            Err(e) => error!("{} server error: {:#}", name, e),
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            restarts = 0;
+        }
+        if restarts >= max_restarts {
+            anyhow::bail!("{} server failed {} times in a row; giving up", name, restarts + 1);
+        }
+        restarts += 1;
+
+        let delay = Duration::from_secs(2_u64.saturating_pow(restarts - 1)).min(MAX_RESTART_DELAY);
+        warn!(
+            "Restarting {} server in {:?} (restart {}/{})",
+            name, delay, restarts, max_restarts
+        );
+        sleep(delay).await;
+
+        listener = bind_with_retry(addr, bind_retries, name).await?;
+        info!("{} server restarted on {}", name, addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_fails_when_port_taken() {
+        let held = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = held.local_addr().unwrap();
+
+        let result = bind_with_retry(addr, 0, "test").await;
+        assert!(result.is_err());
+
+        drop(held);
+        assert!(bind_with_retry(addr, 0, "test").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_supervise_restarts_then_gives_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut runs = 0;
+
+        let result = supervise("test", listener, 0, 1, |_listener| {
+            runs += 1;
+            async { anyhow::bail!("boom") }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(runs, 2);
+    }
+}
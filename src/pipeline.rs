@@ -0,0 +1,624 @@
+use crate::api::websocket::ServerMessage;
+use crate::api::{HttpClient, MqttClient};
+use crate::clock;
+use crate::config::{Config, RecordingFormat, TextOverflow};
+use crate::hooks::CommandHook;
+use crate::journal;
+use crate::keywords::KeywordMatcher;
+use crate::metrics::{Metrics, Subsystem};
+use crate::redact::Redactor;
+use crate::status::StatusFile;
+use crate::storage::{Metadata, SourceType, Storage, Transcription};
+use crate::sync::NodeKey;
+use crate::transcribe::{self, TranscribedRecording};
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// File `transcription.save_recordings` keeps the audio of row `id` in
+pub fn recording_path(dir: &Path, id: &str, format: RecordingFormat) -> PathBuf {
+    dir.join(format!("{}.{}", id, format.extension()))
+}
+
+/// Everything that happens to a newly transcribed text on this node:
+/// blocklist and length limits, storage, then fan-out to WebSocket clients,
+/// the command hook, keyword tagging, the HTTPS endpoint and the MQTT broker
+pub struct TranscriptionPipeline {
+    node_id: String,
+    storage: Storage,
+    metrics: Arc<Metrics>,
+    max_text_length: usize,
+    overflow: TextOverflow,
+    blocklist: Vec<String>,
+    min_words: usize,
+    normalize_text: bool,
+    normalize_unicode: bool,
+    keyword_matcher: KeywordMatcher,
+    /// Applied before anything else sees the text (`transcription.redact`)
+    redactor: Option<Redactor>,
+    /// Signs new rows (`sync.sign_transcriptions`)
+    node_key: Option<NodeKey>,
+    /// Where each recording's audio is saved (`transcription.save_recordings`)
+    recordings_dir: Option<PathBuf>,
+    recording_format: RecordingFormat,
+    hook: Option<CommandHook>,
+    http_client: Option<Arc<HttpClient>>,
+    mqtt_client: Option<MqttClient>,
+    ws_broadcast_tx: Option<broadcast::Sender<Transcription>>,
+    ws_events_tx: Option<broadcast::Sender<ServerMessage>>,
+    status_file: Option<StatusFile>,
+    /// Send stored transcriptions to journald
+    journald: bool,
+    /// Log hook runs instead of starting them, see `with_dry_run`
+    dry_run: bool,
+    /// Background hook runs and HTTPS posts, awaited by `flush`
+    pending: Mutex<Vec<JoinHandle<()>>>,
+    sessions: Mutex<SessionTracker>,
+    /// Stored as `metadata` on new rows, see `with_context`
+    context: Arc<Mutex<Metadata>>,
+}
+
+/// Links consecutive rows of the same source type and device into sessions
+///
+/// A row whose recording started within `gap_ms` of the end of the previous
+/// one from the same device joins its session; otherwise it starts a new one
+/// named after its own id. Rows not transcribed from audio start and end at
+/// their timestamp. End times have one-second resolution, so gaps are
+/// effectively rounded to whole seconds.
+struct SessionTracker {
+    gap_ms: u64,
+    /// End (unix ms) of the latest recording of each source type and device,
+    /// and its session
+    current: HashMap<(SourceType, Option<String>), (i64, String)>,
+}
+
+impl SessionTracker {
+    fn new(gap_ms: u64) -> Self {
+        Self {
+            gap_ms,
+            current: HashMap::new(),
+        }
+    }
+
+    /// Session for a new row recorded on `device` from `started_ms` to
+    /// `ended_ms`, or `None` when linking is disabled
+    fn session_for(
+        &mut self,
+        id: &str,
+        source_type: SourceType,
+        device: Option<&str>,
+        started_ms: i64,
+        ended_ms: i64,
+    ) -> Option<String> {
+        if self.gap_ms == 0 {
+            return None;
+        }
+
+        let key = (source_type, device.map(str::to_string));
+        match self.current.get_mut(&key) {
+            // Recordings may overlap the previous one slightly, as the end
+            // has one-second resolution
+            Some((last_ended_ms, session_id))
+                if ended_ms >= *last_ended_ms
+                    && started_ms.saturating_sub(*last_ended_ms).max(0) as u64 <= self.gap_ms =>
+            {
+                *last_ended_ms = ended_ms;
+                Some(session_id.clone())
+            }
+            _ => {
+                self.current.insert(key, (ended_ms, id.to_string()));
+                Some(id.to_string())
+            }
+        }
+    }
+}
+
+impl TranscriptionPipeline {
+    pub fn new(config: &Config, storage: Storage, metrics: Arc<Metrics>) -> Result<Self> {
+        let hook = config
+            .transcription
+            .on_transcription_command
+            .clone()
+            .filter(|command| !command.is_empty())
+            .map(|command| {
+                CommandHook::new(
+                    command,
+                    Duration::from_secs(config.transcription.on_transcription_timeout),
+                )
+            });
+
+        Ok(Self {
+            node_id: config.node.id.clone(),
+            storage,
+            metrics,
+            max_text_length: config.transcription.max_text_length,
+            overflow: config.transcription.overflow,
+            blocklist: config.transcription.blocklist.clone(),
+            min_words: config.transcription.min_words,
+            normalize_text: config.transcription.normalize_text,
+            normalize_unicode: config.transcription.normalize_unicode,
+            keyword_matcher: KeywordMatcher::new(&config.transcription.keyword_rules)?,
+            redactor: config
+                .transcription
+                .redact
+                .then(|| {
+                    Redactor::new(
+                        &config.transcription.redact_patterns,
+                        &config.transcription.redaction_placeholder,
+                    )
+                })
+                .transpose()?,
+            node_key: config
+                .sync
+                .sign_transcriptions
+                .then(|| Config::node_key_path().and_then(|path| NodeKey::load_or_generate(&path)))
+                .transpose()?,
+            recordings_dir: config
+                .transcription
+                .save_recordings
+                .then(Config::recordings_dir)
+                .transpose()?,
+            recording_format: config.audio.recording_format,
+            hook,
+            http_client: HttpClient::from_config(&config.api).map(Arc::new),
+            mqtt_client: MqttClient::from_config(&config.api, &config.node.id),
+            ws_broadcast_tx: None,
+            ws_events_tx: None,
+            status_file: None,
+            journald: journal::enabled(config.transcription.journald),
+            dry_run: false,
+            pending: Mutex::new(Vec::new()),
+            sessions: Mutex::new(SessionTracker::new(config.transcription.merge_gap_ms)),
+            context: Arc::new(Mutex::new(Metadata(config.transcription.context.clone()))),
+        })
+    }
+
+    /// Send stored transcriptions and keyword matches to WebSocket clients
+    pub fn with_broadcast(
+        mut self,
+        ws_broadcast_tx: broadcast::Sender<Transcription>,
+        ws_events_tx: broadcast::Sender<ServerMessage>,
+    ) -> Self {
+        self.ws_broadcast_tx = Some(ws_broadcast_tx);
+        self.ws_events_tx = Some(ws_events_tx);
+        self
+    }
+
+    /// Take the context for new rows from `context`, which a WebSocket
+    /// server can change at runtime, instead of `transcription.context`
+    pub fn with_context(mut self, context: Arc<Mutex<Metadata>>) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Count stored transcriptions in the daemon's status file
+    pub fn with_status_file(mut self, status_file: StatusFile) -> Self {
+        self.status_file = Some(status_file);
+        self
+    }
+
+    /// Log hook runs, HTTPS posts and MQTT publishes instead of performing them
+    /// (`--dry-run`); pair with a dry-run `Storage`
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self.http_client = self
+            .http_client
+            .take()
+            .and_then(Arc::into_inner)
+            .map(|client| Arc::new(client.with_dry_run(dry_run)));
+        self
+    }
+
+    /// Filter, store and fan out one transcribed text, returning the rows
+    /// that were stored (several if the text was split)
+    pub fn process(&self, text: &str, source_type: SourceType) -> Vec<Transcription> {
+        self.process_at(text, source_type, clock::unix_now(), None)
+    }
+
+    /// `process` for a recording transcribed by the local transcriber,
+    /// attributed to the device that recorded it
+    ///
+    /// With `transcription.save_recordings`, the audio is saved under the
+    /// id of the row, unless the text was split across several.
+    pub fn process_recording(&self, recording: &TranscribedRecording) -> Vec<Transcription> {
+        let origin = &recording.origin;
+        let stored = self.store(
+            &recording.text,
+            SourceType::Device,
+            clock::unix_now(),
+            Some(recording.duration_ms),
+            origin.device.as_deref(),
+            origin.ended_at,
+        );
+
+        if let Some(dir) = &self.recordings_dir {
+            match stored.as_slice() {
+                [row] => self.save_recording(dir, &row.id, &recording.audio),
+                [] => {}
+                rows => debug!("Not saving a recording stored as {} rows", rows.len()),
+            }
+        }
+        stored
+    }
+
+    /// Keep the audio of the row `id` for `reprocess`
+    fn save_recording(&self, dir: &Path, id: &str, audio: &[i16]) {
+        let path = recording_path(dir, id, self.recording_format);
+        if self.dry_run {
+            info!("Dry run: would save the recording of {} to {}", id, path.display());
+            return;
+        }
+
+        let saved = std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))
+            .and_then(|_| {
+                crate::audio::capture::write_recording(
+                    &path,
+                    audio,
+                    transcribe::SAMPLE_RATE,
+                    self.recording_format,
+                )
+            });
+        if let Err(e) = saved {
+            warn!("Failed to save the recording of transcription {}: {:#}", id, e);
+        }
+    }
+
+    /// Replace the text of `row` with `text`, a new transcription of its
+    /// recording, keeping the first text in `original_text`
+    ///
+    /// `text` is redacted, normalized and filtered like new text, and cut to
+    /// `max_text_length` since the row can't be split. Returns the stored
+    /// row, or `None` if the text came out the same or was dropped. The row
+    /// gets a new cursor, so it is pushed to peers again, and is re-signed;
+    /// hooks and other fan-out don't run again.
+    pub fn retranscribe(&self, row: &Transcription, text: &str) -> Result<Option<Transcription>> {
+        let Some(text) = self.clean_text(text) else {
+            return Ok(None);
+        };
+        let truncated =
+            transcribe::enforce_max_length(&text, self.max_text_length, TextOverflow::Truncate);
+        let text = truncated.into_iter().next().unwrap_or_default();
+        if text == row.text {
+            return Ok(None);
+        }
+
+        let mut updated = Transcription {
+            original_text: Some(row.original_text.clone().unwrap_or_else(|| row.text.clone())),
+            text,
+            signature: None,
+            signer_key: None,
+            ..row.clone()
+        };
+        if let Some(node_key) = self.node_key.as_ref().filter(|_| row.source_node == self.node_id) {
+            node_key.sign(&mut updated);
+        }
+        updated.cursor = self
+            .storage
+            .insert_transcription(&updated)
+            .with_context(|| format!("Failed to update transcription {}", row.id))?;
+        Ok(Some(updated))
+    }
+
+    /// `text` redacted and normalized as configured, or `None` if it is
+    /// dropped as empty, blocklisted or too short
+    fn clean_text<'a>(&self, text: &'a str) -> Option<Cow<'a, str>> {
+        let text = match &self.redactor {
+            Some(redactor) => redactor.redact(text),
+            None => Cow::Borrowed(text),
+        };
+
+        let text = if self.normalize_text {
+            let normalized = transcribe::normalize_text(&text, self.normalize_unicode);
+            if normalized.is_empty() {
+                debug!("Dropping transcription that is only whitespace");
+                Metrics::add(&self.metrics.transcriptions_empty, 1);
+                return None;
+            }
+            Cow::Owned(normalized)
+        } else {
+            text
+        };
+
+        if transcribe::is_blocklisted(&text, &self.blocklist) {
+            debug!("Dropping blocklisted transcription: {}", text);
+            Metrics::add(&self.metrics.transcriptions_blocklisted, 1);
+            return None;
+        }
+
+        if transcribe::is_too_short(&text, self.min_words) {
+            debug!("Dropping transcription under {} words: {}", self.min_words, text);
+            Metrics::add(&self.metrics.transcriptions_too_short, 1);
+            return None;
+        }
+
+        Some(text)
+    }
+
+    /// `process` for text recorded at `timestamp` rather than just now,
+    /// from a recording `duration_ms` long if it came from audio
+    pub fn process_at(
+        &self,
+        text: &str,
+        source_type: SourceType,
+        timestamp: i64,
+        duration_ms: Option<i64>,
+    ) -> Vec<Transcription> {
+        self.store(text, source_type, timestamp, duration_ms, None, timestamp)
+    }
+
+    /// `process_at` for text recorded on `device`, in a recording that
+    /// ended at `ended_at`
+    fn store(
+        &self,
+        text: &str,
+        source_type: SourceType,
+        timestamp: i64,
+        duration_ms: Option<i64>,
+        device: Option<&str>,
+        ended_at: i64,
+    ) -> Vec<Transcription> {
+        let Some(text) = self.clean_text(text) else {
+            return Vec::new();
+        };
+
+        // Audio merged from several devices has no device of its own, so a
+        // battery level can then only be attributed when a single device
+        // reports one
+        let device_battery = match (source_type, device) {
+            (SourceType::Device, Some(device)) => {
+                self.metrics.ble_battery.lock().unwrap().get(device).copied()
+            }
+            (SourceType::Device, None) => {
+                let battery = self.metrics.ble_battery.lock().unwrap();
+                match battery.len() {
+                    1 => battery.values().next().copied(),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let ended_ms = ended_at.saturating_mul(1000);
+        let started_ms = ended_ms - duration_ms.unwrap_or(0);
+
+        let metadata = self.context.lock().unwrap().clone();
+        let mut stored = Vec::new();
+
+        // Oversized text is truncated or split into several rows
+        for part in transcribe::enforce_max_length(&text, self.max_text_length, self.overflow) {
+            let id = Uuid::new_v4().to_string();
+            let session_id = self
+                .sessions
+                .lock()
+                .unwrap()
+                .session_for(&id, source_type, device, started_ms, ended_ms);
+            let mut transcription = Transcription {
+                id,
+                timestamp,
+                text: part,
+                source_node: self.node_id.clone(),
+                memo_device_id: device.map(str::to_string),
+                source_type,
+                session_id,
+                device_battery,
+                metadata: metadata.clone(),
+                duration_ms,
+                ..Transcription::default()
+            };
+            if let Some(node_key) = &self.node_key {
+                node_key.sign(&mut transcription);
+            }
+            // Set here rather than on insert so broadcasts carry it too
+            match self.storage.allocate_short_code() {
+                Ok(code) => transcription.short_code = code,
+                Err(e) => warn!("Failed to allocate short code: {:#}", e),
+            }
+
+            // Store in database
+            match self.storage.insert_transcription(&transcription) {
+                Ok(cursor) => transcription.cursor = cursor,
+                Err(e) => {
+                    error!("Failed to store transcription: {}", e);
+                    continue;
+                }
+            }
+
+            info!("Stored transcription: {}", transcription.text);
+            Metrics::add(&self.metrics.transcriptions_stored, 1);
+            self.fan_out(&transcription);
+            stored.push(transcription);
+        }
+
+        stored
+    }
+
+    fn fan_out(&self, transcription: &Transcription) {
+        if let Some(status_file) = &self.status_file {
+            if let Err(e) = status_file.record_transcription(transcription.timestamp) {
+                warn!("Failed to update status file: {}", e);
+            }
+        }
+
+        if let Some(ws_broadcast_tx) = &self.ws_broadcast_tx {
+            let _ = ws_broadcast_tx.send(transcription.clone());
+        }
+
+        if self.journald {
+            journal::emit_transcription(transcription);
+        }
+
+        if let Some(client) = &self.mqtt_client {
+            if self.dry_run {
+                info!(
+                    "Dry run: would publish transcription {} to MQTT topic {}",
+                    transcription.id,
+                    client.topic()
+                );
+            } else if let Err(e) = client.publish(transcription) {
+                warn!("Failed to publish transcription to MQTT: {:#}", e);
+            }
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|task| !task.is_finished());
+
+        if let Some(hook) = &self.hook {
+            if self.dry_run {
+                info!("Dry run: would run transcription hook for {}", transcription.id);
+            } else {
+                pending.push(hook.spawn(transcription));
+            }
+        }
+
+        for keyword_match in self.keyword_matcher.matches(&transcription.text) {
+            if let Err(e) = self.storage.add_tag(transcription, keyword_match.tag) {
+                warn!("Failed to tag transcription {}: {}", transcription.id, e);
+                continue;
+            }
+            info!(
+                "Tagged transcription {} as '{}' (matched '{}')",
+                transcription.id, keyword_match.tag, keyword_match.pattern
+            );
+            if let Some(ws_events_tx) = &self.ws_events_tx {
+                let _ = ws_events_tx.send(ServerMessage::KeywordMatched {
+                    id: transcription.id.clone(),
+                    tag: keyword_match.tag.to_string(),
+                    pattern: keyword_match.pattern.to_string(),
+                });
+            }
+        }
+
+        // Post to HTTPS endpoint if configured
+        if let Some(client) = &self.http_client {
+            let transcription = transcription.clone();
+            let client = client.clone();
+            let metrics = self.metrics.clone();
+            pending.push(tokio::spawn(async move {
+                if let Err(e) = client
+                    .post_transcription(
+                        &transcription.id,
+                        transcription.timestamp,
+                        &transcription.text,
+                        &transcription.source_node,
+                        transcription.memo_device_id.as_deref(),
+                        transcription.duration_ms,
+                    )
+                    .await
+                {
+                    // Log error but don't crash - HTTP failures shouldn't block transcription
+                    warn!("Failed to post transcription to HTTPS endpoint: {}", e);
+                    metrics.record_error(Subsystem::Http, format!("{:#}", e));
+                    Metrics::add(&metrics.http_failed, 1);
+                } else {
+                    Metrics::add(&metrics.http_posted, 1);
+                }
+            }));
+        }
+    }
+
+    /// Wait for outstanding hook runs and HTTPS posts
+    pub async fn flush(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for task in pending {
+            let _ = task.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions_link_rows_within_gap() {
+        let mut sessions = SessionTracker::new(5000);
+        let mut text = |id, source_type, at: i64| {
+            sessions.session_for(id, source_type, None, at * 1000, at * 1000)
+        };
+        assert_eq!(text("a", SourceType::Device, 100).as_deref(), Some("a"));
+        assert_eq!(text("b", SourceType::Device, 104).as_deref(), Some("a"));
+        // The gap is measured from the previous row, not the first
+        assert_eq!(text("c", SourceType::Device, 109).as_deref(), Some("a"));
+        assert_eq!(text("d", SourceType::Device, 120).as_deref(), Some("d"));
+        assert_eq!(text("e", SourceType::Manual, 121).as_deref(), Some("e"));
+
+        assert_eq!(SessionTracker::new(0).session_for("f", SourceType::Device, None, 0, 0), None);
+    }
+
+    #[test]
+    fn test_sessions_are_per_device_and_gap_runs_from_recording_end() {
+        let mut sessions = SessionTracker::new(2000);
+        let mut recording = |id, device, started_ms, ended_ms| {
+            sessions.session_for(id, SourceType::Device, Some(device), started_ms, ended_ms)
+        };
+        // Two devices recording at once each get their own session
+        assert_eq!(recording("a", "memo-1", 0, 10_000).as_deref(), Some("a"));
+        assert_eq!(recording("b", "memo-2", 1_000, 9_000).as_deref(), Some("b"));
+        // A long recording that started 1s after memo-1's ended continues
+        // its session, although it finished 30s later
+        assert_eq!(recording("c", "memo-1", 11_000, 40_000).as_deref(), Some("a"));
+        assert_eq!(recording("d", "memo-2", 10_000, 12_000).as_deref(), Some("b"));
+        // 3s after the end of the previous recording is past the gap
+        assert_eq!(recording("e", "memo-1", 43_000, 45_000).as_deref(), Some("e"));
+    }
+    #[test]
+    fn test_retranscribe_updates_the_row_and_keeps_the_first_text() {
+        let path = std::env::temp_dir().join(format!("memo-node-reprocess-{}.db", Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        let config = Config::load_from(None, Vec::new()).unwrap();
+        let pipeline =
+            TranscriptionPipeline::new(&config, storage.clone(), Arc::new(Metrics::default()))
+                .unwrap();
+        let row = Transcription::sample("a", 1_717_243_200);
+        storage.insert_transcription(&row).unwrap();
+
+        assert!(pipeline.retranscribe(&row, "memo a").unwrap().is_none());
+        let updated = pipeline.retranscribe(&row, "memo eh").unwrap().unwrap();
+        assert_eq!(updated.original_text.as_deref(), Some("memo a"));
+        let stored = storage.get_transcription("a").unwrap().unwrap();
+        assert_eq!(stored.text, "memo eh");
+        assert_eq!(stored.original_text.as_deref(), Some("memo a"));
+
+        // Reprocessing again, e.g. with another model, keeps the first text
+        pipeline.retranscribe(&stored, "memo A").unwrap().unwrap();
+        let stored = storage.get_transcription("a").unwrap().unwrap();
+        assert_eq!(stored.text, "memo A");
+        assert_eq!(stored.original_text.as_deref(), Some("memo a"));
+        assert_eq!(storage.count_transcriptions().unwrap().0, 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recording_is_saved_under_its_row_id() {
+        let path = std::env::temp_dir().join(format!("memo-node-saved-{}.db", Uuid::new_v4()));
+        let dir = std::env::temp_dir().join(format!("memo-node-recordings-{}", Uuid::new_v4()));
+        let config = Config::load_from(None, Vec::new()).unwrap();
+        let storage = Storage::new(&path).unwrap();
+        let mut pipeline =
+            TranscriptionPipeline::new(&config, storage, Arc::new(Metrics::default())).unwrap();
+        pipeline.recordings_dir = Some(dir.clone());
+
+        let audio = vec![100; transcribe::SAMPLE_RATE as usize];
+        let rows = pipeline.process_recording(&TranscribedRecording {
+            text: "buy milk".to_string(),
+            duration_ms: 1000,
+            origin: transcribe::RecordingOrigin::default(),
+            audio: audio.clone(),
+        });
+        assert_eq!(rows.len(), 1);
+        let saved = recording_path(&dir, &rows[0].id, RecordingFormat::Wav);
+        let (samples, sample_rate) = crate::audio::capture::read_recording(&saved).unwrap();
+        assert_eq!((samples, sample_rate), (audio, transcribe::SAMPLE_RATE));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
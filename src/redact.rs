@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::borrow::Cow;
+
+/// Patterns applied by `transcription.redact`, in order
+///
+/// Card numbers go before phone numbers so a whole card number becomes one
+/// placeholder rather than a phone number plus leftover digits.
+const BUILTIN_PATTERNS: &[&str] = &[
+    // Emails, as Whisper writes them when the address is spelled out
+    r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+    // 13-19 digits, optionally grouped with spaces or dashes
+    r"\b(?:\d[ -]?){12,18}\d\b",
+    // International numbers: + followed by at least 8 digits
+    r"\+\d[\d ().-]{6,}\d",
+    // North American numbers: (555) 123-4567, 555.123.4567, 5551234567
+    r"(?:\(\d{3}\)\s?|\b\d{3}[\s.-]?)\d{3}[\s.-]?\d{4}\b",
+];
+
+/// Replaces PII-looking text with a placeholder before it is stored
+///
+/// Matching is purely textual: numbers or addresses spoken as words
+/// ("five five five ...", "jane at example dot com") are not caught, and
+/// unrelated digit runs of the right shape are.
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    placeholder: String,
+}
+
+impl Redactor {
+    /// The built-in patterns followed by `extra_patterns`
+    pub fn new(extra_patterns: &[String], placeholder: &str) -> Result<Self> {
+        let builtin = BUILTIN_PATTERNS
+            .iter()
+            .map(|pattern| Regex::new(pattern).expect("built-in redaction pattern is valid"));
+        let extra = extra_patterns.iter().map(|pattern| {
+            Regex::new(pattern).with_context(|| format!("Invalid redaction pattern '{}'", pattern))
+        });
+
+        Ok(Self {
+            patterns: builtin.map(Ok).chain(extra).collect::<Result<_>>()?,
+            placeholder: placeholder.to_string(),
+        })
+    }
+
+    /// `text` with every match replaced by the placeholder
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            let redacted = match pattern.replace_all(&text, self.placeholder.as_str()) {
+                Cow::Owned(redacted) => redacted,
+                Cow::Borrowed(_) => continue,
+            };
+            text = Cow::Owned(redacted);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_patterns_redact_pii() {
+        let redactor = Redactor::new(&[], "[REDACTED]").unwrap();
+        let redact = |text| redactor.redact(text).into_owned();
+
+        assert_eq!(redact("mail jane.doe@example.com today"), "mail [REDACTED] today");
+        assert_eq!(redact("card 4111 1111 1111 1111 expires"), "card [REDACTED] expires");
+        assert_eq!(redact("call (555) 123-4567 or 555.987.6543"), "call [REDACTED] or [REDACTED]");
+        assert_eq!(redact("ring +44 20 7946 0958 now"), "ring [REDACTED] now");
+        // Ordinary numbers are left alone
+        assert_eq!(redact("buy 12 eggs at 10:30 in 2024"), "buy 12 eggs at 10:30 in 2024");
+        assert!(matches!(redactor.redact("nothing here"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_extra_patterns_are_applied() {
+        let redactor = Redactor::new(&[r"(?i)\bproject \w+".to_string()], "***").unwrap();
+        assert_eq!(redactor.redact("status of Project Falcon"), "status of ***");
+        assert!(Redactor::new(&["(".to_string()], "***").is_err());
+    }
+}
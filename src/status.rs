@@ -0,0 +1,163 @@
+use crate::clock;
+use crate::metrics::MetricsSnapshot;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// How long the status file may go without a heartbeat before the daemon
+/// is considered gone (e.g. killed without a clean shutdown)
+pub const STALE_AFTER_SECS: i64 = 30;
+
+/// Runtime state written by the daemon for the `status` command to read
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started_at: i64,
+    pub updated_at: i64,
+    pub transcriptions_since_start: u64,
+    pub last_transcription_at: Option<i64>,
+    /// Whether audio was being recorded at the last heartbeat
+    #[serde(default)]
+    pub recording: bool,
+    /// Whether new transcriptions were being refused for lack of disk space
+    /// (`storage.min_free_mb`) at the last heartbeat
+    #[serde(default)]
+    pub low_disk_space: bool,
+    #[serde(default)]
+    pub metrics: MetricsSnapshot,
+}
+
+impl DaemonStatus {
+    pub fn uptime_secs(&self, now: i64) -> i64 {
+        (now - self.started_at).max(0)
+    }
+
+    pub fn is_stale(&self, now: i64) -> bool {
+        now - self.updated_at > STALE_AFTER_SECS
+    }
+}
+
+/// Handle to the daemon's status file, shared across tasks
+#[derive(Clone)]
+pub struct StatusFile {
+    path: PathBuf,
+    status: Arc<Mutex<DaemonStatus>>,
+}
+
+impl StatusFile {
+    /// Create (or overwrite) the status file for a freshly started daemon
+    pub fn create(path: PathBuf) -> Result<Self> {
+        let now = clock::unix_now();
+        let status = DaemonStatus {
+            pid: std::process::id(),
+            started_at: now,
+            updated_at: now,
+            ..Default::default()
+        };
+
+        let file = Self {
+            path,
+            status: Arc::new(Mutex::new(status)),
+        };
+        file.flush()?;
+        Ok(file)
+    }
+
+    /// Read the status file written by a running daemon, if any
+    pub fn read(path: &Path) -> Result<Option<DaemonStatus>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read status file {}", path.display()))?;
+        let status = serde_json::from_slice(&data).context("Failed to parse status file")?;
+        Ok(Some(status))
+    }
+
+    /// Apply a change to the in-memory status and persist it
+    pub fn update<F: FnOnce(&mut DaemonStatus)>(&self, f: F) -> Result<()> {
+        {
+            let mut status = self.status.lock().unwrap();
+            f(&mut status);
+        }
+        self.flush()
+    }
+
+    pub fn record_transcription(&self, timestamp: i64) -> Result<()> {
+        self.update(|status| {
+            status.transcriptions_since_start += 1;
+            status.last_transcription_at = Some(timestamp);
+        })
+    }
+
+    /// Refresh the heartbeat and write the status to disk
+    pub fn flush(&self) -> Result<()> {
+        let data = {
+            let mut status = self.status.lock().unwrap();
+            status.updated_at = clock::unix_now();
+            serde_json::to_vec_pretty(&*status).context("Failed to serialize status")?
+        };
+
+        // Write to a temp file and rename so readers never see a partial file
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, data)
+            .with_context(|| format!("Failed to write status file {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace status file {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Remove the status file on graceful shutdown
+    pub fn remove(&self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Format a number of seconds as a compact duration, e.g. "1d 2h 3m 4s"
+pub fn format_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (hours, rem) = (rem / 3_600, rem % 3_600);
+    let (minutes, seconds) = (rem / 60, rem % 60);
+
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(61), "1m 1s");
+        assert_eq!(format_duration(3_725), "1h 2m 5s");
+        assert_eq!(format_duration(90_061), "1d 1h 1m 1s");
+    }
+
+    #[test]
+    fn test_status_file_roundtrip() {
+        let path = std::env::temp_dir().join(format!("memo-node-status-{}.json", uuid::Uuid::new_v4()));
+        let file = StatusFile::create(path.clone()).unwrap();
+        file.record_transcription(1_700_000_000).unwrap();
+
+        let status = StatusFile::read(&path).unwrap().unwrap();
+        assert_eq!(status.pid, std::process::id());
+        assert_eq!(status.transcriptions_since_start, 1);
+        assert_eq!(status.last_transcription_at, Some(1_700_000_000));
+
+        file.remove();
+        assert!(StatusFile::read(&path).unwrap().is_none());
+    }
+}
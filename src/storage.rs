@@ -1,11 +1,169 @@
+use crate::config::IdCollisionMode;
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, DatabaseName, ErrorCode, OpenFlags, OptionalExtension, Row};
 use rusqlite_migration::{Migrations, M};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How a transcription originated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceType {
+    /// Captured from a Memo device and transcribed locally
+    #[default]
+    Device,
+    /// Imported from an external file or system
+    Import,
+    /// Entered manually through an API
+    Manual,
+    /// Received from a peer that didn't report the original source type
+    Peer,
+}
+
+impl SourceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceType::Device => "device",
+            SourceType::Import => "import",
+            SourceType::Manual => "manual",
+            SourceType::Peer => "peer",
+        }
+    }
+}
+
+impl fmt::Display for SourceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SourceType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "device" => Ok(SourceType::Device),
+            "import" => Ok(SourceType::Import),
+            "manual" => Ok(SourceType::Manual),
+            "peer" => Ok(SourceType::Peer),
+            other => Err(anyhow::anyhow!("Unknown source type: {}", other)),
+        }
+    }
+}
+
+impl ToSql for SourceType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for SourceType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: anyhow::Error| FromSqlError::Other(e.into()))
+    }
+}
+
+/// Key-value context attached to a transcription, such as a location or
+/// project set with the WebSocket `set_context` message
+///
+/// Kept as a JSON object string in the database, the CSV export and the
+/// hook's `MEMO_METADATA`, so new keys need no schema change and the CSV
+/// export stays one column. Serializes as an object, and deserializes from
+/// an object or such a string.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata(pub BTreeMap<String, String>);
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The metadata as a JSON object string
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).expect("string map serializes")
+    }
+
+    fn from_json(json: &str) -> serde_json::Result<Self> {
+        if json.is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(json).map(Self)
+    }
+}
+
+impl Serialize for Metadata {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Metadata {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct MetadataVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for MetadataVisitor {
+            type Value = Metadata;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an object of strings or a JSON object string")
+            }
+
+            fn visit_map<A>(self, map: A) -> std::result::Result<Metadata, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let map = serde::de::value::MapAccessDeserializer::new(map);
+                Deserialize::deserialize(map).map(Metadata)
+            }
+
+            // CSV exports and JSON exports from before metadata was an object
+            fn visit_str<E: serde::de::Error>(self, json: &str) -> std::result::Result<Metadata, E> {
+                Metadata::from_json(json).map_err(E::custom)
+            }
+
+            fn visit_unit<E: serde::de::Error>(self) -> std::result::Result<Metadata, E> {
+                Ok(Metadata::default())
+            }
+        }
+
+        deserializer.deserialize_any(MetadataVisitor)
+    }
+}
+
+impl ToSql for Metadata {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        if self.is_empty() {
+            Ok(ToSqlOutput::from(rusqlite::types::Null))
+        } else {
+            Ok(ToSqlOutput::from(self.to_json()))
+        }
+    }
+}
+
+impl FromSql for Metadata {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Null => Ok(Self::default()),
+            value => Self::from_json(value.as_str()?).map_err(|e| FromSqlError::Other(e.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Transcription {
     pub id: String,
     pub timestamp: i64,
@@ -13,6 +171,81 @@ pub struct Transcription {
     pub source_node: String,
     pub memo_device_id: Option<String>,
     pub synced: bool,
+    pub source_type: SourceType,
+    /// Id of the first row of the recording session this row belongs to,
+    /// when `transcription.merge_gap_ms` links consecutive rows
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Last known battery percentage of the recording device, if it reports one
+    #[serde(default)]
+    pub device_battery: Option<u8>,
+    /// Context that was set when the row was created
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// Length of the recording the text was transcribed from; `None` for
+    /// text that didn't come from audio
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+    /// Hex ed25519 signature by the creating node, when it signs its rows
+    /// (`sync.sign_transcriptions`); see `sync::signing`
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// Hex public key `signature` was made with
+    #[serde(default)]
+    pub signer_key: Option<String>,
+    /// Code like `MEMO-7Q2` for referring to the row, unique on this node
+    /// (see `Storage::with_short_codes`). Local to this node, so not synced;
+    /// the same row has a different code on each node.
+    #[serde(default)]
+    pub short_code: Option<String>,
+    /// Text as first transcribed, kept when `reprocess` replaces `text`
+    /// with a new model's transcription. Local to this node, so not synced.
+    #[serde(default)]
+    pub original_text: Option<String>,
+    /// Cursor assigned by this node when the row was stored (see
+    /// `Storage::next_cursor`); 0 until then. Local to this node, so not
+    /// exported or synced.
+    #[serde(skip)]
+    pub cursor: i64,
+}
+
+#[cfg(test)]
+impl Transcription {
+    /// A device row from `test-node` reading `memo <id>`, for tests
+    pub fn sample(id: &str, timestamp: i64) -> Self {
+        Self {
+            id: id.to_string(),
+            timestamp,
+            text: format!("memo {}", id),
+            source_node: "test-node".to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Column list matching `row_to_transcription`
+const TRANSCRIPTION_COLUMNS: &str =
+    "id, timestamp, text, source_node, memo_device_id, synced, source_type, seq, session_id, device_battery, metadata, duration_ms, signature, signer_key, short_code, original_text";
+
+fn row_to_transcription(row: &Row) -> rusqlite::Result<Transcription> {
+    Ok(Transcription {
+        id: row.get(0)?,
+        timestamp: row.get(1)?,
+        text: row.get(2)?,
+        source_node: row.get(3)?,
+        memo_device_id: row.get(4)?,
+        synced: row.get::<_, i32>(5)? != 0,
+        source_type: row.get(6)?,
+        cursor: row.get(7)?,
+        session_id: row.get(8)?,
+        device_battery: row.get(9)?,
+        metadata: row.get(10)?,
+        duration_ms: row.get(11)?,
+        signature: row.get(12)?,
+        signer_key: row.get(13)?,
+        short_code: row.get(14)?,
+        original_text: row.get(15)?,
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,162 +253,1595 @@ pub struct Peer {
     pub node_id: String,
     pub last_seen: i64,
     pub last_sync_timestamp: i64,
+    pub display_name: Option<String>,
+    /// Seconds the peer's clock was ahead of ours at the last measurement
+    pub clock_offset: Option<i64>,
+    /// Where the peer was last reached, so it can be synced with again
+    /// before mDNS rediscovers it
+    pub address: Option<String>,
+    pub grpc_port: Option<u16>,
+}
+
+impl Peer {
+    /// Human-readable name, falling back to the node id
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.node_id)
+    }
+}
+
+const PEER_COLUMNS: &str =
+    "node_id, last_seen, last_sync_timestamp, display_name, clock_offset, address, grpc_port";
+
+fn peer_from_row(row: &Row) -> rusqlite::Result<Peer> {
+    Ok(Peer {
+        node_id: row.get(0)?,
+        last_seen: row.get(1)?,
+        last_sync_timestamp: row.get(2)?,
+        display_name: row.get(3)?,
+        clock_offset: row.get(4)?,
+        address: row.get(5)?,
+        grpc_port: row.get(6)?,
+    })
+}
+
+/// `AND source_node IN (...)` for a non-empty `source_nodes`, numbered to
+/// follow a `?1` timestamp parameter
+fn source_node_filter(source_nodes: &[String]) -> String {
+    if source_nodes.is_empty() {
+        return String::new();
+    }
+    format!(" AND source_node IN ({})", vec!["?"; source_nodes.len()].join(", "))
+}
+
+fn since_params<'a>(since: &'a i64, source_nodes: &'a [String]) -> impl rusqlite::Params + 'a {
+    rusqlite::params_from_iter(
+        std::iter::once(since as &dyn ToSql).chain(source_nodes.iter().map(|node| node as &dyn ToSql)),
+    )
+}
+
+/// Per-day transcription database file name used in rolling mode
+fn day_file_name(day: NaiveDate) -> String {
+    format!("memo-{}.db", day.format("%Y-%m-%d"))
+}
+
+fn parse_day_file_name(name: &str) -> Option<NaiveDate> {
+    let date = name.strip_prefix("memo-")?.strip_suffix(".db")?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// UTC day a transcription timestamp belongs to
+fn day_of(timestamp: i64) -> NaiveDate {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.date_naive())
+        .unwrap_or_default()
+}
+
+fn migrations() -> Migrations<'static> {
+    Migrations::new(migration_steps())
+}
+
+fn migration_steps() -> Vec<M<'static>> {
+    vec![
+        M::up(
+            "CREATE TABLE transcriptions (
+                id TEXT PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                text TEXT NOT NULL,
+                source_node TEXT NOT NULL,
+                memo_device_id TEXT,
+                synced INTEGER DEFAULT 0
+            );
+
+            CREATE INDEX idx_timestamp ON transcriptions(timestamp);
+            CREATE INDEX idx_synced ON transcriptions(synced);
+
+            CREATE TABLE peers (
+                node_id TEXT PRIMARY KEY,
+                last_seen INTEGER,
+                last_sync_timestamp INTEGER
+            );",
+        ),
+        M::up(
+            "ALTER TABLE transcriptions ADD COLUMN source_type TEXT NOT NULL DEFAULT 'device';",
+        ),
+        M::up("ALTER TABLE peers ADD COLUMN display_name TEXT;"),
+        M::up(
+            "CREATE TABLE transcription_tags (
+                transcription_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (transcription_id, tag)
+            );",
+        ),
+        M::up("ALTER TABLE peers ADD COLUMN clock_offset INTEGER;"),
+        // Existing rows keep their insertion order; new cursors are
+        // microsecond-based and so always larger
+        M::up(
+            "ALTER TABLE transcriptions ADD COLUMN seq INTEGER NOT NULL DEFAULT 0;
+            UPDATE transcriptions SET seq = rowid;
+            CREATE INDEX idx_seq ON transcriptions(seq);",
+        ),
+        M::up("ALTER TABLE transcriptions ADD COLUMN session_id TEXT;"),
+        M::up("ALTER TABLE transcriptions ADD COLUMN device_battery INTEGER;"),
+        M::up(
+            "ALTER TABLE peers ADD COLUMN address TEXT;
+            ALTER TABLE peers ADD COLUMN grpc_port INTEGER;",
+        ),
+        M::up("ALTER TABLE transcriptions ADD COLUMN metadata TEXT;"),
+        M::up("ALTER TABLE transcriptions ADD COLUMN duration_ms INTEGER;"),
+        M::up(
+            "ALTER TABLE transcriptions ADD COLUMN signature TEXT;
+            ALTER TABLE transcriptions ADD COLUMN signer_key TEXT;
+            CREATE TABLE node_keys (
+                node_id TEXT PRIMARY KEY,
+                public_key TEXT NOT NULL
+            );",
+        ),
+        M::up(
+            "ALTER TABLE transcriptions ADD COLUMN short_code TEXT;
+            CREATE INDEX idx_short_code ON transcriptions(short_code);
+            CREATE TABLE counters (
+                name TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );",
+        ),
+        M::up(
+            "CREATE TABLE push_cursors (
+                node_id TEXT PRIMARY KEY,
+                cursor INTEGER NOT NULL
+            );",
+        ),
+        M::up("ALTER TABLE transcriptions ADD COLUMN original_text TEXT;"),
+    ]
+}
+
+/// Open a database and bring its schema up to date
+///
+/// With `auto_recover`, the file is also checked with `PRAGMA integrity_check`
+/// and, if that fails or SQLite reports it corrupt or not a database, moved
+/// aside to `<name>.corrupt-<timestamp>` so the node can start with an empty
+/// database. Other errors, such as permissions or a full disk, are returned
+/// as they are.
+fn open_database(path: &Path, auto_recover: bool) -> Result<Connection> {
+    if !auto_recover {
+        return open_database_unchecked(path);
+    }
+
+    let checked = open_database_unchecked(path).and_then(|conn| {
+        check_integrity(&conn)?;
+        Ok(conn)
+    });
+
+    match checked {
+        Ok(conn) => Ok(conn),
+        Err(e) if path.exists() && is_corruption(&e) => {
+            let moved_to = quarantine_database(path)?;
+            error!(
+                "DATABASE CORRUPTION: {} failed its integrity check ({:#}); moved it to {} and starting with an empty database",
+                path.display(),
+                e,
+                moved_to.display()
+            );
+            open_database_unchecked(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn open_database_unchecked(path: &Path) -> Result<Connection> {
+    let mut conn = Connection::open(path)
+        .with_context(|| format!("Failed to open database at {}", path.display()))?;
+
+    migrations()
+        .to_latest(&mut conn)
+        .context("Failed to run migrations")?;
+
+    Ok(conn)
+}
+
+/// Open a database for a dry run without ever writing to it
+///
+/// The file is opened read-only. A missing file is replaced by an empty
+/// in-memory database, and one whose schema is behind is copied into memory
+/// and migrated there, so the file itself is never created or changed.
+fn open_database_read_only(path: &Path) -> Result<Connection> {
+    if !path.exists() {
+        info!("Dry run: {} doesn't exist; using an empty database", path.display());
+        let mut conn = Connection::open_in_memory().context("Failed to open in-memory database")?;
+        migrations()
+            .to_latest(&mut conn)
+            .context("Failed to run migrations")?;
+        return Ok(conn);
+    }
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open database at {} read-only", path.display()))?;
+    let version: usize = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .context("Failed to read schema version")?;
+    if version >= migration_steps().len() {
+        return Ok(conn);
+    }
+
+    info!(
+        "Dry run: {} needs migrating; working on a copy in memory",
+        path.display()
+    );
+    let mut copy = Connection::open_in_memory().context("Failed to open in-memory database")?;
+    copy.restore(DatabaseName::Main, path, None::<fn(rusqlite::backup::Progress)>)
+        .with_context(|| format!("Failed to copy {} into memory", path.display()))?;
+    migrations()
+        .to_latest(&mut copy)
+        .context("Failed to run migrations")?;
+    Ok(copy)
+}
+
+fn check_integrity(conn: &Connection) -> Result<()> {
+    let mut stmt = conn
+        .prepare("PRAGMA integrity_check")
+        .context("Failed to run integrity check")?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .context("Failed to run integrity check")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to run integrity check")?;
+
+    if problems.len() == 1 && problems[0] == "ok" {
+        Ok(())
+    } else {
+        Err(IntegrityError(problems.join("; ")).into())
+    }
+}
+
+/// `PRAGMA integrity_check` found problems
+#[derive(Debug, thiserror::Error)]
+#[error("integrity check reported: {0}")]
+struct IntegrityError(String);
+
+/// Whether `e` means the database file itself is damaged, rather than that
+/// it couldn't be read or written
+fn is_corruption(e: &anyhow::Error) -> bool {
+    let corrupt = |e: &rusqlite::Error| {
+        matches!(
+            e.sqlite_error_code(),
+            Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase)
+        )
+    };
+    e.chain().any(|cause| {
+        cause.is::<IntegrityError>()
+            || cause.downcast_ref::<rusqlite::Error>().is_some_and(corrupt)
+            || matches!(
+                cause.downcast_ref::<rusqlite_migration::Error>(),
+                Some(rusqlite_migration::Error::RusqliteError { err, .. }) if corrupt(err)
+            )
+    })
+}
+
+/// Rename a corrupt database (and its WAL/SHM files) out of the way,
+/// returning the new path of the database file
+fn quarantine_database(path: &Path) -> Result<PathBuf> {
+    let suffix = format!(".corrupt-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+
+    let mut moved_to = path.as_os_str().to_owned();
+    moved_to.push(&suffix);
+    let moved_to = PathBuf::from(moved_to);
+    std::fs::rename(path, &moved_to)
+        .with_context(|| format!("Failed to move corrupt database {} aside", path.display()))?;
+
+    for extension in ["-wal", "-shm"] {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(extension);
+        let sidecar = PathBuf::from(sidecar);
+        if sidecar.exists() {
+            let mut sidecar_to = moved_to.as_os_str().to_owned();
+            sidecar_to.push(extension);
+            if let Err(e) = std::fs::rename(&sidecar, PathBuf::from(sidecar_to)) {
+                warn!("Failed to move {} aside: {}", sidecar.display(), e);
+            }
+        }
+    }
+
+    Ok(moved_to)
+}
+
+fn query_transcriptions<P: rusqlite::Params>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+) -> Result<Vec<Transcription>> {
+    let mut stmt = conn.prepare(sql).context("Failed to prepare statement")?;
+
+    let transcriptions = stmt
+        .query_map(params, row_to_transcription)
+        .context("Failed to query transcriptions")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect transcriptions")?;
+
+    Ok(transcriptions)
+}
+
+fn insert_row(conn: &Connection, transcription: &Transcription) -> Result<()> {
+    conn.execute(
+        // A replaced row keeps its short code and original text
+        "INSERT OR REPLACE INTO transcriptions (id, timestamp, text, source_node, memo_device_id, synced, source_type, seq, session_id, device_battery, metadata, duration_ms, signature, signer_key, short_code, original_text)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14,
+                 COALESCE((SELECT short_code FROM transcriptions WHERE id = ?1), ?15),
+                 COALESCE((SELECT original_text FROM transcriptions WHERE id = ?1), ?16))",
+        params![
+            transcription.id,
+            transcription.timestamp,
+            transcription.text,
+            transcription.source_node,
+            transcription.memo_device_id,
+            transcription.synced as i32,
+            transcription.source_type,
+            transcription.cursor,
+            transcription.session_id,
+            transcription.device_battery,
+            transcription.metadata,
+            transcription.duration_ms,
+            transcription.signature,
+            transcription.signer_key,
+            transcription.short_code,
+            transcription.original_text,
+        ],
+    )
+    .context("Failed to insert transcription")?;
+    Ok(())
+}
+
+/// `n` in Crockford base32 (no I, L, O or U), at least three digits
+fn base32(mut n: u64) -> String {
+    const DIGITS: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+    let mut code = Vec::new();
+    while n > 0 || code.len() < 3 {
+        code.push(DIGITS[(n % 32) as usize]);
+        n /= 32;
+    }
+    code.reverse();
+    String::from_utf8(code).expect("base32 digits are ASCII")
+}
+
+/// Replacement id for a row whose id collided with another row, derived
+/// from the id and source node so syncing the row again replaces the
+/// renamed copy instead of adding another
+fn renamed_id(transcription: &Transcription) -> String {
+    let digest = Sha256::new()
+        .chain_update(transcription.id.as_bytes())
+        .chain_update(b"\n")
+        .chain_update(transcription.source_node.as_bytes())
+        .finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Builder::from_random_bytes(bytes).into_uuid().to_string()
+}
+
+fn insert_batch<'a>(
+    conn: &Connection,
+    transcriptions: impl Iterator<Item = &'a Transcription>,
+) -> Result<()> {
+    let tx = conn
+        .unchecked_transaction()
+        .context("Failed to begin transaction")?;
+    for transcription in transcriptions {
+        insert_row(&tx, transcription)?;
+    }
+    tx.commit().context("Failed to commit buffered transcriptions")
+}
+
+/// Per-day transcription databases with a bounded set of open connections
+struct RollingFiles {
+    dir: PathBuf,
+    max_open: usize,
+    auto_recover: bool,
+    /// Open day files with `open_database_read_only` (dry runs)
+    read_only: bool,
+    /// Open connections, most recently used first
+    open: Vec<(NaiveDate, Connection)>,
+}
+
+impl RollingFiles {
+    /// Days that have a database file on disk, oldest first
+    fn days(&self) -> Result<Vec<NaiveDate>> {
+        let entries = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to list {}", self.dir.display()))?;
+
+        let mut days = Vec::new();
+        for entry in entries {
+            let entry = entry.context("Failed to read directory entry")?;
+            if let Some(day) = entry.file_name().to_str().and_then(parse_day_file_name) {
+                days.push(day);
+            }
+        }
+
+        days.sort();
+        Ok(days)
+    }
+
+    /// Connection for `day` if its file exists, without creating it
+    fn existing_conn(&mut self, day: NaiveDate) -> Result<Option<&Connection>> {
+        let is_open = self.open.iter().any(|(d, _)| *d == day);
+        if !is_open && !self.dir.join(day_file_name(day)).exists() {
+            return Ok(None);
+        }
+        self.conn(day).map(Some)
+    }
+
+    /// Connection for `day`, opening (and creating) its file if needed
+    fn conn(&mut self, day: NaiveDate) -> Result<&Connection> {
+        if let Some(pos) = self.open.iter().position(|(d, _)| *d == day) {
+            let entry = self.open.remove(pos);
+            self.open.insert(0, entry);
+        } else {
+            let path = self.dir.join(day_file_name(day));
+            let conn = if self.read_only {
+                open_database_read_only(&path)?
+            } else {
+                open_database(&path, self.auto_recover)?
+            };
+            self.open.insert(0, (day, conn));
+            // Dropping the least recently used connections closes them
+            self.open.truncate(self.max_open.max(1));
+        }
+
+        Ok(&self.open[0].1)
+    }
+}
+
+/// Transcription inserts waiting to be committed together
+struct WriteBuffer {
+    pending: Vec<Transcription>,
+    /// When the oldest pending row was buffered
+    oldest: Option<Instant>,
+    max_rows: usize,
+    max_delay: Duration,
+}
+
+impl WriteBuffer {
+    fn is_due(&self) -> bool {
+        self.pending.len() >= self.max_rows
+            || self.oldest.is_some_and(|oldest| oldest.elapsed() >= self.max_delay)
+    }
 }
 
 #[derive(Clone)]
 pub struct Storage {
     conn: Arc<Mutex<Connection>>,
+    /// Set in rolling mode, where transcriptions live in per-day files next
+    /// to the main database and the main database only holds peers
+    rolling: Option<Arc<Mutex<RollingFiles>>>,
+    /// Set in buffered-write mode, see `with_write_buffer`
+    buffer: Option<Arc<Mutex<WriteBuffer>>>,
+    /// Read-only connection for queries, see `with_read_connection`
+    reader: Option<Arc<Mutex<Connection>>>,
+    /// Last cursor handed out
+    last_cursor: Arc<AtomicI64>,
+    /// Log writes instead of performing them, see `with_dry_run`
+    dry_run: bool,
+    /// See `with_id_collision`
+    id_collision: IdCollisionMode,
+    /// See `with_min_free_space`
+    min_free: Option<MinFreeSpace>,
+    /// Prefix of short codes, set when they are on; see `with_short_codes`
+    short_code_prefix: Option<String>,
+}
+
+/// Free space the storage filesystem must keep before inserts are refused
+#[derive(Clone)]
+struct MinFreeSpace {
+    /// Directory on the filesystem the database files live on
+    dir: PathBuf,
+    bytes: u64,
+    /// Whether the last check found too little space, so the warning is
+    /// logged once per episode rather than on every insert
+    low: Arc<AtomicBool>,
+}
+
+/// A transcription refused because its id belongs to a different stored row
+/// (`storage.on_id_collision = "reject"`)
+#[derive(Debug, thiserror::Error)]
+#[error("transcription id {id} is already used by a row from {existing_source}")]
+pub struct IdCollisionError {
+    pub id: String,
+    pub existing_source: String,
+}
+
+/// A transcription refused because the storage filesystem is below
+/// `storage.min_free_mb`
+#[derive(Debug, thiserror::Error)]
+#[error("only {available_mb} MB free on the storage filesystem (storage.min_free_mb = {min_free_mb})")]
+pub struct LowDiskSpaceError {
+    pub available_mb: u64,
+    pub min_free_mb: u64,
+}
+
+/// Whether a storage error means the database itself is unusable (missing,
+/// unreadable or corrupt) rather than a failure the next request may not
+/// hit, like a busy database or a failed statement
+pub fn is_fatal_error(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<rusqlite::Error>())
+        .any(|e| {
+            matches!(
+                e.sqlite_error_code(),
+                Some(
+                    ErrorCode::CannotOpen
+                        | ErrorCode::NotADatabase
+                        | ErrorCode::DatabaseCorrupt
+                        | ErrorCode::SystemIoFailure
+                        | ErrorCode::PermissionDenied
+                )
+            )
+        })
 }
 
 impl Storage {
     pub fn new(path: &Path) -> Result<Self> {
-        let mut conn = Connection::open(path)
-            .with_context(|| format!("Failed to open database at {}", path.display()))?;
-
-        let migrations = Migrations::new(vec![
-            M::up(
-                "CREATE TABLE transcriptions (
-                    id TEXT PRIMARY KEY,
-                    timestamp INTEGER NOT NULL,
-                    text TEXT NOT NULL,
-                    source_node TEXT NOT NULL,
-                    memo_device_id TEXT,
-                    synced INTEGER DEFAULT 0
-                );
-
-                CREATE INDEX idx_timestamp ON transcriptions(timestamp);
-                CREATE INDEX idx_synced ON transcriptions(synced);
+        Self::open(path, false)
+    }
 
-                CREATE TABLE peers (
-                    node_id TEXT PRIMARY KEY,
-                    last_seen INTEGER,
-                    last_sync_timestamp INTEGER
-                );",
-            ),
-        ]);
+    /// Open storage, moving a corrupt database aside and starting fresh if
+    /// `auto_recover` is set
+    pub fn open(path: &Path, auto_recover: bool) -> Result<Self> {
+        Self::from_connection(open_database(path, auto_recover)?, false)
+    }
 
-        migrations
-            .to_latest(&mut conn)
-            .context("Failed to run migrations")?;
+    /// Open storage for `--dry-run`: the database is never migrated,
+    /// created or written to (see `open_database_read_only`), and writes are
+    /// only logged, as with `with_dry_run`
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        Self::from_connection(open_database_read_only(path)?, true)
+    }
 
-        Ok(Self {
+    fn from_connection(conn: Connection, dry_run: bool) -> Result<Self> {
+        let storage = Self {
             conn: Arc::new(Mutex::new(conn)),
-        })
+            rolling: None,
+            buffer: None,
+            reader: None,
+            last_cursor: Arc::new(AtomicI64::new(0)),
+            dry_run,
+            id_collision: IdCollisionMode::Replace,
+            min_free: None,
+            short_code_prefix: None,
+        };
+        storage.seed_cursor()?;
+        Ok(storage)
     }
 
-    pub fn insert_transcription(&self, transcription: &Transcription) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO transcriptions (id, timestamp, text, source_node, memo_device_id, synced)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                transcription.id,
-                transcription.timestamp,
-                transcription.text,
-                transcription.source_node,
-                transcription.memo_device_id,
-                transcription.synced as i32,
-            ],
+    /// Open storage that writes transcriptions to one `memo-YYYY-MM-DD.db`
+    /// file per UTC day, keeping at most `max_open_files` connections open
+    pub fn new_rolling(path: &Path, max_open_files: usize, auto_recover: bool) -> Result<Self> {
+        Self::open(path, auto_recover)?.into_rolling(path, max_open_files, auto_recover)
+    }
+
+    /// `new_rolling` for `--dry-run`, opening every file like `open_read_only`
+    pub fn new_rolling_read_only(path: &Path, max_open_files: usize) -> Result<Self> {
+        Self::open_read_only(path)?.into_rolling(path, max_open_files, false)
+    }
+
+    fn into_rolling(mut self, path: &Path, max_open_files: usize, auto_recover: bool) -> Result<Self> {
+        let dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        self.rolling = Some(Arc::new(Mutex::new(RollingFiles {
+            dir,
+            max_open: max_open_files,
+            auto_recover,
+            read_only: self.dry_run,
+            open: Vec::new(),
+        })));
+        self.seed_cursor()?;
+        Ok(self)
+    }
+
+    /// Buffer transcription inserts and commit them in one transaction once
+    /// `max_rows` are pending or the oldest has waited `max_delay`
+    ///
+    /// Rows still in the buffer are lost if the process dies, so callers
+    /// must `flush` on shutdown and should call `flush_if_due` periodically
+    /// so a quiet node doesn't hold rows indefinitely. Every other write of
+    /// transcriptions flushes first, as do queries unless there is a read
+    /// connection, which only sees buffered rows once the buffer is due;
+    /// lookups by id or short code check the buffer either way.
+    pub fn with_write_buffer(mut self, max_rows: usize, max_delay: Duration) -> Self {
+        self.buffer = Some(Arc::new(Mutex::new(WriteBuffer {
+            pending: Vec::new(),
+            oldest: None,
+            max_rows: max_rows.max(1),
+            max_delay,
+        })));
+        self
+    }
+
+    /// Answer queries on a second, read-only connection to `path` (the file
+    /// this storage was opened with), so history and search don't queue
+    /// behind inserts on the write connection
+    ///
+    /// Switches the database to WAL journaling, which lets the reader see
+    /// committed rows while a write is in progress; WAL needs the database
+    /// on a local filesystem that supports shared memory. Not supported in
+    /// rolling mode, where it is ignored with a warning.
+    pub fn with_read_connection(mut self, path: &Path) -> Result<Self> {
+        if self.rolling.is_some() {
+            warn!("storage.read_connection is not supported with rolling storage; ignoring it");
+            return Ok(self);
+        }
+        if self.dry_run {
+            // Switching to WAL would write to the database
+            info!("Dry run: answering queries on the main connection");
+            return Ok(self);
+        }
+
+        let mode: String = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))
+            .context("Failed to enable WAL journaling")?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            anyhow::bail!(
+                "{} can't use WAL journaling (got {}), which the read connection needs",
+                path.display(),
+                mode
+            );
+        }
+
+        let reader = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
         )
-        .context("Failed to insert transcription")?;
-        Ok(())
+        .with_context(|| format!("Failed to open read connection to {}", path.display()))?;
+        self.reader = Some(Arc::new(Mutex::new(reader)));
+        Ok(self)
+    }
+
+    /// Log inserts, tags, sync marks and peer updates instead of writing
+    /// them (`--dry-run`)
+    ///
+    /// Reads still hit the database, so a dry-run node sees existing history
+    /// but never the rows it would have added.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// How `insert_transcription` treats a row whose id is already stored
+    /// with a different source node or text
+    ///
+    /// Only the database the row would be written to is checked, which in
+    /// rolling mode is the file for its day.
+    pub fn with_id_collision(mut self, mode: IdCollisionMode) -> Self {
+        self.id_collision = mode;
+        self
     }
 
-    pub fn get_transcriptions_since(&self, since: i64) -> Result<Vec<Transcription>> {
+    /// Refuse inserts with a `LowDiskSpaceError` while the filesystem
+    /// holding `path` has less than `min_free_mb` MB available
+    ///
+    /// Already buffered rows are still committed, and dry runs are never
+    /// refused.
+    pub fn with_min_free_space(mut self, path: &Path, min_free_mb: u64) -> Self {
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        self.min_free = Some(MinFreeSpace {
+            dir,
+            bytes: min_free_mb.saturating_mul(1024 * 1024),
+            low: Arc::new(AtomicBool::new(false)),
+        });
+        self
+    }
+
+    /// Give each stored row that has none a short code: `prefix`, a dash and
+    /// a counter in Crockford base32 (`MEMO-001`, ..., `MEMO-7Q2`)
+    ///
+    /// The counter lives in the main database and only goes up, so codes
+    /// are never reused on this node, in rolling mode too. Rows keep the
+    /// code they were first stored with when replaced; rows that already
+    /// carry a code (from `allocate_short_code`) are stored with it.
+    pub fn with_short_codes(mut self, prefix: &str) -> Self {
+        self.short_code_prefix = Some(prefix.to_ascii_uppercase());
+        self
+    }
+
+    /// The next short code, or `None` if short codes are off
+    pub fn allocate_short_code(&self) -> Result<Option<String>> {
+        let Some(prefix) = &self.short_code_prefix else {
+            return Ok(None);
+        };
+
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn
-            .prepare("SELECT id, timestamp, text, source_node, memo_device_id, synced FROM transcriptions WHERE timestamp > ?1 ORDER BY timestamp ASC")
-            .context("Failed to prepare statement")?;
+        let value: i64 = if self.dry_run {
+            conn.query_row(
+                "SELECT COALESCE(MAX(value), 0) + 1 FROM counters WHERE name = 'short_code'",
+                [],
+                |row| row.get(0),
+            )
+        } else {
+            conn.query_row(
+                "INSERT INTO counters (name, value) VALUES ('short_code', 1)
+                 ON CONFLICT(name) DO UPDATE SET value = value + 1
+                 RETURNING value",
+                [],
+                |row| row.get(0),
+            )
+        }
+        .context("Failed to allocate short code")?;
+
+        Ok(Some(format!("{}-{}", prefix, base32(value as u64))))
+    }
+
+    /// `transcription` with a new short code if it needs one
+    ///
+    /// A row that is already stored keeps its code when replaced and isn't
+    /// stored at all by `insert_transcription_if_absent`, so re-synced rows
+    /// don't use up codes.
+    fn with_short_code(&self, transcription: Transcription) -> Result<Transcription> {
+        if transcription.short_code.is_some()
+            || self.short_code_prefix.is_none()
+            || self.get_transcription(&transcription.id)?.is_some()
+        {
+            return Ok(transcription);
+        }
+        Ok(Transcription {
+            short_code: self.allocate_short_code()?,
+            ..transcription
+        })
+    }
+
+    /// Whether the last insert was refused for lack of disk space
+    pub fn is_low_on_disk_space(&self) -> bool {
+        self.min_free
+            .as_ref()
+            .is_some_and(|min_free| min_free.low.load(Ordering::Relaxed))
+    }
+
+    /// Fail with a `LowDiskSpaceError` if the storage filesystem is below
+    /// the configured free space
+    fn check_free_space(&self) -> Result<()> {
+        let Some(min_free) = &self.min_free else {
+            return Ok(());
+        };
+        let available = match fs2::available_space(&min_free.dir) {
+            Ok(available) => available,
+            Err(e) => {
+                // Don't stop storing just because the check itself failed
+                warn!("Failed to check free space on {}: {}", min_free.dir.display(), e);
+                return Ok(());
+            }
+        };
+
+        let low = available < min_free.bytes;
+        let was_low = min_free.low.swap(low, Ordering::Relaxed);
+        if !low {
+            if was_low {
+                info!(
+                    "{} MB free on {} again; storing transcriptions",
+                    available / (1024 * 1024),
+                    min_free.dir.display()
+                );
+            }
+            return Ok(());
+        }
+
+        let err = LowDiskSpaceError {
+            available_mb: available / (1024 * 1024),
+            min_free_mb: min_free.bytes / (1024 * 1024),
+        };
+        if !was_low {
+            error!(
+                "DISK ALMOST FULL on {}: {}. New transcriptions will NOT be stored until \
+                 space is freed",
+                min_free.dir.display(),
+                err
+            );
+        }
+        Err(err.into())
+    }
+
+    /// Commit any buffered inserts
+    pub fn flush(&self) -> Result<()> {
+        let Some(buffer) = &self.buffer else {
+            return Ok(());
+        };
+
+        let pending = {
+            let mut buffer = buffer.lock().unwrap();
+            buffer.oldest = None;
+            std::mem::take(&mut buffer.pending)
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = self.write_batch(&pending) {
+            // Keep the rows for the next attempt, ahead of anything newer
+            let mut buffer = buffer.lock().unwrap();
+            let newer = std::mem::replace(&mut buffer.pending, pending);
+            buffer.pending.extend(newer);
+            buffer.oldest.get_or_insert_with(Instant::now);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Commit buffered inserts if the row or time limit has been reached
+    pub fn flush_if_due(&self) -> Result<()> {
+        match &self.buffer {
+            Some(buffer) if buffer.lock().unwrap().is_due() => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Insert rows with one transaction per database file
+    fn write_batch(&self, transcriptions: &[Transcription]) -> Result<()> {
+        match &self.rolling {
+            Some(rolling) => {
+                let mut rolling = rolling.lock().unwrap();
+                let mut days: Vec<NaiveDate> =
+                    transcriptions.iter().map(|t| day_of(t.timestamp)).collect();
+                days.sort();
+                days.dedup();
+
+                for day in days {
+                    let conn = rolling.conn(day)?;
+                    insert_batch(
+                        conn,
+                        transcriptions.iter().filter(|t| day_of(t.timestamp) == day),
+                    )?;
+                }
+            }
+            None => {
+                let conn = self.conn.lock().unwrap();
+                insert_batch(&conn, transcriptions.iter())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run `f` against the database holding transcriptions for `timestamp`
+    fn with_db_for<T>(&self, timestamp: i64, f: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+        self.flush()?;
+        self.with_db_for_unflushed(timestamp, f)
+    }
+
+    /// `with_db_for` without committing buffered inserts first
+    fn with_db_for_unflushed<T>(
+        &self,
+        timestamp: i64,
+        f: impl FnOnce(&Connection) -> Result<T>,
+    ) -> Result<T> {
+        match &self.rolling {
+            Some(rolling) => {
+                let mut rolling = rolling.lock().unwrap();
+                f(rolling.conn(day_of(timestamp))?)
+            }
+            None => {
+                let conn = self.conn.lock().unwrap();
+                f(&conn)
+            }
+        }
+    }
+
+    /// Run `f` against the database holding transcriptions for `timestamp`
+    /// if it exists, without committing buffered inserts; in rolling mode a
+    /// missing day file is not created and `f` isn't run
+    fn with_existing_db_for<T>(
+        &self,
+        timestamp: i64,
+        f: impl FnOnce(&Connection) -> Result<T>,
+    ) -> Result<Option<T>> {
+        match &self.rolling {
+            Some(rolling) => {
+                let mut rolling = rolling.lock().unwrap();
+                rolling.existing_conn(day_of(timestamp))?.map(f).transpose()
+            }
+            None => {
+                let conn = self.conn.lock().unwrap();
+                f(&conn).map(Some)
+            }
+        }
+    }
+
+    /// `for_each_db` for queries that don't write, which use the read
+    /// connection if there is one
+    fn for_each_read_db(
+        &self,
+        from_day: Option<NaiveDate>,
+        newest_first: bool,
+        mut f: impl FnMut(&Connection) -> Result<ControlFlow<()>>,
+    ) -> Result<()> {
+        let Some(reader) = &self.reader else {
+            return self.for_each_db(from_day, newest_first, f);
+        };
+
+        // Committing buffered rows here would put every query back behind
+        // the write connection, so the reader only sees them once the buffer
+        // is due; lookups by id check the buffer itself
+        self.flush_if_due()?;
+        let conn = reader.lock().unwrap();
+        f(&conn)?;
+        Ok(())
+    }
+
+    /// Run `f` against every transcription database, oldest day first (or
+    /// newest first), skipping days before `from_day`. `f` can stop the scan
+    /// early by returning `ControlFlow::Break`.
+    fn for_each_db(
+        &self,
+        from_day: Option<NaiveDate>,
+        newest_first: bool,
+        mut f: impl FnMut(&Connection) -> Result<ControlFlow<()>>,
+    ) -> Result<()> {
+        self.flush()?;
+        match &self.rolling {
+            Some(rolling) => {
+                let mut rolling = rolling.lock().unwrap();
+                let mut days = rolling.days()?;
+                if let Some(from_day) = from_day {
+                    days.retain(|day| *day >= from_day);
+                }
+                if newest_first {
+                    days.reverse();
+                }
 
-        let transcriptions = stmt
-            .query_map(params![since], |row| {
-                Ok(Transcription {
-                    id: row.get(0)?,
-                    timestamp: row.get(1)?,
-                    text: row.get(2)?,
-                    source_node: row.get(3)?,
-                    memo_device_id: row.get(4)?,
-                    synced: row.get::<_, i32>(5)? != 0,
+                for day in days {
+                    if f(rolling.conn(day)?)?.is_break() {
+                        break;
+                    }
+                }
+            }
+            None => {
+                let conn = self.conn.lock().unwrap();
+                f(&conn)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start cursors after the highest one stored, so rows inserted after a
+    /// restart sort after every existing row even if the clock went back
+    fn seed_cursor(&self) -> Result<()> {
+        let mut max = 0;
+        self.for_each_db(None, false, |conn| {
+            let seq: i64 = conn
+                .query_row("SELECT COALESCE(MAX(seq), 0) FROM transcriptions", [], |row| {
+                    row.get(0)
                 })
+                .context("Failed to read the highest cursor")?;
+            max = max.max(seq);
+            Ok(ControlFlow::Continue(()))
+        })?;
+        self.last_cursor.fetch_max(max, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// A cursor for a newly stored row: the current time in microseconds,
+    /// bumped past the previous cursor if needed, so cursors strictly
+    /// increase in insert order, also across restarts
+    fn next_cursor(&self) -> i64 {
+        let now = chrono::Utc::now().timestamp_micros();
+        let previous = self
+            .last_cursor
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| Some(now.max(last + 1)))
+            .expect("closure always returns Some");
+        now.max(previous + 1)
+    }
+
+    /// The newest row still waiting in the write buffer that matches
+    fn buffered(&self, matches: impl Fn(&Transcription) -> bool) -> Option<Transcription> {
+        let buffer = self.buffer.as_ref()?.lock().unwrap();
+        buffer.pending.iter().rev().find(|pending| matches(pending)).cloned()
+    }
+
+    /// The row `transcription` would overwrite, if it came from another
+    /// node or has different text. Buffered rows are checked without
+    /// committing them.
+    fn colliding_row(&self, transcription: &Transcription) -> Result<Option<Transcription>> {
+        let buffered = self.buffered(|pending| pending.id == transcription.id);
+        let existing = match buffered {
+            Some(row) => Some(row),
+            None => self.with_db_for_unflushed(transcription.timestamp, |conn| {
+                let sql = format!("SELECT {} FROM transcriptions WHERE id = ?1", TRANSCRIPTION_COLUMNS);
+                Ok(query_transcriptions(conn, &sql, params![transcription.id])?.pop())
+            })?,
+        };
+
+        Ok(existing.filter(|existing| {
+            existing.source_node != transcription.source_node || existing.text != transcription.text
+        }))
+    }
+
+    /// Store (or replace) a transcription, returning its new cursor
+    ///
+    /// If the id is taken by a different row, `with_id_collision` decides
+    /// whether it is replaced, the new row is refused with an
+    /// `IdCollisionError`, or the new row is stored under an id derived from
+    /// its own id and source node.
+    pub fn insert_transcription(&self, transcription: &Transcription) -> Result<i64> {
+        let transcription = self.with_short_code(self.resolve_id_collision(transcription)?)?;
+        self.store_transcription(&transcription)
+    }
+
+    /// Store transcriptions all at once, with one transaction per database
+    /// file, returning how many were stored
+    ///
+    /// Ids are checked like `insert_transcription`, except that rows refused
+    /// with an `IdCollisionError` are skipped. Nothing is stored if a commit
+    /// fails, apart from days already committed in rolling mode.
+    pub fn insert_transcriptions(&self, transcriptions: &[Transcription]) -> Result<usize> {
+        let mut rows = Vec::with_capacity(transcriptions.len());
+        for transcription in transcriptions {
+            match self.resolve_id_collision(transcription) {
+                Ok(row) => rows.push(Transcription {
+                    cursor: self.next_cursor(),
+                    ..self.with_short_code(row)?
+                }),
+                // Logged by `resolve_id_collision`
+                Err(e) if e.is::<IdCollisionError>() => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self.dry_run {
+            info!("Dry run: would store {} transcriptions", rows.len());
+            return Ok(rows.len());
+        }
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        self.check_free_space()?;
+
+        // Buffered rows have older cursors; keep them ahead of the batch
+        self.flush()?;
+        self.write_batch(&rows)?;
+        Ok(rows.len())
+    }
+
+    /// `transcription` as it should be stored under the id collision mode
+    fn resolve_id_collision(&self, transcription: &Transcription) -> Result<Transcription> {
+        if self.id_collision != IdCollisionMode::Replace {
+            if let Some(existing) = self.colliding_row(transcription)? {
+                if self.id_collision == IdCollisionMode::Reject {
+                    warn!(
+                        "Rejecting transcription {} from {}: id already used by a row from {}",
+                        transcription.id, transcription.source_node, existing.source_node
+                    );
+                    return Err(IdCollisionError {
+                        id: transcription.id.clone(),
+                        existing_source: existing.source_node,
+                    }
+                    .into());
+                }
+
+                // The signature covers the old id, so it would no longer verify
+                let renamed = Transcription {
+                    id: renamed_id(transcription),
+                    signature: None,
+                    signer_key: None,
+                    ..transcription.clone()
+                };
+                warn!(
+                    "Transcription id {} from {} is already used by a row from {}; storing it as {}",
+                    transcription.id, transcription.source_node, existing.source_node, renamed.id
+                );
+                return Ok(renamed);
+            }
+        }
+
+        Ok(transcription.clone())
+    }
+
+    fn store_transcription(&self, transcription: &Transcription) -> Result<i64> {
+        let cursor = self.next_cursor();
+        if self.dry_run {
+            info!("Dry run: would store transcription {}", transcription.id);
+            return Ok(cursor);
+        }
+        self.check_free_space()?;
+
+        if let Some(buffer) = &self.buffer {
+            let due = {
+                let mut buffer = buffer.lock().unwrap();
+                buffer.pending.push(Transcription {
+                    cursor,
+                    ..transcription.clone()
+                });
+                buffer.oldest.get_or_insert_with(Instant::now);
+                buffer.is_due()
+            };
+            if due {
+                self.flush()?;
+            }
+            return Ok(cursor);
+        }
+
+        self.with_db_for(transcription.timestamp, |conn| {
+            insert_row(
+                conn,
+                &Transcription {
+                    cursor,
+                    ..transcription.clone()
+                },
+            )
+        })?;
+        Ok(cursor)
+    }
+
+    /// Insert a transcription unless one with the same id already exists,
+    /// returning its cursor if it was stored
+    pub fn insert_transcription_if_absent(&self, transcription: &Transcription) -> Result<Option<i64>> {
+        let cursor = self.next_cursor();
+        if self.dry_run {
+            info!("Dry run: would store transcription {} if absent", transcription.id);
+            return Ok(Some(cursor));
+        }
+        self.check_free_space()?;
+        let transcription = self.with_short_code(transcription.clone())?;
+        self.with_db_for(transcription.timestamp, |conn| {
+            let inserted = conn
+                .execute(
+                    "INSERT OR IGNORE INTO transcriptions (id, timestamp, text, source_node, memo_device_id, synced, source_type, seq, session_id, device_battery, metadata, duration_ms, signature, signer_key, short_code, original_text)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                    params![
+                        transcription.id,
+                        transcription.timestamp,
+                        transcription.text,
+                        transcription.source_node,
+                        transcription.memo_device_id,
+                        transcription.synced as i32,
+                        transcription.source_type,
+                        cursor,
+                        transcription.session_id,
+                        transcription.device_battery,
+                        transcription.metadata,
+                        transcription.duration_ms,
+                        transcription.signature,
+                        transcription.signer_key,
+                        transcription.short_code,
+                        transcription.original_text,
+                    ],
+                )
+                .context("Failed to insert transcription")?;
+            Ok((inserted > 0).then_some(cursor))
+        })
+    }
+
+    /// The transcription with this id, if stored
+    pub fn get_transcription(&self, id: &str) -> Result<Option<Transcription>> {
+        if let Some(buffered) = self.buffered(|pending| pending.id == id) {
+            return Ok(Some(buffered));
+        }
+
+        let sql = format!("SELECT {} FROM transcriptions WHERE id = ?1", TRANSCRIPTION_COLUMNS);
+
+        let mut found = None;
+        self.for_each_read_db(None, true, |conn| {
+            found = query_transcriptions(conn, &sql, params![id])?.pop();
+            Ok(if found.is_some() {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
             })
-            .context("Failed to query transcriptions")?
-            .collect::<Result<Vec<_>, _>>()
-            .context("Failed to collect transcriptions")?;
+        })?;
+
+        Ok(found)
+    }
+
+    /// The transcription with this id or, failing that, this short code
+    /// (case-insensitive)
+    pub fn find_transcription(&self, id_or_code: &str) -> Result<Option<Transcription>> {
+        if let Some(found) = self.get_transcription(id_or_code)? {
+            return Ok(Some(found));
+        }
+
+        let sql =
+            format!("SELECT {} FROM transcriptions WHERE short_code = ?1", TRANSCRIPTION_COLUMNS);
+        let code = id_or_code.to_ascii_uppercase();
+        let buffered = self.buffered(|pending| pending.short_code.as_deref() == Some(code.as_str()));
+        if let Some(buffered) = buffered {
+            return Ok(Some(buffered));
+        }
+
+        let mut found = None;
+        self.for_each_read_db(None, true, |conn| {
+            found = query_transcriptions(conn, &sql, params![code])?.pop();
+            Ok(if found.is_some() {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        })?;
+
+        Ok(found)
+    }
+
+    /// Up to `limit` transcriptions created by `source_node` and stored
+    /// after `cursor`, in cursor order
+    pub fn get_node_transcriptions_after_cursor(
+        &self,
+        source_node: &str,
+        cursor: i64,
+        limit: usize,
+    ) -> Result<Vec<Transcription>> {
+        let sql = format!(
+            "SELECT {} FROM transcriptions WHERE source_node = ?1 AND seq > ?2 ORDER BY seq ASC LIMIT ?3",
+            TRANSCRIPTION_COLUMNS
+        );
+
+        let mut transcriptions = Vec::new();
+        self.for_each_read_db(None, false, |conn| {
+            let rows = query_transcriptions(conn, &sql, params![source_node, cursor, limit])?;
+            transcriptions.extend(rows);
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        transcriptions.sort_by_key(|t| t.cursor);
+        transcriptions.truncate(limit);
+        Ok(transcriptions)
+    }
+
+    /// Transcriptions newer than `since`, limited to rows created by
+    /// `source_nodes` unless it is empty
+    pub fn get_transcriptions_since(&self, since: i64, source_nodes: &[String]) -> Result<Vec<Transcription>> {
+        let sql = format!(
+            "SELECT {} FROM transcriptions WHERE timestamp > ?1{} ORDER BY timestamp ASC, id ASC",
+            TRANSCRIPTION_COLUMNS,
+            source_node_filter(source_nodes)
+        );
+
+        let mut transcriptions = Vec::new();
+        self.for_each_read_db(Some(day_of(since)), false, |conn| {
+            transcriptions.extend(query_transcriptions(conn, &sql, since_params(&since, source_nodes))?);
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        Ok(transcriptions)
+    }
+
+    /// Ids of the rows `get_transcriptions_since` would return, sorted
+    pub fn get_ids_since(&self, since: i64, source_nodes: &[String]) -> Result<Vec<String>> {
+        let sql = format!(
+            "SELECT id FROM transcriptions WHERE timestamp > ?1{}",
+            source_node_filter(source_nodes)
+        );
+
+        let mut ids = Vec::new();
+        self.for_each_read_db(Some(day_of(since)), false, |conn| {
+            let mut stmt = conn.prepare(&sql).context("Failed to prepare statement")?;
+            let rows = stmt
+                .query_map(since_params(&since, source_nodes), |row| row.get::<_, String>(0))
+                .context("Failed to query ids")?;
+            for id in rows {
+                ids.push(id.context("Failed to read id")?);
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// At most `limit` of the ids `get_ids_since` would return, starting
+    /// after `after_id` (`""` for the first page)
+    pub fn get_ids_page(
+        &self,
+        since: i64,
+        source_nodes: &[String],
+        after_id: &str,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let sql = format!(
+            "SELECT id FROM transcriptions WHERE timestamp > ?1 AND id > ?2{} ORDER BY id LIMIT {}",
+            source_node_filter(source_nodes),
+            limit
+        );
+        let params = || {
+            rusqlite::params_from_iter(
+                [&since as &dyn ToSql, &after_id as &dyn ToSql]
+                    .into_iter()
+                    .chain(source_nodes.iter().map(|node| node as &dyn ToSql)),
+            )
+        };
+
+        let mut ids = Vec::new();
+        self.for_each_read_db(Some(day_of(since)), false, |conn| {
+            let mut stmt = conn.prepare(&sql).context("Failed to prepare statement")?;
+            let rows = stmt
+                .query_map(params(), |row| row.get::<_, String>(0))
+                .context("Failed to query ids")?;
+            for id in rows {
+                ids.push(id.context("Failed to read id")?);
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        // Each day file contributed its own first `limit` ids
+        ids.sort();
+        ids.dedup();
+        ids.truncate(limit);
+        Ok(ids)
+    }
+
+    /// Copy every database file into `dir` with SQLite's online backup API,
+    /// which gives a consistent snapshot even while another process writes.
+    /// The main database is written as `main_name`, day files under their
+    /// own names. Returns the file names written.
+    pub fn backup_to(&self, dir: &Path, main_name: &str) -> Result<Vec<String>> {
+        self.flush()?;
+        let mut written = Vec::new();
+
+        {
+            let conn = self.conn.lock().unwrap();
+            conn.backup(DatabaseName::Main, dir.join(main_name), None)
+                .context("Failed to back up main database")?;
+            written.push(main_name.to_string());
+        }
+
+        if let Some(rolling) = &self.rolling {
+            let mut rolling = rolling.lock().unwrap();
+            for day in rolling.days()? {
+                let name = day_file_name(day);
+                rolling
+                    .conn(day)?
+                    .backup(DatabaseName::Main, dir.join(&name), None)
+                    .with_context(|| format!("Failed to back up {}", name))?;
+                written.push(name);
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Up to `limit` transcriptions stored after `cursor`, in cursor order
+    pub fn get_transcriptions_after_cursor(&self, cursor: i64, limit: usize) -> Result<Vec<Transcription>> {
+        let sql = format!(
+            "SELECT {} FROM transcriptions WHERE seq > ?1 ORDER BY seq ASC LIMIT ?2",
+            TRANSCRIPTION_COLUMNS
+        );
+
+        // Rows land in the file for their timestamp, so any day file can
+        // hold rows stored after the cursor
+        let mut transcriptions = Vec::new();
+        self.for_each_read_db(None, false, |conn| {
+            transcriptions.extend(query_transcriptions(conn, &sql, params![cursor, limit])?);
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        transcriptions.sort_by_key(|t| t.cursor);
+        transcriptions.truncate(limit);
+        Ok(transcriptions)
+    }
+
+    /// Every stored transcription, oldest first
+    pub fn get_all_transcriptions(&self) -> Result<Vec<Transcription>> {
+        let sql = format!(
+            "SELECT {} FROM transcriptions ORDER BY timestamp ASC, id ASC",
+            TRANSCRIPTION_COLUMNS
+        );
+
+        let mut transcriptions = Vec::new();
+        self.for_each_read_db(None, false, |conn| {
+            transcriptions.extend(query_transcriptions(conn, &sql, [])?);
+            Ok(ControlFlow::Continue(()))
+        })?;
 
         Ok(transcriptions)
     }
 
     pub fn get_recent_transcriptions(&self, limit: usize) -> Result<Vec<Transcription>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn
-            .prepare("SELECT id, timestamp, text, source_node, memo_device_id, synced FROM transcriptions ORDER BY timestamp DESC LIMIT ?1")
-            .context("Failed to prepare statement")?;
+        let sql = format!(
+            "SELECT {} FROM transcriptions ORDER BY timestamp DESC, id DESC LIMIT ?1",
+            TRANSCRIPTION_COLUMNS
+        );
 
-        let transcriptions = stmt
-            .query_map(params![limit], |row| {
-                Ok(Transcription {
-                    id: row.get(0)?,
-                    timestamp: row.get(1)?,
-                    text: row.get(2)?,
-                    source_node: row.get(3)?,
-                    memo_device_id: row.get(4)?,
-                    synced: row.get::<_, i32>(5)? != 0,
-                })
+        let mut transcriptions = Vec::new();
+        self.for_each_read_db(None, true, |conn| {
+            let remaining = limit - transcriptions.len();
+            transcriptions.extend(query_transcriptions(conn, &sql, params![remaining])?);
+
+            Ok(if transcriptions.len() >= limit {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
             })
-            .context("Failed to query transcriptions")?
-            .collect::<Result<Vec<_>, _>>()
-            .context("Failed to collect transcriptions")?;
+        })?;
 
         Ok(transcriptions)
     }
 
     pub fn count_transcriptions(&self) -> Result<(usize, usize)> {
+        let mut total = 0;
+        let mut synced = 0;
+
+        self.for_each_read_db(None, false, |conn| {
+            total += conn
+                .query_row("SELECT COUNT(*) FROM transcriptions", [], |row| row.get::<_, usize>(0))
+                .context("Failed to count total transcriptions")?;
+            synced += conn
+                .query_row(
+                    "SELECT COUNT(*) FROM transcriptions WHERE synced = 1",
+                    [],
+                    |row| row.get::<_, usize>(0),
+                )
+                .context("Failed to count synced transcriptions")?;
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        Ok((total, synced))
+    }
+
+    pub fn mark_synced(&self, id: &str) -> Result<()> {
+        if self.dry_run {
+            info!("Dry run: would mark transcription {} as synced", id);
+            return Ok(());
+        }
+        self.for_each_db(None, true, |conn| {
+            let updated = conn
+                .execute("UPDATE transcriptions SET synced = 1 WHERE id = ?1", params![id])
+                .context("Failed to mark transcription as synced")?;
+            Ok(if updated > 0 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        })
+    }
+
+    /// Tag a transcription; adding an existing tag is a no-op
+    pub fn add_tag(&self, transcription: &Transcription, tag: &str) -> Result<()> {
+        if self.dry_run {
+            info!("Dry run: would tag transcription {} with '{}'", transcription.id, tag);
+            return Ok(());
+        }
+        self.with_db_for(transcription.timestamp, |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO transcription_tags (transcription_id, tag) VALUES (?1, ?2)",
+                params![transcription.id, tag],
+            )
+            .context("Failed to add tag")?;
+            Ok(())
+        })
+    }
+
+    /// Tags of `transcription`, read from the file for its day without
+    /// creating one
+    pub fn get_tags(&self, transcription: &Transcription) -> Result<Vec<String>> {
+        let tags = self.with_existing_db_for(transcription.timestamp, |conn| {
+            let mut stmt = conn
+                .prepare("SELECT tag FROM transcription_tags WHERE transcription_id = ?1 ORDER BY tag")
+                .context("Failed to prepare statement")?;
+
+            let tags = stmt
+                .query_map(params![transcription.id], |row| row.get(0))
+                .context("Failed to query tags")?
+                .collect::<Result<Vec<String>, _>>()
+                .context("Failed to collect tags")?;
+
+            Ok(tags)
+        })?;
+        Ok(tags.unwrap_or_default())
+    }
+
+    pub fn upsert_peer(&self, peer: &Peer) -> Result<()> {
+        if self.dry_run {
+            info!("Dry run: would update peer {}", peer.node_id);
+            return Ok(());
+        }
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("INSERT OR REPLACE INTO peers ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", PEER_COLUMNS),
+            params![
+                peer.node_id,
+                peer.last_seen,
+                peer.last_sync_timestamp,
+                peer.display_name,
+                peer.clock_offset,
+                peer.address,
+                peer.grpc_port,
+            ],
+        )
+        .context("Failed to upsert peer")?;
+        Ok(())
+    }
+
+    /// Cursor of the last of our rows `node_id` confirmed holding, every
+    /// earlier one included; 0 before the first push
+    pub fn get_push_cursor(&self, node_id: &str) -> Result<i64> {
         let conn = self.conn.lock().unwrap();
-        let total: usize = conn
-            .query_row("SELECT COUNT(*) FROM transcriptions", [], |row| row.get(0))
-            .context("Failed to count total transcriptions")?;
-        let synced: usize = conn
+        let cursor = conn
             .query_row(
-                "SELECT COUNT(*) FROM transcriptions WHERE synced = 1",
-                [],
+                "SELECT cursor FROM push_cursors WHERE node_id = ?1",
+                params![node_id],
                 |row| row.get(0),
             )
-            .context("Failed to count synced transcriptions")?;
-        Ok((total, synced))
+            .optional()
+            .context("Failed to query push cursor")?;
+        Ok(cursor.unwrap_or(0))
     }
 
-    pub fn mark_synced(&self, id: &str) -> Result<()> {
+    pub fn set_push_cursor(&self, node_id: &str, cursor: i64) -> Result<()> {
+        if self.dry_run {
+            info!("Dry run: would set push cursor of peer {} to {}", node_id, cursor);
+            return Ok(());
+        }
         let conn = self.conn.lock().unwrap();
-        conn.execute("UPDATE transcriptions SET synced = 1 WHERE id = ?1", params![id])
-            .context("Failed to mark transcription as synced")?;
+        conn.execute(
+            "INSERT INTO push_cursors (node_id, cursor) VALUES (?1, ?2)
+             ON CONFLICT(node_id) DO UPDATE SET cursor = excluded.cursor",
+            params![node_id, cursor],
+        )
+        .context("Failed to set push cursor")?;
         Ok(())
     }
 
-    pub fn upsert_peer(&self, peer: &Peer) -> Result<()> {
+    /// Record where a peer can be reached, adding it if it's new
+    pub fn set_peer_address(&self, node_id: &str, address: &str, grpc_port: u16, now: i64) -> Result<()> {
+        if self.dry_run {
+            info!("Dry run: would record address of peer {}", node_id);
+            return Ok(());
+        }
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT OR REPLACE INTO peers (node_id, last_seen, last_sync_timestamp)
-             VALUES (?1, ?2, ?3)",
-            params![peer.node_id, peer.last_seen, peer.last_sync_timestamp],
+            "INSERT INTO peers (node_id, last_seen, last_sync_timestamp, address, grpc_port)
+             VALUES (?1, ?2, 0, ?3, ?4)
+             ON CONFLICT(node_id) DO UPDATE SET address = excluded.address, grpc_port = excluded.grpc_port",
+            params![node_id, now, address, grpc_port],
         )
-        .context("Failed to upsert peer")?;
+        .context("Failed to record peer address")?;
         Ok(())
     }
 
+    /// The public key pinned for `node_id`, if it has signed a row we've seen
+    pub fn get_node_key(&self, node_id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT public_key FROM node_keys WHERE node_id = ?1",
+            params![node_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Failed to query node key")
+    }
+
+    /// The public key pinned for `node_id`, pinning `public_key` if it has
+    /// none yet
+    pub fn pin_node_key(&self, node_id: &str, public_key: &str) -> Result<String> {
+        let conn = self.conn.lock().unwrap();
+        if !self.dry_run {
+            conn.execute(
+                "INSERT OR IGNORE INTO node_keys (node_id, public_key) VALUES (?1, ?2)",
+                params![node_id, public_key],
+            )
+            .context("Failed to pin node key")?;
+        }
+        let pinned = conn
+            .query_row(
+                "SELECT public_key FROM node_keys WHERE node_id = ?1",
+                params![node_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Failed to query node key")?;
+        Ok(pinned.unwrap_or_else(|| public_key.to_string()))
+    }
+
     pub fn get_peers(&self) -> Result<Vec<Peer>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn
-            .prepare("SELECT node_id, last_seen, last_sync_timestamp FROM peers")
+            .prepare(&format!("SELECT {} FROM peers", PEER_COLUMNS))
             .context("Failed to prepare statement")?;
 
         let peers = stmt
-            .query_map([], |row| {
-                Ok(Peer {
-                    node_id: row.get(0)?,
-                    last_seen: row.get(1)?,
-                    last_sync_timestamp: row.get(2)?,
-                })
-            })
+            .query_map([], peer_from_row)
             .context("Failed to query peers")?
             .collect::<Result<Vec<_>, _>>()
             .context("Failed to collect peers")?;
@@ -187,15 +1853,9 @@ impl Storage {
         let conn = self.conn.lock().unwrap();
         let peer = conn
             .query_row(
-                "SELECT node_id, last_seen, last_sync_timestamp FROM peers WHERE node_id = ?1",
+                &format!("SELECT {} FROM peers WHERE node_id = ?1", PEER_COLUMNS),
                 params![node_id],
-                |row| {
-                    Ok(Peer {
-                        node_id: row.get(0)?,
-                        last_seen: row.get(1)?,
-                        last_sync_timestamp: row.get(2)?,
-                    })
-                },
+                peer_from_row,
             )
             .optional()
             .context("Failed to query peer")?;
@@ -203,3 +1863,439 @@ impl Storage {
         Ok(peer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling_storage_splits_by_day() {
+        let dir = std::env::temp_dir().join(format!("memo-node-rolling-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = Storage::new_rolling(&dir.join("transcriptions.db"), 1, false).unwrap();
+
+        // 2024-06-01 and 2024-06-02 (UTC)
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        storage.insert_transcription(&Transcription::sample("b", 1_717_329_600)).unwrap();
+        storage.insert_transcription(&Transcription::sample("c", 1_717_329_700)).unwrap();
+
+        assert!(dir.join("memo-2024-06-01.db").exists());
+        assert!(dir.join("memo-2024-06-02.db").exists());
+
+        let recent: Vec<_> = storage
+            .get_recent_transcriptions(2)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(recent, vec!["c", "b"]);
+
+        let since: Vec<_> = storage
+            .get_transcriptions_since(0, &[])
+            .unwrap()
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(since, vec!["a", "b", "c"]);
+
+        storage
+            .insert_transcription(&Transcription {
+                source_node: "other-node".to_string(),
+                ..Transcription::sample("d", 1_717_329_800)
+            })
+            .unwrap();
+        let filtered: Vec<_> = storage
+            .get_transcriptions_since(0, &["other-node".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(filtered, vec!["d"]);
+        assert_eq!(storage.get_ids_since(1_717_243_200, &[]).unwrap(), vec!["b", "c", "d"]);
+        assert_eq!(storage.get_ids_page(0, &[], "", 2).unwrap(), vec!["a", "b"]);
+        assert_eq!(storage.get_ids_page(0, &[], "b", 2).unwrap(), vec!["c", "d"]);
+        assert!(storage.get_ids_page(0, &[], "d", 2).unwrap().is_empty());
+
+        assert_eq!(storage.count_transcriptions().unwrap(), (4, 0));
+
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().timestamp, 1_717_243_200);
+        assert!(storage.get_transcription("missing").unwrap().is_none());
+
+        // Tags are read from the row's day file, and reading never creates one
+        storage.add_tag(&Transcription::sample("a", 1_717_243_200), "todo").unwrap();
+        assert_eq!(storage.get_tags(&Transcription::sample("a", 1_717_243_200)).unwrap(), ["todo"]);
+        assert!(storage.get_tags(&Transcription::sample("e", 1_717_416_000)).unwrap().is_empty());
+        assert!(!dir.join("memo-2024-06-03.db").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_same_second_rows_have_stable_order() {
+        let path = std::env::temp_dir().join(format!("memo-node-order-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+
+        for id in ["c", "a", "d", "b"] {
+            storage.insert_transcription(&Transcription::sample(id, 1_717_243_200)).unwrap();
+        }
+
+        let ids = |transcriptions: Vec<Transcription>| -> Vec<String> {
+            transcriptions.into_iter().map(|t| t.id).collect()
+        };
+        assert_eq!(ids(storage.get_recent_transcriptions(3).unwrap()), vec!["d", "c", "b"]);
+        assert_eq!(ids(storage.get_all_transcriptions().unwrap()), vec!["a", "b", "c", "d"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_database_is_moved_aside_with_auto_recover() {
+        let dir = std::env::temp_dir().join(format!("memo-node-corrupt-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("transcriptions.db");
+        std::fs::write(&path, vec![0xAB; 8192]).unwrap();
+
+        assert!(Storage::new(&path).is_err());
+
+        let storage = Storage::open(&path, true).unwrap();
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        assert_eq!(storage.count_transcriptions().unwrap(), (1, 0));
+
+        let moved_aside = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().starts_with("transcriptions.db.corrupt-"));
+        assert!(moved_aside);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_database_is_not_moved_aside() {
+        // A directory in place of the file can't be opened, but isn't corrupt
+        let dir = std::env::temp_dir().join(format!("memo-node-unreadable-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("transcriptions.db");
+        std::fs::create_dir_all(&path).unwrap();
+
+        assert!(Storage::open(&path, true).is_err());
+        assert!(path.is_dir());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_connection_sees_committed_rows_and_finds_buffered_ones() {
+        let path = std::env::temp_dir().join(format!("memo-node-reader-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path)
+            .unwrap()
+            .with_write_buffer(10, Duration::from_secs(3600))
+            .with_read_connection(&path)
+            .unwrap();
+
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        storage.insert_transcription(&Transcription::sample("b", 1_717_243_201)).unwrap();
+        // Queries don't commit a buffer that isn't due, but lookups see it
+        assert_eq!(storage.count_transcriptions().unwrap(), (0, 0));
+        assert_eq!(storage.get_transcription("b").unwrap().unwrap().text, "memo b");
+
+        storage.flush().unwrap();
+        let recent = storage.get_recent_transcriptions(10).unwrap();
+        assert_eq!(recent.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(), ["b", "a"]);
+        assert_eq!(storage.count_transcriptions().unwrap(), (2, 0));
+
+        // Writes still go through the write connection
+        storage.mark_synced("a").unwrap();
+        assert_eq!(storage.count_transcriptions().unwrap(), (2, 1));
+
+        drop(storage);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_write_buffer_commits_in_batches() {
+        let path = std::env::temp_dir().join(format!("memo-node-buffered-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path)
+            .unwrap()
+            .with_write_buffer(3, Duration::from_secs(3600));
+        let committed = || {
+            Connection::open(&path)
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM transcriptions", [], |row| row.get::<_, usize>(0))
+                .unwrap()
+        };
+
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        storage.insert_transcription(&Transcription::sample("b", 1_717_243_201)).unwrap();
+        assert_eq!(committed(), 0);
+
+        storage.insert_transcription(&Transcription::sample("c", 1_717_243_202)).unwrap();
+        assert_eq!(committed(), 3);
+
+        // Reads see buffered rows
+        storage.insert_transcription(&Transcription::sample("d", 1_717_243_203)).unwrap();
+        assert_eq!(storage.count_transcriptions().unwrap(), (4, 0));
+
+        storage.insert_transcription(&Transcription::sample("e", 1_717_243_204)).unwrap();
+        storage.flush().unwrap();
+        assert_eq!(committed(), 5);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_id_collision_rejects_or_renames() {
+        let path = std::env::temp_dir().join(format!("memo-node-collision-{}.db", uuid::Uuid::new_v4()));
+        let local = Transcription::sample("a", 1_717_243_200);
+        let foreign = Transcription {
+            source_node: "other-node".to_string(),
+            text: "someone else's memo".to_string(),
+            ..local.clone()
+        };
+
+        let storage = Storage::new(&path)
+            .unwrap()
+            .with_id_collision(IdCollisionMode::Reject);
+        storage.insert_transcription(&local).unwrap();
+        // The same row again is not a collision
+        storage.insert_transcription(&local).unwrap();
+        let err = storage.insert_transcription(&foreign).unwrap_err();
+        assert!(err.is::<IdCollisionError>());
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().text, local.text);
+
+        let storage = storage.with_id_collision(IdCollisionMode::Rename);
+        storage.insert_transcription(&foreign).unwrap();
+        // Syncing the foreign row again replaces its renamed copy
+        storage.insert_transcription(&foreign).unwrap();
+        assert_eq!(storage.count_transcriptions().unwrap(), (2, 0));
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().text, local.text);
+        let renamed = storage.get_transcription(&renamed_id(&foreign)).unwrap().unwrap();
+        assert_eq!(renamed.source_node, "other-node");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_low_disk_space_refuses_inserts() {
+        let path = std::env::temp_dir().join(format!("memo-node-disk-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap().with_min_free_space(&path, 0);
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        assert!(!storage.is_low_on_disk_space());
+
+        // No filesystem has this much free
+        let storage = storage.with_min_free_space(&path, u64::MAX);
+        let err = storage
+            .insert_transcription(&Transcription::sample("b", 1_717_243_201))
+            .unwrap_err();
+        assert!(err.is::<LowDiskSpaceError>());
+        assert!(storage.is_low_on_disk_space());
+        let batch = [Transcription::sample("c", 1_717_243_202)];
+        assert!(storage.insert_transcriptions(&batch).is_err());
+        assert_eq!(storage.count_transcriptions().unwrap(), (1, 0));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_insert_transcriptions_skips_rejected_ids() {
+        let path = std::env::temp_dir().join(format!("memo-node-batch-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path)
+            .unwrap()
+            .with_id_collision(IdCollisionMode::Reject);
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        let foreign = Transcription {
+            source_node: "other-node".to_string(),
+            text: "someone else's memo".to_string(),
+            ..Transcription::sample("a", 1_717_243_200)
+        };
+
+        let batch = [
+            Transcription::sample("b", 1_717_243_201),
+            foreign,
+            Transcription::sample("c", 1_717_243_202),
+        ];
+        assert_eq!(storage.insert_transcriptions(&batch).unwrap(), 2);
+        assert_eq!(storage.count_transcriptions().unwrap(), (3, 0));
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().source_node, "test-node");
+        let b = storage.get_transcription("b").unwrap().unwrap();
+        let c = storage.get_transcription("c").unwrap().unwrap();
+        assert!(b.cursor < c.cursor);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cursors_keep_increasing_across_restarts() {
+        let path = std::env::temp_dir().join(format!("memo-node-cursor-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        // As if the clock was ahead when the row was stored
+        let future = chrono::Utc::now().timestamp_micros() + 3_600_000_000;
+        storage
+            .conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE transcriptions SET seq = ?1", [future])
+            .unwrap();
+        drop(storage);
+
+        let storage = Storage::new(&path).unwrap();
+        storage.insert_transcription(&Transcription::sample("b", 1_717_243_201)).unwrap();
+        let b = storage.get_transcription("b").unwrap().unwrap();
+        assert!(b.cursor > future);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_short_codes_are_unique_and_kept() {
+        let path = std::env::temp_dir().join(format!("memo-node-codes-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap().with_short_codes("memo");
+        assert_eq!(base32(0), "000");
+        assert_eq!(base32(7_394), "772");
+        assert_eq!(base32(32 * 32 * 32), "1000");
+
+        let row = Transcription::sample("a", 1_717_243_200);
+        storage.insert_transcription(&row).unwrap();
+        storage.insert_transcriptions(&[Transcription::sample("b", 1_717_243_201)]).unwrap();
+        let a = storage.get_transcription("a").unwrap().unwrap();
+        assert_eq!(a.short_code.as_deref(), Some("MEMO-001"));
+        let b = storage.get_transcription("b").unwrap().unwrap();
+        assert_eq!(b.short_code.as_deref(), Some("MEMO-002"));
+
+        // Replacing a row keeps its code and doesn't use up a new one
+        storage.insert_transcription(&row).unwrap();
+        storage.insert_transcription_if_absent(&row).unwrap();
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().short_code, a.short_code);
+        assert_eq!(storage.allocate_short_code().unwrap().as_deref(), Some("MEMO-003"));
+
+        assert_eq!(storage.find_transcription("memo-002").unwrap().unwrap().id, "b");
+        assert_eq!(storage.find_transcription("a").unwrap().unwrap().id, "a");
+        assert!(storage.find_transcription("MEMO-999").unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_is_stored_as_json() {
+        let path = std::env::temp_dir().join(format!("memo-node-metadata-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        let row = Transcription {
+            metadata: Metadata([("project".to_string(), "garden".to_string())].into()),
+            ..Transcription::sample("a", 1_717_243_200)
+        };
+        storage.insert_transcription(&row).unwrap();
+        storage.insert_transcription(&Transcription::sample("b", 1_717_243_201)).unwrap();
+
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().metadata, row.metadata);
+        assert!(storage.get_transcription("b").unwrap().unwrap().metadata.is_empty());
+        let stored: Option<String> = Connection::open(&path)
+            .unwrap()
+            .query_row("SELECT metadata FROM transcriptions WHERE id = 'a'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(stored.as_deref(), Some(r#"{"project":"garden"}"#));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_skips_writes() {
+        let path = std::env::temp_dir().join(format!("memo-node-dry-run-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap().with_dry_run(true);
+
+        let row = Transcription::sample("a", 1_717_243_200);
+        storage.insert_transcription(&row).unwrap();
+        assert!(storage.insert_transcription_if_absent(&row).unwrap().is_some());
+        storage.add_tag(&row, "todo").unwrap();
+        assert_eq!(storage.count_transcriptions().unwrap(), (0, 0));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_storage_never_migrates_or_creates_the_database() {
+        let path = std::env::temp_dir().join(format!("memo-node-read-only-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::open_read_only(&path).unwrap();
+        assert_eq!(storage.count_transcriptions().unwrap(), (0, 0));
+        assert!(!path.exists());
+
+        // A database from before most migrations
+        let mut conn = Connection::open(&path).unwrap();
+        migrations().to_version(&mut conn, 1).unwrap();
+        conn.execute(
+            "INSERT INTO transcriptions (id, timestamp, text, source_node) VALUES ('a', 1717243200, 'memo a', 'test-node')",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+
+        let storage = Storage::open_read_only(&path).unwrap();
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().text, "memo a");
+        storage.insert_transcription(&Transcription::sample("b", 1_717_243_201)).unwrap();
+        assert_eq!(storage.count_transcriptions().unwrap(), (1, 0));
+        drop(storage);
+
+        let version: usize = Connection::open(&path)
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_push_cursor_is_per_peer() {
+        let path = std::env::temp_dir().join(format!("memo-node-push-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        let foreign = Transcription {
+            source_node: "other-node".to_string(),
+            ..Transcription::sample("b", 1_717_243_201)
+        };
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        storage.insert_transcription(&foreign).unwrap();
+        storage.insert_transcription(&Transcription::sample("c", 1_717_243_202)).unwrap();
+
+        let own = storage.get_node_transcriptions_after_cursor("test-node", 0, 10).unwrap();
+        assert_eq!(own.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(), ["a", "c"]);
+
+        assert_eq!(storage.get_push_cursor("peer-1").unwrap(), 0);
+        storage.set_push_cursor("peer-1", own[0].cursor).unwrap();
+        // Updating the peer's details doesn't reset its push progress
+        storage.set_peer_address("peer-1", "10.0.0.5", 9876, 100).unwrap();
+        assert_eq!(storage.get_push_cursor("peer-1").unwrap(), own[0].cursor);
+        assert_eq!(storage.get_push_cursor("peer-2").unwrap(), 0);
+        let rest = storage
+            .get_node_transcriptions_after_cursor("test-node", own[0].cursor, 10)
+            .unwrap();
+        assert_eq!(rest.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(), ["c"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_peer_address_is_kept_across_updates() {
+        let path = std::env::temp_dir().join(format!("memo-node-peers-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+
+        storage.set_peer_address("peer", "10.0.0.5", 9876, 100).unwrap();
+        let peer = storage.get_peer("peer").unwrap().unwrap();
+        assert_eq!((peer.address.as_deref(), peer.grpc_port), (Some("10.0.0.5"), Some(9876)));
+        assert_eq!(peer.last_sync_timestamp, 0);
+
+        storage
+            .upsert_peer(&Peer {
+                last_sync_timestamp: 200,
+                ..peer
+            })
+            .unwrap();
+        storage.set_peer_address("peer", "10.0.0.6", 9876, 300).unwrap();
+        let peer = storage.get_peer("peer").unwrap().unwrap();
+        assert_eq!(peer.address.as_deref(), Some("10.0.0.6"));
+        assert_eq!((peer.last_seen, peer.last_sync_timestamp), (100, 200));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
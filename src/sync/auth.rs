@@ -0,0 +1,113 @@
+use super::peer::proto::memo_sync_client::MemoSyncClient;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+/// gRPC metadata key carrying the cluster secret
+const SECRET_METADATA_KEY: &str = "x-memo-cluster-secret";
+
+/// gRPC client for another node that presents our cluster secret on every call
+pub type SyncClient = MemoSyncClient<InterceptedService<Channel, ClusterSecret>>;
+
+/// Shared secret every node in a cluster presents on gRPC calls
+///
+/// Unset (the default) disables the check, so clusters without a secret keep
+/// working as before.
+#[derive(Clone, Default)]
+pub struct ClusterSecret(Option<MetadataValue<Ascii>>);
+
+impl ClusterSecret {
+    pub fn new(secret: Option<&str>) -> Result<Self> {
+        let secret = secret
+            .filter(|secret| !secret.is_empty())
+            .map(MetadataValue::try_from)
+            .transpose()
+            .context("sync.cluster_secret must be printable ASCII")?;
+        Ok(Self(secret))
+    }
+
+    /// Short hash of the secret, advertised over mDNS so nodes can skip
+    /// peers from other clusters without broadcasting the secret itself
+    pub fn fingerprint(&self) -> Option<String> {
+        self.0.as_ref().map(|secret| {
+            let mut hasher = Sha256::new();
+            hasher.update(b"memo-node-cluster:");
+            hasher.update(secret.as_bytes());
+            hasher.finalize()[..8]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        })
+    }
+
+    /// Server-side check, rejecting calls that don't carry our secret
+    pub fn verify<T>(&self, request: Request<T>) -> Result<Request<T>, Status> {
+        let Some(secret) = &self.0 else {
+            return Ok(request);
+        };
+
+        match request.metadata().get(SECRET_METADATA_KEY) {
+            Some(presented) if constant_time_eq(presented.as_bytes(), secret.as_bytes()) => {
+                Ok(request)
+            }
+            _ => Err(Status::unauthenticated("invalid cluster secret")),
+        }
+    }
+
+    /// Connect to a peer's gRPC server (`http://host:port`)
+    pub async fn connect(&self, addr: String) -> Result<SyncClient> {
+        let channel = Channel::from_shared(addr)
+            .context("Invalid peer address")?
+            .connect()
+            .await?;
+        Ok(MemoSyncClient::with_interceptor(channel, self.clone()))
+    }
+}
+
+impl Interceptor for ClusterSecret {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(secret) = &self.0 {
+            request
+                .metadata_mut()
+                .insert(SECRET_METADATA_KEY, secret.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Compare without returning early, so response timing doesn't reveal how
+/// much of a guessed secret was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_roundtrip_and_rejection() {
+        let mut ours = ClusterSecret::new(Some("hunter2")).unwrap();
+        let request = ours.call(Request::new(())).unwrap();
+        assert!(ours.verify(request).is_ok());
+
+        let mut theirs = ClusterSecret::new(Some("hunter3")).unwrap();
+        let request = theirs.call(Request::new(())).unwrap();
+        let status = ours.verify(request).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        assert!(ours.verify(Request::new(())).is_err());
+        assert_ne!(ours.fingerprint(), theirs.fingerprint());
+    }
+
+    #[test]
+    fn test_unset_secret_accepts_everything() {
+        let secret = ClusterSecret::new(Some("")).unwrap();
+        assert!(secret.verify(Request::new(())).is_ok());
+        assert_eq!(secret.fingerprint(), None);
+    }
+}
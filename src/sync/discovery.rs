@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
@@ -10,29 +10,46 @@ const SERVICE_TYPE: &str = "_memo-node._tcp.local.";
 #[derive(Debug, Clone)]
 pub struct DiscoveredPeer {
     pub node_id: String,
+    pub display_name: Option<String>,
     pub address: IpAddr,
     pub grpc_port: u16,
 }
 
+#[derive(Debug, Clone)]
+pub enum DiscoveryEvent {
+    Found(DiscoveredPeer),
+    /// The peer's mDNS record was withdrawn or expired
+    Lost { node_id: String },
+    /// Another machine advertises our own node_id (e.g. a copied config);
+    /// it is never synced with
+    DuplicateNodeId { address: IpAddr },
+}
+
 pub struct Discovery {
     node_id: String,
+    display_name: String,
     grpc_port: u16,
+    /// `ClusterSecret::fingerprint` of our cluster, if it has a secret
+    cluster: Option<String>,
     mdns: ServiceDaemon,
-    peer_tx: mpsc::UnboundedSender<DiscoveredPeer>,
+    peer_tx: mpsc::UnboundedSender<DiscoveryEvent>,
 }
 
 impl Discovery {
     pub fn new(
         node_id: String,
+        display_name: String,
         grpc_port: u16,
-    ) -> Result<(Self, mpsc::UnboundedReceiver<DiscoveredPeer>)> {
+    ) -> Result<(Self, mpsc::UnboundedReceiver<DiscoveryEvent>)> {
         let mdns = ServiceDaemon::new().context("Failed to create mDNS daemon")?;
         let (peer_tx, peer_rx) = mpsc::unbounded_channel();
 
         Ok((
             Self {
                 node_id,
+                display_name,
                 grpc_port,
+                cluster: None,
                 mdns,
                 peer_tx,
             },
@@ -40,6 +57,12 @@ impl Discovery {
         ))
     }
 
+    /// Advertise `cluster` and only report peers advertising the same one
+    pub fn with_cluster(mut self, cluster: Option<String>) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
     pub fn start(&self) -> Result<()> {
         // Register this node as a service
         self.register_service()?;
@@ -54,6 +77,10 @@ impl Discovery {
         let mut properties = HashMap::new();
         properties.insert("node_id".to_string(), self.node_id.clone());
         properties.insert("grpc_port".to_string(), self.grpc_port.to_string());
+        properties.insert("display_name".to_string(), self.display_name.clone());
+        if let Some(cluster) = &self.cluster {
+            properties.insert("cluster".to_string(), cluster.clone());
+        }
 
         let service_info = ServiceInfo::new(
             SERVICE_TYPE,
@@ -86,26 +113,52 @@ impl Discovery {
 
         let peer_tx = self.peer_tx.clone();
         let own_node_id = self.node_id.clone();
+        let own_cluster = self.cluster.clone();
 
         // Spawn a task to handle service events
         tokio::spawn(async move {
+            // Addresses already reported as using our node_id
+            let mut duplicates = HashSet::new();
+
             while let Ok(event) = receiver.recv_async().await {
                 match event {
                     ServiceEvent::ServiceResolved(info) => {
-                        if let Some(peer) = Self::parse_service_info(&info, &own_node_id) {
+                        let local_ips = local_ip_addresses();
+                        if let Some(address) = Self::duplicate_node_address(&info, &own_node_id, &local_ips) {
+                            if duplicates.insert(address) {
+                                error!(
+                                    node_id = %own_node_id,
+                                    address = %address,
+                                    "Another node advertises our node_id; not syncing with it. Give one of them a unique node.id"
+                                );
+                                let _ = peer_tx.send(DiscoveryEvent::DuplicateNodeId { address });
+                            }
+                            continue;
+                        }
+                        if let Some(peer) = Self::parse_service_info(&info, &own_node_id, own_cluster.as_deref(), &local_ips) {
                             info!(
                                 node_id = %peer.node_id,
                                 address = %peer.address,
                                 port = peer.grpc_port,
                                 "Discovered peer"
                             );
-                            if let Err(e) = peer_tx.send(peer) {
+                            if let Err(e) = peer_tx.send(DiscoveryEvent::Found(peer)) {
                                 error!("Failed to send discovered peer: {}", e);
                             }
                         }
                     }
                     ServiceEvent::ServiceRemoved(_, fullname) => {
                         debug!("Service removed: {}", fullname);
+                        // Nodes register with their node_id as the instance name
+                        let node_id = fullname
+                            .strip_suffix(SERVICE_TYPE)
+                            .and_then(|name| name.strip_suffix('.'));
+                        if let Some(node_id) = node_id.filter(|id| *id != own_node_id) {
+                            info!(node_id = %node_id, "Peer left");
+                            let _ = peer_tx.send(DiscoveryEvent::Lost {
+                                node_id: node_id.to_string(),
+                            });
+                        }
                     }
                     ServiceEvent::SearchStarted(_) => {
                         debug!("mDNS search started");
@@ -121,7 +174,31 @@ impl Discovery {
         Ok(())
     }
 
-    fn parse_service_info(info: &ServiceInfo, own_node_id: &str) -> Option<DiscoveredPeer> {
+    /// An address of another machine advertising `own_node_id`, if `info`
+    /// is such a record. Our own record only resolves to local addresses;
+    /// without a list of those we can't tell the two apart.
+    fn duplicate_node_address(
+        info: &ServiceInfo,
+        own_node_id: &str,
+        local_ips: &HashSet<IpAddr>,
+    ) -> Option<IpAddr> {
+        let node_id = info.get_properties().get("node_id")?.val_str();
+        if node_id != own_node_id || local_ips.is_empty() {
+            return None;
+        }
+
+        info.get_addresses()
+            .iter()
+            .find(|addr| !local_ips.contains(addr))
+            .copied()
+    }
+
+    fn parse_service_info(
+        info: &ServiceInfo,
+        own_node_id: &str,
+        own_cluster: Option<&str>,
+        local_ips: &HashSet<IpAddr>,
+    ) -> Option<DiscoveredPeer> {
         let properties = info.get_properties();
 
         let node_id = properties
@@ -133,6 +210,24 @@ impl Discovery {
             return None;
         }
 
+        // A stale record from a previous run (or a renamed node) can carry a
+        // different node_id but still resolve to one of our own addresses
+        if info.get_addresses().iter().any(|addr| local_ips.contains(addr)) {
+            debug!(
+                node_id = %node_id,
+                "Ignoring mDNS record that resolves to a local address"
+            );
+            return None;
+        }
+
+        // A node with a different (or no) cluster secret would reject our
+        // calls, or we theirs
+        let cluster = properties.get("cluster").map(|v| v.val_str());
+        if cluster != own_cluster {
+            debug!(node_id = %node_id, "Ignoring node from a different cluster");
+            return None;
+        }
+
         let grpc_port = properties
             .get("grpc_port")
             .map(|v| v.val_str())
@@ -140,8 +235,15 @@ impl Discovery {
 
         let address = info.get_addresses().iter().next()?.clone();
 
+        // Older nodes don't advertise a display name
+        let display_name = properties
+            .get("display_name")
+            .map(|v| v.val_str().to_string())
+            .filter(|name| !name.is_empty() && *name != node_id);
+
         Some(DiscoveredPeer {
             node_id,
+            display_name,
             address,
             grpc_port,
         })
@@ -157,6 +259,20 @@ impl Discovery {
     }
 }
 
+/// Enumerate the IP addresses assigned to this host's network interfaces
+///
+/// Used to recognise mDNS records that point back at this node. Returns an
+/// empty set if the interfaces can't be listed, which disables the check.
+pub fn local_ip_addresses() -> HashSet<IpAddr> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces.into_iter().map(|iface| iface.ip()).collect(),
+        Err(e) => {
+            warn!("Failed to enumerate local interfaces: {}", e);
+            HashSet::new()
+        }
+    }
+}
+
 impl Drop for Discovery {
     fn drop(&mut self) {
         let _ = self.shutdown();
@@ -1,5 +1,10 @@
+pub mod auth;
 pub mod discovery;
 pub mod peer;
+pub mod signing;
+pub mod verify;
 
-pub use discovery::Discovery;
-pub use peer::{PeerManager, PeerSyncServer};
+pub use auth::ClusterSecret;
+pub use discovery::{Discovery, DiscoveryEvent};
+pub use peer::{PeerEvent, PeerManager, PeerSyncServer};
+pub use signing::NodeKey;
@@ -1,12 +1,22 @@
-use crate::storage::{Peer, Storage, Transcription};
+use super::auth::{ClusterSecret, SyncClient};
+use super::signing;
+use super::verify::ids_digest;
+use crate::clock;
+use crate::config::{ClockSkewMode, SyncDirection};
+use crate::metrics::{Metrics, Subsystem};
+use crate::storage::{IdCollisionError, Metadata, Peer, SourceType, Storage, Transcription};
+use crate::transcribe::{self, TranscriptionQueue};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::net::IpAddr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::{mpsc, RwLock};
-use tokio::time::{interval, Duration};
-use tonic::{transport::Server, Request, Response, Status};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::time::{interval, Duration, Instant};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
 use tracing::{debug, info, warn};
 
 // Generated proto code
@@ -16,14 +26,107 @@ pub mod proto {
 
 use proto::{
     memo_sync_server::{MemoSync, MemoSyncServer as TonicMemoSyncServer},
-    PingRequest, PingResponse, PushResponse, SinceRequest, Transcription as ProtoTranscription,
+    AudioChunk, DigestResponse, IdsRequest, IdsResponse, PingRequest, PingResponse, PushResponse,
+    SinceRequest, TranscribeResponse, Transcription as ProtoTranscription,
 };
 
+/// Own transcriptions sent per `PushTranscriptions` call
+const PUSH_BATCH: usize = 500;
+
+/// Most ids returned by one `GetIds` call
+const IDS_PAGE_SIZE: usize = 10_000;
+
+/// Keep the originating source type reported by a peer, treating rows from
+/// older nodes (or unknown values) as plain peer data
+fn peer_source_type(value: &str) -> SourceType {
+    value.parse().unwrap_or(SourceType::Peer)
+}
+
+fn to_proto(t: Transcription) -> ProtoTranscription {
+    ProtoTranscription {
+        id: t.id,
+        timestamp: t.timestamp,
+        text: t.text,
+        source_node: t.source_node,
+        memo_device_id: t.memo_device_id.unwrap_or_default(),
+        source_type: t.source_type.to_string(),
+        session_id: t.session_id.unwrap_or_default(),
+        device_battery: t.device_battery.map(u32::from),
+        metadata: t.metadata.0.into_iter().collect(),
+        duration_ms: t.duration_ms,
+        signature: t.signature.unwrap_or_default(),
+        signer_key: t.signer_key.unwrap_or_default(),
+    }
+}
+
+/// Estimate how many seconds the peer's clock is ahead of ours from one Ping
+/// round trip, assuming the peer stamped its reply halfway through it. The
+/// reply only has one-second resolution, so smaller offsets read as 0.
+async fn measure_clock_offset(client: &mut SyncClient, node_id: &str) -> Result<i64> {
+    let sent_ms = chrono::Utc::now().timestamp_millis();
+    let response = client
+        .ping(PingRequest {
+            node_id: node_id.to_string(),
+        })
+        .await
+        .context("Ping failed")?
+        .into_inner();
+    let received_ms = chrono::Utc::now().timestamp_millis();
+
+    Ok(clock_offset(sent_ms, received_ms, response.timestamp))
+}
+
+/// Seconds a peer that replied with `peer_timestamp` to a ping sent at
+/// `sent_ms` and answered at `received_ms` is ahead of us, with offsets
+/// within the reply's resolution read as 0
+fn clock_offset(sent_ms: i64, received_ms: i64, peer_timestamp: i64) -> i64 {
+    let midpoint_secs = (sent_ms + received_ms) / 2 / 1000;
+    let offset = peer_timestamp - midpoint_secs;
+    if offset.abs() <= 1 {
+        0
+    } else {
+        offset
+    }
+}
+
+/// Convert a transcription received from a peer, marked as synced
+fn from_proto(proto_t: ProtoTranscription) -> Transcription {
+    Transcription {
+        source_type: peer_source_type(&proto_t.source_type),
+        id: proto_t.id,
+        timestamp: proto_t.timestamp,
+        text: proto_t.text,
+        source_node: proto_t.source_node,
+        memo_device_id: if proto_t.memo_device_id.is_empty() {
+            None
+        } else {
+            Some(proto_t.memo_device_id)
+        },
+        synced: true,
+        session_id: Some(proto_t.session_id).filter(|id| !id.is_empty()),
+        device_battery: proto_t.device_battery.map(|level| level.min(100) as u8),
+        metadata: Metadata(proto_t.metadata.into_iter().collect()),
+        duration_ms: proto_t.duration_ms,
+        signature: Some(proto_t.signature).filter(|signature| !signature.is_empty()),
+        signer_key: Some(proto_t.signer_key).filter(|key| !key.is_empty()),
+        ..Transcription::default()
+    }
+}
+
 #[derive(Clone)]
 pub struct PeerSyncServer {
     node_id: String,
     storage: Storage,
     broadcast_tx: mpsc::UnboundedSender<Transcription>,
+    /// Serves `TranscribeAudio` for relay peers when set
+    transcriber: Option<TranscriptionQueue>,
+    /// Longest audio accepted in one `TranscribeAudio` call, in samples
+    max_remote_samples: usize,
+    /// Most ids returned by one `GetIds` call
+    ids_page_size: usize,
+    cluster_secret: ClusterSecret,
+    /// Refuse pushed rows whose signature doesn't verify
+    reject_invalid_signatures: bool,
 }
 
 impl PeerSyncServer {
@@ -36,16 +139,47 @@ impl PeerSyncServer {
             node_id,
             storage,
             broadcast_tx,
+            transcriber: None,
+            max_remote_samples: 0,
+            ids_page_size: IDS_PAGE_SIZE,
+            cluster_secret: ClusterSecret::default(),
+            reject_invalid_signatures: false,
         }
     }
 
-    pub async fn serve(self, port: u16) -> Result<()> {
-        let addr = format!("0.0.0.0:{}", port).parse()?;
+    /// Reject calls from peers that don't present `cluster_secret`
+    pub fn with_cluster_secret(mut self, cluster_secret: ClusterSecret) -> Self {
+        self.cluster_secret = cluster_secret;
+        self
+    }
+
+    /// Refuse pushed rows with an invalid signature instead of storing them
+    /// with a warning
+    pub fn with_reject_invalid_signatures(mut self, reject: bool) -> Self {
+        self.reject_invalid_signatures = reject;
+        self
+    }
+
+    /// Accept audio from relay peers and transcribe it on the local engines
+    pub fn with_transcriber(mut self, queue: TranscriptionQueue, max_recording: Duration) -> Self {
+        self.transcriber = Some(queue);
+        let sample_rate = transcribe::SAMPLE_RATE as usize;
+        self.max_remote_samples = max_recording.as_secs() as usize * sample_rate;
+        self
+    }
+
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        let addr = listener
+            .local_addr()
+            .context("Failed to get gRPC listener address")?;
         info!("Starting gRPC server on {}", addr);
 
+        let cluster_secret = self.cluster_secret.clone();
         Server::builder()
-            .add_service(TonicMemoSyncServer::new(self))
-            .serve(addr)
+            .add_service(TonicMemoSyncServer::with_interceptor(self, move |request| {
+                cluster_secret.verify(request)
+            }))
+            .serve_with_incoming(TcpListenerStream::new(listener))
             .await
             .context("gRPC server failed")?;
 
@@ -59,10 +193,7 @@ impl MemoSync for PeerSyncServer {
         let req = request.into_inner();
         debug!("Received ping from {}", req.node_id);
 
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let timestamp = clock::unix_now();
 
         Ok(Response::new(PingResponse {
             node_id: self.node_id.clone(),
@@ -82,20 +213,14 @@ impl MemoSync for PeerSyncServer {
 
         let transcriptions = self
             .storage
-            .get_transcriptions_since(req.since_timestamp)
+            .get_transcriptions_since(req.since_timestamp, &req.source_nodes)
             .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
 
         let (tx, rx) = mpsc::channel(100);
 
         tokio::spawn(async move {
             for t in transcriptions {
-                let proto_t = ProtoTranscription {
-                    id: t.id,
-                    timestamp: t.timestamp,
-                    text: t.text,
-                    source_node: t.source_node,
-                    memo_device_id: t.memo_device_id.unwrap_or_default(),
-                };
+                let proto_t = to_proto(t);
 
                 if tx.send(Ok(proto_t)).await.is_err() {
                     break;
@@ -112,41 +237,165 @@ impl MemoSync for PeerSyncServer {
         &self,
         request: Request<tonic::Streaming<ProtoTranscription>>,
     ) -> Result<Response<PushResponse>, Status> {
+        let peer = request
+            .remote_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| "a peer".to_string());
         let mut stream = request.into_inner();
-        let mut received = 0;
+        let mut response = PushResponse::default();
 
         while let Some(proto_t) = stream
             .message()
             .await
             .map_err(|e| Status::internal(format!("Stream error: {}", e)))?
         {
-            let transcription = Transcription {
-                id: proto_t.id,
-                timestamp: proto_t.timestamp,
-                text: proto_t.text,
-                source_node: proto_t.source_node,
-                memo_device_id: if proto_t.memo_device_id.is_empty() {
-                    None
-                } else {
-                    Some(proto_t.memo_device_id)
-                },
-                synced: true, // Mark as synced since it came from a peer
+            // Mark as synced since it came from a peer
+            let mut transcription = from_proto(proto_t);
+            response.received += 1;
+
+            let accepted =
+                signing::accept(&self.storage, &transcription, &peer, self.reject_invalid_signatures);
+            match accepted {
+                Ok(true) => {}
+                Ok(false) => {
+                    response.rejected_ids.push(transcription.id);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to check signature of {}: {:#}", transcription.id, e);
+                    response.rejected_ids.push(transcription.id);
+                    continue;
+                }
+            }
+
+            // A pushed row replaces the stored one, as pulled rows do; an
+            // identical copy is only reported back as a duplicate
+            let existing = match self.storage.get_transcription(&transcription.id) {
+                Ok(existing) => existing,
+                Err(e) => {
+                    warn!("Failed to look up pushed transcription {}: {}", transcription.id, e);
+                    response.rejected_ids.push(transcription.id);
+                    continue;
+                }
             };
+            let duplicate = existing.is_some_and(|existing| {
+                existing.source_node == transcription.source_node
+                    && existing.text == transcription.text
+            });
 
-            self.storage
-                .insert_transcription(&transcription)
-                .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+            match self.storage.insert_transcription(&transcription) {
+                Ok(_) if duplicate => response.duplicate_ids.push(transcription.id),
+                Ok(cursor) => {
+                    transcription.cursor = cursor;
+                    response.persisted_ids.push(transcription.id.clone());
+                    // Broadcast to connected clients (memo-desktop)
+                    let _ = self.broadcast_tx.send(transcription);
+                }
+                // Logged by `resolve_id_collision`
+                Err(e) if e.is::<IdCollisionError>() => {
+                    response.rejected_ids.push(transcription.id);
+                }
+                Err(e) => {
+                    warn!("Failed to store pushed transcription {}: {}", transcription.id, e);
+                    response.rejected_ids.push(transcription.id);
+                }
+            }
+        }
 
-            // Broadcast to connected clients (memo-desktop)
-            let _ = self.broadcast_tx.send(transcription);
+        debug!(
+            "Received {} transcriptions ({} persisted, {} duplicate, {} rejected)",
+            response.received,
+            response.persisted_ids.len(),
+            response.duplicate_ids.len(),
+            response.rejected_ids.len()
+        );
+
+        Ok(Response::new(response))
+    }
+
+    async fn transcribe_audio(
+        &self,
+        request: Request<tonic::Streaming<AudioChunk>>,
+    ) -> Result<Response<TranscribeResponse>, Status> {
+        let Some(queue) = &self.transcriber else {
+            return Err(Status::unimplemented(
+                "This node does not transcribe for peers",
+            ));
+        };
 
-            received += 1;
+        let mut stream = request.into_inner();
+        let mut audio = Vec::new();
+
+        while let Some(chunk) = stream
+            .message()
+            .await
+            .map_err(|e| Status::internal(format!("Stream error: {}", e)))?
+        {
+            if chunk.sample_rate != transcribe::SAMPLE_RATE {
+                return Err(Status::invalid_argument(format!(
+                    "Unsupported sample rate {} (expected {})",
+                    chunk.sample_rate,
+                    transcribe::SAMPLE_RATE
+                )));
+            }
+            // A peer streaming without end shouldn't exhaust our memory
+            if audio.len() + chunk.pcm.len() / 2 > self.max_remote_samples {
+                return Err(Status::resource_exhausted(format!(
+                    "Recording longer than {}s",
+                    self.max_remote_samples / transcribe::SAMPLE_RATE as usize
+                )));
+            }
+            audio.extend(transcribe::pcm_to_samples(&chunk.pcm));
         }
 
-        debug!("Received {} transcriptions", received);
+        debug!("Transcribing {} samples for a peer", audio.len());
 
-        Ok(Response::new(PushResponse { received }))
+        let text = queue
+            .transcribe(audio)
+            .await
+            .map_err(|e| Status::internal(format!("Transcription failed: {:#}", e)))?;
+
+        Ok(Response::new(TranscribeResponse {
+            text,
+            node_id: self.node_id.clone(),
+        }))
     }
+
+    async fn get_ids(&self, request: Request<IdsRequest>) -> Result<Response<IdsResponse>, Status> {
+        let req = request.into_inner();
+        // One extra id tells whether another page follows
+        let mut ids = self
+            .storage
+            .get_ids_page(
+                req.since_timestamp,
+                &req.source_nodes,
+                &req.after_id,
+                self.ids_page_size + 1,
+            )
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+        let truncated = ids.len() > self.ids_page_size;
+        ids.truncate(self.ids_page_size);
+        Ok(Response::new(IdsResponse { ids, truncated }))
+    }
+
+    async fn get_digest(&self, request: Request<SinceRequest>) -> Result<Response<DigestResponse>, Status> {
+        let req = request.into_inner();
+        let ids = self
+            .storage
+            .get_ids_since(req.since_timestamp, &req.source_nodes)
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+        Ok(Response::new(DigestResponse {
+            digest: ids_digest(&ids),
+            count: ids.len() as u64,
+        }))
+    }
+}
+
+/// Peer presence changes, for surfacing to WebSocket clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    Connected { node_id: String, display_name: String },
+    Disconnected { node_id: String, display_name: String },
 }
 
 pub struct PeerManager {
@@ -154,36 +403,322 @@ pub struct PeerManager {
     storage: Storage,
     peers: Arc<RwLock<HashMap<String, PeerConnection>>>,
     sync_interval: Duration,
+    /// How often to re-request everything from a peer; `None` disables
+    full_reconcile_interval: Option<Duration>,
+    last_full_reconcile: RwLock<HashMap<String, Instant>>,
+    default_direction: SyncDirection,
+    /// Per-peer overrides of `default_direction`, from static peer config
+    peer_directions: HashMap<String, SyncDirection>,
+    /// Source nodes to pull from peers; empty pulls every row
+    default_source_nodes: Vec<String>,
+    /// Per-peer overrides of `default_source_nodes`
+    peer_source_nodes: HashMap<String, Vec<String>>,
+    events_tx: Option<mpsc::UnboundedSender<PeerEvent>>,
+    metrics: Arc<Metrics>,
+    clock_skew: ClockSkewMode,
+    max_clock_skew_secs: i64,
+    /// Store pulled rows in one batch once the stream has ended
+    batch_pulls: bool,
+    /// Drop pulled rows whose signature doesn't verify
+    reject_invalid_signatures: bool,
+    cluster_secret: ClusterSecret,
+    /// Log pushes instead of sending them (`--dry-run`)
+    dry_run: bool,
+    /// Stored peers not seen for this long aren't restored; `None` restores
+    /// every peer with a known address
+    peer_expiry: Option<Duration>,
 }
 
 struct PeerConnection {
     node_id: String,
+    display_name: Option<String>,
     address: IpAddr,
     grpc_port: u16,
+    direction: SyncDirection,
+    /// Only pull rows created by these nodes; empty pulls every row
+    source_nodes: Vec<String>,
+    /// Connected client reused across sync cycles; cleared after a failed
+    /// sync so the next cycle reconnects
+    client: Mutex<Option<SyncClient>>,
+    /// Whether the peer has been seen or synced with since it was added;
+    /// `Connected` is only reported once it has
+    reached: AtomicBool,
+}
+
+impl PeerConnection {
+    /// The cached client, connecting first if there isn't one
+    async fn client(&self, cluster_secret: &ClusterSecret) -> Result<SyncClient> {
+        let mut cached = self.client.lock().await;
+        if let Some(client) = cached.as_ref() {
+            return Ok(client.clone());
+        }
+
+        // Brackets IPv6 addresses, which a bare `address:port` would not
+        let addr = format!("http://{}", SocketAddr::new(self.address, self.grpc_port));
+        let client = cluster_secret
+            .connect(addr)
+            .await
+            .context("Failed to connect to peer")?;
+        *cached = Some(client.clone());
+        Ok(client)
+    }
+
+    async fn drop_client(&self) {
+        self.client.lock().await.take();
+    }
 }
 
 impl PeerManager {
-    pub fn new(node_id: String, storage: Storage, sync_interval_secs: u64) -> Self {
+    pub fn new(
+        node_id: String,
+        storage: Storage,
+        sync_interval_secs: u64,
+        full_reconcile_interval_secs: u64,
+    ) -> Self {
         Self {
             node_id,
             storage,
             peers: Arc::new(RwLock::new(HashMap::new())),
             sync_interval: Duration::from_secs(sync_interval_secs),
+            full_reconcile_interval: (full_reconcile_interval_secs > 0)
+                .then(|| Duration::from_secs(full_reconcile_interval_secs)),
+            last_full_reconcile: RwLock::new(HashMap::new()),
+            default_direction: SyncDirection::Both,
+            peer_directions: HashMap::new(),
+            default_source_nodes: Vec::new(),
+            peer_source_nodes: HashMap::new(),
+            events_tx: None,
+            metrics: Arc::new(Metrics::default()),
+            clock_skew: ClockSkewMode::Off,
+            max_clock_skew_secs: 0,
+            batch_pulls: true,
+            reject_invalid_signatures: false,
+            cluster_secret: ClusterSecret::default(),
+            dry_run: false,
+            peer_expiry: None,
+        }
+    }
+
+    /// Present `cluster_secret` on every call to peers
+    pub fn with_cluster_secret(mut self, cluster_secret: ClusterSecret) -> Self {
+        self.cluster_secret = cluster_secret;
+        self
+    }
+
+    /// Log pushes to peers instead of sending them; pulled rows still go
+    /// through `storage`, which should be in dry-run mode too
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Don't restore stored peers last seen more than `peer_expiry_secs`
+    /// ago; 0 restores them all
+    pub fn with_peer_expiry(mut self, peer_expiry_secs: u64) -> Self {
+        self.peer_expiry = (peer_expiry_secs > 0).then(|| Duration::from_secs(peer_expiry_secs));
+        self
+    }
+
+    /// Record sync failures in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Report peers being added and removed on `events_tx`
+    pub fn with_events(mut self, events_tx: mpsc::UnboundedSender<PeerEvent>) -> Self {
+        self.events_tx = Some(events_tx);
+        self
+    }
+
+    fn emit(&self, event: PeerEvent) {
+        if let Some(events_tx) = &self.events_tx {
+            let _ = events_tx.send(event);
+        }
+    }
+
+    /// Set the sync direction for peers, with per-node overrides that also
+    /// apply when the same node is later rediscovered over mDNS
+    pub fn with_directions(
+        mut self,
+        default_direction: SyncDirection,
+        peer_directions: HashMap<String, SyncDirection>,
+    ) -> Self {
+        self.default_direction = default_direction;
+        self.peer_directions = peer_directions;
+        self
+    }
+
+    /// Only pull rows created by the given source nodes, with per-node
+    /// overrides. Keeps rows a peer merely relayed from propagating further.
+    pub fn with_source_filters(
+        mut self,
+        default_source_nodes: Vec<String>,
+        peer_source_nodes: HashMap<String, Vec<String>>,
+    ) -> Self {
+        self.default_source_nodes = default_source_nodes;
+        self.peer_source_nodes = peer_source_nodes;
+        self
+    }
+
+    /// Correct for (or reject rows from) peers whose clocks are off
+    pub fn with_clock_skew(mut self, clock_skew: ClockSkewMode, max_clock_skew_secs: i64) -> Self {
+        self.clock_skew = clock_skew;
+        self.max_clock_skew_secs = max_clock_skew_secs;
+        self
+    }
+
+    /// Store each pull's rows in one batch after its stream ends instead of
+    /// one at a time as they arrive
+    pub fn with_batch_pulls(mut self, batch_pulls: bool) -> Self {
+        self.batch_pulls = batch_pulls;
+        self
+    }
+
+    /// Drop pulled rows with an invalid signature instead of storing them
+    /// with a warning
+    pub fn with_reject_invalid_signatures(mut self, reject: bool) -> Self {
+        self.reject_invalid_signatures = reject;
+        self
+    }
+
+    /// Whether the next sync with this peer should request everything
+    async fn full_reconcile_due(&self, node_id: &str) -> bool {
+        let Some(interval) = self.full_reconcile_interval else {
+            return false;
+        };
+
+        let last = self.last_full_reconcile.read().await.get(node_id).copied();
+        match last {
+            Some(last) => last.elapsed() >= interval,
+            // Never reconciled in this process: wait one interval from startup
+            None => {
+                self.last_full_reconcile
+                    .write()
+                    .await
+                    .insert(node_id.to_string(), Instant::now());
+                false
+            }
         }
     }
 
-    pub async fn add_peer(&self, node_id: String, address: IpAddr, grpc_port: u16) {
+    /// Sync with a peer that was just discovered or configured, reporting
+    /// it as connected
+    pub async fn add_peer(
+        &self,
+        node_id: String,
+        display_name: Option<String>,
+        address: IpAddr,
+        grpc_port: u16,
+    ) {
+        self.insert_peer(node_id, display_name, address, grpc_port, true).await;
+    }
+
+    /// Add a peer, reporting it as connected now if `reached`, or else after
+    /// the first successful sync with it
+    async fn insert_peer(
+        &self,
+        node_id: String,
+        display_name: Option<String>,
+        address: IpAddr,
+        grpc_port: u16,
+        reached: bool,
+    ) {
+        let direction = self
+            .peer_directions
+            .get(&node_id)
+            .copied()
+            .unwrap_or(self.default_direction);
+        let source_nodes = self
+            .peer_source_nodes
+            .get(&node_id)
+            .unwrap_or(&self.default_source_nodes)
+            .clone();
+
         let mut peers = self.peers.write().await;
+
+        // Rediscovery at the same address keeps the open connection
+        let existing = peers.remove(&node_id);
+        let was_reached = existing
+            .as_ref()
+            .is_some_and(|existing| existing.reached.load(Ordering::Relaxed));
+        let client = match existing {
+            Some(existing) if existing.address == address && existing.grpc_port == grpc_port => {
+                existing.client
+            }
+            _ => {
+                let now = clock::unix_now();
+                if let Err(e) = self
+                    .storage
+                    .set_peer_address(&node_id, &address.to_string(), grpc_port, now)
+                {
+                    warn!("Failed to record address of peer {}: {:#}", node_id, e);
+                }
+                Mutex::new(None)
+            }
+        };
+
+        if reached && !was_reached {
+            self.emit(PeerEvent::Connected {
+                node_id: node_id.clone(),
+                display_name: display_name.clone().unwrap_or_else(|| node_id.clone()),
+            });
+        }
+
         peers.insert(
             node_id.clone(),
             PeerConnection {
                 node_id,
+                display_name,
                 address,
                 grpc_port,
+                direction,
+                source_nodes,
+                client,
+                reached: AtomicBool::new(reached || was_reached),
             },
         );
     }
 
+    /// Add every peer with a recorded address that was seen within the
+    /// expiry, so syncing resumes before mDNS has rediscovered them; returns
+    /// how many were added. They are reported as connected once reached.
+    pub async fn restore_peers(&self) -> Result<usize> {
+        let now = clock::unix_now();
+        let mut restored = 0;
+        for peer in self.storage.get_peers()? {
+            let (Some(address), Some(grpc_port)) = (&peer.address, peer.grpc_port) else {
+                continue;
+            };
+            if let Some(expiry) = self.peer_expiry {
+                if now.saturating_sub(peer.last_seen) > expiry.as_secs() as i64 {
+                    debug!("Not restoring peer {}, last seen at {}", peer.node_id, peer.last_seen);
+                    continue;
+                }
+            }
+            let Ok(address) = address.parse() else {
+                warn!("Ignoring invalid stored address {:?} of peer {}", address, peer.node_id);
+                continue;
+            };
+            debug!("Restoring peer {} at {}", peer.node_id, SocketAddr::new(address, grpc_port));
+            self.insert_peer(peer.node_id, peer.display_name, address, grpc_port, false).await;
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    /// Stop syncing with a peer that has gone away
+    pub async fn remove_peer(&self, node_id: &str) {
+        let removed = self.peers.write().await.remove(node_id);
+        // Peers never reached were never reported as connected
+        if let Some(peer) = removed.filter(|peer| peer.reached.load(Ordering::Relaxed)) {
+            self.emit(PeerEvent::Disconnected {
+                display_name: peer.display_name.unwrap_or_else(|| peer.node_id.clone()),
+                node_id: peer.node_id,
+            });
+        }
+    }
+
     pub async fn start_sync_loop(self: Arc<Self>) {
         let mut ticker = interval(self.sync_interval);
 
@@ -197,32 +732,163 @@ impl PeerManager {
         let peers = self.peers.read().await;
 
         for peer_conn in peers.values() {
-            if let Err(e) = self.sync_with_peer(peer_conn).await {
-                warn!(
-                    "Failed to sync with peer {}: {}",
-                    peer_conn.node_id, e
-                );
+            match self.sync_with_peer(peer_conn).await {
+                Ok(()) => {
+                    if !peer_conn.reached.swap(true, Ordering::Relaxed) {
+                        self.emit(PeerEvent::Connected {
+                            node_id: peer_conn.node_id.clone(),
+                            display_name: peer_conn
+                                .display_name
+                                .clone()
+                                .unwrap_or_else(|| peer_conn.node_id.clone()),
+                        });
+                    }
+                    self.metrics
+                        .peers_synced
+                        .lock()
+                        .unwrap()
+                        .insert(peer_conn.node_id.clone());
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to sync with peer {}: {}",
+                        peer_conn.node_id, e
+                    );
+                    self.metrics.record_error(
+                        Subsystem::Sync,
+                        format!("{}: {:#}", peer_conn.node_id, e),
+                    );
+                    // The channel may be stale; reconnect on the next cycle
+                    peer_conn.drop_client().await;
+                }
             }
         }
     }
 
     async fn sync_with_peer(&self, peer_conn: &PeerConnection) -> Result<()> {
-        let addr = format!("http://{}:{}", peer_conn.address, peer_conn.grpc_port);
-
-        let mut client = proto::memo_sync_client::MemoSyncClient::connect(addr)
-            .await
-            .context("Failed to connect to peer")?;
+        let mut client = peer_conn.client(&self.cluster_secret).await?;
 
         // Get the last sync timestamp for this peer
-        let last_sync = self
-            .storage
-            .get_peer(&peer_conn.node_id)?
-            .map(|p| p.last_sync_timestamp)
-            .unwrap_or(0);
+        let stored = self.storage.get_peer(&peer_conn.node_id)?;
+        let last_sync = stored.as_ref().map(|p| p.last_sync_timestamp).unwrap_or(0);
+        let mut clock_offset = stored.and_then(|p| p.clock_offset);
+
+        if self.clock_skew == ClockSkewMode::Adjust {
+            match measure_clock_offset(&mut client, &self.node_id).await {
+                Ok(offset) => {
+                    if offset != 0 {
+                        debug!("Clock of {} is {}s ahead of ours", peer_conn.node_id, offset);
+                    }
+                    clock_offset = Some(offset);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to measure clock offset of {}, using the last known one: {:#}",
+                        peer_conn.node_id, e
+                    );
+                }
+            }
+        }
+
+        let now = clock::unix_now();
+
+        let latest_timestamp = if peer_conn.direction.pulls() {
+            self.pull_from_peer(&mut client, peer_conn, last_sync, now, clock_offset)
+                .await?
+        } else {
+            last_sync
+        };
+
+        // Update peer sync timestamp
+        self.storage.upsert_peer(&Peer {
+            node_id: peer_conn.node_id.clone(),
+            last_seen: now,
+            last_sync_timestamp: latest_timestamp,
+            display_name: peer_conn.display_name.clone(),
+            clock_offset,
+            address: Some(peer_conn.address.to_string()),
+            grpc_port: Some(peer_conn.grpc_port),
+        })?;
+
+        if peer_conn.direction.pushes() {
+            self.push_to_peer(&mut client, peer_conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a row pulled from `peer_node_id` according to `clock_skew`:
+    /// shift the peer's own rows onto our clock by `clock_offset`, or return
+    /// false if the row is too far in our future to keep
+    fn apply_clock_skew(
+        &self,
+        transcription: &mut Transcription,
+        peer_node_id: &str,
+        now: i64,
+        clock_offset: Option<i64>,
+    ) -> bool {
+        match self.clock_skew {
+            ClockSkewMode::Off => true,
+            ClockSkewMode::Adjust => {
+                // Only the peer's own rows were stamped by its clock
+                if transcription.source_node == peer_node_id {
+                    transcription.timestamp -= clock_offset.unwrap_or(0);
+                }
+                true
+            }
+            ClockSkewMode::Reject => {
+                if transcription.timestamp > now + self.max_clock_skew_secs {
+                    debug!(
+                        "Rejecting transcription {} from {}: timestamp {} is {}s in the future",
+                        transcription.id,
+                        peer_node_id,
+                        transcription.timestamp,
+                        transcription.timestamp - now
+                    );
+                    return false;
+                }
+                true
+            }
+        }
+    }
+
+    /// Fetch the peer's transcriptions newer than `last_sync`, returning the
+    /// new high-water mark
+    ///
+    /// The high-water mark stays in the peer's clock (it's compared against
+    /// the peer's rows), even when timestamps are adjusted by `clock_offset`
+    /// before storing.
+    async fn pull_from_peer(
+        &self,
+        client: &mut SyncClient,
+        peer_conn: &PeerConnection,
+        last_sync: i64,
+        now: i64,
+        clock_offset: Option<i64>,
+    ) -> Result<i64> {
+        // A high-water mark in the future would never match anything again,
+        // so fall back to a full reconcile and let id dedup catch us up
+        let full_reconcile = if last_sync > now {
+            warn!(
+                "Sync high-water mark for {} is in the future ({} > {}), running full reconcile",
+                peer_conn.node_id, last_sync, now
+            );
+            true
+        } else {
+            self.full_reconcile_due(&peer_conn.node_id).await
+        };
+
+        let since_timestamp = if full_reconcile {
+            info!("Running full reconcile with {}", peer_conn.node_id);
+            0
+        } else {
+            last_sync
+        };
 
         // Fetch transcriptions since last sync
         let request = tonic::Request::new(SinceRequest {
-            since_timestamp: last_sync,
+            since_timestamp,
+            source_nodes: peer_conn.source_nodes.clone(),
         });
 
         let mut stream = client
@@ -232,43 +898,90 @@ impl PeerManager {
             .into_inner();
 
         let mut count = 0;
-        let mut latest_timestamp = last_sync;
+        let mut rejected = 0;
+        let mut filtered = 0;
+        let mut latest_timestamp = since_timestamp;
+        // Held until the stream ends when batching, so a stream that fails
+        // midway stores nothing and the high-water mark doesn't move
+        let mut batch = Vec::new();
 
         while let Some(proto_t) = stream.message().await? {
-            let transcription = Transcription {
-                id: proto_t.id,
-                timestamp: proto_t.timestamp,
-                text: proto_t.text.clone(),
-                source_node: proto_t.source_node,
-                memo_device_id: if proto_t.memo_device_id.is_empty() {
-                    None
-                } else {
-                    Some(proto_t.memo_device_id)
-                },
-                synced: true,
-            };
+            let mut transcription = from_proto(proto_t);
+            let received_timestamp = transcription.timestamp;
 
-            self.storage.insert_transcription(&transcription)?;
+            // Older peers ignore `source_nodes` and send everything
+            if !peer_conn.source_nodes.is_empty()
+                && !peer_conn.source_nodes.contains(&transcription.source_node)
+            {
+                latest_timestamp = latest_timestamp.max(received_timestamp);
+                filtered += 1;
+                continue;
+            }
+
+            // Fetching it again wouldn't change the signature, so the
+            // high-water mark moves past a dropped row
+            if !signing::accept(
+                &self.storage,
+                &transcription,
+                &peer_conn.node_id,
+                self.reject_invalid_signatures,
+            )? {
+                latest_timestamp = latest_timestamp.max(received_timestamp);
+                continue;
+            }
 
-            if proto_t.timestamp > latest_timestamp {
-                latest_timestamp = proto_t.timestamp;
+            if !self.apply_clock_skew(&mut transcription, &peer_conn.node_id, now, clock_offset) {
+                rejected += 1;
+                continue;
             }
 
-            count += 1;
-            debug!("Synced transcription: {}", proto_t.text);
+            if received_timestamp > latest_timestamp {
+                latest_timestamp = received_timestamp;
+            }
+
+            if self.batch_pulls {
+                batch.push(transcription);
+                continue;
+            }
+
+            match self.storage.insert_transcription(&transcription) {
+                Ok(_) => {
+                    count += 1;
+                    debug!("Synced transcription: {}", transcription.text);
+                }
+                // Logged by storage; the high-water mark still moves past it
+                Err(e) if e.is::<IdCollisionError>() => {}
+                Err(e) => return Err(e),
+            }
         }
 
-        // Update peer sync timestamp
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        if !batch.is_empty() {
+            count = self
+                .storage
+                .insert_transcriptions(&batch)
+                .with_context(|| format!("Failed to store transcriptions from {}", peer_conn.node_id))?;
+        }
 
-        self.storage.upsert_peer(&Peer {
-            node_id: peer_conn.node_id.clone(),
-            last_seen: now,
-            last_sync_timestamp: latest_timestamp,
-        })?;
+        if rejected > 0 {
+            warn!(
+                "Rejected {} transcriptions from {} timestamped more than {}s in the future",
+                rejected, peer_conn.node_id, self.max_clock_skew_secs
+            );
+        }
+
+        if filtered > 0 {
+            debug!(
+                "Skipped {} transcriptions from {} outside the source node filter",
+                filtered, peer_conn.node_id
+            );
+        }
+
+        if full_reconcile {
+            self.last_full_reconcile
+                .write()
+                .await
+                .insert(peer_conn.node_id.clone(), Instant::now());
+        }
 
         if count > 0 {
             info!(
@@ -277,6 +990,320 @@ impl PeerManager {
             );
         }
 
-        Ok(())
+        Ok(latest_timestamp)
+    }
+
+    /// Push our own transcriptions stored since this peer last confirmed
+    /// holding them, in batches of `PUSH_BATCH`
+    ///
+    /// Progress is a cursor per peer: it moves past a row only once the peer
+    /// confirms it, and stops at the first row the peer rejected, so that
+    /// row and everything after it are pushed again on the next sync.
+    async fn push_to_peer(
+        &self,
+        client: &mut SyncClient,
+        peer_conn: &PeerConnection,
+    ) -> Result<()> {
+        let mut cursor = self.storage.get_push_cursor(&peer_conn.node_id)?;
+        loop {
+            let pending =
+                self.storage
+                    .get_node_transcriptions_after_cursor(&self.node_id, cursor, PUSH_BATCH)?;
+            if pending.is_empty() {
+                return Ok(());
+            }
+            if self.dry_run {
+                info!(
+                    "Dry run: would push {} transcriptions to {}",
+                    pending.len(),
+                    peer_conn.node_id
+                );
+                return Ok(());
+            }
+
+            let batch_full = pending.len() == PUSH_BATCH;
+            let cursors: Vec<(String, i64)> =
+                pending.iter().map(|t| (t.id.clone(), t.cursor)).collect();
+            let outgoing: Vec<ProtoTranscription> = pending.into_iter().map(to_proto).collect();
+            let response = client
+                .push_transcriptions(tokio_stream::iter(outgoing))
+                .await
+                .context("Failed to push transcriptions")?
+                .into_inner();
+
+            // Rows the peer already had are just as durable as newly stored ones
+            let confirmed: HashSet<&String> =
+                response.persisted_ids.iter().chain(&response.duplicate_ids).collect();
+            for id in &confirmed {
+                self.storage.mark_synced(id)?;
+            }
+            let held = cursors.iter().take_while(|(id, _)| confirmed.contains(id)).count();
+            if let Some((_, last)) = held.checked_sub(1).map(|i| &cursors[i]) {
+                cursor = *last;
+                self.storage.set_push_cursor(&peer_conn.node_id, cursor)?;
+            }
+
+            if !response.rejected_ids.is_empty() {
+                warn!(
+                    "{} rejected {} pushed transcriptions; will retry next sync",
+                    peer_conn.node_id,
+                    response.rejected_ids.len()
+                );
+            }
+
+            info!(
+                "Pushed {} transcriptions to {} ({} new, {} already present)",
+                response.received,
+                peer_conn.node_id,
+                response.persisted_ids.len(),
+                response.duplicate_ids.len()
+            );
+
+            if !batch_full || held < cursors.len() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IdCollisionMode;
+
+    /// Push `rows` to a server on `storage` and return its response
+    async fn push(storage: &Storage, rows: Vec<Transcription>) -> PushResponse {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (broadcast_tx, _broadcast_rx) = mpsc::unbounded_channel();
+        let server = PeerSyncServer::new("node-b".to_string(), storage.clone(), broadcast_tx);
+        tokio::spawn(server.serve(listener));
+
+        let mut client = ClusterSecret::default()
+            .connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        client
+            .push_transcriptions(tokio_stream::iter(rows.into_iter().map(to_proto)))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_pushed_id_collision_follows_storage_mode() {
+        let foreign = Transcription {
+            source_node: "node-c".to_string(),
+            text: "someone else's memo".to_string(),
+            ..Transcription::sample("a", 1_717_243_260)
+        };
+
+        let path = std::env::temp_dir().join(format!("memo-node-push-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap().with_id_collision(IdCollisionMode::Reject);
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        let response = push(&storage, vec![foreign.clone()]).await;
+        assert_eq!(response.rejected_ids, vec!["a"]);
+        assert!(response.persisted_ids.is_empty());
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().source_node, "test-node");
+        assert_eq!(storage.count_transcriptions().unwrap().0, 1);
+        std::fs::remove_file(&path).unwrap();
+
+        let path = std::env::temp_dir().join(format!("memo-node-push-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap().with_id_collision(IdCollisionMode::Rename);
+        storage.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        let response = push(&storage, vec![foreign]).await;
+        assert_eq!(response.persisted_ids, vec!["a"]);
+        assert_eq!(storage.get_transcription("a").unwrap().unwrap().source_node, "test-node");
+        assert_eq!(storage.count_transcriptions().unwrap().0, 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_audio_refuses_recordings_over_the_cap() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!("memo-node-relay-{}.db", uuid::Uuid::new_v4()));
+        let (broadcast_tx, _broadcast_rx) = mpsc::unbounded_channel();
+        let (queue, mut jobs) = TranscriptionQueue::bounded(1);
+        let server =
+            PeerSyncServer::new("node-b".to_string(), Storage::new(&path).unwrap(), broadcast_tx)
+                .with_transcriber(queue, Duration::from_secs(1));
+        tokio::spawn(server.serve(listener));
+
+        let mut client = ClusterSecret::default()
+            .connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let second = || AudioChunk {
+            pcm: transcribe::samples_to_pcm(&[0; transcribe::SAMPLE_RATE as usize]),
+            sample_rate: transcribe::SAMPLE_RATE,
+        };
+        let status = client
+            .transcribe_audio(tokio_stream::iter(vec![second(), second()]))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(jobs.try_recv().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_ids_pages_through_every_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!("memo-node-ids-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        for (i, id) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+            storage
+                .insert_transcription(&Transcription::sample(id, 1_717_243_200 + i as i64))
+                .unwrap();
+        }
+        let (broadcast_tx, _broadcast_rx) = mpsc::unbounded_channel();
+        let mut server = PeerSyncServer::new("node-b".to_string(), storage, broadcast_tx);
+        server.ids_page_size = 2;
+        tokio::spawn(server.serve(listener));
+
+        let mut client = ClusterSecret::default()
+            .connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let page = client
+            .get_ids(IdsRequest {
+                since_timestamp: 0,
+                source_nodes: Vec::new(),
+                after_id: "b".to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(page.ids, vec!["c", "d"]);
+        assert!(page.truncated);
+
+        let local_path =
+            std::env::temp_dir().join(format!("memo-node-ids-{}.db", uuid::Uuid::new_v4()));
+        let local = Storage::new(&local_path).unwrap();
+        local.insert_transcription(&Transcription::sample("a", 1_717_243_200)).unwrap();
+        let diff = crate::sync::verify::verify_sync(
+            &local,
+            &ClusterSecret::default(),
+            format!("http://{}", addr),
+            0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(diff.remote_count, 5);
+        assert_eq!(diff.missing_locally, vec!["b", "c", "d", "e"]);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&local_path).unwrap();
+    }
+
+    #[test]
+    fn test_clock_offset_from_ping_midpoint() {
+        let sent_ms = 1_717_243_200_000;
+        // Peer stamped 1717243320 halfway through a 400ms round trip
+        assert_eq!(clock_offset(sent_ms, sent_ms + 400, 1_717_243_320), 120);
+        assert_eq!(clock_offset(sent_ms, sent_ms + 400, 1_717_243_080), -120);
+        // Within the reply's one-second resolution
+        assert_eq!(clock_offset(sent_ms, sent_ms + 400, 1_717_243_201), 0);
+        assert_eq!(clock_offset(sent_ms, sent_ms + 400, 1_717_243_199), 0);
+    }
+
+    #[tokio::test]
+    async fn test_measure_clock_offset_of_peer_with_our_clock() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!("memo-node-ping-{}.db", uuid::Uuid::new_v4()));
+        let (broadcast_tx, _broadcast_rx) = mpsc::unbounded_channel();
+        let server =
+            PeerSyncServer::new("node-b".to_string(), Storage::new(&path).unwrap(), broadcast_tx);
+        tokio::spawn(server.serve(listener));
+
+        let mut client = ClusterSecret::default()
+            .connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        assert_eq!(measure_clock_offset(&mut client, "node-a").await.unwrap(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_clock_skew_adjusts_or_rejects_pulled_rows() {
+        let path = std::env::temp_dir().join(format!("memo-node-skew-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        let now = 1_717_243_200;
+        let peer_row = Transcription {
+            source_node: "peer".to_string(),
+            ..Transcription::sample("a", now + 600)
+        };
+        // Relayed by the peer, so stamped by another node's clock
+        let relayed_row = Transcription {
+            source_node: "other".to_string(),
+            ..Transcription::sample("b", now + 600)
+        };
+
+        let manager = PeerManager::new("node-a".to_string(), storage.clone(), 30, 0)
+            .with_clock_skew(ClockSkewMode::Adjust, 300);
+        let mut row = peer_row.clone();
+        assert!(manager.apply_clock_skew(&mut row, "peer", now, Some(600)));
+        assert_eq!(row.timestamp, now);
+        let mut row = relayed_row.clone();
+        assert!(manager.apply_clock_skew(&mut row, "peer", now, Some(600)));
+        assert_eq!(row.timestamp, now + 600);
+        // No measurement yet leaves timestamps alone
+        let mut row = peer_row.clone();
+        assert!(manager.apply_clock_skew(&mut row, "peer", now, None));
+        assert_eq!(row.timestamp, now + 600);
+
+        let manager = PeerManager::new("node-a".to_string(), storage, 30, 0)
+            .with_clock_skew(ClockSkewMode::Reject, 300);
+        assert!(!manager.apply_clock_skew(&mut peer_row.clone(), "peer", now, Some(600)));
+        assert!(!manager.apply_clock_skew(&mut relayed_row.clone(), "peer", now, None));
+        let mut row = Transcription::sample("c", now + 300);
+        assert!(manager.apply_clock_skew(&mut row, "peer", now, None));
+        assert_eq!(row.timestamp, now + 300);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restored_peers_expire_and_connect_once_reached() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server_path =
+            std::env::temp_dir().join(format!("memo-node-peer-{}.db", uuid::Uuid::new_v4()));
+        let (broadcast_tx, _broadcast_rx) = mpsc::unbounded_channel();
+        let server =
+            PeerSyncServer::new("fresh".to_string(), Storage::new(&server_path).unwrap(), broadcast_tx);
+        tokio::spawn(server.serve(listener));
+
+        let path = std::env::temp_dir().join(format!("memo-node-peer-{}.db", uuid::Uuid::new_v4()));
+        let storage = Storage::new(&path).unwrap();
+        let now = clock::unix_now();
+        storage.set_peer_address("fresh", "127.0.0.1", port, now - 60).unwrap();
+        storage.set_peer_address("stale", "127.0.0.1", port, now - 30 * 24 * 3600).unwrap();
+
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let manager = PeerManager::new("node-a".to_string(), storage, 30, 0)
+            .with_peer_expiry(7 * 24 * 3600)
+            .with_events(events_tx);
+        assert_eq!(manager.restore_peers().await.unwrap(), 1);
+        assert!(events_rx.try_recv().is_err());
+
+        manager.sync_with_peers().await;
+        assert_eq!(
+            events_rx.try_recv().unwrap(),
+            PeerEvent::Connected {
+                node_id: "fresh".to_string(),
+                display_name: "fresh".to_string(),
+            }
+        );
+        manager.sync_with_peers().await;
+        assert!(events_rx.try_recv().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&server_path).unwrap();
     }
 }
@@ -0,0 +1,302 @@
+use crate::storage::{Storage, Transcription};
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Prefix of the signed bytes, so a signature can't be replayed as one over
+/// some other message
+const DOMAIN: &[u8] = b"memo-node-transcription-v1";
+
+/// This node's ed25519 key for signing the rows it creates
+/// (`sync.sign_transcriptions`)
+pub struct NodeKey(SigningKey);
+
+impl NodeKey {
+    /// Read the key at `path`, generating and saving a new one if there is
+    /// none yet
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let seed = std::fs::read(path)
+                .with_context(|| format!("Failed to read node key {}", path.display()))?;
+            let seed: [u8; 32] = seed
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("{} is not a 32-byte ed25519 key", path.display()))?;
+            return Ok(Self(SigningKey::from_bytes(&seed)));
+        }
+
+        let mut seed = [0u8; 32];
+        getrandom::getrandom(&mut seed).context("Failed to generate node key")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create data directory")?;
+        }
+        write_private(path, &seed)
+            .with_context(|| format!("Failed to write node key {}", path.display()))?;
+
+        let key = Self(SigningKey::from_bytes(&seed));
+        info!("Generated node signing key {} ({})", key.public_key(), path.display());
+        Ok(key)
+    }
+
+    /// Hex-encoded public key, as stored in `signer_key`
+    pub fn public_key(&self) -> String {
+        hex(self.0.verifying_key().as_bytes())
+    }
+
+    /// Set `signature` and `signer_key` on a row this node created
+    pub fn sign(&self, transcription: &mut Transcription) {
+        let signature = self.0.sign(&signed_content(transcription));
+        transcription.signature = Some(hex(&signature.to_bytes()));
+        transcription.signer_key = Some(self.public_key());
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+/// Bytes a row's signature covers: every field the creating node sets,
+/// each length-prefixed
+///
+/// The timestamp is left out because `sync.clock_skew = "adjust"` rewrites
+/// it on the way, and `synced`, `device_battery` and the cursor are local
+/// bookkeeping rather than content.
+fn signed_content(transcription: &Transcription) -> Vec<u8> {
+    let source_type = transcription.source_type.to_string();
+    let duration_ms = transcription.duration_ms.map(|ms| ms.to_string()).unwrap_or_default();
+    // A BTreeMap serializes with sorted keys, so this is canonical
+    let metadata = serde_json::to_string(&transcription.metadata.0).unwrap_or_default();
+    let fields = [
+        transcription.id.as_str(),
+        transcription.text.as_str(),
+        transcription.source_node.as_str(),
+        transcription.memo_device_id.as_deref().unwrap_or(""),
+        source_type.as_str(),
+        transcription.session_id.as_deref().unwrap_or(""),
+        duration_ms.as_str(),
+        metadata.as_str(),
+    ];
+
+    let mut content = DOMAIN.to_vec();
+    for field in fields {
+        content.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        content.extend_from_slice(field.as_bytes());
+    }
+    content
+}
+
+/// Outcome of checking a received row's signature
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureCheck {
+    /// Created by a node that doesn't sign, and never has as far as we know
+    Unsigned,
+    Valid,
+    Invalid(String),
+}
+
+/// Check `transcription`'s signature against its `signer_key`, and that key
+/// against the one first seen for its source node
+///
+/// The first key seen for a node is pinned in the database, so a peer that
+/// rewrites a row can't simply re-sign it with a key of its own, nor strip
+/// the signature: once a node has a pinned key, its unsigned rows are
+/// invalid.
+pub fn check(storage: &Storage, transcription: &Transcription) -> Result<SignatureCheck> {
+    let (signature, signer_key) = match (&transcription.signature, &transcription.signer_key) {
+        (None, None) => {
+            return Ok(match storage.get_node_key(&transcription.source_node)? {
+                Some(pinned) => SignatureCheck::Invalid(format!(
+                    "unsigned, but {} signs with {}",
+                    transcription.source_node, pinned
+                )),
+                None => SignatureCheck::Unsigned,
+            });
+        }
+        (Some(signature), Some(signer_key)) => (signature, signer_key),
+        _ => return Ok(SignatureCheck::Invalid("signature without key".to_string())),
+    };
+
+    let key = unhex::<32>(signer_key).and_then(|key| VerifyingKey::from_bytes(&key).ok());
+    let Some(key) = key else {
+        return Ok(SignatureCheck::Invalid("malformed signer key".to_string()));
+    };
+    let Some(signature) = unhex::<64>(signature) else {
+        return Ok(SignatureCheck::Invalid("malformed signature".to_string()));
+    };
+    if key
+        .verify_strict(&signed_content(transcription), &Signature::from_bytes(&signature))
+        .is_err()
+    {
+        return Ok(SignatureCheck::Invalid("signature doesn't match the content".to_string()));
+    }
+
+    let pinned = storage.pin_node_key(&transcription.source_node, signer_key)?;
+    if pinned != *signer_key {
+        return Ok(SignatureCheck::Invalid(format!(
+            "signed with {} but {} signs with {}",
+            signer_key, transcription.source_node, pinned
+        )));
+    }
+    Ok(SignatureCheck::Valid)
+}
+
+/// Check a row received from `peer`, logging an invalid signature, and
+/// return whether to store it: rows with an invalid signature are dropped
+/// when `reject_invalid` is set
+pub fn accept(
+    storage: &Storage,
+    transcription: &Transcription,
+    peer: &str,
+    reject_invalid: bool,
+) -> Result<bool> {
+    let SignatureCheck::Invalid(reason) = check(storage, transcription)? else {
+        return Ok(true);
+    };
+    if reject_invalid {
+        warn!(
+            "Rejecting transcription {} from {}: invalid signature ({})",
+            transcription.id, peer, reason
+        );
+    } else {
+        warn!(
+            "Transcription {} from {} has an invalid signature ({}); storing it anyway",
+            transcription.id, peer, reason
+        );
+    }
+    Ok(!reject_invalid)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (byte, pair) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Metadata;
+
+    fn temp_db() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("memo-node-signing-{}.db", uuid::Uuid::new_v4()))
+    }
+
+    fn key(seed: u8) -> NodeKey {
+        NodeKey(SigningKey::from_bytes(&[seed; 32]))
+    }
+
+
+    #[test]
+    fn test_signature_detects_altered_content() {
+        let path = temp_db();
+        let storage = Storage::new(&path).unwrap();
+        let mut row = Transcription {
+            text: "the archive is append only".to_string(),
+            source_node: "node-a".to_string(),
+            memo_device_id: Some("memo-1".to_string()),
+            device_battery: Some(80),
+            metadata: Metadata([("project".to_string(), "audit".to_string())].into()),
+            duration_ms: Some(2_000),
+            ..Transcription::sample("t1", 1_700_000_000)
+        };
+        assert_eq!(check(&storage, &row).unwrap(), SignatureCheck::Unsigned);
+
+        key(1).sign(&mut row);
+        assert_eq!(check(&storage, &row).unwrap(), SignatureCheck::Valid);
+
+        // Local bookkeeping may change in transit
+        let relayed = Transcription {
+            synced: true,
+            device_battery: None,
+            timestamp: row.timestamp - 3,
+            ..row.clone()
+        };
+        assert_eq!(check(&storage, &relayed).unwrap(), SignatureCheck::Valid);
+
+        let altered = Transcription {
+            text: "the archive is editable".to_string(),
+            ..row.clone()
+        };
+        assert!(matches!(check(&storage, &altered).unwrap(), SignatureCheck::Invalid(_)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_first_key_seen_is_pinned() {
+        let path = temp_db();
+        let storage = Storage::new(&path).unwrap();
+        let mut row = Transcription {
+            source_node: "node-a".to_string(),
+            ..Transcription::sample("t1", 1_700_000_000)
+        };
+        let mut forged = row.clone();
+        key(1).sign(&mut row);
+        assert_eq!(check(&storage, &row).unwrap(), SignatureCheck::Valid);
+
+        // Validly signed, but not by the key node-a signed with before
+        key(2).sign(&mut forged);
+        assert!(matches!(check(&storage, &forged).unwrap(), SignatureCheck::Invalid(_)));
+        assert!(!accept(&storage, &forged, "peer-b", true).unwrap());
+        assert!(accept(&storage, &forged, "peer-b", false).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stripped_signature_is_invalid_once_key_is_pinned() {
+        let path = temp_db();
+        let storage = Storage::new(&path).unwrap();
+        let mut row = Transcription {
+            source_node: "node-a".to_string(),
+            ..Transcription::sample("t1", 1_700_000_000)
+        };
+        let stripped = Transcription {
+            text: "the archive is editable".to_string(),
+            ..row.clone()
+        };
+        assert_eq!(check(&storage, &stripped).unwrap(), SignatureCheck::Unsigned);
+
+        key(1).sign(&mut row);
+        assert_eq!(check(&storage, &row).unwrap(), SignatureCheck::Valid);
+        assert!(matches!(check(&storage, &stripped).unwrap(), SignatureCheck::Invalid(_)));
+        assert!(!accept(&storage, &stripped, "peer-b", true).unwrap());
+
+        // Nodes that never signed are unaffected
+        let other = Transcription::sample("t2", 1_700_000_000);
+        assert_eq!(check(&storage, &other).unwrap(), SignatureCheck::Unsigned);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let bytes = [0x00, 0x7f, 0xff, 0x10];
+        assert_eq!(unhex::<4>(&hex(&bytes)), Some(bytes));
+        assert_eq!(unhex::<4>("00112"), None);
+        assert_eq!(unhex::<2>("zz00"), None);
+    }
+}
@@ -0,0 +1,173 @@
+use super::auth::ClusterSecret;
+use super::peer::proto::{IdsRequest, SinceRequest};
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+
+/// gRPC URL for a peer given as `host`, `host:port`, an IP address or a
+/// socket address (`[fe80::1]:9876`), using `default_port` when none is given
+pub fn peer_url(peer: &str, default_port: u16) -> String {
+    if let Ok(addr) = peer.parse::<SocketAddr>() {
+        return format!("http://{}", addr);
+    }
+    let bare = peer.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return format!("http://{}", SocketAddr::new(ip, default_port));
+    }
+    let has_port = peer
+        .split_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    if has_port {
+        format!("http://{}", peer)
+    } else {
+        format!("http://{}:{}", peer, default_port)
+    }
+}
+
+/// SHA-256 over sorted ids, each followed by a newline
+pub fn ids_digest(ids: &[String]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for id in ids {
+        hasher.update(id.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Transcription ids held by only one of two nodes
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncDiff {
+    pub local_count: usize,
+    pub remote_count: usize,
+    /// On the peer but not here
+    pub missing_locally: Vec<String>,
+    /// Here but not on the peer
+    pub missing_on_peer: Vec<String>,
+}
+
+impl SyncDiff {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_locally.is_empty() && self.missing_on_peer.is_empty()
+    }
+}
+
+/// Compare two sorted, deduplicated id lists
+fn diff_ids(local: &[String], remote: &[String]) -> SyncDiff {
+    let mut diff = SyncDiff {
+        local_count: local.len(),
+        remote_count: remote.len(),
+        ..SyncDiff::default()
+    };
+
+    let (mut l, mut r) = (0, 0);
+    while l < local.len() || r < remote.len() {
+        match (local.get(l), remote.get(r)) {
+            (Some(a), Some(b)) if a == b => {
+                l += 1;
+                r += 1;
+            }
+            (Some(a), Some(b)) if a < b => {
+                diff.missing_on_peer.push(a.clone());
+                l += 1;
+            }
+            (Some(a), None) => {
+                diff.missing_on_peer.push(a.clone());
+                l += 1;
+            }
+            (_, Some(b)) => {
+                diff.missing_locally.push(b.clone());
+                r += 1;
+            }
+            (None, None) => unreachable!("loop condition"),
+        }
+    }
+
+    diff
+}
+
+/// Compare the transcriptions newer than `since` held here and by the peer
+/// at `addr` (`http://host:port`)
+///
+/// Digests are compared first; the full id lists are only fetched when they
+/// differ.
+pub async fn verify_sync(
+    storage: &Storage,
+    cluster_secret: &ClusterSecret,
+    addr: String,
+    since: i64,
+) -> Result<SyncDiff> {
+    let mut client = cluster_secret
+        .connect(addr.clone())
+        .await
+        .with_context(|| format!("Failed to connect to {}", addr))?;
+
+    let local = storage.get_ids_since(since, &[])?;
+    let remote_digest = client
+        .get_digest(SinceRequest {
+            since_timestamp: since,
+            source_nodes: Vec::new(),
+        })
+        .await
+        .context("GetDigest failed (is the peer running an older memo-node?)")?
+        .into_inner();
+    if remote_digest.digest == ids_digest(&local) {
+        return Ok(SyncDiff {
+            local_count: local.len(),
+            remote_count: remote_digest.count as usize,
+            ..SyncDiff::default()
+        });
+    }
+
+    // Older peers answer with every id and never set `truncated`
+    let mut remote = Vec::new();
+    loop {
+        let page = client
+            .get_ids(IdsRequest {
+                since_timestamp: since,
+                source_nodes: Vec::new(),
+                after_id: remote.last().cloned().unwrap_or_default(),
+            })
+            .await
+            .context("GetIds failed")?
+            .into_inner();
+        let done = !page.truncated || page.ids.is_empty();
+        remote.extend(page.ids);
+        if done {
+            break;
+        }
+    }
+    Ok(diff_ids(&local, &remote))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff_ids_reports_both_sides() {
+        let diff = diff_ids(&ids(&["a", "b", "d", "f"]), &ids(&["b", "c", "d", "e"]));
+        assert_eq!(diff.missing_on_peer, ids(&["a", "f"]));
+        assert_eq!(diff.missing_locally, ids(&["c", "e"]));
+        assert!(!diff.is_consistent());
+
+        assert!(diff_ids(&ids(&["a", "b"]), &ids(&["a", "b"])).is_consistent());
+        assert_eq!(ids_digest(&ids(&["a", "b"])), ids_digest(&ids(&["a", "b"])));
+        assert_ne!(ids_digest(&ids(&["a", "b"])), ids_digest(&ids(&["ab"])));
+    }
+
+    #[test]
+    fn test_peer_url_handles_ipv6_and_default_port() {
+        assert_eq!(peer_url("10.0.0.5", 9876), "http://10.0.0.5:9876");
+        assert_eq!(peer_url("10.0.0.5:7000", 9876), "http://10.0.0.5:7000");
+        assert_eq!(peer_url("fe80::1", 9876), "http://[fe80::1]:9876");
+        assert_eq!(peer_url("[fe80::1]", 9876), "http://[fe80::1]:9876");
+        assert_eq!(peer_url("[fe80::1]:7000", 9876), "http://[fe80::1]:7000");
+        assert_eq!(peer_url("memo-den", 9876), "http://memo-den:9876");
+        assert_eq!(peer_url("memo-den:7000", 9876), "http://memo-den:7000");
+    }
+}
@@ -1,124 +1,630 @@
+use crate::clock;
+use crate::config::{Compute, RecordingFormat, TextOverflow};
+use crate::metrics::{Metrics, Subsystem};
+use crate::sync::auth::{ClusterSecret, SyncClient};
+use crate::sync::peer::proto::AudioChunk;
 use anyhow::{Context, Result};
 use memo_stt::SttEngine;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, error, info, warn};
+use unicode_normalization::UnicodeNormalization;
+
+/// Sample rate of all audio handed to the transcriber
+pub const SAMPLE_RATE: u32 = 16000;
+
+/// Where decoded audio comes from: one audio stream of one device
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AudioSource {
+    pub device: String,
+    /// Index of the device's audio characteristic, see `AudioPacket`
+    pub stream: usize,
+}
+
+/// Decoded 16kHz audio on its way to the transcriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedAudio {
+    /// Samples of the recording in progress on `source`
+    Samples { source: AudioSource, samples: Vec<i16> },
+    /// Recording stopped; the samples each source sent before it make up
+    /// a recording of their own
+    Stopped,
+    /// A whole recording from `source`, queued as its own job: one that
+    /// ended while the model was still loading
+    Recording { source: AudioSource, samples: Vec<i16> },
+}
+
+/// Where and when a local recording was made, carried along with its audio
+/// so its text can be attributed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordingOrigin {
+    /// Device that recorded it; `None` for audio merged from several
+    pub device: Option<String>,
+    /// Unix time the recording ended, or its audio was cut in continuous
+    /// mode or on shutdown
+    pub ended_at: i64,
+}
+
+impl RecordingOrigin {
+    /// A recording from `source` that ends now
+    fn ending_now(source: &AudioSource) -> Self {
+        Self {
+            device: Some(source.device.clone()),
+            ended_at: clock::unix_now(),
+        }
+    }
+
+    /// The origin of two recordings merged into one
+    fn merge(self, other: Self) -> Self {
+        Self {
+            device: self.device.filter(|device| other.device.as_ref() == Some(device)),
+            ended_at: self.ended_at.max(other.ended_at),
+        }
+    }
+}
+
+/// Text of one local recording, emitted on the transcriber's output channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscribedRecording {
+    pub text: String,
+    /// Length of the recorded audio
+    pub duration_ms: i64,
+    pub origin: RecordingOrigin,
+    /// The recorded audio, for `transcription.save_recordings`
+    pub audio: Vec<i16>,
+}
+
+/// Length of `samples` of 16kHz audio in milliseconds
+pub fn duration_ms(samples: usize) -> i64 {
+    (samples as u64 * 1000 / SAMPLE_RATE as u64) as i64
+}
+
+/// Work waiting for an engine
+pub enum TranscriptionJob {
+    /// A finished recording
+    Recording {
+        audio: Vec<i16>,
+        /// Default for audio from a relay peer
+        origin: RecordingOrigin,
+        /// Set when the caller wants the text back instead of it being
+        /// emitted on the transcriber's output channel
+        reply: Option<oneshot::Sender<Result<String>>>,
+    },
+    /// Queued once per worker on shutdown, behind every recording; the
+    /// worker that takes it exits
+    Stop,
+}
+
+/// Bounded queue of finished recordings in front of the transcription engines
+#[derive(Clone)]
+pub struct TranscriptionQueue {
+    tx: mpsc::Sender<TranscriptionJob>,
+}
+
+impl TranscriptionQueue {
+    pub fn bounded(size: usize) -> (Self, mpsc::Receiver<TranscriptionJob>) {
+        let (tx, rx) = mpsc::channel(size.max(1));
+        (Self { tx }, rx)
+    }
+
+    /// Queue a local recording, waiting for space if every engine is busy
+    /// and the queue is full
+    ///
+    /// Fails once every worker has stopped, which only happens when their
+    /// engines died and couldn't be restarted.
+    async fn submit(&self, audio: Vec<i16>, origin: RecordingOrigin) -> Result<()> {
+        let job = TranscriptionJob::Recording {
+            audio,
+            origin,
+            reply: None,
+        };
+        let job = match self.tx.try_send(job) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(job)) => {
+                warn!("Transcription queue full, waiting for a free engine");
+                job
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                anyhow::bail!("Transcription workers have stopped; dropping recording");
+            }
+        };
+
+        self.tx
+            .send(job)
+            .await
+            .map_err(|_| anyhow::anyhow!("Transcription workers have stopped; dropping recording"))
+    }
+
+    /// Transcribe audio on behalf of a caller that wants the text back,
+    /// such as a relay peer
+    pub async fn transcribe(&self, audio: Vec<i16>) -> Result<String> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx
+            .send(TranscriptionJob::Recording {
+                audio,
+                origin: RecordingOrigin::default(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("Transcription workers have stopped"))?;
+
+        reply_rx
+            .await
+            .context("Transcription worker dropped the request")?
+    }
+
+    /// Ask `workers` workers to exit once the recordings queued so far are
+    /// transcribed
+    async fn stop(&self, workers: usize) {
+        for _ in 0..workers {
+            if self.tx.send(TranscriptionJob::Stop).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// A speech-to-text engine owned by one worker
+trait Engine: Send + 'static {
+    /// `prompt` is text Whisper treats as what came just before the audio
+    fn transcribe(&mut self, audio: &[i16], prompt: Option<&str>) -> Result<String>;
+
+    /// Whether `transcribe` uses its `prompt`
+    fn takes_prompt(&self) -> bool {
+        false
+    }
+}
+
+impl Engine for SttEngine {
+    // memo-stt has no way to pass Whisper an initial prompt yet, so the
+    // prompt is dropped and context priming stays off with this engine
+    fn transcribe(&mut self, audio: &[i16], _prompt: Option<&str>) -> Result<String> {
+        // memo-stt expects i16 samples directly, no conversion needed
+        // It handles normalization internally
+        SttEngine::transcribe(self, audio)
+            .map_err(|e| anyhow::anyhow!("Transcription error: {}", e))
+    }
+}
+
+/// Loads a fresh engine for a worker whose engine panicked
+type EngineLoader = Arc<dyn Fn() -> Result<Box<dyn Engine>> + Send + Sync>;
+
+/// Longest prompt built by context priming, whatever the config says;
+/// Whisper only looks at the last couple of hundred tokens anyway
+const MAX_PROMPT_CHARS: usize = 1000;
+
+/// Longest wait before reloading a worker's engine
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+enum Backend {
+    /// Local memo-stt engines, one worker each
+    Local {
+        engines: Vec<Box<dyn Engine>>,
+        load: EngineLoader,
+    },
+    /// A peer's `TranscribeAudio` RPC at `host:port`
+    Remote {
+        address: String,
+        cluster_secret: ClusterSecret,
+    },
+}
+
+/// What to do when transcribing a recording fails
+#[derive(Clone, Default)]
+struct FailurePolicy {
+    /// Extra attempts before giving up
+    retries: u32,
+    /// Where to save the audio of local recordings that still failed
+    save_dir: Option<PathBuf>,
+    /// File format of saved audio
+    format: RecordingFormat,
+}
+
+impl FailurePolicy {
+    /// Backoff before retry number `attempt` (1-based)
+    fn retry_delay(attempt: u32) -> tokio::time::Duration {
+        tokio::time::Duration::from_millis(500 * attempt as u64)
+    }
+
+    /// Keep a recording that couldn't be transcribed for manual reprocessing
+    fn save_audio(&self, audio: &[i16]) {
+        let Some(dir) = &self.save_dir else {
+            return;
+        };
+
+        let path = dir.join(format!(
+            "failed-{}.{}",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S-%3f"),
+            self.format.extension()
+        ));
+        let saved = std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))
+            .and_then(|_| crate::audio::capture::write_recording(&path, audio, SAMPLE_RATE, self.format));
+
+        match saved {
+            Ok(()) => warn!("Saved audio of the failed recording to {}", path.display()),
+            Err(e) => error!("Failed to save audio of the failed recording: {:#}", e),
+        }
+    }
+}
+
+/// How a local worker recovers when its engine panics
+#[derive(Clone)]
+struct EngineRestart {
+    /// Reloads per worker before it gives up and exits
+    max_restarts: u32,
+    load: EngineLoader,
+}
+
+impl EngineRestart {
+    /// A fresh engine after backing off 1s, 2s, 4s... (max 60s), or `None`
+    /// once the worker has used up its restarts
+    async fn reload(&self, worker_id: usize, restarts: &mut u32) -> Option<Box<dyn Engine>> {
+        while *restarts < self.max_restarts {
+            *restarts += 1;
+            let delay = Duration::from_secs(2_u64.saturating_pow(*restarts - 1)).min(MAX_RESTART_DELAY);
+            warn!(
+                "Restarting transcription worker {} in {:?} (restart {}/{})",
+                worker_id, delay, restarts, self.max_restarts
+            );
+            tokio::time::sleep(delay).await;
+
+            let load = self.load.clone();
+            match tokio::task::spawn_blocking(move || load()).await {
+                Ok(Ok(engine)) => {
+                    info!("Transcription worker {} restarted with a new engine", worker_id);
+                    return Some(engine);
+                }
+                Ok(Err(e)) => error!("Failed to reload engine for worker {}: {:#}", worker_id, e),
+                Err(e) => error!("Failed to reload engine for worker {}: {}", worker_id, e),
+            }
+        }
+
+        error!(
+            "Transcription worker {} stopped after {} restarts (transcription.max_restarts)",
+            worker_id, self.max_restarts
+        );
+        None
+    }
+}
 
 /// Whisper transcription using memo-stt
+///
+/// Finished recordings go onto a bounded queue served by a pool of engines,
+/// so a recording that finishes while another is being transcribed doesn't
+/// wait for it when more than one engine is configured. With several engines,
+/// results can be emitted out of recording order. Alternatively the queue can
+/// be served by a remote peer, for nodes too weak to run Whisper.
 pub struct WhisperTranscriber {
-    engine: Arc<tokio::sync::Mutex<SttEngine>>,
-    audio_rx: mpsc::UnboundedReceiver<Vec<i16>>,
-    transcription_tx: mpsc::UnboundedSender<String>,
+    backend: Backend,
+    audio_rx: mpsc::UnboundedReceiver<DecodedAudio>,
+    transcription_tx: mpsc::UnboundedSender<TranscribedRecording>,
     is_recording: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    /// In continuous mode, samples per window transcribed while recording
+    /// stays on
+    continuous_window: Option<usize>,
+    failure_policy: FailurePolicy,
+    /// Engine reloads allowed per local worker, see `with_max_restarts`
+    max_restarts: u32,
+    /// Minimum time between recordings going to the queue, see
+    /// `with_cooldown`
+    cooldown: Duration,
+    /// See `with_context_priming`
+    priming: Option<Priming>,
+    /// Fires when the daemon shuts down, see `with_shutdown`
+    shutdown: Option<oneshot::Receiver<()>>,
 }
 
 impl WhisperTranscriber {
     pub fn new(
         model_name: &str,
+        fallback_models: &[String],
         threads: u8,
-        audio_rx: mpsc::UnboundedReceiver<Vec<i16>>,
+        compute: Compute,
+        audio_rx: mpsc::UnboundedReceiver<DecodedAudio>,
         is_recording: Arc<AtomicBool>,
-    ) -> Result<(Self, mpsc::UnboundedReceiver<String>)> {
+        metrics: Arc<Metrics>,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<TranscribedRecording>)> {
         let (transcription_tx, transcription_rx) = mpsc::unbounded_channel();
 
-        // Validate model name for Raspberry Pi (optimized for base.en and small.en)
-        validate_model_for_pi(model_name)?;
-
-        // Map config model names to memo-stt model paths
-        let model_path = map_model_name_to_path(model_name)?;
-
         info!("Initializing Whisper engine with model: {} (configured for {} threads)", model_name, threads);
-        info!("Model path: {:?}", model_path);
         // Note: Thread count is optimized automatically by memo-stt based on CPU cores
         // The configured thread count is logged for reference but memo-stt will use
         // optimal thread count (min of CPU cores or 8) for best performance
 
-        // Create memo-stt engine
-        // memo-stt handles model downloading automatically
-        let engine = SttEngine::new(&model_path, 16000)
-            .context("Failed to create Whisper engine")?;
-
-        // Warm up the engine to reduce first-transcription latency
-        engine.warmup()
-            .context("Failed to warm up Whisper engine")?;
+        // Try the configured model first, then each fallback in order
+        let (loaded_model, engine) = load_first_available(model_name, fallback_models, compute)?;
 
         info!("Whisper engine initialized and warmed up");
 
         Ok((
             Self {
-                engine: Arc::new(tokio::sync::Mutex::new(engine)),
+                backend: Backend::Local {
+                    engines: vec![Box::new(engine)],
+                    load: engine_loader(loaded_model.to_string(), compute),
+                },
                 audio_rx,
                 transcription_tx,
                 is_recording,
+                metrics,
+                continuous_window: None,
+                failure_policy: FailurePolicy::default(),
+                max_restarts: 0,
+                cooldown: Duration::ZERO,
+                priming: None,
+                shutdown: None,
             },
             transcription_rx,
         ))
     }
 
-    pub async fn start(mut self) -> Result<()> {
+    /// Send recordings to a peer's transcription server instead of loading
+    /// a local model
+    pub fn remote(
+        address: String,
+        cluster_secret: ClusterSecret,
+        audio_rx: mpsc::UnboundedReceiver<DecodedAudio>,
+        is_recording: Arc<AtomicBool>,
+        metrics: Arc<Metrics>,
+    ) -> (Self, mpsc::UnboundedReceiver<TranscribedRecording>) {
+        let (transcription_tx, transcription_rx) = mpsc::unbounded_channel();
+
+        info!("Routing recordings to remote transcriber at {}", address);
+
+        (
+            Self {
+                backend: Backend::Remote {
+                    address,
+                    cluster_secret,
+                },
+                audio_rx,
+                transcription_tx,
+                is_recording,
+                metrics,
+                continuous_window: None,
+                failure_policy: FailurePolicy::default(),
+                max_restarts: 0,
+                cooldown: Duration::ZERO,
+                priming: None,
+                shutdown: None,
+            },
+            transcription_rx,
+        )
+    }
+
+    /// Transcribe every `window_secs` of audio while recording stays on,
+    /// for devices that never send a stop event
+    pub fn with_continuous(mut self, window_secs: u64) -> Self {
+        let window = (window_secs.max(1) * SAMPLE_RATE as u64) as usize;
+        info!("Continuous transcription: {}s windows", window_secs.max(1));
+        self.continuous_window = Some(window);
+        self
+    }
+
+    /// Try a failed recording up to `retries` more times, then save its audio
+    /// to `save_dir` (if given) before giving up
+    pub fn with_retries(mut self, retries: u32, save_dir: Option<PathBuf>) -> Self {
+        self.failure_policy.retries = retries;
+        self.failure_policy.save_dir = save_dir;
+        self
+    }
+
+    /// Save failed recordings as `format` (WAV unless set)
+    pub fn with_recording_format(mut self, format: RecordingFormat) -> Self {
+        self.failure_policy.format = format;
+        self
+    }
+
+    /// Reload a local worker's engine up to `max_restarts` times if it
+    /// panics; a worker that runs out stops, and once none are left `start`
+    /// fails
+    pub fn with_max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    /// Send recordings to the queue at most once per `cooldown`; recordings
+    /// finished sooner are held and merged into one, so rapid push-to-talk
+    /// toggles don't each take an engine
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Prompt each local recording with the text of the last `transcriptions`
+    /// recordings, at most `max_chars` of it, so Whisper keeps names,
+    /// spelling and style consistent across ongoing dictation
+    ///
+    /// This couples consecutive recordings: a misheard word can be repeated
+    /// in the next one, and the model may continue the previous text into
+    /// silence. Requests from relay peers are never primed, and priming does
+    /// nothing with a remote transcriber or an engine that takes no prompt,
+    /// which for now includes memo-stt's.
+    pub fn with_context_priming(mut self, transcriptions: usize, max_chars: usize) -> Self {
+        match &self.backend {
+            Backend::Remote { .. } => {
+                warn!("transcription.context_priming only applies to local engines; ignoring it");
+                return self;
+            }
+            Backend::Local { engines, .. }
+                if !engines.iter().all(|engine| engine.takes_prompt()) =>
+            {
+                warn!(
+                    "transcription.context_priming needs an engine that takes a prompt, \
+                     which memo-stt does not yet; ignoring it"
+                );
+                return self;
+            }
+            Backend::Local { .. } => {}
+        }
+        if transcriptions == 0 || max_chars == 0 {
+            return self;
+        }
+        let max_chars = max_chars.min(MAX_PROMPT_CHARS);
+        info!(
+            "Context priming: prompting with the last {} transcriptions (up to {} characters)",
+            transcriptions, max_chars
+        );
+        self.priming = Some(Priming::new(transcriptions, max_chars));
+        self
+    }
+
+    /// On `shutdown`, transcribe the audio received so far even if
+    /// recording is still on, then return from `start` once every queued
+    /// and in-progress transcription has been delivered
+    pub fn with_shutdown(mut self, shutdown: oneshot::Receiver<()>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Load extra engines (of the model that loaded successfully) so up to
+    /// `engines` recordings transcribe in parallel
+    pub fn with_pool(mut self, pool_size: usize) -> Result<Self> {
+        if let Backend::Local { engines, load } = &mut self.backend {
+            while engines.len() < pool_size {
+                engines.push(load()?);
+            }
+
+            if engines.len() > 1 {
+                info!("Transcription pool: {} engines", engines.len());
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Run the transcriber, feeding finished recordings into `queue` and
+    /// serving `jobs` (which may also hold requests from peers)
+    ///
+    /// Returns when the audio channel closes or on shutdown, after the
+    /// workers have finished every recording queued until then, so the
+    /// output channel closes only once the last transcription is sent.
+    pub async fn start(
+        self,
+        queue: TranscriptionQueue,
+        jobs: mpsc::Receiver<TranscriptionJob>,
+    ) -> Result<()> {
         info!("Starting Whisper transcriber");
 
-        // Buffer to accumulate audio samples for the full recording
-        let mut audio_buffer: Vec<i16> = Vec::new();
-        let mut was_recording = self.is_recording.load(Ordering::Acquire);
+        let Self {
+            backend,
+            mut audio_rx,
+            transcription_tx,
+            is_recording,
+            metrics,
+            continuous_window,
+            failure_policy,
+            max_restarts,
+            cooldown,
+            priming,
+            mut shutdown,
+        } = self;
+
+        let workers = match backend {
+            Backend::Local { engines, load, .. } => {
+                let jobs = Arc::new(tokio::sync::Mutex::new(jobs));
+                let restart = EngineRestart { max_restarts, load };
+                engines
+                    .into_iter()
+                    .enumerate()
+                    .map(|(worker_id, engine)| {
+                        tokio::spawn(run_worker(
+                            worker_id,
+                            engine,
+                            jobs.clone(),
+                            transcription_tx.clone(),
+                            metrics.clone(),
+                            failure_policy.clone(),
+                            restart.clone(),
+                            priming.clone(),
+                        ))
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Backend::Remote {
+                address,
+                cluster_secret,
+            } => {
+                vec![tokio::spawn(run_remote_worker(
+                    address,
+                    cluster_secret,
+                    jobs,
+                    transcription_tx.clone(),
+                    metrics.clone(),
+                    failure_policy,
+                ))]
+            }
+        };
+        drop(transcription_tx);
+
+        // Buffers to accumulate audio samples for the full recording, one
+        // per source so devices and streams recording at once aren't mixed
+        let mut audio_buffers: BTreeMap<AudioSource, Vec<i16>> = BTreeMap::new();
+        let mut cooldown = Cooldown::new(cooldown);
 
         loop {
-            // Receive audio chunks (with timeout to allow periodic recording state checks)
+            if let Some((audio, origin)) = cooldown.due(Instant::now()) {
+                info!("Cooldown over, transcribing {} held samples", audio.len());
+                queue.submit(audio, origin).await?;
+            }
+
+            // Receive audio chunks (with timeout to release held recordings
+            // once the cooldown is over)
             tokio::select! {
-                audio_chunk = self.audio_rx.recv() => {
+                audio_chunk = audio_rx.recv() => {
                     match audio_chunk {
-                        Some(chunk) => {
-                            let is_recording_now = self.is_recording.load(Ordering::Acquire);
-                            
-                            // If recording just stopped, transcribe the accumulated audio
-                            if was_recording && !is_recording_now && !audio_buffer.is_empty() {
-                                info!("Recording stopped, transcribing {} samples", audio_buffer.len());
-                                
-                                match self.transcribe_audio(&audio_buffer).await {
-                                    Ok(text) => {
-                                        if !text.trim().is_empty() {
-                                            info!("Transcribed: {}", text);
-                                            if let Err(e) = self.transcription_tx.send(text) {
-                                                error!("Failed to send transcription: {}", e);
-                                            }
-                                        } else {
-                                            debug!("Transcription returned empty text");
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("Transcription failed: {}", e);
+                        Some(DecodedAudio::Recording { source, samples: audio }) => {
+                            info!("Transcribing a recording of {} samples captured during startup", audio.len());
+                            queue.submit(audio, RecordingOrigin::ending_now(&source)).await?;
+                        }
+                        Some(DecodedAudio::Samples { source, samples: chunk }) => {
+                            // Only recording audio is decoded, plus what the
+                            // decoder still held when recording stopped
+                            debug!("Received audio chunk from {}: {} samples", source.device, chunk.len());
+                            let origin = RecordingOrigin::ending_now(&source);
+                            let audio_buffer = audio_buffers.entry(source).or_default();
+                            audio_buffer.extend_from_slice(&chunk);
+
+                            // In continuous mode, emit a result each window
+                            // without waiting for recording to stop
+                            if let Some(window) = continuous_window {
+                                if audio_buffer.len() >= window {
+                                    info!("Continuous window full, transcribing {} samples", audio_buffer.len());
+                                    let audio = std::mem::take(audio_buffer);
+                                    if let Some((audio, origin)) = cooldown.release(audio, origin, Instant::now()) {
+                                        queue.submit(audio, origin).await?;
                                     }
                                 }
-
-                                // Clear buffer after transcription
-                                audio_buffer.clear();
                             }
+                        }
+                        Some(DecodedAudio::Stopped) => {
+                            // Recording stopped, transcribe the accumulated audio
+                            for (source, audio) in std::mem::take(&mut audio_buffers) {
+                                if audio.is_empty() {
+                                    continue;
+                                }
+                                info!("Recording stopped, transcribing {} samples from {}", audio.len(), source.device);
 
-                            // Only accumulate audio while recording
-                            if is_recording_now {
-                                debug!("Received audio chunk: {} samples", chunk.len());
-                                audio_buffer.extend_from_slice(&chunk);
+                                let origin = RecordingOrigin::ending_now(&source);
+                                if let Some((audio, origin)) = cooldown.release(audio, origin, Instant::now()) {
+                                    queue.submit(audio, origin).await?;
+                                }
                             }
-                            
-                            was_recording = is_recording_now;
                         }
                         None => {
                             // Channel closed, check if we need to transcribe final buffer
-                            let is_recording_now = self.is_recording.load(Ordering::Acquire);
-                            if was_recording && !is_recording_now && !audio_buffer.is_empty() {
-                                info!("Channel closed, transcribing final {} samples", audio_buffer.len());
-                                
-                                match self.transcribe_audio(&audio_buffer).await {
-                                    Ok(text) => {
-                                        if !text.trim().is_empty() {
-                                            info!("Transcribed: {}", text);
-                                            if let Err(e) = self.transcription_tx.send(text) {
-                                                error!("Failed to send transcription: {}", e);
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("Transcription failed: {}", e);
+                            if let Some((audio, origin)) = cooldown.take() {
+                                queue.submit(audio, origin).await?;
+                            }
+                            if !is_recording.load(Ordering::Acquire) {
+                                for (source, audio) in std::mem::take(&mut audio_buffers) {
+                                    if !audio.is_empty() {
+                                        info!("Channel closed, transcribing final {} samples", audio.len());
+                                        queue.submit(audio, RecordingOrigin::ending_now(&source)).await?;
                                     }
                                 }
                             }
@@ -126,59 +632,578 @@ impl WhisperTranscriber {
                         }
                     }
                 }
-                _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
-                    // Periodic check for recording state changes
-                    let is_recording_now = self.is_recording.load(Ordering::Acquire);
-                    
-                    // If recording just stopped, transcribe the accumulated audio
-                    if was_recording && !is_recording_now && !audio_buffer.is_empty() {
-                        info!("Recording stopped (periodic check), transcribing {} samples", audio_buffer.len());
-                        
-                        match self.transcribe_audio(&audio_buffer).await {
-                            Ok(text) => {
-                                if !text.trim().is_empty() {
-                                    info!("Transcribed: {}", text);
-                                    if let Err(e) = self.transcription_tx.send(text) {
-                                        error!("Failed to send transcription: {}", e);
-                                    }
-                                } else {
-                                    debug!("Transcription returned empty text");
-                                }
+                _ = shutdown_requested(&mut shutdown) => {
+                    // Audio already decoded belongs to the final recording
+                    while let Ok(audio) = audio_rx.try_recv() {
+                        match audio {
+                            DecodedAudio::Samples { source, samples } => {
+                                audio_buffers.entry(source).or_default().extend_from_slice(&samples);
                             }
-                            Err(e) => {
-                                error!("Transcription failed: {}", e);
+                            DecodedAudio::Stopped => {}
+                            DecodedAudio::Recording { source, samples } => {
+                                queue.submit(samples, RecordingOrigin::ending_now(&source)).await?;
                             }
                         }
-
-                        // Clear buffer after transcription
-                        audio_buffer.clear();
                     }
-                    
-                    was_recording = is_recording_now;
+                    let held = cooldown.take().into_iter();
+                    let buffered = std::mem::take(&mut audio_buffers)
+                        .into_iter()
+                        .map(|(source, audio)| (audio, RecordingOrigin::ending_now(&source)));
+                    for (audio, origin) in held.chain(buffered) {
+                        if !audio.is_empty() {
+                            info!("Shutting down, transcribing final {} samples", audio.len());
+                            queue.submit(audio, origin).await?;
+                        }
+                    }
+                    break;
                 }
+                _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {}
             }
         }
 
+        // Workers take jobs in order, so each takes its stop only after
+        // every recording queued above has been taken; waiting for them
+        // covers the ones still being transcribed
+        queue.stop(workers.len()).await;
+        for worker in workers {
+            let _ = worker.await;
+        }
+        debug!("Transcription workers stopped");
+
         Ok(())
     }
+}
+
+/// Resolves once `shutdown` fires (or its sender is dropped); never without
+/// a shutdown signal
+async fn shutdown_requested(shutdown: &mut Option<oneshot::Receiver<()>>) {
+    match shutdown {
+        Some(rx) => {
+            let _ = rx.await;
+        }
+        None => std::future::pending().await,
+    }
+}
 
-    async fn transcribe_audio(&self, audio: &[i16]) -> Result<String> {
-        debug!("Transcribing {} samples", audio.len());
+/// Spaces recordings going to the queue at least `interval` apart,
+/// merging the ones that finish in between (`transcription.cooldown_ms`)
+struct Cooldown {
+    interval: Duration,
+    last_release: Option<Instant>,
+    /// Recordings finished during the cooldown, concatenated
+    held: Vec<i16>,
+    held_origin: RecordingOrigin,
+}
 
-        // memo-stt expects i16 samples directly, no conversion needed
-        // It handles normalization internally
-        let mut engine = self.engine.lock().await;
-        
-        engine.transcribe(audio)
-            .map_err(|e| anyhow::anyhow!("Transcription error: {}", e))
+impl Cooldown {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_release: None,
+            held: Vec::new(),
+            held_origin: RecordingOrigin::default(),
+        }
+    }
+
+    fn is_cooling(&self, now: Instant) -> bool {
+        self.last_release
+            .is_some_and(|last| now.saturating_duration_since(last) < self.interval)
+    }
+
+    /// A finished recording to transcribe now, or `None` if it was held
+    /// (merged with any recordings already held) until the cooldown ends
+    fn release(
+        &mut self,
+        audio: Vec<i16>,
+        origin: RecordingOrigin,
+        now: Instant,
+    ) -> Option<(Vec<i16>, RecordingOrigin)> {
+        self.hold(audio, origin);
+        if self.is_cooling(now) {
+            debug!("In transcription cooldown, holding {} samples", self.held.len());
+            return None;
+        }
+        self.last_release = Some(now);
+        self.take()
+    }
+
+    /// The held recordings, once the cooldown has passed
+    fn due(&mut self, now: Instant) -> Option<(Vec<i16>, RecordingOrigin)> {
+        if self.held.is_empty() || self.is_cooling(now) {
+            return None;
+        }
+        self.last_release = Some(now);
+        self.take()
+    }
+
+    fn hold(&mut self, audio: Vec<i16>, origin: RecordingOrigin) {
+        if self.held.is_empty() {
+            self.held = audio;
+            self.held_origin = origin;
+        } else {
+            self.held.extend_from_slice(&audio);
+            self.held_origin = std::mem::take(&mut self.held_origin).merge(origin);
+        }
+    }
+
+    /// The held recordings regardless of the cooldown, if any
+    fn take(&mut self) -> Option<(Vec<i16>, RecordingOrigin)> {
+        let origin = std::mem::take(&mut self.held_origin);
+        Some((std::mem::take(&mut self.held), origin)).filter(|(audio, _)| !audio.is_empty())
+    }
+}
+
+/// Recent text of local recordings, the initial prompt of the next one
+/// (`transcription.context_priming`)
+///
+/// Shared by the workers, so with several engines a recording is primed
+/// with what had finished when its transcription started.
+#[derive(Clone)]
+struct Priming {
+    recent: Arc<std::sync::Mutex<VecDeque<String>>>,
+    transcriptions: usize,
+    max_chars: usize,
+}
+
+impl Priming {
+    fn new(transcriptions: usize, max_chars: usize) -> Self {
+        Self {
+            recent: Arc::new(std::sync::Mutex::new(VecDeque::with_capacity(transcriptions))),
+            transcriptions,
+            max_chars,
+        }
+    }
+
+    fn record(&self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_back(text.to_string());
+        while recent.len() > self.transcriptions {
+            recent.pop_front();
+        }
+    }
+
+    /// The recent texts, oldest first, keeping only the last `max_chars`
+    /// (cut at a word boundary where possible)
+    fn prompt(&self) -> Option<String> {
+        let recent = self.recent.lock().unwrap();
+        let joined = recent.iter().map(String::as_str).collect::<Vec<_>>().join(" ");
+        drop(recent);
+        let Some((start, _)) = joined.char_indices().rev().nth(self.max_chars - 1) else {
+            return Some(joined).filter(|prompt| !prompt.is_empty());
+        };
+        let tail = &joined[start..];
+        let cut_mid_word = start > 0
+            && !joined[..start].ends_with(char::is_whitespace)
+            && !tail.starts_with(char::is_whitespace);
+        let tail = match tail.split_once(char::is_whitespace) {
+            Some((_, rest)) if cut_mid_word && !rest.trim().is_empty() => rest,
+            _ => tail,
+        };
+        Some(tail.trim_start().to_string()).filter(|prompt| !prompt.is_empty())
     }
 }
 
+/// Transcribe queued recordings with one engine until the queue closes
+#[allow(clippy::too_many_arguments)]
+async fn run_worker(
+    worker_id: usize,
+    mut engine: Box<dyn Engine>,
+    jobs: Arc<tokio::sync::Mutex<mpsc::Receiver<TranscriptionJob>>>,
+    transcription_tx: mpsc::UnboundedSender<TranscribedRecording>,
+    metrics: Arc<Metrics>,
+    failure_policy: FailurePolicy,
+    restart: EngineRestart,
+    priming: Option<Priming>,
+) {
+    let mut restarts = 0;
+    'jobs: loop {
+        let Some(TranscriptionJob::Recording { mut audio, origin, reply }) =
+            jobs.lock().await.recv().await
+        else {
+            break;
+        };
+
+        debug!("Worker {} transcribing {} samples", worker_id, audio.len());
+
+        // A relay peer's recording has nothing to do with this node's
+        let prompt = match (&priming, &reply) {
+            (Some(priming), None) => priming.prompt(),
+            _ => None,
+        };
+
+        let mut attempt = 0;
+        let result = loop {
+            // Whisper is CPU-bound; run it off the async runtime threads
+            let prompt = prompt.clone();
+            let joined = tokio::task::spawn_blocking(move || {
+                let result = engine.transcribe(&audio, prompt.as_deref());
+                (engine, audio, result)
+            })
+            .await;
+
+            let result = match joined {
+                Ok((returned_engine, returned_audio, result)) => {
+                    engine = returned_engine;
+                    audio = returned_audio;
+                    result
+                }
+                Err(e) => {
+                    // The engine and the recording went down with the panic
+                    error!("Transcription worker {} died, losing a recording: {}", worker_id, e);
+                    metrics.record_error(
+                        Subsystem::Transcription,
+                        format!("worker {} died: {}", worker_id, e),
+                    );
+                    match restart.reload(worker_id, &mut restarts).await {
+                        Some(reloaded) => {
+                            engine = reloaded;
+                            continue 'jobs;
+                        }
+                        None => return,
+                    }
+                }
+            };
+
+            match result {
+                Err(e) if attempt < failure_policy.retries => {
+                    attempt += 1;
+                    warn!(
+                        "Transcription failed, retrying ({}/{}): {:#}",
+                        attempt, failure_policy.retries, e
+                    );
+                    tokio::time::sleep(FailurePolicy::retry_delay(attempt)).await;
+                }
+                result => break result,
+            }
+        };
+
+        if result.is_err() && reply.is_none() {
+            failure_policy.save_audio(&audio);
+        }
+        if let (Some(priming), Ok(text), None) = (&priming, &result, &reply) {
+            priming.record(text);
+        }
+        deliver(reply, result, audio, origin, &transcription_tx, &metrics);
+    }
+}
+
+/// Forward queued recordings to a peer's `TranscribeAudio` RPC
+async fn run_remote_worker(
+    address: String,
+    cluster_secret: ClusterSecret,
+    mut jobs: mpsc::Receiver<TranscriptionJob>,
+    transcription_tx: mpsc::UnboundedSender<TranscribedRecording>,
+    metrics: Arc<Metrics>,
+    failure_policy: FailurePolicy,
+) {
+    let mut client = None;
+
+    while let Some(TranscriptionJob::Recording { audio, origin, reply }) = jobs.recv().await {
+        debug!("Sending {} samples to {}", audio.len(), address);
+
+        let mut attempt = 0;
+        let result = loop {
+            let result = transcribe_remote(&address, &cluster_secret, &mut client, &audio).await;
+            if result.is_err() {
+                // Reconnect for the next attempt
+                client = None;
+            }
+
+            match result {
+                Err(e) if attempt < failure_policy.retries => {
+                    attempt += 1;
+                    warn!(
+                        "Remote transcription failed, retrying ({}/{}): {:#}",
+                        attempt, failure_policy.retries, e
+                    );
+                    tokio::time::sleep(FailurePolicy::retry_delay(attempt)).await;
+                }
+                result => break result,
+            }
+        };
+
+        if result.is_err() && reply.is_none() {
+            failure_policy.save_audio(&audio);
+        }
+        deliver(reply, result, audio, origin, &transcription_tx, &metrics);
+    }
+}
+
+async fn transcribe_remote(
+    address: &str,
+    cluster_secret: &ClusterSecret,
+    client: &mut Option<SyncClient>,
+    audio: &[i16],
+) -> Result<String> {
+    if client.is_none() {
+        let connected = cluster_secret
+            .connect(format!("http://{}", address))
+            .await
+            .context("Failed to connect to remote transcriber")?;
+        *client = Some(connected);
+    }
+    let client = client.as_mut().expect("connected above");
+
+    // One-second chunks keep each message well under gRPC's size limit
+    let chunks: Vec<AudioChunk> = audio
+        .chunks(SAMPLE_RATE as usize)
+        .map(|samples| AudioChunk {
+            pcm: samples_to_pcm(samples),
+            sample_rate: SAMPLE_RATE,
+        })
+        .collect();
+
+    let response = client
+        .transcribe_audio(tokio_stream::iter(chunks))
+        .await
+        .context("Remote transcription failed")?
+        .into_inner();
+
+    debug!("Transcribed remotely by {}", response.node_id);
+    Ok(response.text)
+}
+
+/// Hand a transcription result to whoever asked for it: the requesting peer
+/// if there is one, otherwise the local output channel
+fn deliver(
+    reply: Option<oneshot::Sender<Result<String>>>,
+    result: Result<String>,
+    audio: Vec<i16>,
+    origin: RecordingOrigin,
+    transcription_tx: &mpsc::UnboundedSender<TranscribedRecording>,
+    metrics: &Metrics,
+) {
+    if let Some(reply) = reply {
+        let _ = reply.send(result);
+        return;
+    }
+
+    match result {
+        Ok(text) => {
+            if !text.trim().is_empty() {
+                // The text itself is logged once stored, after any redaction
+                info!("Transcribed {} words", text.split_whitespace().count());
+                let recording = TranscribedRecording {
+                    text,
+                    duration_ms: duration_ms(audio.len()),
+                    origin,
+                    audio,
+                };
+                if let Err(e) = transcription_tx.send(recording) {
+                    error!("Failed to send transcription: {}", e);
+                }
+            } else {
+                debug!("Transcription returned empty text");
+                Metrics::add(&metrics.transcriptions_empty, 1);
+            }
+        }
+        Err(e) => {
+            error!("Transcription failed: {}", e);
+            metrics.record_error(Subsystem::Transcription, format!("{:#}", e));
+        }
+    }
+}
+
+/// Little-endian 16-bit PCM, as carried by `AudioChunk`
+pub fn samples_to_pcm(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+}
+
+/// Inverse of `samples_to_pcm`; a trailing odd byte is ignored
+pub fn pcm_to_samples(pcm: &[u8]) -> Vec<i16> {
+    pcm.chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect()
+}
+
+/// Latency of repeated transcriptions of one recording, from `bench`
+#[derive(Debug, PartialEq)]
+pub struct LatencyStats {
+    pub min: Duration,
+    pub median: Duration,
+    pub max: Duration,
+    /// Median latency over the recording's length; above 1.0 the engine
+    /// can't keep up with real-time audio
+    pub real_time_factor: f64,
+}
+
+impl LatencyStats {
+    /// Stats over `runs` transcriptions of `audio_len` of audio; `None` if
+    /// there were no runs
+    pub fn from_runs(runs: &[Duration], audio_len: Duration) -> Option<Self> {
+        let mut sorted = runs.to_vec();
+        sorted.sort();
+        let (&min, &max) = (sorted.first()?, sorted.last()?);
+        let mid = sorted.len() / 2;
+        let median = if sorted.len() % 2 == 0 {
+            (sorted[mid - 1] + sorted[mid]) / 2
+        } else {
+            sorted[mid]
+        };
+
+        Some(Self {
+            min,
+            median,
+            max,
+            real_time_factor: median.as_secs_f64() / audio_len.as_secs_f64().max(f64::EPSILON),
+        })
+    }
+}
+
+/// `secs` of a voice-like test signal: a 120Hz harmonic tone pulsing at a
+/// syllable rate, so the engine does comparable work to real speech without
+/// needing a sample file
+pub fn synthetic_speech(secs: u64) -> Vec<i16> {
+    let samples = SAMPLE_RATE as u64 * secs;
+    (0..samples)
+        .map(|n| {
+            let t = n as f32 / SAMPLE_RATE as f32;
+            let envelope = (std::f32::consts::PI * 4.0 * t).sin().abs();
+            let tone: f32 = (1..=5)
+                .map(|harmonic| {
+                    let harmonic = harmonic as f32;
+                    (2.0 * std::f32::consts::PI * 120.0 * harmonic * t).sin() / harmonic
+                })
+                .sum();
+            (tone * envelope * 6000.0) as i16
+        })
+        .collect()
+}
+
+/// Load the primary model, falling back through `fallback_models` in order,
+/// returning the name of the model that loaded
+fn load_first_available<'a>(
+    model_name: &'a str,
+    fallback_models: &'a [String],
+    compute: Compute,
+) -> Result<(&'a str, SttEngine)> {
+    let candidates = std::iter::once(model_name).chain(fallback_models.iter().map(String::as_str));
+
+    for candidate in candidates {
+        match load_engine(candidate, compute) {
+            Ok(engine) => {
+                if candidate != model_name {
+                    warn!(
+                        "Primary model '{}' unavailable, downgraded to fallback model '{}'",
+                        model_name, candidate
+                    );
+                }
+                return Ok((candidate, engine));
+            }
+            Err(e) => {
+                warn!("Failed to load model '{}': {:#}", candidate, e);
+            }
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "No Whisper model could be loaded (tried '{}' and {} fallback(s))",
+        model_name,
+        fallback_models.len()
+    ))
+}
+
+/// Reloads `model_name` for workers whose engine panicked
+fn engine_loader(model_name: String, compute: Compute) -> EngineLoader {
+    Arc::new(move || -> Result<Box<dyn Engine>> {
+        Ok(Box::new(load_engine(&model_name, compute)?))
+    })
+}
+
+/// Create and warm up an engine for a single model name
+///
+/// `compute` is the backend chosen by `select_compute`; the Pi model size
+/// warnings only apply on the CPU.
+pub fn load_engine(model_name: &str, compute: Compute) -> Result<SttEngine> {
+    // Validate model name for Raspberry Pi (optimized for base.en and small.en)
+    if compute == Compute::Cpu {
+        validate_model_for_pi(model_name)?;
+    }
+
+    // Map config model names to memo-stt model paths
+    let model_path = map_model_name_to_path(model_name)?;
+    info!("Model path: {:?}", model_path);
+
+    // Create memo-stt engine
+    // memo-stt handles model downloading automatically
+    let engine = SttEngine::new(&model_path, 16000)
+        .context("Failed to create Whisper engine")?;
+
+    // Warm up the engine to reduce first-transcription latency
+    engine.warmup()
+        .context("Failed to warm up Whisper engine")?;
+
+    Ok(engine)
+}
+
+/// Backend to transcribe on for `transcription.compute = requested`,
+/// logged, with a warning if a GPU was asked for but none is found
+///
+/// memo-stt has no backend setting: whisper.cpp uses whichever GPU backend
+/// memo-stt was built with, so choosing the CPU hides CUDA devices from it.
+/// Call before loading engines.
+pub fn select_compute(requested: Compute) -> Compute {
+    let gpu = detect_gpu();
+    let selected = resolve_compute(requested, gpu.is_some());
+    if requested == Compute::Gpu && selected == Compute::Cpu {
+        warn!("transcription.compute is \"gpu\" but no GPU was found; transcribing on the CPU");
+    }
+
+    match gpu.filter(|_| selected == Compute::Gpu) {
+        Some(gpu) => info!(
+            "Transcription compute backend: GPU ({}), if memo-stt was built with support for it",
+            gpu
+        ),
+        None => {
+            info!("Transcription compute backend: CPU");
+            // Must happen before whisper.cpp initializes CUDA, i.e. before
+            // the first engine loads
+            std::env::set_var("CUDA_VISIBLE_DEVICES", "");
+        }
+    }
+    selected
+}
+
+/// Backend `select_compute` would choose, without logging or hiding devices
+pub fn detect_compute(requested: Compute) -> Compute {
+    resolve_compute(requested, detect_gpu().is_some())
+}
+
+/// `Compute::Cpu` or `Compute::Gpu` for `requested`
+fn resolve_compute(requested: Compute, gpu_found: bool) -> Compute {
+    match (requested, gpu_found) {
+        (Compute::Cpu, _) | (_, false) => Compute::Cpu,
+        (Compute::Auto | Compute::Gpu, true) => Compute::Gpu,
+    }
+}
+
+/// The kind of GPU whisper.cpp can use on this host, if any
+///
+/// Only checks that the hardware is present: a memo-stt build without the
+/// matching whisper.cpp backend still runs on the CPU.
+fn detect_gpu() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        return Some("Metal");
+    }
+    if Path::new("/dev/nvidia0").exists() {
+        return Some("CUDA");
+    }
+    // Jetson's integrated GPU has no /dev/nvidia* nodes
+    if Path::new("/dev/nvhost-ctrl-gpu").exists() {
+        return Some("CUDA, Jetson");
+    }
+    None
+}
+
 /// Validate model name for Raspberry Pi optimization
 /// 
 /// Recommends base.en or small.en for Pi hardware, but allows other models
 /// with a warning. Full model filenames (containing .bin) are always allowed.
-fn validate_model_for_pi(model_name: &str) -> Result<()> {
+pub fn validate_model_for_pi(model_name: &str) -> Result<()> {
     // Allow full model filenames
     if model_name.contains(".bin") {
         // Warn if not a recommended model for Pi
@@ -208,7 +1233,7 @@ fn validate_model_for_pi(model_name: &str) -> Result<()> {
 /// 
 /// Converts simple names like "base.en" to full model file paths
 /// that memo-stt can use. Models will be auto-downloaded if needed.
-fn map_model_name_to_path(model_name: &str) -> Result<String> {
+pub fn map_model_name_to_path(model_name: &str) -> Result<String> {
     // Map config model names to actual Whisper model file names
     let model_file = match model_name {
         "base.en" => "ggml-base.en.bin",
@@ -233,14 +1258,495 @@ fn map_model_name_to_path(model_name: &str) -> Result<String> {
     Ok(model_file.to_string())
 }
 
+/// Model names understood by `map_model_name_to_path`
+pub const KNOWN_MODELS: &[&str] = &["tiny.en", "base.en", "small.en"];
+
+/// Directory where memo-stt caches downloaded model files
+pub fn model_cache_dir() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|dirs| dirs.cache_dir().join("memo-stt").join("models"))
+}
+
+/// Whether the model file for `model_name` is already downloaded
+pub fn is_model_cached(model_name: &str) -> Result<bool> {
+    let file = map_model_name_to_path(model_name)?;
+    Ok(model_cache_dir().map_or(false, |dir| dir.join(file).exists()))
+}
+
+/// Whether a transcription matches a blocklisted phrase
+///
+/// Matching ignores case, surrounding whitespace and trailing punctuation, so
+/// a blocklist entry of "thank you" also drops Whisper's "Thank you." on silence.
+pub fn is_blocklisted(text: &str, blocklist: &[String]) -> bool {
+    let normalize = |s: &str| {
+        s.trim()
+            .trim_end_matches(|c: char| c.is_ascii_punctuation())
+            .to_lowercase()
+    };
+
+    let text = normalize(text);
+    blocklist.iter().any(|phrase| normalize(phrase) == text)
+}
+
+/// Whether `text` has fewer than `min_words` whitespace-separated words;
+/// `min_words = 0` disables the check
+pub fn is_too_short(text: &str, min_words: usize) -> bool {
+    min_words > 0 && text.split_whitespace().nth(min_words - 1).is_none()
+}
+
+/// Trim a transcription and collapse internal whitespace runs to one space,
+/// optionally NFC-normalizing it first
+pub fn normalize_text(text: &str, nfc: bool) -> String {
+    let text: String = if nfc { text.nfc().collect() } else { text.to_string() };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Marker appended to truncated transcriptions
+pub const TRUNCATION_MARKER: char = '…';
+
+/// Enforce `max_len` characters on a transcription, returning the text(s) to store
+///
+/// Lengths are counted in characters so multibyte text is never cut mid-char.
+/// Splitting prefers to break at whitespace. A `max_len` of 0 disables the limit.
+pub fn enforce_max_length(text: &str, max_len: usize, overflow: TextOverflow) -> Vec<String> {
+    let len = text.chars().count();
+    if max_len == 0 || len <= max_len {
+        return vec![text.to_string()];
+    }
+
+    match overflow {
+        TextOverflow::Truncate => {
+            warn!("Truncating {}-character transcription to {} characters", len, max_len);
+            let mut truncated: String = text.chars().take(max_len.saturating_sub(1)).collect();
+            truncated.push(TRUNCATION_MARKER);
+            vec![truncated]
+        }
+        TextOverflow::Split => {
+            let mut parts = Vec::new();
+            let mut rest: Vec<char> = text.chars().collect();
+
+            while rest.len() > max_len {
+                // Break after the last whitespace that fits, or hard-split if there is none
+                let cut = rest[..=max_len]
+                    .iter()
+                    .rposition(|c| c.is_whitespace())
+                    .filter(|&pos| pos > 0)
+                    .unwrap_or(max_len);
+
+                let part: String = rest[..cut].iter().collect();
+                if !part.trim().is_empty() {
+                    parts.push(part.trim().to_string());
+                }
+                rest.drain(..cut);
+            }
+
+            let last: String = rest.iter().collect();
+            if !last.trim().is_empty() {
+                parts.push(last.trim().to_string());
+            }
+
+            warn!(
+                "Splitting {}-character transcription into {} parts of at most {} characters",
+                len,
+                parts.len(),
+                max_len
+            );
+            parts
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// `len` samples of the recording in progress on memo-1
+    fn samples(len: usize) -> DecodedAudio {
+        DecodedAudio::Samples {
+            source: AudioSource {
+                device: "memo-1".to_string(),
+                stream: 0,
+            },
+            samples: vec![1; len],
+        }
+    }
+
+    /// Takes `delay` per recording and transcribes it as its length
+    struct SlowEngine(Duration);
+
+    impl Engine for SlowEngine {
+        fn transcribe(&mut self, audio: &[i16], _prompt: Option<&str>) -> Result<String> {
+            std::thread::sleep(self.0);
+            Ok(format!("{} samples", audio.len()))
+        }
+    }
+
+    /// Loader for tests whose engine is never reloaded
+    fn not_reloadable() -> EngineLoader {
+        Arc::new(|| -> Result<Box<dyn Engine>> { anyhow::bail!("not reloadable") })
+    }
+
+    /// A transcriber on `engine` with every option off, the sender for its
+    /// audio and the receiver for its transcriptions
+    fn test_transcriber(
+        engine: Box<dyn Engine>,
+        load: EngineLoader,
+        is_recording: bool,
+    ) -> (
+        WhisperTranscriber,
+        mpsc::UnboundedSender<DecodedAudio>,
+        mpsc::UnboundedReceiver<TranscribedRecording>,
+    ) {
+        let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (transcription_tx, transcription_rx) = mpsc::unbounded_channel();
+        let transcriber = WhisperTranscriber {
+            backend: Backend::Local {
+                engines: vec![engine],
+                load,
+            },
+            audio_rx,
+            transcription_tx,
+            is_recording: Arc::new(AtomicBool::new(is_recording)),
+            metrics: Arc::new(Metrics::default()),
+            continuous_window: None,
+            failure_policy: FailurePolicy::default(),
+            max_restarts: 0,
+            cooldown: Duration::ZERO,
+            priming: None,
+            shutdown: None,
+        };
+        (transcriber, audio_tx, transcription_rx)
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_active_transcription_and_flushes_buffer() {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let (mut transcriber, audio_tx, mut transcription_rx) = test_transcriber(
+            Box::new(SlowEngine(Duration::from_millis(300))),
+            not_reloadable(),
+            true,
+        );
+        transcriber.continuous_window = Some(1000);
+        let transcriber = transcriber.with_shutdown(shutdown_rx);
+        let (queue, jobs) = TranscriptionQueue::bounded(4);
+        let transcriber = tokio::spawn(transcriber.start(queue, jobs));
+
+        // A full window goes to the engine; the rest is still buffered
+        // (recording never stops) when shutdown arrives mid-transcription
+        audio_tx.send(samples(1000)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        audio_tx.send(samples(400)).unwrap();
+        shutdown_tx.send(()).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), transcriber)
+            .await
+            .expect("shutdown finished")
+            .unwrap()
+            .unwrap();
+
+        // Both are delivered before the output channel closes
+        let mut texts = Vec::new();
+        while let Some(recording) = transcription_rx.recv().await {
+            texts.push((recording.text, recording.duration_ms));
+        }
+        assert_eq!(
+            texts,
+            [("1000 samples".to_string(), 62), ("400 samples".to_string(), 25)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_startup_recordings_are_transcribed_separately() {
+        // Both recordings ended before the transcriber started
+        let (transcriber, audio_tx, mut transcription_rx) =
+            test_transcriber(Box::new(SlowEngine(Duration::ZERO)), not_reloadable(), false);
+        let (queue, jobs) = TranscriptionQueue::bounded(4);
+        let transcriber = tokio::spawn(transcriber.start(queue, jobs));
+
+        let recording = |len| DecodedAudio::Recording {
+            source: AudioSource {
+                device: "memo-1".to_string(),
+                stream: 0,
+            },
+            samples: vec![1; len],
+        };
+        audio_tx.send(recording(800)).unwrap();
+        audio_tx.send(recording(600)).unwrap();
+        drop(audio_tx);
+        transcriber.await.unwrap().unwrap();
+
+        let mut texts = Vec::new();
+        while let Some(recording) = transcription_rx.recv().await {
+            texts.push(recording.text);
+        }
+        assert_eq!(texts, ["800 samples", "600 samples"]);
+    }
+
+    #[tokio::test]
+    async fn test_sources_recording_at_once_are_transcribed_separately() {
+        let (transcriber, audio_tx, mut transcription_rx) =
+            test_transcriber(Box::new(SlowEngine(Duration::ZERO)), not_reloadable(), true);
+        let (queue, jobs) = TranscriptionQueue::bounded(4);
+        let transcriber = tokio::spawn(transcriber.start(queue, jobs));
+
+        let from = |device: &str, len: usize| DecodedAudio::Samples {
+            source: AudioSource {
+                device: device.to_string(),
+                stream: 0,
+            },
+            samples: vec![1; len],
+        };
+        for audio in [from("memo-2", 100), from("memo-1", 300), from("memo-2", 100)] {
+            audio_tx.send(audio).unwrap();
+        }
+        audio_tx.send(DecodedAudio::Stopped).unwrap();
+        drop(audio_tx);
+        transcriber.await.unwrap().unwrap();
+
+        let mut texts = Vec::new();
+        while let Some(recording) = transcription_rx.recv().await {
+            texts.push(recording.text);
+        }
+        assert_eq!(texts, ["300 samples", "200 samples"]);
+    }
+
+    /// Panics on its first recording
+    struct PanickingEngine;
+
+    impl Engine for PanickingEngine {
+        fn transcribe(&mut self, _audio: &[i16], _prompt: Option<&str>) -> Result<String> {
+            panic!("engine crashed");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicked_engine_is_reloaded() {
+        let reload: EngineLoader = Arc::new(|| -> Result<Box<dyn Engine>> {
+            Ok(Box::new(SlowEngine(Duration::ZERO)))
+        });
+        let (mut transcriber, audio_tx, mut transcription_rx) =
+            test_transcriber(Box::new(PanickingEngine), reload, true);
+        transcriber.continuous_window = Some(1000);
+        let metrics = transcriber.metrics.clone();
+        let transcriber = transcriber.with_max_restarts(1);
+        let (queue, jobs) = TranscriptionQueue::bounded(4);
+        let transcriber = tokio::spawn(transcriber.start(queue, jobs));
+
+        // The first window is lost with the engine; the reloaded engine
+        // transcribes the next one
+        audio_tx.send(samples(1000)).unwrap();
+        audio_tx.send(samples(1200)).unwrap();
+        let recording = tokio::time::timeout(Duration::from_secs(5), transcription_rx.recv())
+            .await
+            .expect("transcribed after the restart")
+            .unwrap();
+        assert_eq!(recording.text, "1200 samples");
+        assert!(metrics.snapshot().last_errors.contains_key(&Subsystem::Transcription));
+
+        drop(audio_tx);
+        transcriber.await.unwrap().unwrap();
+    }
+
+    /// Transcribes a recording as its length and the prompt it was given
+    struct PromptEchoEngine;
+
+    impl Engine for PromptEchoEngine {
+        fn transcribe(&mut self, audio: &[i16], prompt: Option<&str>) -> Result<String> {
+            Ok(format!("{} after [{}]", audio.len(), prompt.unwrap_or_default()))
+        }
+
+        fn takes_prompt(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_priming_caps_count_and_length_newest_last() {
+        let priming = Priming::new(3, 30);
+        for text in ["one", "two", "three", "four"] {
+            priming.record(text);
+        }
+        // Only the newest three, oldest first so the prompt ends with the
+        // text spoken just before the next recording
+        assert_eq!(priming.prompt().as_deref(), Some("two three four"));
+
+        priming.record("a much longer fifth recording");
+        let prompt = priming.prompt().unwrap();
+        assert!(prompt.chars().count() <= 30, "{:?}", prompt);
+        assert!(prompt.ends_with("a much longer fifth recording"), "{:?}", prompt);
+        assert_eq!(prompt, "a much longer fifth recording");
+    }
+
+    #[tokio::test]
+    async fn test_context_priming_prompts_with_previous_text() {
+        let (mut transcriber, audio_tx, mut transcription_rx) =
+            test_transcriber(Box::new(PromptEchoEngine), not_reloadable(), true);
+        transcriber.continuous_window = Some(1000);
+        let transcriber = transcriber.with_context_priming(1, 100);
+        let (queue, jobs) = TranscriptionQueue::bounded(4);
+        let relay = queue.clone();
+        let transcriber = tokio::spawn(transcriber.start(queue, jobs));
+
+        let mut texts = Vec::new();
+        for len in [1000, 1100] {
+            audio_tx.send(samples(len)).unwrap();
+            texts.push(transcription_rx.recv().await.unwrap().text);
+        }
+        assert_eq!(texts, ["1000 after []", "1100 after [1000 after []]"]);
+
+        // A relay peer's request is neither primed nor remembered
+        assert_eq!(relay.transcribe(vec![1; 500]).await.unwrap(), "500 after []");
+        audio_tx.send(samples(1200)).unwrap();
+        let text = transcription_rx.recv().await.unwrap().text;
+        assert_eq!(text, "1200 after [1100 after [1000 after []]]");
+
+        drop(relay);
+        drop(audio_tx);
+        transcriber.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_priming_prompt_keeps_the_end() {
+        let priming = Priming::new(2, 20);
+        assert_eq!(priming.prompt(), None);
+        priming.record("  ");
+        priming.record("first memo");
+        assert_eq!(priming.prompt().as_deref(), Some("first memo"));
+        priming.record("second one");
+        priming.record("the third recording");
+        // Only the last two are kept, cut to 20 characters at a word boundary
+        assert_eq!(priming.prompt().as_deref(), Some("the third recording"));
+        priming.record("x");
+        assert_eq!(priming.prompt().as_deref(), Some("third recording x"));
+
+        let priming = Priming::new(1, 4);
+        priming.record("unbreakable");
+        assert_eq!(priming.prompt().as_deref(), Some("able"));
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let ms = Duration::from_millis;
+        let stats = LatencyStats::from_runs(&[ms(900), ms(500), ms(700), ms(1300)], ms(2000)).unwrap();
+        assert_eq!(stats.min, ms(500));
+        assert_eq!(stats.median, ms(800));
+        assert_eq!(stats.max, ms(1300));
+        assert!((stats.real_time_factor - 0.4).abs() < 1e-9);
+
+        assert!(LatencyStats::from_runs(&[], ms(2000)).is_none());
+        assert_eq!(synthetic_speech(2).len(), 2 * SAMPLE_RATE as usize);
+    }
+
+    #[test]
+    fn test_pcm_roundtrip() {
+        let samples = vec![0, 1, -1, i16::MAX, i16::MIN, 12345];
+        let pcm = samples_to_pcm(&samples);
+        assert_eq!(pcm.len(), samples.len() * 2);
+        assert_eq!(pcm_to_samples(&pcm), samples);
+    }
+
+    #[test]
+    fn test_is_blocklisted() {
+        let blocklist = vec!["thank you".to_string(), "[BLANK_AUDIO]".to_string()];
+        assert!(is_blocklisted(" Thank you. ", &blocklist));
+        assert!(is_blocklisted("[blank_audio]", &blocklist));
+        assert!(!is_blocklisted("thank you for the reminder", &blocklist));
+    }
+
+    #[test]
+    fn test_is_too_short() {
+        assert!(!is_too_short("ok", 0));
+        assert!(is_too_short("  uh  huh ", 3));
+        assert!(!is_too_short("buy more milk", 3));
+        assert!(is_too_short("", 1));
+    }
+
+    #[test]
+    fn test_normalize_text_whitespace() {
+        assert_eq!(normalize_text("  Hello   world.\n", false), "Hello world.");
+        assert_eq!(normalize_text("tab\tand\u{00A0}nbsp", false), "tab and nbsp");
+        assert_eq!(normalize_text(" \t\n", false), "");
+    }
+
+    #[test]
+    fn test_normalize_text_nfc() {
+        let decomposed = "cafe\u{0301}";
+        assert_eq!(normalize_text(decomposed, true), "caf\u{00E9}");
+        assert_eq!(normalize_text(decomposed, false), decomposed);
+    }
+
+    #[test]
+    fn test_enforce_max_length_disabled_or_short() {
+        assert_eq!(enforce_max_length("hello", 0, TextOverflow::Truncate), vec!["hello"]);
+        assert_eq!(enforce_max_length("hello", 5, TextOverflow::Split), vec!["hello"]);
+    }
+
+    #[test]
+    fn test_enforce_max_length_truncate() {
+        assert_eq!(enforce_max_length("hello world", 6, TextOverflow::Truncate), vec!["hello…"]);
+        // Multibyte characters count as one
+        assert_eq!(enforce_max_length("ééééé", 3, TextOverflow::Truncate), vec!["éé…"]);
+    }
+
+    #[test]
+    fn test_enforce_max_length_split() {
+        assert_eq!(
+            enforce_max_length("the quick brown fox", 10, TextOverflow::Split),
+            vec!["the quick", "brown fox"]
+        );
+        assert_eq!(
+            enforce_max_length("abcdefghij", 4, TextOverflow::Split),
+            vec!["abcd", "efgh", "ij"]
+        );
+    }
+
+    #[test]
+    fn test_cooldown_merges_recordings() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let from = |device: &str, ended_at| RecordingOrigin {
+            device: Some(device.to_string()),
+            ended_at,
+        };
+        let mut cooldown = Cooldown::new(Duration::from_millis(1000));
+
+        let released = cooldown.release(vec![1; 10], from("memo-1", 100), at(0));
+        assert_eq!(released, Some((vec![1; 10], from("memo-1", 100))));
+        // Two quick toggles are held and merged
+        assert_eq!(cooldown.release(vec![2; 5], from("memo-1", 101), at(200)), None);
+        assert_eq!(cooldown.release(vec![3; 5], from("memo-1", 102), at(400)), None);
+        assert_eq!(cooldown.due(at(900)), None);
+        let merged: Vec<i16> = [vec![2; 5], vec![3; 5]].concat();
+        assert_eq!(cooldown.due(at(1000)), Some((merged, from("memo-1", 102))));
+        assert_eq!(cooldown.due(at(3000)), None);
+
+        // The cooldown restarts from the held recordings' release; audio
+        // merged from two devices belongs to neither
+        assert_eq!(cooldown.release(vec![4; 5], from("memo-1", 103), at(1500)), None);
+        assert_eq!(cooldown.release(vec![5; 5], from("memo-2", 104), at(1600)), None);
+        let merged: Vec<i16> = [vec![4; 5], vec![5; 5]].concat();
+        let origin = RecordingOrigin {
+            device: None,
+            ended_at: 104,
+        };
+        assert_eq!(cooldown.take(), Some((merged, origin)));
+
+        let mut disabled = Cooldown::new(Duration::ZERO);
+        assert!(disabled.release(vec![1], from("memo-1", 100), at(0)).is_some());
+        assert!(disabled.release(vec![1], from("memo-1", 100), at(0)).is_some());
+    }
+
     #[test]
     fn test_model_name_mapping() {
         assert_eq!(map_model_name_to_path("base.en").unwrap(), "ggml-base.en.bin");
         assert_eq!(map_model_name_to_path("small.en").unwrap(), "ggml-small.en-q5_1.bin");
     }
+
+    #[test]
+    fn test_resolve_compute_falls_back_to_cpu_without_a_gpu() {
+        assert_eq!(resolve_compute(Compute::Cpu, true), Compute::Cpu);
+        assert_eq!(resolve_compute(Compute::Auto, true), Compute::Gpu);
+        assert_eq!(resolve_compute(Compute::Auto, false), Compute::Cpu);
+        assert_eq!(resolve_compute(Compute::Gpu, true), Compute::Gpu);
+        assert_eq!(resolve_compute(Compute::Gpu, false), Compute::Cpu);
+    }
 }